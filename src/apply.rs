@@ -0,0 +1,557 @@
+use crate::merge::{remove_file, write_file};
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+
+// The most lines of context ignored at each end of a hunk which does not
+// match as it is, like `patch`.
+const MAX_FUZZ: usize = 2;
+
+const NO_NEWLINE: &str = "\\ No newline at end of file";
+
+/// A line of a hunk, which keeps its line break unless it is the last line
+/// of a file without one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HunkLine {
+    Context(String),
+    Delete(String),
+    Insert(String),
+}
+
+impl HunkLine {
+    fn text(&self) -> &str {
+        match self {
+            HunkLine::Context(line) | HunkLine::Delete(line) | HunkLine::Insert(line) => line,
+        }
+    }
+
+    fn text_mut(&mut self) -> &mut String {
+        match self {
+            HunkLine::Context(line) | HunkLine::Delete(line) | HunkLine::Insert(line) => line,
+        }
+    }
+}
+
+/// A hunk of a unified diff, replacing `old_len` lines from `old_start` by
+/// `new_len` lines from `new_start`. Line numbers start at 1, and a start
+/// of an empty range is the line before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<HunkLine>,
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|line| !matches!(line, HunkLine::Insert(_)))
+            .map(|line| line.text())
+            .collect()
+    }
+
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|line| !matches!(line, HunkLine::Delete(_)))
+            .map(|line| line.text())
+            .collect()
+    }
+
+    // The numbers of context lines at the start and at the end.
+    fn context_lens(&self) -> (usize, usize) {
+        let is_context = |line: &&HunkLine| matches!(line, HunkLine::Context(_));
+        let leading = self.lines.iter().take_while(is_context).count();
+        let trailing = self.lines.iter().rev().take_while(is_context).count();
+        (leading, trailing.min(self.lines.len() - leading))
+    }
+
+    fn reversed(&self) -> Hunk {
+        Hunk {
+            old_start: self.new_start,
+            old_len: self.new_len,
+            new_start: self.old_start,
+            new_len: self.old_len,
+            lines: self
+                .lines
+                .iter()
+                .map(|line| match line {
+                    HunkLine::Context(line) => HunkLine::Context(line.to_owned()),
+                    HunkLine::Delete(line) => HunkLine::Insert(line.to_owned()),
+                    HunkLine::Insert(line) => HunkLine::Delete(line.to_owned()),
+                })
+                .collect(),
+        }
+    }
+}
+
+// A range of a hunk header, the length is left out when it is 1.
+fn format_range(start: usize, len: usize) -> String {
+    if len == 1 {
+        start.to_string()
+    } else {
+        format!("{},{}", start, len)
+    }
+}
+
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    match range.split_once(',') {
+        Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
+    }
+}
+
+impl fmt::Display for Hunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "@@ -{} +{} @@",
+            format_range(self.old_start, self.old_len),
+            format_range(self.new_start, self.new_len)
+        )?;
+        for line in self.lines.iter() {
+            let sign = match line {
+                HunkLine::Context(_) => ' ',
+                HunkLine::Delete(_) => '-',
+                HunkLine::Insert(_) => '+',
+            };
+            write!(f, "{}{}", sign, line.text())?;
+            if !line.text().ends_with('\n') {
+                writeln!(f, "\n{}", NO_NEWLINE)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The changes of a file in a patch. A created file has no old path, a
+/// deleted file has no new path, and a renamed or copied file has both.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    /// The old file is kept as it is, and the new file starts as its copy.
+    pub copy: bool,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FilePatch {
+    /// The name of the file in messages, `old => new` for a rename.
+    pub fn name(&self) -> String {
+        match (&self.old_path, &self.new_path) {
+            (Some(old), Some(new)) if old != new => format!("{} => {}", old, new),
+            (Some(path), _) | (None, Some(path)) => path.to_owned(),
+            (None, None) => String::new(),
+        }
+    }
+
+    fn reversed(&self) -> FilePatch {
+        // Undoing a copy removes the copy.
+        let (old_path, new_path) = match self.copy {
+            true => (self.new_path.clone(), None),
+            false => (self.new_path.clone(), self.old_path.clone()),
+        };
+        FilePatch {
+            old_path,
+            new_path,
+            copy: self.copy,
+            hunks: self.hunks.iter().map(|hunk| hunk.reversed()).collect(),
+        }
+    }
+}
+
+/// A patch in the unified diff format, as `diff -u` or `git diff` writes
+/// it. Lines out of the diff like a commit message are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Patch {
+    pub files: Vec<FilePatch>,
+}
+
+impl Patch {
+    /// A patch undoing this patch.
+    pub fn reversed(&self) -> Patch {
+        Patch {
+            files: self.files.iter().map(|file| file.reversed()).collect(),
+        }
+    }
+}
+
+// A path of a `---` or `+++` line without the first directory like `a/`,
+// none for `/dev/null`.
+fn parse_path(path: &str) -> Option<String> {
+    let path = path.split('\t').next().unwrap_or(path).trim_end();
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(match path.split_once('/') {
+        Some((_, path)) => path.to_owned(),
+        None => path.to_owned(),
+    })
+}
+
+// A file being parsed, with what the `diff --git` header tells.
+#[derive(Default)]
+struct FileHeader {
+    patch: FilePatch,
+    created: bool,
+    deleted: bool,
+}
+
+impl FileHeader {
+    fn finish(mut self) -> FilePatch {
+        if self.created {
+            self.patch.old_path = None;
+        }
+        if self.deleted {
+            self.patch.new_path = None;
+        }
+        self.patch
+    }
+}
+
+impl FromStr for Patch {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let mut files = vec![];
+        let mut file: Option<FileHeader> = None;
+        // Lines of the old and the new side left in the current hunk.
+        let mut remaining = (0, 0);
+
+        for line in s.split_inclusive('\n') {
+            // The marker follows the line it is about, which may be the
+            // last line of a hunk.
+            if line.starts_with('\\') {
+                let last = file
+                    .as_mut()
+                    .and_then(|file| file.patch.hunks.last_mut())
+                    .and_then(|hunk| hunk.lines.last_mut());
+                if let Some(last) = last {
+                    last.text_mut().pop();
+                }
+                continue;
+            }
+            if remaining != (0, 0) {
+                let hunk = file
+                    .as_mut()
+                    .and_then(|file| file.patch.hunks.last_mut())
+                    .ok_or_else(|| anyhow!("A hunk is outside of a file."))?;
+                let mut chars = line.chars();
+                let (sign, text) = match line {
+                    // Some tools strip the space of an empty context line.
+                    "\n" => (' ', "\n"),
+                    _ => (chars.next().unwrap_or(' '), chars.as_str()),
+                };
+                let hunk_line = match sign {
+                    ' ' if remaining.0 > 0 && remaining.1 > 0 => {
+                        remaining = (remaining.0 - 1, remaining.1 - 1);
+                        HunkLine::Context(text.to_owned())
+                    }
+                    '-' if remaining.0 > 0 => {
+                        remaining.0 -= 1;
+                        HunkLine::Delete(text.to_owned())
+                    }
+                    '+' if remaining.1 > 0 => {
+                        remaining.1 -= 1;
+                        HunkLine::Insert(text.to_owned())
+                    }
+                    _ => return Err(anyhow!("A hunk is broken at a line: {}", line.trim_end())),
+                };
+                hunk.lines.push(hunk_line);
+                continue;
+            }
+
+            let text = line.trim_end_matches('\n');
+            if let Some(paths) = text.strip_prefix("diff --git ") {
+                files.extend(file.take().map(FileHeader::finish));
+                let mut header = FileHeader::default();
+                if let Some(index) = paths.rfind(" b/") {
+                    header.patch.old_path = parse_path(&paths[..index]);
+                    header.patch.new_path = parse_path(&paths[index + 1..]);
+                }
+                file = Some(header);
+            } else if let Some(path) = text.strip_prefix("--- ") {
+                // A patch of `diff -u` starts a file at `---`.
+                if file
+                    .as_ref()
+                    .is_none_or(|file| !file.patch.hunks.is_empty())
+                {
+                    files.extend(file.take().map(FileHeader::finish));
+                    file = Some(FileHeader::default());
+                }
+                if let Some(file) = file.as_mut() {
+                    file.patch.old_path = parse_path(path);
+                    file.created = file.patch.old_path.is_none();
+                }
+            } else if let Some(path) = text.strip_prefix("+++ ") {
+                if let Some(file) = file.as_mut() {
+                    file.patch.new_path = parse_path(path);
+                    file.deleted = file.patch.new_path.is_none();
+                }
+            } else if let Some(header) = text.strip_prefix("@@ -") {
+                let file = file
+                    .as_mut()
+                    .ok_or_else(|| anyhow!("A hunk is outside of a file: {}", text))?;
+                let ranges = header.split(" @@").next().unwrap_or("");
+                let (old, new) = ranges
+                    .split_once(" +")
+                    .and_then(|(old, new)| Some((parse_range(old)?, parse_range(new)?)))
+                    .ok_or_else(|| anyhow!("Invalid hunk header: {}", text))?;
+                file.patch.hunks.push(Hunk {
+                    old_start: old.0,
+                    old_len: old.1,
+                    new_start: new.0,
+                    new_len: new.1,
+                    lines: vec![],
+                });
+                remaining = (old.1, new.1);
+            } else if let Some(file) = file.as_mut() {
+                if text.starts_with("new file mode ") {
+                    file.created = true;
+                } else if text.starts_with("deleted file mode ") {
+                    file.deleted = true;
+                } else if let Some(path) = text.strip_prefix("rename from ") {
+                    file.patch.old_path = Some(path.to_owned());
+                } else if let Some(path) = text.strip_prefix("rename to ") {
+                    file.patch.new_path = Some(path.to_owned());
+                } else if let Some(path) = text.strip_prefix("copy from ") {
+                    file.patch.old_path = Some(path.to_owned());
+                    file.patch.copy = true;
+                } else if let Some(path) = text.strip_prefix("copy to ") {
+                    file.patch.new_path = Some(path.to_owned());
+                    file.patch.copy = true;
+                }
+            }
+        }
+        if remaining != (0, 0) {
+            return Err(anyhow!("A patch ends in the middle of a hunk."));
+        }
+        files.extend(file.take().map(FileHeader::finish));
+        Ok(Patch { files })
+    }
+}
+
+/// What became of a hunk, with the line of the file it was applied at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HunkOutcome {
+    /// Applied `offset` lines away from the line in the hunk header, with
+    /// `fuzz` lines of context at each end ignored.
+    Applied {
+        line: usize,
+        offset: isize,
+        fuzz: usize,
+    },
+    Failed {
+        line: usize,
+    },
+}
+
+/// The outcome of the hunks of a file in a patch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReport {
+    pub name: String,
+    pub hunks: Vec<HunkOutcome>,
+    /// The file written with the hunks which failed, unless checking.
+    pub reject_file: Option<String>,
+}
+
+impl FileReport {
+    pub fn is_clean(&self) -> bool {
+        self.hunks
+            .iter()
+            .all(|hunk| matches!(hunk, HunkOutcome::Applied { .. }))
+    }
+}
+
+// Find where `pattern` matches `lines` at or after `min`, nearest to
+// `expected`.
+fn find_lines(lines: &[&str], pattern: &[&str], min: usize, expected: usize) -> Option<usize> {
+    if lines.len() < pattern.len() {
+        return None;
+    }
+    let max = lines.len() - pattern.len();
+    let expected = expected.clamp(min, max.max(min));
+    let matches = |start: usize| start <= max && lines[start..start + pattern.len()] == *pattern;
+    for distance in 0..=lines.len() {
+        if matches(expected + distance) {
+            return Some(expected + distance);
+        }
+        if distance > 0 && expected >= min + distance && matches(expected - distance) {
+            return Some(expected - distance);
+        }
+    }
+    None
+}
+
+// Apply hunks to `contents`, and return the new contents with the outcome
+// of each hunk. Failed hunks are left out.
+fn apply_hunks(contents: &str, hunks: &[Hunk]) -> (String, Vec<HunkOutcome>) {
+    let lines: Vec<&str> = contents.split_inclusive('\n').collect();
+    let mut result = String::new();
+    let mut outcomes = vec![];
+    let mut pos = 0;
+    // How far earlier hunks moved from their headers.
+    let mut offset: isize = 0;
+    for hunk in hunks.iter() {
+        let old = hunk.old_lines();
+        let new = hunk.new_lines();
+        let (leading, trailing) = hunk.context_lens();
+        let start = match hunk.old_len {
+            0 => hunk.old_start,
+            _ => hunk.old_start.saturating_sub(1),
+        };
+        let expected = (start as isize + offset).max(0) as usize;
+
+        let mut applied = None;
+        for fuzz in 0..=MAX_FUZZ {
+            let (lead, trail) = (fuzz.min(leading), fuzz.min(trailing));
+            if fuzz > 0 && (lead + trail == 0 || lead + trail >= old.len()) {
+                break;
+            }
+            let pattern = &old[lead..old.len() - trail];
+            if let Some(found) = find_lines(&lines, pattern, pos, expected + lead) {
+                applied = Some((found, lead, trail, fuzz));
+                break;
+            }
+        }
+        match applied {
+            Some((found, lead, trail, fuzz)) => {
+                for line in lines[pos..found].iter() {
+                    result.push_str(line);
+                }
+                for line in new[lead..new.len() - trail].iter() {
+                    result.push_str(line);
+                }
+                pos = found + old.len() - lead - trail;
+                let moved = found as isize - (expected + lead) as isize;
+                offset += moved;
+                outcomes.push(HunkOutcome::Applied {
+                    line: found - lead + 1,
+                    offset,
+                    fuzz,
+                });
+            }
+            None => outcomes.push(HunkOutcome::Failed { line: expected + 1 }),
+        }
+    }
+    for line in lines[pos..].iter() {
+        result.push_str(line);
+    }
+    (result, outcomes)
+}
+
+fn read_file(path: &str) -> Result<String> {
+    fs::read_to_string(path).with_context(|| format!("Failed to read file: {}", path))
+}
+
+// A file of the rejected hunks of a file, written next to it.
+fn reject_contents(file: &FilePatch, hunks: &[HunkOutcome]) -> String {
+    let mut contents = format!(
+        "--- a/{}\n+++ b/{}\n",
+        file.old_path.as_deref().unwrap_or("/dev/null"),
+        file.new_path.as_deref().unwrap_or("/dev/null")
+    );
+    for (hunk, outcome) in file.hunks.iter().zip(hunks.iter()) {
+        if let HunkOutcome::Failed { .. } = outcome {
+            contents.push_str(&hunk.to_string());
+        }
+    }
+    contents
+}
+
+// Refuse a path of a patch which reaches outside of the working tree or
+// into the repository, like `git apply`. A patch may come from anyone.
+fn check_path(path: &str) -> Result<()> {
+    let invalid = |reason: &str| Err(anyhow!("A patch {}: {}", reason, path));
+    if path.is_empty() {
+        return invalid("has an empty path");
+    }
+    if path.starts_with('/') || path.starts_with('\\') || Path::new(path).is_absolute() {
+        return invalid("has an absolute path");
+    }
+    for component in path.split(&['/', '\\'][..]) {
+        match component {
+            ".." => return invalid("has a path going up a directory"),
+            ".dsgit" => return invalid("has a path into the repository"),
+            // A drive letter on Windows.
+            _ if component.contains(':') => return invalid("has an absolute path"),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Apply a patch to the working tree. Hunks which do not match even with an
+/// offset or fuzz are written to `<file>.rej` and the rest of them are
+/// applied. Files to patch must exist and files to create must not, or
+/// nothing is written. Paths which are absolute, go up a directory or reach
+/// into `.dsgit` are refused. With `check`, only the outcome is returned.
+pub fn apply_patch(patch: &Patch, check: bool) -> Result<Vec<FileReport>> {
+    for file in patch.files.iter() {
+        for path in file.old_path.iter().chain(file.new_path.iter()) {
+            check_path(path)?;
+        }
+    }
+
+    let mut reports = vec![];
+    let mut writes = vec![];
+    for file in patch.files.iter() {
+        let contents = match &file.old_path {
+            Some(path) => read_file(path)?,
+            None => String::new(),
+        };
+        if let Some(path) = &file.new_path {
+            if file.old_path.as_ref() != Some(path) && Path::new(path).exists() {
+                return Err(anyhow!("A file to create already exists: {}", path));
+            }
+        }
+        let (contents, hunks) = apply_hunks(&contents, &file.hunks);
+        let mut report = FileReport {
+            name: file.name(),
+            hunks,
+            reject_file: None,
+        };
+        if file.new_path.is_none() && report.is_clean() && !file.copy && !contents.is_empty() {
+            return Err(anyhow!(
+                "A removal patch leaves contents in the file: {}",
+                file.name()
+            ));
+        }
+        if !report.is_clean() {
+            let path = file.new_path.as_ref().or(file.old_path.as_ref());
+            report.reject_file = path.map(|path| format!("{}.rej", path));
+        }
+        writes.push((file, contents, report.clone()));
+        reports.push(report);
+    }
+    if check {
+        return Ok(reports);
+    }
+
+    for (file, contents, report) in writes.into_iter() {
+        if let Some(path) = &report.reject_file {
+            write_file(path, &reject_contents(file, &report.hunks))?;
+        }
+        // A file whose creation, deletion or rename failed is left as it is.
+        if !report.is_clean() && file.old_path != file.new_path {
+            continue;
+        }
+        match (&file.old_path, &file.new_path) {
+            (_, Some(new_path)) => write_file(new_path, &contents)?,
+            (Some(old_path), None) => remove_file(old_path)?,
+            (None, None) => continue,
+        }
+        if let (Some(old_path), Some(new_path)) = (&file.old_path, &file.new_path) {
+            if old_path != new_path && !file.copy {
+                remove_file(old_path)?;
+            }
+        }
+    }
+    Ok(reports)
+}
@@ -0,0 +1,227 @@
+use crate::data::{get_oid, object_exists, RepositoryFormat};
+use crate::hash::ObjectId;
+use crate::pack::{read_pack, verify_pack, write_pack};
+use crate::protocol::{objects_to_send, RefAdvertisement};
+use crate::remote::resolve_ref;
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+
+use anyhow::{anyhow, Context, Result};
+
+// The first line of a bundle file.
+const BUNDLE_SIGNATURE: &str = "# dsgit bundle v1";
+
+/// A file carrying references and the objects they need, so that history
+/// moves between repositories without a connection:
+///   # dsgit bundle v1
+///   -<oid>          a commit the receiver must already have
+///   <oid> <ref>     a reference in the bundle
+///   (an empty line)
+///   <pack>
+#[derive(Debug, PartialEq)]
+pub struct Bundle {
+    pub path: String,
+    pub prerequisites: Vec<String>,
+    pub refs: Vec<(String, String)>,
+}
+
+// Read lines of the header up to the empty line before the pack.
+fn read_header(reader: &mut impl BufRead, path: &str) -> Result<Vec<String>> {
+    let mut lines = vec![];
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(anyhow!("A bundle ended unexpectedly: {}", path));
+        }
+        let line = line.trim_end_matches('\n');
+        if line.is_empty() {
+            return Ok(lines);
+        }
+        lines.push(line.to_owned());
+    }
+}
+
+impl Bundle {
+    /// Whether a file starts as a bundle.
+    pub fn is_bundle(path: &str) -> bool {
+        let mut signature = vec![0u8; BUNDLE_SIGNATURE.len()];
+        match File::open(path) {
+            Ok(mut file) => {
+                file.read_exact(&mut signature).is_ok() && signature == BUNDLE_SIGNATURE.as_bytes()
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Write a bundle of the current repository. Each of `revs` is a
+    /// reference to include, `^<commit>` or `<commit>..<ref>` to leave out
+    /// history the receiver already has, or `--all` for every branch and
+    /// tag.
+    pub fn create(path: &str, revs: &[String]) -> Result<Self> {
+        let mut refs: Vec<(String, String)> = vec![];
+        let mut prerequisites = vec![];
+        for rev in revs.iter() {
+            let names = if rev == "--all" {
+                RefAdvertisement::current()?
+                    .refs
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .collect()
+            } else if let Some(excluded) = rev.strip_prefix('^') {
+                prerequisites.push(get_oid(excluded)?.into());
+                vec![]
+            } else if let Some((excluded, included)) = rev.split_once("..") {
+                prerequisites.push(get_oid(excluded)?.into());
+                vec![if included.is_empty() {
+                    "HEAD"
+                } else {
+                    included
+                }
+                .to_owned()]
+            } else {
+                vec![rev.to_owned()]
+            };
+            for name in names.iter() {
+                let (ref_name, oid) = resolve_ref(name)?
+                    .ok_or_else(|| anyhow!("A bundle needs references, not found: {}", name))?;
+                if !refs.iter().any(|(name, _)| *name == ref_name) {
+                    refs.push((ref_name, oid));
+                }
+            }
+        }
+        if refs.is_empty() {
+            return Err(anyhow!("Refusing to create an empty bundle."));
+        }
+        prerequisites.sort();
+        prerequisites.dedup();
+
+        let mut contents = format!("{}\n", BUNDLE_SIGNATURE).into_bytes();
+        for oid in prerequisites.iter() {
+            contents.extend(format!("-{}\n", oid).into_bytes());
+        }
+        for (name, oid) in refs.iter() {
+            contents.extend(format!("{} {}\n", oid, name).into_bytes());
+        }
+        contents.push(b'\n');
+        let oids: Vec<String> = refs.iter().map(|(_, oid)| oid.to_owned()).collect();
+        write_pack(&mut contents, &objects_to_send(&oids, &prerequisites)?)?;
+
+        fs::write(path, contents).with_context(|| format!("Failed to write bundle: {}", path))?;
+        Ok(Bundle {
+            path: path.to_owned(),
+            prerequisites,
+            refs,
+        })
+    }
+
+    /// Read the header of a bundle file.
+    pub fn open(path: &str) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open bundle: {}", path))?;
+        let (bundle, _) = Bundle::read(path, file)?;
+        Ok(bundle)
+    }
+
+    // Parse the header, and return the reader positioned at the pack.
+    fn read(path: &str, file: File) -> Result<(Self, BufReader<File>)> {
+        let mut reader = BufReader::new(file);
+        let lines = read_header(&mut reader, path)?;
+        if lines.first().map(|line| line.as_str()) != Some(BUNDLE_SIGNATURE) {
+            return Err(anyhow!("Not a bundle file: {}", path));
+        }
+        let mut bundle = Bundle {
+            path: path.to_owned(),
+            prerequisites: vec![],
+            refs: vec![],
+        };
+        for line in lines[1..].iter() {
+            if let Some(oid) = line.strip_prefix('-') {
+                bundle.prerequisites.push(oid.to_owned());
+                continue;
+            }
+            let (oid, name) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("Broken bundle line: {}", line))?;
+            bundle.refs.push((name.to_owned(), oid.to_owned()));
+        }
+        Ok((bundle, reader))
+    }
+
+    fn open_pack(&self) -> Result<BufReader<File>> {
+        let file = File::open(&self.path)
+            .with_context(|| format!("Failed to open bundle: {}", self.path))?;
+        Ok(Bundle::read(&self.path, file)?.1)
+    }
+
+    /// Prerequisite commits missing in the current repository.
+    pub fn missing_prerequisites(&self) -> Vec<String> {
+        self.prerequisites
+            .iter()
+            .filter(|oid| !object_exists(oid))
+            .cloned()
+            .collect()
+    }
+
+    fn check_prerequisites(&self) -> Result<()> {
+        let missing = self.missing_prerequisites();
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "The repository lacks these prerequisite commits: {}",
+                missing.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that the current repository has every prerequisite, and that
+    /// every object in the bundle matches its hash.
+    pub fn verify(&self) -> Result<()> {
+        self.check_prerequisites()?;
+        verify_pack(&mut self.open_pack()?)?;
+        Ok(())
+    }
+
+    /// Store objects of the bundle into the current repository, and return
+    /// the number of objects which were missing.
+    pub fn unbundle(&self) -> Result<usize> {
+        self.check_prerequisites()?;
+        read_pack(&mut self.open_pack()?)
+    }
+
+    /// The references of the bundle as a remote offers them. HEAD follows a
+    /// branch pointing to the same commit if any.
+    pub fn advertisement(&self) -> RefAdvertisement {
+        let head_oid = self
+            .refs
+            .iter()
+            .find(|(name, _)| name == "HEAD")
+            .map(|(_, oid)| oid.to_owned());
+        let head_branch = head_oid.as_ref().and_then(|head_oid| {
+            self.refs
+                .iter()
+                .find(|(name, oid)| name.starts_with("refs/heads/") && oid == head_oid)
+                .map(|(name, _)| name.to_owned())
+        });
+        // A bundle tells only hashes, which tell the hash algorithm.
+        let hash = self
+            .refs
+            .iter()
+            .find_map(|(_, oid)| ObjectId::parse(oid).ok())
+            .map(|oid| oid.algorithm())
+            .unwrap_or_default();
+        RefAdvertisement {
+            head_branch,
+            head_oid,
+            refs: self
+                .refs
+                .iter()
+                .filter(|(name, _)| name != "HEAD")
+                .cloned()
+                .collect(),
+            format: RepositoryFormat {
+                hash,
+                ..Default::default()
+            },
+        }
+    }
+}
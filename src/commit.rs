@@ -1,30 +1,60 @@
-use crate::data::{get_object, hash_object, TypeObject};
+use crate::data::{get_object, get_oid, hash_object, ObjectEncoding, RepositoryFormat, TypeObject};
 use crate::entry::Tree;
+use crate::hash::ObjectId;
+use crate::identity::{get_identity, get_timestamp};
+use crate::merge::{apply_tree_changes, ensure_clean_working_tree};
 use crate::reference::RefValue;
+use crate::shallow;
 use anyhow::{anyhow, Result};
+use std::collections::{HashSet, VecDeque};
+
+/// A reference to the commit being merged while a merge is stopped by
+/// conflicts.
+pub const MERGE_HEAD: &str = "MERGE_HEAD";
 
 #[derive(Debug, PartialEq)]
 pub struct Commit {
-    pub tree: String,
-    pub parent: Option<String>,
+    pub tree: ObjectId,
+    pub parent: Option<ObjectId>,
+    // The second parent of a merge commit.
+    pub merge_parent: Option<ObjectId>,
+    /// `Name <email> <seconds> <timezone>` of the author. dsgit's encoding
+    /// records it only for a commit made on behalf of someone, like by `am`.
+    pub author: Option<String>,
     pub message: String,
 }
 
 impl Commit {
+    /// Read a commit. A shallow commit is read without parents, since they
+    /// are missing in a shallow clone.
     pub fn get_commit(oid: &str) -> Result<Self> {
+        let mut commit = Commit::read_commit(oid)?;
+        if shallow::is_shallow(oid)? {
+            commit.parent = None;
+            commit.merge_parent = None;
+        }
+        Ok(commit)
+    }
+
+    /// Read a commit with the parents it records, even if they are cut off
+    /// by a shallow clone.
+    pub fn read_commit(oid: &str) -> Result<Self> {
         let commit_obj = get_object(oid, TypeObject::Commit)?;
         let lines: Vec<&str> = commit_obj.lines().collect::<Vec<&str>>();
 
         // Parse each line from below commit format:
         //   tree [commit hash]
         //   parent [commit hash] // if first commit, this line is nothing.
+        //   parent [commit hash] // only in a merge commit.
+        //   author [name] <[email]> [seconds] [timezone] // only in Git's encoding.
+        //   committer [name] <[email]> [seconds] [timezone] // only in Git's encoding.
         //
         //   [commit message]
         //
         // Parse a tree line as line0.
         let line0: Vec<&str> = lines[0].split(' ').collect();
         let tree = if line0[0] == "tree" {
-            line0[1].to_string()
+            ObjectId::parse(line0[1])?
         } else {
             return Err(anyhow!(
                 "Commit object expected including tree object, but got {}",
@@ -36,10 +66,20 @@ impl Commit {
         // this line may None in this case of first commit.
         let line1: Vec<&str> = lines[1].split(' ').collect();
         let parent = if line1[0] == "parent" {
-            Some(line1[1].to_string())
+            Some(ObjectId::parse(line1[1])?)
         } else {
             None
         };
+        let merge_parent = match lines.get(2).and_then(|l| l.strip_prefix("parent ")) {
+            Some(merge_parent) if parent.is_some() => Some(ObjectId::parse(merge_parent)?),
+            _ => None,
+        };
+
+        let author = lines
+            .iter()
+            .take_while(|line| !line.is_empty())
+            .find_map(|line| line.strip_prefix("author "))
+            .map(|author| author.to_owned());
 
         // Parse a commit message at last line.
         let message = String::from("") + lines.last().unwrap();
@@ -47,21 +87,272 @@ impl Commit {
         Ok(Commit {
             tree,
             parent,
+            merge_parent,
+            author,
             message,
         })
     }
 
-    pub fn commit(message: &str, ignore_options: &[String]) -> Result<String> {
-        let oid = Tree::write_tree(".", ignore_options)?;
-        let mut commit = String::from("tree ") + &oid + "\n";
+    /// Parents of the commit, the first parent comes first.
+    pub fn parents(&self) -> Vec<ObjectId> {
+        self.parent
+            .iter()
+            .chain(self.merge_parent.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Check whether `ancestor` is reachable from `oid` by following parents,
+    /// a commit is treated as an ancestor of itself.
+    pub fn is_ancestor(ancestor: &str, oid: &str) -> Result<bool> {
+        Ok(Commit::ancestors(oid)?.contains(ancestor))
+    }
 
-        if let Some(ref_value) = RefValue::get_ref("HEAD", true)? {
-            commit = commit + "parent " + &ref_value.value + "\n"
+    // Every commit reachable from `oid` by following parents, in order of
+    // the distance from `oid`.
+    fn ancestors_in_order(oid: &str) -> Result<Vec<ObjectId>> {
+        let mut visited = HashSet::new();
+        let mut ancestors = vec![];
+        let mut queue = VecDeque::from(vec![ObjectId::parse(oid)?]);
+        while let Some(cur) = queue.pop_front() {
+            if !visited.insert(cur.clone()) {
+                continue;
+            }
+            queue.extend(Commit::get_commit(&cur)?.parents());
+            ancestors.push(cur);
+        }
+        Ok(ancestors)
+    }
+
+    pub(crate) fn ancestors(oid: &str) -> Result<HashSet<ObjectId>> {
+        Ok(Commit::ancestors_in_order(oid)?.into_iter().collect())
+    }
+
+    /// Find the best common ancestor of two commits, which is the base of a
+    /// three-way merge between them. When criss-cross merges leave several
+    /// of them, the nearest one from `other` is taken.
+    pub fn merge_base(oid: &str, other: &str) -> Result<Option<ObjectId>> {
+        Ok(Commit::merge_bases(oid, other)?.into_iter().next())
+    }
+
+    /// Find the best common ancestors of two commits, the common ancestors
+    /// which are not reachable from another common ancestor, in order of
+    /// the distance from `other`.
+    pub fn merge_bases(oid: &str, other: &str) -> Result<Vec<ObjectId>> {
+        let ours = Commit::ancestors(oid)?;
+        let common: Vec<ObjectId> = Commit::ancestors_in_order(other)?
+            .into_iter()
+            .filter(|ancestor| ours.contains(ancestor))
+            .collect();
+        // Ancestors of a common ancestor are worse bases than it.
+        let mut worse = HashSet::new();
+        for ancestor in common.iter() {
+            if worse.contains(ancestor) {
+                continue;
+            }
+            for parent in Commit::get_commit(ancestor)?.parents().iter() {
+                worse.extend(Commit::ancestors_in_order(parent)?);
+            }
+        }
+        Ok(common
+            .into_iter()
+            .filter(|ancestor| !worse.contains(ancestor))
+            .collect())
+    }
+
+    /// Count commits reachable only from `oid` and only from `other`, which
+    /// tell how many commits a branch is ahead of and behind its upstream.
+    pub fn count_ahead_behind(oid: &str, other: &str) -> Result<(usize, usize)> {
+        let ours = Commit::ancestors(oid)?;
+        let theirs = Commit::ancestors(other)?;
+        Ok((
+            ours.difference(&theirs).count(),
+            theirs.difference(&ours).count(),
+        ))
+    }
+
+    pub fn create(tree: &str, parent: Option<&str>, message: &str) -> Result<ObjectId> {
+        Commit::create_merge(tree, parent, None, message)
+    }
+
+    /// Create a commit which may have the second parent as a merge commit.
+    pub fn create_merge(
+        tree: &str,
+        parent: Option<&str>,
+        merge_parent: Option<&str>,
+        message: &str,
+    ) -> Result<ObjectId> {
+        Commit::write_commit(tree, parent, merge_parent, None, message)
+    }
+
+    /// Create a commit on behalf of `author`, which is formatted as
+    /// `Name <email> <seconds> <timezone>`. The author is recorded in every
+    /// encoding, and the current user is the committer.
+    pub fn create_authored(
+        tree: &str,
+        parent: Option<&str>,
+        author: &str,
+        message: &str,
+    ) -> Result<ObjectId> {
+        Commit::write_commit(tree, parent, None, Some(author), message)
+    }
+
+    fn write_commit(
+        tree: &str,
+        parent: Option<&str>,
+        merge_parent: Option<&str>,
+        author: Option<&str>,
+        message: &str,
+    ) -> Result<ObjectId> {
+        let mut commit = String::from("tree ") + tree + "\n";
+        for parent in parent.iter().chain(merge_parent.iter()) {
+            commit = commit + "parent " + parent + "\n"
+        }
+        let signature = format!("{} {}", get_identity(), get_timestamp());
+        // Git requires a commit to record its author and committer, while
+        // dsgit's encoding records only an author given on purpose.
+        match RepositoryFormat::read()?.encoding {
+            ObjectEncoding::Git => {
+                let author = author.unwrap_or(&signature);
+                commit = commit + "author " + author + "\ncommitter " + &signature + "\n";
+            }
+            ObjectEncoding::Dsgit => {
+                if let Some(author) = author {
+                    commit = commit + "author " + author + "\n";
+                }
+            }
         }
 
         commit = commit + "\n" + message + "\n";
-        let commit_oid = hash_object(&commit, TypeObject::Commit)?;
+        hash_object(&commit, TypeObject::Commit)
+    }
+
+    /// Record the working tree as a new commit on HEAD. While a merge is
+    /// stopped by conflicts, the commit concludes the merge with `MERGE_HEAD`
+    /// as the second parent.
+    pub fn commit(message: &str, ignore_options: &[String]) -> Result<ObjectId> {
+        Commit::commit_as(message, None, ignore_options)
+    }
+
+    // Record the working tree as `commit` does, logging the update of HEAD
+    // with `reason` instead of one of `commit` when it is given.
+    fn commit_as(
+        message: &str,
+        reason: Option<&str>,
+        ignore_options: &[String],
+    ) -> Result<ObjectId> {
+        let oid = Tree::write_tree(".", ignore_options)?;
+        let parent = RefValue::get_ref("HEAD", true)?.map(|ref_value| ref_value.value);
+        let merge_parent = RefValue::get_ref(MERGE_HEAD, false)?.map(|ref_value| ref_value.value);
+        let commit_oid =
+            Commit::create_merge(&oid, parent.as_deref(), merge_parent.as_deref(), message)?;
         let ref_value = RefValue::new(Some(&commit_oid), false, &commit_oid);
-        RefValue::update_ref("HEAD", &ref_value, true)
+        let reason = reason.unwrap_or(match (&parent, &merge_parent) {
+            (_, Some(_)) => "commit (merge)",
+            (Some(_), None) => "commit",
+            (None, None) => "commit (initial)",
+        });
+        let value = RefValue::update_ref(
+            "HEAD",
+            &ref_value,
+            true,
+            &format!("{}: {}", reason, message),
+        )?;
+        if merge_parent.is_some() {
+            RefValue::delete_ref(MERGE_HEAD)?;
+        }
+        ObjectId::parse(&value)
+    }
+
+    // Apply the changes from `from` to `to` onto the working tree, and record
+    // them as a new commit with `message` unless `no_commit` is given. The
+    // update of HEAD is logged with `reason`, the name of the command.
+    fn apply_commit(
+        reason: &str,
+        oid: &str,
+        from: Tree,
+        to: Tree,
+        message: &str,
+        no_commit: bool,
+        ignore_options: &[String],
+    ) -> Result<Option<ObjectId>> {
+        let label = format!("{} ({})", &oid[..7], message);
+        let conflicts = apply_tree_changes(from, to, &label, ignore_options)?;
+        if !conflicts.is_empty() {
+            return Err(anyhow!(
+                "Could not apply {}... {}\nConflicts in: {}\n\
+                 Resolve them and record the result with `dsgit commit -m`.",
+                &oid[..7],
+                message,
+                conflicts.join(", ")
+            ));
+        }
+
+        if no_commit {
+            return Ok(None);
+        }
+        Ok(Some(Commit::commit_as(
+            message,
+            Some(reason),
+            ignore_options,
+        )?))
+    }
+
+    fn parent_tree(commit: &Commit) -> Result<Tree> {
+        match &commit.parent {
+            Some(parent) => Tree::get_commit_tree(parent),
+            None => Ok(Tree { entries: vec![] }),
+        }
+    }
+
+    pub fn cherry_pick(
+        names: &[String],
+        no_commit: bool,
+        ignore_options: &[String],
+    ) -> Result<Vec<ObjectId>> {
+        ensure_clean_working_tree("cherry-pick", ignore_options)?;
+
+        let mut oids = vec![];
+        for name in names.iter() {
+            let oid = get_oid(name)?;
+            let commit = Commit::get_commit(&oid)?;
+            let from = Commit::parent_tree(&commit)?;
+            let to = Tree::get_commit_tree(&oid)?;
+            if let Some(new_oid) = Commit::apply_commit(
+                "cherry-pick",
+                &oid,
+                from,
+                to,
+                &commit.message,
+                no_commit,
+                ignore_options,
+            )? {
+                oids.push(new_oid);
+            }
+        }
+        Ok(oids)
+    }
+
+    pub fn revert(
+        name: &str,
+        no_commit: bool,
+        ignore_options: &[String],
+    ) -> Result<Option<ObjectId>> {
+        ensure_clean_working_tree("revert", ignore_options)?;
+
+        let oid = get_oid(name)?;
+        let commit = Commit::get_commit(&oid)?;
+        let from = Tree::get_commit_tree(&oid)?;
+        let to = Commit::parent_tree(&commit)?;
+        let message = format!("Revert \"{}\"", commit.message);
+        Commit::apply_commit(
+            "revert",
+            &oid,
+            from,
+            to,
+            &message,
+            no_commit,
+            ignore_options,
+        )
     }
 }
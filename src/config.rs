@@ -0,0 +1,231 @@
+use crate::data::{dsgit_dir, RepositoryFormat};
+use crate::transaction::LockFile;
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+
+#[derive(Debug, PartialEq)]
+struct Section {
+    name: String,
+    subsection: Option<String>,
+    entries: Vec<(String, String)>,
+}
+
+/// Settings of a repository kept in `.dsgit/config`, which has the same
+/// format as git:
+///   [remote "origin"]
+///       url = ../repository
+///       fetch = +refs/heads/*:refs/remotes/origin/*
+///   [branch "main"]
+///       remote = origin
+///       merge = refs/heads/main
+/// A key is written as `remote.origin.url` or `core.name`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    sections: Vec<Section>,
+}
+
+// Split a key into a section, a subsection and a variable name, the
+// subsection may contain dots like `branch.feature.x.remote`.
+fn split_key(key: &str) -> Result<(&str, Option<&str>, &str)> {
+    let (section, name) = key
+        .split_once('.')
+        .ok_or_else(|| anyhow!("A key does not contain a section: {}", key))?;
+    let (subsection, name) = match name.rsplit_once('.') {
+        Some((subsection, name)) => (Some(subsection), name),
+        None => (None, name),
+    };
+    if section.is_empty() || name.is_empty() {
+        return Err(anyhow!("Invalid key: {}", key));
+    }
+    Ok((section, subsection, name))
+}
+
+fn config_path() -> String {
+    format!("{}/config", dsgit_dir())
+}
+
+impl Config {
+    pub fn read() -> Result<Self> {
+        let path = config_path();
+        if !Path::new(&path).is_file() {
+            return Ok(Config::default());
+        }
+        let contents =
+            fs::read_to_string(&path).with_context(|| format!("Failed to read file: {}", path))?;
+        contents.parse()
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let mut lock = LockFile::acquire(&config_path())?;
+        lock.write(&self.to_string())?;
+        lock.commit()?;
+        RepositoryFormat::forget();
+        Ok(())
+    }
+
+    fn find_section(&self, section: &str, subsection: Option<&str>) -> Option<&Section> {
+        self.sections
+            .iter()
+            .find(|s| s.name == section && s.subsection.as_deref() == subsection)
+    }
+
+    /// Every value of a key in order, a key like `remote.origin.fetch` may
+    /// have several values.
+    pub fn get_all(&self, key: &str) -> Result<Vec<String>> {
+        let (section, subsection, name) = split_key(key)?;
+        Ok(match self.find_section(section, subsection) {
+            Some(section) => section
+                .entries
+                .iter()
+                .filter(|(key, _)| key == name)
+                .map(|(_, value)| value.to_owned())
+                .collect(),
+            None => vec![],
+        })
+    }
+
+    /// The last value of a key.
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.get_all(key)?.pop())
+    }
+
+    /// Append a value to a key, creating the section if needed.
+    pub fn add(&mut self, key: &str, value: &str) -> Result<()> {
+        let (section, subsection, name) = split_key(key)?;
+        let index = match self
+            .sections
+            .iter()
+            .position(|s| s.name == section && s.subsection.as_deref() == subsection)
+        {
+            Some(index) => index,
+            None => {
+                self.sections.push(Section {
+                    name: section.to_owned(),
+                    subsection: subsection.map(|s| s.to_owned()),
+                    entries: vec![],
+                });
+                self.sections.len() - 1
+            }
+        };
+        self.sections[index]
+            .entries
+            .push((name.to_owned(), value.to_owned()));
+        Ok(())
+    }
+
+    /// Replace every value of a key with `value`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        self.unset(key)?;
+        self.add(key, value)
+    }
+
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        let (section, subsection, name) = split_key(key)?;
+        for s in self.sections.iter_mut() {
+            if s.name == section && s.subsection.as_deref() == subsection {
+                s.entries.retain(|(key, _)| key != name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of subsections in a section, like names of remotes.
+    pub fn subsections(&self, section: &str) -> Vec<String> {
+        self.sections
+            .iter()
+            .filter(|s| s.name == section)
+            .filter_map(|s| s.subsection.to_owned())
+            .collect()
+    }
+
+    /// Remove a section given as `branch.main`, return whether it existed.
+    pub fn remove_section(&mut self, section: &str) -> bool {
+        let len = self.sections.len();
+        let (section, subsection) = match section.split_once('.') {
+            Some((section, subsection)) => (section, Some(subsection)),
+            None => (section, None),
+        };
+        self.sections
+            .retain(|s| !(s.name == section && s.subsection.as_deref() == subsection));
+        self.sections.len() != len
+    }
+
+    /// Rename a subsection, like `branch.old` to `branch.new`.
+    pub fn rename_section(&mut self, old: &str, new: &str) -> Result<()> {
+        let (section, old_subsection) = old
+            .split_once('.')
+            .ok_or_else(|| anyhow!("A section must have a subsection: {}", old))?;
+        let (_, new_subsection) = new
+            .split_once('.')
+            .ok_or_else(|| anyhow!("A section must have a subsection: {}", new))?;
+        for s in self.sections.iter_mut() {
+            if s.name == section && s.subsection.as_deref() == Some(old_subsection) {
+                s.subsection = Some(new_subsection.to_owned());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(contents: &str) -> Result<Self> {
+        let mut config = Config::default();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let (name, subsection) = match header.split_once(' ') {
+                    Some((name, subsection)) => {
+                        let subsection = subsection
+                            .trim()
+                            .strip_prefix('"')
+                            .and_then(|s| s.strip_suffix('"'))
+                            .ok_or_else(|| anyhow!("Broken config section: {}", line))?;
+                        (name, Some(subsection.to_owned()))
+                    }
+                    None => (header, None),
+                };
+                config.sections.push(Section {
+                    name: name.to_owned(),
+                    subsection,
+                    entries: vec![],
+                });
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Broken config line: {}", line))?;
+            match config.sections.last_mut() {
+                Some(section) => section
+                    .entries
+                    .push((key.trim().to_owned(), value.trim().to_owned())),
+                None => return Err(anyhow!("A config line out of sections: {}", line)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for section in self.sections.iter() {
+            match &section.subsection {
+                Some(subsection) => writeln!(f, "[{} \"{}\"]", section.name, subsection)?,
+                None => writeln!(f, "[{}]", section.name)?,
+            }
+            for (key, value) in section.entries.iter() {
+                writeln!(f, "\t{} = {}", key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
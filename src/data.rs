@@ -1,28 +1,191 @@
+use crate::config::Config;
+use crate::git;
+use crate::hash::{HashAlgorithm, ObjectId};
 use crate::reference::RefValue;
+use crate::reflog;
+use crate::tag::Tag;
 use anyhow::{anyhow, Context, Result};
-use hex;
 use sha1::{Digest, Sha1};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt;
-use std::fs::{create_dir, File, OpenOptions};
+use std::fs::{self, create_dir, File, OpenOptions};
 use std::io::{Read, Write};
+use std::path::Path;
+use std::rc::Rc;
 use std::str::FromStr;
+use std::time::SystemTime;
 
-const DSGIT_DIR: &str = ".dsgit";
+thread_local! {
+    // The `.dsgit` directory of the repository which operations act on.
+    static DSGIT_DIR: RefCell<String> = RefCell::new(String::from(".dsgit"));
+    // Formats of repositories by the paths of their config files.
+    static FORMATS: FileCache<RepositoryFormat> = FileCache::new();
+}
+
+// The modification time and the size of a file, `None` if it is missing.
+type FileStamp = Option<(SystemTime, u64)>;
+
+fn file_stamp(path: &str) -> FileStamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Values read from files of repositories, kept while the files are
+/// unchanged so that a command parses each file once. A file is read again
+/// when its modification time or size changes, or when it is forgotten
+/// after being written.
+pub(crate) struct FileCache<T> {
+    entries: RefCell<HashMap<String, (FileStamp, Rc<T>)>>,
+}
+
+impl<T> FileCache<T> {
+    pub(crate) fn new() -> Self {
+        FileCache {
+            entries: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// The value of the file at `path`, made by `read` unless it is kept.
+    pub(crate) fn get(&self, path: &str, read: impl FnOnce() -> Result<T>) -> Result<Rc<T>> {
+        let stamp = file_stamp(path);
+        if let Some((kept_stamp, value)) = self.entries.borrow().get(path) {
+            if *kept_stamp == stamp {
+                return Ok(Rc::clone(value));
+            }
+        }
+        let value = Rc::new(read()?);
+        self.entries
+            .borrow_mut()
+            .insert(path.to_owned(), (stamp, Rc::clone(&value)));
+        Ok(value)
+    }
+
+    /// Drop the value of the file at `path`, which has just been written.
+    pub(crate) fn forget(&self, path: &str) {
+        self.entries.borrow_mut().remove(path);
+    }
+}
+
+/// The `.dsgit` directory of the current repository.
+pub fn dsgit_dir() -> String {
+    DSGIT_DIR.with(|dir| dir.borrow().to_owned())
+}
+
+/// Make the repository whose `.dsgit` directory is `dir` the one which the
+/// current thread acts on, like a server choosing the repository it serves.
+/// The working tree is not affected.
+pub fn set_dsgit_dir(dir: &str) {
+    DSGIT_DIR.with(|cur| cur.replace(dir.to_owned()));
+}
+
+/// A key of `.dsgit/config` which selects the encoding of objects written
+/// to the repository, set when the repository is created.
+pub const OBJECT_ENCODING_KEY: &str = "core.objectEncoding";
+/// A key of `.dsgit/config` which selects the hash algorithm of objects,
+/// named as Git names it.
+pub const OBJECT_FORMAT_KEY: &str = "extensions.objectFormat";
+
+/// How objects of a repository are written and hashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RepositoryFormat {
+    pub encoding: ObjectEncoding,
+    pub hash: HashAlgorithm,
+}
+
+impl RepositoryFormat {
+    /// The format of the current repository, read from `.dsgit/config` once
+    /// while the config is unchanged.
+    pub fn read() -> Result<Self> {
+        let path = format!("{}/config", dsgit_dir());
+        let format = FORMATS.with(|formats| formats.get(&path, RepositoryFormat::read_config))?;
+        Ok(*format)
+    }
+
+    /// Read the format again on next use, after the config is written.
+    pub(crate) fn forget() {
+        let path = format!("{}/config", dsgit_dir());
+        FORMATS.with(|formats| formats.forget(&path));
+    }
+
+    fn read_config() -> Result<Self> {
+        let config = Config::read()?;
+        let encoding = match config.get(OBJECT_ENCODING_KEY)? {
+            Some(encoding) => encoding.parse()?,
+            None => ObjectEncoding::default(),
+        };
+        let hash = match config.get(OBJECT_FORMAT_KEY)? {
+            Some(hash) => hash.parse()?,
+            None => HashAlgorithm::default(),
+        };
+        Ok(RepositoryFormat { encoding, hash })
+    }
+}
 
 pub fn init() -> Result<()> {
-    create_dir(DSGIT_DIR)?;
-    create_dir(format!("{}/objects", DSGIT_DIR))
-        .with_context(|| format!("Failed to create a directory: {}/objects", DSGIT_DIR))?;
+    init_with_format(RepositoryFormat::default())
+}
+
+/// Create a repository whose objects are written in `format`.
+pub fn init_with_format(format: RepositoryFormat) -> Result<()> {
+    let dsgit_dir = dsgit_dir();
+    create_dir(&dsgit_dir)
+        .with_context(|| format!("Failed to create a directory: {}", dsgit_dir))?;
+    create_dir(format!("{}/objects", dsgit_dir))
+        .with_context(|| format!("Failed to create a directory: {}/objects", dsgit_dir))?;
 
-    RefValue::update_ref("HEAD", &RefValue::new(None, true, "refs/heads/main"), true)?;
+    if format != RepositoryFormat::default() {
+        let mut config = Config::read()?;
+        if format.encoding != ObjectEncoding::default() {
+            config.set(OBJECT_ENCODING_KEY, &format.encoding.to_string())?;
+        }
+        if format.hash != HashAlgorithm::default() {
+            config.set(OBJECT_FORMAT_KEY, &format.hash.to_string())?;
+        }
+        config.write()?;
+    }
+    let head_ref = RefValue::new(None, true, "refs/heads/main");
+    RefValue::update_ref("HEAD", &head_ref, true, "init")?;
     Ok(())
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Ord, Eq)]
+/// How an object is encoded into the bytes which are hashed and stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjectEncoding {
+    /// `<type>\0<contents>`, with trees of `<type> <oid> <path>` lines.
+    #[default]
+    Dsgit,
+    /// Git's encoding, `<type> <size>\0<contents>` with binary trees and
+    /// commits recording their author, so that hashes are the same as Git's.
+    Git,
+}
+
+impl FromStr for ObjectEncoding {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dsgit" => Ok(ObjectEncoding::Dsgit),
+            "git" => Ok(ObjectEncoding::Git),
+            _ => Err(anyhow!("Unknown object encoding: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ObjectEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ObjectEncoding::Dsgit => write!(f, "dsgit"),
+            ObjectEncoding::Git => write!(f, "git"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Ord, Eq)]
 pub enum TypeObject {
     Blob,
     Tree,
     Commit,
+    Tag,
 }
 
 impl FromStr for TypeObject {
@@ -33,6 +196,7 @@ impl FromStr for TypeObject {
             "blob" => Ok(TypeObject::Blob),
             "tree" => Ok(TypeObject::Tree),
             "commit" => Ok(TypeObject::Commit),
+            "tag" => Ok(TypeObject::Tag),
             _ => Err(()),
         }
     }
@@ -44,6 +208,7 @@ impl fmt::Display for TypeObject {
             TypeObject::Blob => write!(f, "blob"),
             TypeObject::Tree => write!(f, "tree"),
             TypeObject::Commit => write!(f, "commit"),
+            TypeObject::Tag => write!(f, "tag"),
         }
     }
 }
@@ -54,74 +219,158 @@ pub fn sha1_hash(data: impl AsRef<[u8]>, out: &mut [u8]) {
     out.copy_from_slice(&hasher.finalize())
 }
 
-pub fn hash_object(data: &str, type_obj: TypeObject) -> Result<String> {
-    let obj = match type_obj {
-        TypeObject::Blob => "blob".to_owned() + "\x00" + data,
-        TypeObject::Tree => "tree".to_owned() + "\x00" + data,
-        TypeObject::Commit => "commit".to_owned() + "\x00" + data,
+// The bytes of an object which its hash is taken over.
+fn encode_object(data: &str, type_obj: TypeObject, encoding: ObjectEncoding) -> Result<Vec<u8>> {
+    Ok(match encoding {
+        ObjectEncoding::Dsgit => format!("{}\x00{}", type_obj, data).into_bytes(),
+        ObjectEncoding::Git => {
+            let contents = match type_obj {
+                TypeObject::Tree => git::encode_tree(data)?,
+                _ => data.as_bytes().to_vec(),
+            };
+            let mut obj = format!("{} {}\x00", type_obj, contents.len()).into_bytes();
+            obj.extend_from_slice(&contents);
+            obj
+        }
+    })
+}
+
+// Read an object in either encoding, since a header with a size tells an
+// object in Git's encoding. A tree in Git's encoding is read as the lines
+// of a dsgit tree, with paths relative to the tree.
+fn decode_object(obj: &[u8], hash: HashAlgorithm) -> Result<(TypeObject, String)> {
+    let nul = obj
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or_else(|| anyhow!("dsgit object must be obj type and contents"))?;
+    let header = std::str::from_utf8(&obj[..nul])?;
+    let contents = &obj[nul + 1..];
+    let (name, size) = match header.split_once(' ') {
+        Some((name, size)) => (name, Some(size)),
+        None => (header, None),
     };
+    let type_obj =
+        TypeObject::from_str(name).map_err(|_| anyhow!("Unknown object type: {}", name))?;
+
+    match size {
+        None => Ok((type_obj, String::from_utf8(contents.to_vec())?)),
+        Some(size) => {
+            if size.parse::<usize>().ok() != Some(contents.len()) {
+                return Err(anyhow!("An object does not match its size: {}", header));
+            }
+            let contents = match type_obj {
+                TypeObject::Tree => git::decode_tree(contents, hash)?,
+                _ => String::from_utf8(contents.to_vec())?,
+            };
+            Ok((type_obj, contents))
+        }
+    }
+}
 
-    let mut hash = [0u8; 20];
-    sha1_hash(&obj.as_bytes(), &mut hash);
-    let oid = hex::encode(&hash);
+pub fn hash_object(data: &str, type_obj: TypeObject) -> Result<ObjectId> {
+    let format = RepositoryFormat::read()?;
+    let obj = encode_object(data, type_obj, format.encoding)?;
+    let oid = format.hash.hash(&obj);
 
     let mut file = OpenOptions::new()
         .read(true)
         .write(true)
         .create(true)
-        .open(format!("{}/objects/{}", DSGIT_DIR, oid))
+        .open(format!("{}/objects/{}", dsgit_dir(), oid))
         .with_context(|| format!("Failed to open object file: objects/{}", oid))?;
 
-    file.write_all(obj.as_bytes())?;
+    file.write_all(&obj)?;
     Ok(oid)
 }
 
-pub fn get_object(oid: &str, expected_type: TypeObject) -> Result<String> {
-    let mut file = File::open(format!("{}/objects/{}", DSGIT_DIR, oid))
+/// Read an object in the encoding it is stored in, which may differ from
+/// the encoding of the repository for an object fetched from another. An
+/// object left out of a partial clone must be fetched first by
+/// `remote::fetch_missing_objects`.
+pub fn read_object(oid: &str) -> Result<(TypeObject, String)> {
+    let mut file = File::open(format!("{}/objects/{}", dsgit_dir(), oid))
         .with_context(|| format!("Failed to open object file: objects/{}", oid))?;
 
-    let mut buf = String::new();
-    file.read_to_string(&mut buf)?;
-
-    let objs: Vec<&str> = buf.split('\x00').collect();
-    if objs.len() != 2 {
-        anyhow!("dsgit object must be obj type and contents");
-    }
+    let mut buf = vec![];
+    file.read_to_end(&mut buf)?;
+    decode_object(&buf, ObjectId::parse(oid)?.algorithm())
+}
 
-    let type_obj = TypeObject::from_str(objs[0]).unwrap();
+pub fn get_object(oid: &str, expected_type: TypeObject) -> Result<String> {
+    let (type_obj, contents) = read_object(oid)?;
     if type_obj != expected_type {
-        anyhow!(
+        return Err(anyhow!(
             "Missing object type, expected: {}, but got {}",
             expected_type,
             type_obj,
-        );
+        ));
     }
 
-    Ok(objs[1].to_owned())
+    Ok(contents)
 }
 
-pub fn get_oid(name: &str) -> Result<String> {
-    let refs_walk = [
+/// Candidates of a reference path for a given name, in order of priority.
+pub fn ref_candidates(name: &str) -> [String; 5] {
+    [
         name.to_string(),
         format!("refs/{}", name),
         format!("refs/tags/{}", name),
         format!("refs/heads/{}", name),
-    ];
-    for path in refs_walk.iter() {
-        match RefValue::get_ref(path, false)? {
-            Some(ref_value) => return Ok(ref_value.value),
+        format!("refs/remotes/{}", name),
+    ]
+}
+
+pub fn object_exists(oid: &str) -> bool {
+    Path::new(&format!("{}/objects/{}", dsgit_dir(), oid)).is_file()
+}
+
+/// Follow annotated tag objects until reaching an object which is not a tag.
+pub fn peel_oid(oid: &str) -> Result<ObjectId> {
+    let mut oid = ObjectId::parse(oid)?;
+    while object_exists(&oid) {
+        match read_object(&oid)? {
+            (TypeObject::Tag, _) => oid = Tag::get_tag(&oid)?.object,
+            _ => break,
+        }
+    }
+    Ok(oid)
+}
+
+/// Resolve a name to an object hash, annotated tags are peeled to the
+/// object they point to.
+pub fn get_oid(name: &str) -> Result<ObjectId> {
+    peel_oid(&get_oid_unpeeled(name)?)
+}
+
+/// Resolve a name to an object hash, keeping the hash of an annotated tag.
+pub fn get_oid_unpeeled(name: &str) -> Result<ObjectId> {
+    // `<ref>@{N}` refers to the value of a reference N updates before,
+    // and `@{N}` is a shorthand of `HEAD@{N}`.
+    if let Some((refs, rest)) = name.split_once("@{") {
+        if let Some(index) = rest.strip_suffix('}').and_then(|n| n.parse::<usize>().ok()) {
+            let refs = if refs.is_empty() { "HEAD" } else { refs };
+            for path in ref_candidates(refs).iter() {
+                if reflog::exists(path) {
+                    return ObjectId::parse(&reflog::get_nth_oid(path, index)?);
+                }
+            }
+            return Err(anyhow!("No reflog for reference: {}", refs));
+        }
+    }
+
+    for path in ref_candidates(name).iter() {
+        match RefValue::get_ref(path, true)? {
+            Some(ref_value) => return ObjectId::parse(&ref_value.value),
             None => continue,
         };
     }
 
-    // Check a given name is hash value.
-    let is_hex = name
-        .chars()
-        .collect::<Vec<char>>()
-        .iter()
-        .all(|c| c.is_ascii_hexdigit());
-    if name.len() == 40 && is_hex {
-        return Ok(name.to_string());
+    // Check a given name is hash value of the hash algorithm of the
+    // repository.
+    if let Ok(oid) = ObjectId::parse(&name.to_lowercase()) {
+        if oid.algorithm() == RepositoryFormat::read()?.hash {
+            return Ok(oid);
+        }
     }
 
     Err(anyhow!(format!(
@@ -1,72 +1,572 @@
+use crate::config::Config;
 use crate::data::{get_object, TypeObject};
-use crate::entry::Tree;
+use crate::entry::{Entry, Tree};
+use crate::hash::ObjectId;
+use crate::remote::fetch_missing_objects;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use console::{style, Style};
 use similar::{ChangeTag, TextDiff};
 
-fn convert_dict(tree: Tree) -> HashMap<String, String> {
-    let mut tree_dict: HashMap<String, String> = HashMap::new();
+pub fn convert_dict(tree: &Tree) -> HashMap<String, ObjectId> {
+    let mut tree_dict: HashMap<String, ObjectId> = HashMap::new();
     for entry in tree.entries.iter() {
         tree_dict.insert(entry.path.to_owned(), entry.oid.to_owned());
     }
     tree_dict
 }
 
-pub fn diff_trees(
-    from: Tree,
-    to: Tree,
-    display: bool,
-) -> Result<(Vec<String>, Vec<String>, Vec<String>)> {
-    let from_tree = convert_dict(from);
-    let to_tree = convert_dict(to);
-
-    // Extract unique paths vector from current tree and parent tree.
-    let mut paths = from_tree.keys().cloned().collect::<Vec<String>>();
-    let mut to_paths = to_tree.keys().cloned().collect::<Vec<String>>();
-    paths.append(&mut to_paths);
-    let uniq_paths: HashSet<String> = paths.iter().cloned().collect();
-
-    let mut changed_entries: Vec<String> = vec![];
-    let mut created_entries: Vec<String> = vec![];
-    let mut removed_entries: Vec<String> = vec![];
-    for path in uniq_paths.iter() {
-        match &from_tree.get(path) {
-            Some(from_oid) => match &to_tree.get(path) {
-                Some(to_oid) => {
-                    if from_oid != to_oid {
-                        if display {
-                            println!("Modified: {}", path);
-                            display_diff_file(Some(from_oid), Some(to_oid))?;
-                        }
-                        changed_entries.push(path.to_owned());
-                    }
+/// A change of a path between two trees. The entries hold the path, the
+/// oid and the type of the object. A tree is flattened to its files, so a
+/// file replaced by a directory is the file removed and the files below the
+/// directory added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(Entry),
+    Removed(Entry),
+    Modified {
+        old: Entry,
+        new: Entry,
+    },
+    /// A file moved to another path, with the similarity of the contents in
+    /// percent.
+    Renamed {
+        old: Entry,
+        new: Entry,
+        similarity: usize,
+    },
+    /// A file copied to another path, the old path is left as it is.
+    Copied {
+        old: Entry,
+        new: Entry,
+        similarity: usize,
+    },
+}
+
+impl Change {
+    /// The entry before the change, none for an added path.
+    pub fn old_entry(&self) -> Option<&Entry> {
+        match self {
+            Change::Added(_) => None,
+            Change::Removed(old)
+            | Change::Modified { old, .. }
+            | Change::Renamed { old, .. }
+            | Change::Copied { old, .. } => Some(old),
+        }
+    }
+
+    /// The entry after the change, none for a removed path.
+    pub fn new_entry(&self) -> Option<&Entry> {
+        match self {
+            Change::Removed(_) => None,
+            Change::Added(new)
+            | Change::Modified { new, .. }
+            | Change::Renamed { new, .. }
+            | Change::Copied { new, .. } => Some(new),
+        }
+    }
+
+    /// What happened to the path, in lowercase.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Change::Added(_) => "created",
+            Change::Removed(_) => "removed",
+            Change::Modified { .. } => "modified",
+            Change::Renamed { .. } => "renamed",
+            Change::Copied { .. } => "copied",
+        }
+    }
+
+    /// The path the change is sorted by, the new path unless it is removed.
+    pub fn path(&self) -> &str {
+        match self.new_entry().or_else(|| self.old_entry()) {
+            Some(entry) => &entry.path,
+            None => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Added(new) => write!(f, "Created: {}", new.path),
+            Change::Removed(old) => write!(f, "Removed: {}", old.path),
+            Change::Modified { new, .. } => write!(f, "Modified: {}", new.path),
+            Change::Renamed {
+                old,
+                new,
+                similarity,
+            } => write!(f, "Renamed: {} -> {} ({}%)", old.path, new.path, similarity),
+            Change::Copied {
+                old,
+                new,
+                similarity,
+            } => write!(f, "Copied: {} -> {} ({}%)", old.path, new.path, similarity),
+        }
+    }
+}
+
+/// The changes between two trees in the order of their paths.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeDiff {
+    pub changes: Vec<Change>,
+}
+
+impl TreeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Paths whose contents changed in place.
+    pub fn modified(&self) -> Vec<&str> {
+        self.paths(|change| matches!(change, Change::Modified { .. }))
+    }
+
+    /// Paths newly added, besides renamed and copied ones.
+    pub fn added(&self) -> Vec<&str> {
+        self.paths(|change| matches!(change, Change::Added(_)))
+    }
+
+    /// Paths removed, besides renamed ones.
+    pub fn removed(&self) -> Vec<&str> {
+        self.paths(|change| matches!(change, Change::Removed(_)))
+    }
+
+    fn paths(&self, filter: impl Fn(&Change) -> bool) -> Vec<&str> {
+        self.changes
+            .iter()
+            .filter(|change| filter(change))
+            .map(|change| change.path())
+            .collect()
+    }
+}
+
+/// How renamed and copied files are found, configured like git with
+/// `diff.renames` (`true`, `false` or `copies`) and `diff.renameThreshold`,
+/// the least similarity in percent of a file to pair it with another path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenameDetection {
+    pub renames: bool,
+    pub copies: bool,
+    pub threshold: usize,
+}
+
+impl Default for RenameDetection {
+    fn default() -> Self {
+        RenameDetection {
+            renames: true,
+            copies: false,
+            threshold: 50,
+        }
+    }
+}
+
+impl RenameDetection {
+    pub fn configured() -> Result<Self> {
+        let config = Config::read()?;
+        let mut detection = RenameDetection::default();
+        match config.get("diff.renames")?.as_deref() {
+            Some("true") | None => (),
+            Some("false") => detection.renames = false,
+            Some("copies") => detection.copies = true,
+            Some(value) => return Err(anyhow!("Invalid value of diff.renames: {}", value)),
+        }
+        if let Some(value) = config.get("diff.renameThreshold")? {
+            detection.threshold = match value.trim_end_matches('%').parse() {
+                Ok(threshold) if threshold <= 100 => threshold,
+                _ => return Err(anyhow!("Invalid value of diff.renameThreshold: {}", value)),
+            };
+        }
+        Ok(detection)
+    }
+
+    /// Pair added entries with removed ones as renames, and with the other
+    /// entries of the old tree as copies when they are detected, most
+    /// similar ones first. Paired entries are taken out of `added` and
+    /// `removed`.
+    fn detect(
+        &self,
+        from: &[Entry],
+        added: &mut Vec<Entry>,
+        removed: &mut Vec<Entry>,
+    ) -> Result<Vec<Change>> {
+        let mut changes = vec![];
+        if !self.renames {
+            return Ok(changes);
+        }
+        let mut contents = HashMap::new();
+        let mut candidates = vec![];
+        for (to, new) in added.iter().enumerate() {
+            for (from, old) in removed.iter().enumerate() {
+                let similarity = similarity(&old.oid, &new.oid, &mut contents)?;
+                if similarity >= self.threshold {
+                    candidates.push((similarity, from, to));
                 }
-                None => {
-                    if display {
-                        println!("Removed: {}", path);
-                        display_diff_file(Some(from_oid), None)?;
-                    }
-                    removed_entries.push(path.to_owned());
+            }
+        }
+        // The most similar pairs win, and ties are broken by paths since
+        // entries are sorted, so that the result does not depend on the
+        // order of the trees.
+        candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+        let mut renamed: HashSet<usize> = HashSet::new();
+        let mut paired: HashSet<usize> = HashSet::new();
+        for (similarity, from, to) in candidates {
+            if renamed.contains(&from) || paired.contains(&to) {
+                continue;
+            }
+            renamed.insert(from);
+            paired.insert(to);
+            changes.push(Change::Renamed {
+                old: removed[from].clone(),
+                new: added[to].clone(),
+                similarity,
+            });
+        }
+
+        if self.copies {
+            for (to, new) in added.iter().enumerate() {
+                if paired.contains(&to) {
+                    continue;
                 }
-            },
-            None => match &to_tree.get(path) {
-                Some(to_oid) => {
-                    if display {
-                        println!("Created: {}", path);
-                        display_diff_file(None, Some(to_oid))?;
+                let mut best: Option<(usize, &Entry)> = None;
+                for old in from.iter() {
+                    let similarity = similarity(&old.oid, &new.oid, &mut contents)?;
+                    if similarity >= self.threshold
+                        && best.is_none_or(|(most, _)| similarity > most)
+                    {
+                        best = Some((similarity, old));
                     }
-                    created_entries.push(path.to_owned());
                 }
-                None => continue,
-            },
+                if let Some((similarity, old)) = best {
+                    paired.insert(to);
+                    changes.push(Change::Copied {
+                        old: old.clone(),
+                        new: new.clone(),
+                        similarity,
+                    });
+                }
+            }
+        }
+
+        *added = unpaired(added, &paired);
+        *removed = unpaired(removed, &renamed);
+        Ok(changes)
+    }
+}
+
+// Entries whose indexes are not paired.
+fn unpaired(entries: &mut Vec<Entry>, paired: &HashSet<usize>) -> Vec<Entry> {
+    entries
+        .drain(..)
+        .enumerate()
+        .filter(|(index, _)| !paired.contains(index))
+        .map(|(_, entry)| entry)
+        .collect()
+}
+
+// The similarity of two blobs in percent, the share of lines they have in
+// common. Contents are cached since a blob is compared with many others.
+fn similarity(
+    from_oid: &ObjectId,
+    to_oid: &ObjectId,
+    contents: &mut HashMap<ObjectId, String>,
+) -> Result<usize> {
+    if from_oid == to_oid {
+        return Ok(100);
+    }
+    for oid in [from_oid, to_oid].iter() {
+        if !contents.contains_key(*oid) {
+            contents.insert((*oid).clone(), get_object(oid, TypeObject::Blob)?);
         }
     }
+    let diff = TextDiff::from_lines(&contents[from_oid], &contents[to_oid]);
+    // Different files are never the same, even when they round up to it.
+    Ok(((diff.ratio() * 100.0) as usize).min(99))
+}
 
-    Ok((changed_entries, created_entries, removed_entries))
+/// Compare two trees with renames detected as configured.
+pub fn diff_trees(from: &Tree, to: &Tree) -> Result<TreeDiff> {
+    diff_trees_with(from, to, RenameDetection::configured()?)
+}
+
+pub fn diff_trees_with(from: &Tree, to: &Tree, detection: RenameDetection) -> Result<TreeDiff> {
+    let from_entries: BTreeMap<&str, &Entry> = from
+        .entries
+        .iter()
+        .map(|entry| (entry.path.as_str(), entry))
+        .collect();
+    let to_entries: BTreeMap<&str, &Entry> = to
+        .entries
+        .iter()
+        .map(|entry| (entry.path.as_str(), entry))
+        .collect();
+
+    let mut changes = vec![];
+    let mut added = vec![];
+    let mut removed = vec![];
+    for (path, old) in from_entries.iter() {
+        match to_entries.get(path) {
+            Some(new) if old.oid != new.oid => changes.push(Change::Modified {
+                old: (*old).clone(),
+                new: (*new).clone(),
+            }),
+            Some(_) => continue,
+            None => removed.push((*old).clone()),
+        }
+    }
+    for (path, new) in to_entries.iter() {
+        if !from_entries.contains_key(path) {
+            added.push((*new).clone());
+        }
+    }
+
+    // Blobs compared for renames or shown in a patch are fetched together,
+    // when a partial clone left them out.
+    let mut blobs = vec![];
+    for change in changes.iter() {
+        blobs.extend(change.old_entry().map(|old| old.oid.clone()));
+        blobs.extend(change.new_entry().map(|new| new.oid.clone()));
+    }
+    blobs.extend(
+        added
+            .iter()
+            .chain(removed.iter())
+            .map(|entry| entry.oid.clone()),
+    );
+    fetch_missing_objects(&blobs)?;
+
+    let sources: Vec<Entry> = from_entries
+        .values()
+        .map(|entry| (*entry).clone())
+        .collect();
+    changes.extend(detection.detect(&sources, &mut added, &mut removed)?);
+    changes.extend(added.into_iter().map(Change::Added));
+    changes.extend(removed.into_iter().map(Change::Removed));
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(TreeDiff { changes })
+}
+
+/// How the changes of a diff are printed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffFormat {
+    /// Lines numbered on both sides and colored, to read on a terminal.
+    Colored,
+    /// A unified diff in the format of git, which `patch` can apply.
+    Patch,
+}
+
+/// Options of printing a diff, `context` is the number of unchanged lines
+/// shown around changed lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiffOptions {
+    pub format: DiffFormat,
+    pub context: usize,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            format: DiffFormat::Patch,
+            context: 3,
+        }
+    }
+}
+
+/// Print a diff to stdout in the format of `options`.
+pub fn print_diff(diff: &TreeDiff, options: &DiffOptions) -> Result<()> {
+    match options.format {
+        DiffFormat::Colored => display_diff(diff, options.context),
+        DiffFormat::Patch => {
+            print!("{}", patch(diff, options.context)?);
+            Ok(())
+        }
+    }
+}
+
+/// Print every change with the lines of the files changed, numbered on both
+/// sides and colored.
+pub fn display_diff(diff: &TreeDiff, context: usize) -> Result<()> {
+    for change in diff.changes.iter() {
+        println!("{}", change);
+        match change {
+            Change::Renamed {
+                similarity: 100, ..
+            }
+            | Change::Copied {
+                similarity: 100, ..
+            } => (),
+            _ => display_diff_file(
+                change.old_entry().map(|entry| &entry.oid),
+                change.new_entry().map(|entry| &entry.oid),
+                context,
+            )?,
+        }
+    }
+    Ok(())
+}
+
+// The mode git records for an object of a tree entry.
+fn git_mode(obj_type: TypeObject) -> &'static str {
+    match obj_type {
+        TypeObject::Tree => "040000",
+        TypeObject::Commit => "160000",
+        _ => "100644",
+    }
+}
+
+// A path of a patch, relative to the top of the working tree.
+fn patch_path(path: &str) -> String {
+    path.trim_start_matches("./").replace('\\', "/")
+}
+
+fn abbrev(entry: Option<&Entry>) -> &str {
+    match entry {
+        Some(entry) => &entry.oid[..7],
+        None => "0000000",
+    }
+}
+
+/// Render a diff as a unified diff in the format of git, with `context`
+/// unchanged lines around changed lines.
+pub fn patch(diff: &TreeDiff, context: usize) -> Result<String> {
+    let mut out = String::new();
+    for change in diff.changes.iter() {
+        let (old, new) = (change.old_entry(), change.new_entry());
+        let old_path = patch_path(old.or(new).map_or("", |entry| &entry.path));
+        let new_path = patch_path(change.path());
+        out.push_str(&format!("diff --git a/{} b/{}\n", old_path, new_path));
+        match change {
+            Change::Added(new) => {
+                out.push_str(&format!("new file mode {}\n", git_mode(new.obj_type)));
+            }
+            Change::Removed(old) => {
+                out.push_str(&format!("deleted file mode {}\n", git_mode(old.obj_type)));
+            }
+            Change::Renamed { similarity, .. } | Change::Copied { similarity, .. } => {
+                let kind = match change {
+                    Change::Renamed { .. } => "rename",
+                    _ => "copy",
+                };
+                out.push_str(&format!("similarity index {}%\n", similarity));
+                out.push_str(&format!("{} from {}\n", kind, old_path));
+                out.push_str(&format!("{} to {}\n", kind, new_path));
+                if *similarity == 100 {
+                    continue;
+                }
+            }
+            Change::Modified { .. } => (),
+        }
+        match change {
+            Change::Added(_) | Change::Removed(_) => {
+                out.push_str(&format!("index {}..{}\n", abbrev(old), abbrev(new)))
+            }
+            _ => out.push_str(&format!(
+                "index {}..{} {}\n",
+                abbrev(old),
+                abbrev(new),
+                git_mode(
+                    change
+                        .new_entry()
+                        .map_or(TypeObject::Blob, |new| new.obj_type)
+                )
+            )),
+        }
+
+        let old_contents = read_blob(old)?;
+        let new_contents = read_blob(new)?;
+        let text_diff = TextDiff::from_lines(&old_contents, &new_contents);
+        let mut unified = text_diff.unified_diff();
+        unified.context_radius(context);
+        for (index, hunk) in unified.iter_hunks().enumerate() {
+            if index == 0 {
+                let old_name = old.map_or(String::from("/dev/null"), |_| format!("a/{}", old_path));
+                let new_name = new.map_or(String::from("/dev/null"), |_| format!("b/{}", new_path));
+                out.push_str(&format!("--- {}\n+++ {}\n", old_name, new_name));
+            }
+            out.push_str(&format!("{}\n", hunk.header()));
+            for line in hunk.iter_changes() {
+                out.push_str(&format!("{}{}", line.tag(), line.value()));
+                if line.missing_newline() {
+                    out.push_str("\n\\ No newline at end of file\n");
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+// The widest bar of a diffstat, longer bars are scaled down to it.
+const STAT_WIDTH: usize = 50;
+
+/// Render a diff as a diffstat in the format of git, the numbers of changed
+/// lines of each file with a bar of `+` and `-`, and the summary line.
+pub fn diffstat(diff: &TreeDiff) -> Result<String> {
+    let mut stats = vec![];
+    for change in diff.changes.iter() {
+        let name = match change {
+            Change::Renamed { old, new, .. } | Change::Copied { old, new, .. } => {
+                format!("{} => {}", patch_path(&old.path), patch_path(&new.path))
+            }
+            _ => patch_path(change.path()),
+        };
+        let (mut insertions, mut deletions) = (0, 0);
+        let old_contents = read_blob(change.old_entry())?;
+        let new_contents = read_blob(change.new_entry())?;
+        for line in TextDiff::from_lines(&old_contents, &new_contents).iter_all_changes() {
+            match line.tag() {
+                ChangeTag::Insert => insertions += 1,
+                ChangeTag::Delete => deletions += 1,
+                ChangeTag::Equal => (),
+            }
+        }
+        stats.push((name, insertions, deletions));
+    }
+
+    let name_width = stats.iter().map(|stat| stat.0.len()).max().unwrap_or(0);
+    let max_changes = stats.iter().map(|stat| stat.1 + stat.2).max().unwrap_or(0);
+    let count_width = max_changes.to_string().len();
+    let scale = |n: usize| match max_changes > STAT_WIDTH {
+        true => (n * STAT_WIDTH).div_ceil(max_changes),
+        false => n,
+    };
+    let mut out = String::new();
+    for (name, insertions, deletions) in stats.iter() {
+        out.push_str(&format!(
+            " {:name_width$} | {:>count_width$} {}{}\n",
+            name,
+            insertions + deletions,
+            "+".repeat(scale(*insertions)),
+            "-".repeat(scale(*deletions)),
+            name_width = name_width,
+            count_width = count_width,
+        ));
+    }
+
+    let plural = |n: usize, word: &str| match n {
+        1 => format!("{} {}", n, word),
+        _ => format!("{} {}s", n, word),
+    };
+    let insertions: usize = stats.iter().map(|stat| stat.1).sum();
+    let deletions: usize = stats.iter().map(|stat| stat.2).sum();
+    out.push_str(&format!(" {} changed", plural(stats.len(), "file")));
+    if insertions > 0 || deletions == 0 {
+        out.push_str(&format!(", {}(+)", plural(insertions, "insertion")));
+    }
+    if deletions > 0 || insertions == 0 {
+        out.push_str(&format!(", {}(-)", plural(deletions, "deletion")));
+    }
+    out.push('\n');
+    Ok(out)
+}
+
+fn read_blob(entry: Option<&Entry>) -> Result<String> {
+    match entry {
+        Some(entry) => get_object(&entry.oid, TypeObject::Blob),
+        None => Ok(String::new()),
+    }
 }
 
 struct Line(Option<usize>);
@@ -80,7 +580,11 @@ impl fmt::Display for Line {
     }
 }
 
-fn display_diff_file(old_oid: Option<&str>, new_oid: Option<&str>) -> Result<()> {
+fn display_diff_file(
+    old_oid: Option<&ObjectId>,
+    new_oid: Option<&ObjectId>,
+    context: usize,
+) -> Result<()> {
     let old_contents = match old_oid {
         Some(oid) => get_object(oid, TypeObject::Blob)?,
         None => String::from(""),
@@ -91,7 +595,7 @@ fn display_diff_file(old_oid: Option<&str>, new_oid: Option<&str>) -> Result<()>
     };
 
     let diff = TextDiff::from_lines(&old_contents, &new_contents);
-    for (idx, group) in diff.grouped_ops(3).iter().enumerate() {
+    for (idx, group) in diff.grouped_ops(context).iter().enumerate() {
         if idx > 0 {
             println!("{:-^1$}", "-", 80);
         }
@@ -1,18 +1,21 @@
 use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{is_separator, Path, MAIN_SEPARATOR};
 use std::str::FromStr;
 
 use crate::commit::Commit;
 use crate::data::{get_object, hash_object, TypeObject};
+use crate::hash::ObjectId;
 use crate::reference::get_head_oid;
+use crate::remote::fetch_missing_objects;
 
-#[derive(Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Entry {
     pub path: String,
-    pub oid: String,
+    pub oid: ObjectId,
     pub obj_type: TypeObject,
 }
 
@@ -22,24 +25,24 @@ impl fmt::Display for Entry {
     }
 }
 
-impl From<&str> for Entry {
-    fn from(item: &str) -> Entry {
-        let entry: Vec<&str> = item.split(' ').collect();
+impl FromStr for Entry {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let entry: Vec<&str> = line.split(' ').collect();
         if entry.len() != 3 {
-            panic!(
-                "Entry must be length == 3, but this length got {}",
-                entry.len()
-            );
+            return Err(anyhow!("Broken tree entry: {}", line));
         }
-        Entry {
+        Ok(Entry {
             path: entry[2].to_owned(),
-            oid: entry[1].to_owned(),
-            obj_type: TypeObject::from_str(entry[0]).unwrap(),
-        }
+            oid: ObjectId::parse(entry[1])?,
+            obj_type: TypeObject::from_str(entry[0])
+                .map_err(|_| anyhow!("Broken tree entry: {}", line))?,
+        })
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Tree {
     pub entries: Vec<Entry>,
 }
@@ -61,7 +64,7 @@ impl Tree {
                 let oid = hash_object(&contents, TypeObject::Blob)?;
                 entries.push(Entry {
                     path: path.to_str().unwrap().to_string(),
-                    oid: oid.to_string(),
+                    oid,
                     obj_type: TypeObject::Blob,
                 })
             }
@@ -74,7 +77,7 @@ impl Tree {
         Ok(Tree { entries })
     }
 
-    pub fn write_tree(target_path: &str, ignore_options: &[String]) -> Result<String> {
+    pub fn write_tree(target_path: &str, ignore_options: &[String]) -> Result<ObjectId> {
         let mut entries: Vec<Entry> = vec![];
         for entry in fs::read_dir(target_path)
             .with_context(|| format!("Failed to read directory: {}", target_path))?
@@ -90,7 +93,7 @@ impl Tree {
                 let oid = hash_object(&contents, TypeObject::Blob)?;
                 entries.push(Entry {
                     path: path.to_str().unwrap().to_string(),
-                    oid: oid.to_string(),
+                    oid,
                     obj_type: TypeObject::Blob,
                 })
             }
@@ -98,7 +101,7 @@ impl Tree {
                 let oid = Tree::write_tree(path.to_str().unwrap(), ignore_options)?;
                 entries.push(Entry {
                     path: path.to_str().unwrap().to_string(),
-                    oid: oid.to_string(),
+                    oid,
                     obj_type: TypeObject::Tree,
                 })
             }
@@ -136,6 +139,8 @@ impl Tree {
         Tree::clear_current_directory(ignore_options)?;
         let tree_contents = get_object(oid, TypeObject::Tree)?;
         let tree = &Tree::get_tree(&tree_contents)?;
+        let blobs: Vec<ObjectId> = tree.entries.iter().map(|entry| entry.oid.clone()).collect();
+        fetch_missing_objects(&blobs)?;
 
         for entry in tree.entries.iter() {
             let path = Path::new(&entry.path);
@@ -156,17 +161,31 @@ impl Tree {
     }
 
     pub fn get_tree(tree: &str) -> Result<Self> {
+        Tree::get_tree_at(tree, ".")
+    }
+
+    // Read the files of a tree in the directory `dir`. A path of an entry is
+    // joined to `dir` by its name, since a tree in Git's encoding has paths
+    // relative to itself. The path is joined the way `Tree::new` reads it
+    // from the working tree, so that both compare equal on any platform.
+    fn get_tree_at(tree: &str, dir: &str) -> Result<Self> {
         let mut entries = vec![];
 
         for line in tree.lines() {
-            let entry = Entry::from(line);
+            let mut entry: Entry = line.parse()?;
+            let path = Path::new(dir).join(
+                Path::new(&entry.path)
+                    .file_name()
+                    .ok_or_else(|| anyhow!("A tree entry has no name: {}", entry.path))?,
+            );
+            entry.path = path.display().to_string();
             match entry.obj_type {
                 TypeObject::Blob => {
                     entries.push(entry);
                 }
                 TypeObject::Tree => {
                     let tmp_tree = get_object(&entry.oid, TypeObject::Tree)?;
-                    let mut tmp_tree = Tree::get_tree(&tmp_tree)?;
+                    let mut tmp_tree = Tree::get_tree_at(&tmp_tree, &entry.path)?;
                     entries.append(&mut tmp_tree.entries);
                 }
                 _ => return Err(anyhow!("Unknown tree entry.")),
@@ -175,15 +194,63 @@ impl Tree {
         Ok(Tree { entries })
     }
 
+    /// Write tree objects of the files in a tree, the same as
+    /// `Tree::write_tree` writes for the files in a working tree, and return
+    /// the hash of the root tree.
+    pub fn write(&self) -> Result<ObjectId> {
+        let entries: Vec<&Entry> = self.entries.iter().collect();
+        Tree::write_entries(".", &entries)
+    }
+
+    // Paths of entries may be separated by `/` or by the separator of the
+    // platform, and are written with the latter like `Tree::write_tree`.
+    fn write_entries(dir: &str, entries: &[&Entry]) -> Result<ObjectId> {
+        let mut tree_entries = vec![];
+        let mut sub_dirs: BTreeMap<String, Vec<&Entry>> = BTreeMap::new();
+        for entry in entries.iter() {
+            let name = entry
+                .path
+                .strip_prefix(dir)
+                .and_then(|rest| rest.strip_prefix(is_separator))
+                .ok_or_else(|| anyhow!("A path is out of the tree: {}", entry.path))?;
+            match name.split_once(is_separator) {
+                Some((sub_dir, _)) => sub_dirs
+                    .entry(format!("{}{}{}", dir, MAIN_SEPARATOR, sub_dir))
+                    .or_default()
+                    .push(entry),
+                None => tree_entries.push(Entry {
+                    path: format!("{}{}{}", dir, MAIN_SEPARATOR, name),
+                    oid: entry.oid.to_owned(),
+                    obj_type: TypeObject::Blob,
+                }),
+            }
+        }
+        for (path, entries) in sub_dirs.into_iter() {
+            let oid = Tree::write_entries(&path, &entries)?;
+            tree_entries.push(Entry {
+                path,
+                oid,
+                obj_type: TypeObject::Tree,
+            });
+        }
+
+        tree_entries.sort();
+        let contents: String = tree_entries.iter().map(|entry| entry.to_string()).collect();
+        hash_object(&contents, TypeObject::Tree)
+    }
+
     pub fn get_working_tree(ignore_options: &[String]) -> Result<Tree> {
         Tree::new(".", ignore_options)
     }
 
     pub fn get_head_tree() -> Result<Tree> {
-        let oid = get_head_oid();
-        let head_commit = Commit::get_commit(&oid)?;
-        let head_tree = get_object(&head_commit.tree, TypeObject::Tree)?;
-        Tree::get_tree(&head_tree)
+        Tree::get_commit_tree(&get_head_oid())
+    }
+
+    pub fn get_commit_tree(oid: &str) -> Result<Tree> {
+        let commit = Commit::get_commit(oid)?;
+        let tree = get_object(&commit.tree, TypeObject::Tree)?;
+        Tree::get_tree(&tree)
     }
 
     fn is_ignored(path: &str, ignore_options: &[String]) -> bool {
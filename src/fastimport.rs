@@ -0,0 +1,715 @@
+use crate::commit::Commit;
+use crate::data::{dsgit_dir, get_object, get_oid, hash_object, read_object, TypeObject};
+use crate::entry::{Entry, Tree};
+use crate::hash::ObjectId;
+use crate::identity::{get_identity, get_timestamp};
+use crate::protocol::RefAdvertisement;
+use crate::reference::{check_branch_name, check_ref_format, RefValue};
+use crate::remote::{fetch_missing_objects, get_ref_oid, is_bare, is_forced, resolve_ref};
+use crate::tag::Tag;
+use crate::transaction::RefTransaction;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+/// Marks of a fast-import stream, numbers given to objects so that later
+/// commands refer to them as `:<mark>`. A marks file keeps them as
+/// `:<mark> <oid>` lines, so that another stream continues from them.
+#[derive(Debug, Default, PartialEq)]
+pub struct Marks {
+    marks: BTreeMap<usize, ObjectId>,
+}
+
+impl Marks {
+    /// Read a marks file, which is empty unless it exists.
+    pub fn read(path: &str) -> Result<Self> {
+        let mut marks = Marks::default();
+        if !Path::new(path).is_file() {
+            return Ok(marks);
+        }
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("Failed to read file: {}", path))?;
+        for line in contents.lines() {
+            let (mark, oid) = line
+                .split_once(' ')
+                .and_then(|(mark, oid)| Some((parse_mark(mark)?, oid)))
+                .ok_or_else(|| anyhow!("Broken line in a marks file: {}", line))?;
+            marks.insert(mark, &ObjectId::parse(oid)?);
+        }
+        Ok(marks)
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        let contents: String = self
+            .marks
+            .iter()
+            .map(|(mark, oid)| format!(":{} {}\n", mark, oid))
+            .collect();
+        fs::write(path, contents).with_context(|| format!("Failed to write file: {}", path))
+    }
+
+    pub fn get(&self, mark: usize) -> Option<&ObjectId> {
+        self.marks.get(&mark)
+    }
+
+    pub fn insert(&mut self, mark: usize, oid: &ObjectId) {
+        self.marks.insert(mark, oid.clone());
+    }
+
+    // The number for a new mark.
+    fn next(&self) -> usize {
+        self.marks.keys().next_back().map_or(1, |mark| mark + 1)
+    }
+}
+
+fn parse_mark(mark: &str) -> Option<usize> {
+    mark.strip_prefix(':')?.parse().ok()
+}
+
+// A path of a stream for a dsgit path like `./dir/foo.txt`.
+fn stream_path(path: &str) -> &str {
+    path.trim_start_matches("./")
+}
+
+// A dsgit path for a path of a stream, which dsgit can record.
+fn dsgit_path(path: &str) -> Result<String> {
+    if path.is_empty()
+        || path.starts_with('/')
+        || path.contains(' ')
+        || path.contains('\n')
+        || path
+            .split('/')
+            .any(|name| name.is_empty() || name == "." || name == "..")
+    {
+        return Err(anyhow!("A path can not be recorded by dsgit: {:?}", path));
+    }
+    Ok(format!("./{}", path))
+}
+
+// Split a path quoted as a C string like `"foo\tbar"` from the rest of the
+// line, or an unquoted path up to a space if `up_to_space` is given.
+fn split_path(line: &str, up_to_space: bool) -> Result<(String, &str)> {
+    let quoted = match line.strip_prefix('"') {
+        Some(quoted) => quoted,
+        None if up_to_space => {
+            let (path, rest) = line.split_once(' ').unwrap_or((line, ""));
+            return Ok((path.to_owned(), rest));
+        }
+        None => return Ok((line.to_owned(), "")),
+    };
+
+    let mut bytes = vec![];
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let path = String::from_utf8(bytes)
+                    .map_err(|_| anyhow!("A quoted path is not UTF-8: {}", line))?;
+                return Ok((path, quoted[i + 1..].trim_start_matches(' ')));
+            }
+            '\\' => {
+                let byte = match chars.next().map(|(_, c)| c) {
+                    Some('n') => b'\n',
+                    Some('t') => b'\t',
+                    Some('"') => b'"',
+                    Some('\\') => b'\\',
+                    Some(digit @ '0'..='7') => {
+                        let mut value = digit.to_digit(8).unwrap();
+                        for _ in 0..2 {
+                            let digit = chars.next().and_then(|(_, c)| c.to_digit(8));
+                            value = value * 8
+                                + digit.ok_or_else(|| anyhow!("A broken quoted path: {}", line))?;
+                        }
+                        value as u8
+                    }
+                    _ => return Err(anyhow!("A broken quoted path: {}", line)),
+                };
+                bytes.push(byte);
+            }
+            c => bytes.extend_from_slice(c.to_string().as_bytes()),
+        }
+    }
+    Err(anyhow!("A quoted path is not closed: {}", line))
+}
+
+fn write_data(out: &mut impl Write, data: &str) -> Result<()> {
+    write!(out, "data {}\n{}\n", data.len(), data)?;
+    Ok(())
+}
+
+struct Exporter<'a, W: Write> {
+    out: &'a mut W,
+    marks: &'a mut Marks,
+    // Marks of objects already in the stream or in an imported marks file.
+    exported: HashMap<ObjectId, usize>,
+    signature: String,
+}
+
+impl<'a, W: Write> Exporter<'a, W> {
+    fn mark(&mut self, oid: &ObjectId) -> usize {
+        let mark = self.marks.next();
+        self.marks.insert(mark, oid);
+        self.exported.insert(oid.clone(), mark);
+        mark
+    }
+
+    fn export_blob(&mut self, oid: &ObjectId) -> Result<usize> {
+        if let Some(mark) = self.exported.get(oid) {
+            return Ok(*mark);
+        }
+        let contents = get_object(oid, TypeObject::Blob)?;
+        let mark = self.mark(oid);
+        writeln!(self.out, "blob\nmark :{}", mark)?;
+        write_data(self.out, &contents)?;
+        Ok(mark)
+    }
+
+    // Write commits reachable from `oid` which are not in the stream yet,
+    // parents first, as commits to `ref_name`.
+    fn export_commits(&mut self, ref_name: &str, oid: &ObjectId) -> Result<usize> {
+        let mut stack = vec![oid.clone()];
+        while let Some(cur) = stack.last().cloned() {
+            if self.exported.contains_key(&cur) {
+                stack.pop();
+                continue;
+            }
+            let commit = Commit::get_commit(&cur)?;
+            let unexported: Vec<ObjectId> = commit
+                .parents()
+                .into_iter()
+                .filter(|parent| !self.exported.contains_key(parent))
+                .collect();
+            if !unexported.is_empty() {
+                stack.extend(unexported);
+                continue;
+            }
+            stack.pop();
+            self.export_commit(ref_name, &cur, &commit)?;
+        }
+        Ok(self.exported[oid])
+    }
+
+    fn export_commit(&mut self, ref_name: &str, oid: &ObjectId, commit: &Commit) -> Result<()> {
+        let files = |tree: Tree| -> BTreeMap<String, ObjectId> {
+            tree.entries
+                .into_iter()
+                .map(|entry| (entry.path, entry.oid))
+                .collect()
+        };
+        let new_files = files(Tree::get_commit_tree(oid)?);
+        let old_files = match &commit.parent {
+            Some(parent) => files(Tree::get_commit_tree(parent)?),
+            None => BTreeMap::new(),
+        };
+
+        let blobs: Vec<ObjectId> = new_files
+            .iter()
+            .filter(|(path, blob)| old_files.get(*path) != Some(*blob))
+            .map(|(_, blob)| blob.clone())
+            .collect();
+        fetch_missing_objects(&blobs)?;
+
+        let mut changes = vec![];
+        for (path, blob) in new_files.iter() {
+            if old_files.get(path) != Some(blob) {
+                let mark = self.export_blob(blob)?;
+                changes.push(format!("M 100644 :{} {}", mark, stream_path(path)));
+            }
+        }
+        for path in old_files
+            .keys()
+            .filter(|path| !new_files.contains_key(*path))
+        {
+            changes.push(format!("D {}", stream_path(path)));
+        }
+
+        // A root commit starts over, instead of following the branch.
+        if commit.parent.is_none() {
+            write!(self.out, "reset {}\n\n", ref_name)?;
+        }
+        let mark = self.mark(oid);
+        writeln!(self.out, "commit {}\nmark :{}", ref_name, mark)?;
+        let contents = get_object(oid, TypeObject::Commit)?;
+        let (header, message) = contents.split_once("\n\n").unwrap_or((&contents, ""));
+        // A commit keeps its own author and committer if it records them.
+        let signature = &self.signature;
+        let field = |name: &str| {
+            header
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .unwrap_or(signature)
+        };
+        let (author, committer) = (field("author "), field("committer "));
+        writeln!(self.out, "author {}\ncommitter {}", author, committer)?;
+        write_data(self.out, message)?;
+        if let Some(parent) = &commit.parent {
+            writeln!(self.out, "from :{}", self.exported[parent])?;
+        }
+        if let Some(merge_parent) = &commit.merge_parent {
+            writeln!(self.out, "merge :{}", self.exported[merge_parent])?;
+        }
+        for change in changes.iter() {
+            writeln!(self.out, "{}", change)?;
+        }
+        writeln!(self.out)?;
+        Ok(())
+    }
+
+    fn export_ref(&mut self, ref_name: &str, oid: &str) -> Result<()> {
+        let (obj_type, _) = read_object(oid)?;
+        match obj_type {
+            TypeObject::Commit => {
+                let mark = self.export_commits(ref_name, &ObjectId::parse(oid)?)?;
+                // The reference may point to a commit written before.
+                write!(self.out, "reset {}\nfrom :{}\n\n", ref_name, mark)?;
+            }
+            TypeObject::Tag => {
+                let tag = Tag::get_tag(oid)?;
+                if tag.obj_type != TypeObject::Commit {
+                    return Err(anyhow!(
+                        "Only tags of commits can be exported: {}",
+                        ref_name
+                    ));
+                }
+                let mark = self.export_commits(ref_name, &tag.object)?;
+                writeln!(self.out, "tag {}\nfrom :{}", tag.tag, mark)?;
+                writeln!(self.out, "tagger {}", tag.tagger)?;
+                write_data(self.out, &format!("{}\n", tag.message))?;
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Only commits and tags can be exported: {}",
+                    ref_name
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write references given by `revs` with the history they point to as a
+/// fast-import stream, `--all` takes every branch and tag. Objects which
+/// `marks` has are taken as written by an earlier stream and left out, and
+/// marks of the objects written are added to it. Commits are authored by
+/// the current user, since dsgit does not record authors.
+pub fn fast_export(out: &mut impl Write, revs: &[String], marks: &mut Marks) -> Result<()> {
+    let mut refs = vec![];
+    for rev in revs.iter() {
+        if rev == "--all" {
+            refs.extend(RefAdvertisement::current()?.refs);
+        } else {
+            let resolved = resolve_ref(rev)?;
+            refs.push(resolved.ok_or_else(|| anyhow!("A reference is not found: {}", rev))?);
+        }
+    }
+
+    let exported = marks
+        .marks
+        .iter()
+        .map(|(mark, oid)| (oid.clone(), *mark))
+        .collect();
+    let mut exporter = Exporter {
+        out,
+        marks,
+        exported,
+        signature: format!("{} {}", get_identity(), get_timestamp()),
+    };
+    for (ref_name, oid) in refs.iter() {
+        exporter.export_ref(ref_name, oid)?;
+    }
+    writeln!(exporter.out, "done")?;
+    Ok(())
+}
+
+/// Objects and references written by `fast_import`.
+#[derive(Debug, Default, PartialEq)]
+pub struct FastImport {
+    pub blobs: usize,
+    pub commits: usize,
+    pub tags: usize,
+    pub refs: Vec<(String, ObjectId)>,
+}
+
+struct Parser<'a, R: BufRead> {
+    input: &'a mut R,
+    peeked: Option<String>,
+}
+
+impl<'a, R: BufRead> Parser<'a, R> {
+    // The next line of commands without its newline, skipping comments.
+    fn next_line(&mut self) -> Result<Option<String>> {
+        if let Some(line) = self.peeked.take() {
+            return Ok(Some(line));
+        }
+        loop {
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.starts_with('#') {
+                return Ok(Some(line.trim_end_matches('\n').to_owned()));
+            }
+        }
+    }
+
+    fn peek_line(&mut self) -> Result<Option<&str>> {
+        if self.peeked.is_none() {
+            self.peeked = self.next_line()?;
+        }
+        Ok(self.peeked.as_deref())
+    }
+
+    // The value of an optional `<key> <value>` line.
+    fn optional(&mut self, key: &str) -> Result<Option<String>> {
+        let value = match self.peek_line()? {
+            Some(line) => match line.strip_prefix(key) {
+                Some(value) if value.starts_with(' ') => value[1..].to_owned(),
+                _ => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        self.peeked = None;
+        Ok(Some(value))
+    }
+
+    // Read `data <count>` followed by as many bytes, or `data <<<delimiter>`
+    // followed by lines up to the delimiter.
+    fn read_data(&mut self) -> Result<String> {
+        let line = self.next_line()?.unwrap_or_default();
+        let arg = line
+            .strip_prefix("data ")
+            .ok_or_else(|| anyhow!("Expected a data command, but got: {}", line))?;
+        let data = match arg.strip_prefix("<<") {
+            Some(delimiter) => {
+                let mut data = String::new();
+                loop {
+                    let mut line = String::new();
+                    if self.input.read_line(&mut line)? == 0 {
+                        return Err(anyhow!("A data ended before its delimiter: {}", delimiter));
+                    }
+                    if line.trim_end_matches('\n') == delimiter {
+                        break data;
+                    }
+                    data.push_str(&line);
+                }
+            }
+            None => {
+                let size: usize = arg
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid size of data: {}", arg))?;
+                let mut bytes = vec![0u8; size];
+                self.input.read_exact(&mut bytes)?;
+                // The newline after the data is optional.
+                if self.input.fill_buf()?.first() == Some(&b'\n') {
+                    self.input.consume(1);
+                }
+                String::from_utf8(bytes).map_err(|_| anyhow!("Binary data is not supported."))?
+            }
+        };
+        Ok(data)
+    }
+}
+
+struct Importer<'a, 'b, R: BufRead> {
+    parser: Parser<'b, R>,
+    marks: &'a mut Marks,
+    // References updated at the end, `None` for a branch reset to start
+    // over without a parent.
+    refs: BTreeMap<String, Option<ObjectId>>,
+    result: FastImport,
+}
+
+impl<'a, 'b, R: BufRead> Importer<'a, 'b, R> {
+    // Resolve a mark, a reference updated by the stream, or a name.
+    fn resolve(&self, name: &str) -> Result<ObjectId> {
+        if let Some(mark) = parse_mark(name) {
+            return self
+                .marks
+                .marks
+                .get(&mark)
+                .cloned()
+                .ok_or_else(|| anyhow!("An unknown mark: {}", name));
+        }
+        match self.refs.get(name) {
+            Some(Some(oid)) => Ok(oid.to_owned()),
+            _ => get_oid(name),
+        }
+    }
+
+    fn set_mark(&mut self, mark: Option<String>, oid: &ObjectId) -> Result<()> {
+        if let Some(mark) = mark {
+            let mark = parse_mark(&mark).ok_or_else(|| anyhow!("Invalid mark: {}", mark))?;
+            self.marks.insert(mark, oid);
+        }
+        Ok(())
+    }
+
+    fn import_blob(&mut self) -> Result<()> {
+        let mark = self.parser.optional("mark")?;
+        self.parser.optional("original-oid")?;
+        let oid = hash_object(&self.parser.read_data()?, TypeObject::Blob)?;
+        self.set_mark(mark, &oid)?;
+        self.result.blobs += 1;
+        Ok(())
+    }
+
+    // Apply a file command to the files of a commit, false if the line is
+    // not a file command.
+    fn modify_files(&mut self, line: &str, files: &mut BTreeMap<String, ObjectId>) -> Result<bool> {
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "M" => {
+                let fields: Vec<&str> = arg.splitn(3, ' ').collect();
+                if fields.len() != 3 {
+                    return Err(anyhow!("A broken file command: {}", line));
+                }
+                let (mode, data_ref) = (fields[0], fields[1]);
+                if !["100644", "644", "100755", "755"].contains(&mode) {
+                    return Err(anyhow!("A file mode is not supported by dsgit: {}", line));
+                }
+                let (path, _) = split_path(fields[2], false)?;
+                let path = dsgit_path(&path)?;
+                let oid = if data_ref == "inline" {
+                    hash_object(&self.parser.read_data()?, TypeObject::Blob)?
+                } else {
+                    let oid = self.resolve(data_ref)?;
+                    get_object(&oid, TypeObject::Blob)?;
+                    oid
+                };
+                files.insert(path, oid);
+            }
+            "D" => {
+                let (path, _) = split_path(arg, false)?;
+                let path = dsgit_path(&path)?;
+                let dir = format!("{}/", path);
+                files.retain(|file, _| *file != path && !file.starts_with(&dir));
+            }
+            "C" | "R" => {
+                let (src, rest) = split_path(arg, true)?;
+                let (dst, _) = split_path(rest, false)?;
+                let (src, dst) = (dsgit_path(&src)?, dsgit_path(&dst)?);
+                let dir = format!("{}/", src);
+                let moved: Vec<(String, ObjectId)> = files
+                    .iter()
+                    .filter(|(file, _)| **file == src || file.starts_with(&dir))
+                    .map(|(file, oid)| (file.to_owned(), oid.to_owned()))
+                    .collect();
+                if moved.is_empty() {
+                    return Err(anyhow!("A path to copy or rename is not found: {}", line));
+                }
+                for (file, oid) in moved.into_iter() {
+                    if command == "R" {
+                        files.remove(&file);
+                    }
+                    files.insert(format!("{}{}", dst, &file[src.len()..]), oid);
+                }
+            }
+            "deleteall" => files.clear(),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn import_commit(&mut self, ref_name: &str) -> Result<()> {
+        let mark = self.parser.optional("mark")?;
+        self.parser.optional("original-oid")?;
+        self.parser.optional("author")?;
+        self.parser.optional("committer")?;
+        self.parser.optional("encoding")?;
+        let message = self.parser.read_data()?;
+
+        let parent = match self.parser.optional("from")? {
+            Some(from) => Some(self.resolve(&from)?),
+            None => match self.refs.get(ref_name) {
+                Some(oid) => oid.to_owned(),
+                None => get_ref_oid(ref_name)?
+                    .map(|oid| ObjectId::parse(&oid))
+                    .transpose()?,
+            },
+        };
+        let mut parents: Vec<ObjectId> = parent.into_iter().collect();
+        while let Some(merge) = self.parser.optional("merge")? {
+            parents.push(self.resolve(&merge)?);
+        }
+        if parents.len() > 2 {
+            return Err(anyhow!(
+                "A merge of more than two parents is not supported: {}",
+                ref_name
+            ));
+        }
+
+        let mut files: BTreeMap<String, ObjectId> = match parents.first() {
+            Some(parent) => Tree::get_commit_tree(parent)?
+                .entries
+                .into_iter()
+                .map(|entry| (entry.path, entry.oid))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+        while let Some(line) = self.parser.next_line()? {
+            if line.is_empty() {
+                break;
+            }
+            if !self.modify_files(&line, &mut files)? {
+                self.parser.peeked = Some(line);
+                break;
+            }
+        }
+
+        let tree = Tree {
+            entries: files
+                .into_iter()
+                .map(|(path, oid)| Entry {
+                    path,
+                    oid,
+                    obj_type: TypeObject::Blob,
+                })
+                .collect(),
+        };
+        // A dsgit commit message is a single line, the subject.
+        let subject = message.lines().next().unwrap_or("");
+        let oid = Commit::create_merge(
+            &tree.write()?,
+            parents.first().map(|oid| oid.as_str()),
+            parents.get(1).map(|oid| oid.as_str()),
+            subject,
+        )?;
+        self.set_mark(mark, &oid)?;
+        self.refs.insert(ref_name.to_owned(), Some(oid));
+        self.result.commits += 1;
+        Ok(())
+    }
+
+    fn reset(&mut self, ref_name: &str) -> Result<()> {
+        let oid = match self.parser.optional("from")? {
+            Some(from) => Some(self.resolve(&from)?),
+            None => None,
+        };
+        self.refs.insert(ref_name.to_owned(), oid);
+        Ok(())
+    }
+
+    fn import_tag(&mut self, name: &str) -> Result<()> {
+        let mark = self.parser.optional("mark")?;
+        let from = self
+            .parser
+            .optional("from")?
+            .ok_or_else(|| anyhow!("A tag needs an object to point to: {}", name))?;
+        self.parser.optional("original-oid")?;
+        let tagger = self.parser.optional("tagger")?;
+        let message = self.parser.read_data()?;
+
+        let object = self.resolve(&from)?;
+        let (obj_type, _) = read_object(&object)?;
+        let tag = Tag {
+            object,
+            obj_type,
+            tag: name.to_owned(),
+            tagger: tagger.unwrap_or_else(|| format!("{} {}", get_identity(), get_timestamp())),
+            message: message.trim_end_matches('\n').to_owned(),
+        };
+        let oid = tag.write()?;
+        self.set_mark(mark, &oid)?;
+        self.refs.insert(format!("refs/tags/{}", name), Some(oid));
+        self.result.tags += 1;
+        Ok(())
+    }
+
+    // Update every reference in a single transaction. An update which loses
+    // commits or moves a tag is refused unless `force` is given, and the
+    // branch checked out in a working tree is not moved, since its files
+    // would no longer match it.
+    fn update_refs(&mut self, force: bool) -> Result<()> {
+        let checked_out = match is_bare(&dsgit_dir()) {
+            true => None,
+            false => Some(RefValue::resolve_ref_name("HEAD", true)?),
+        };
+        let mut transaction = RefTransaction::new();
+        for (ref_name, oid) in self.refs.iter() {
+            let oid = match oid {
+                Some(oid) => oid,
+                None => continue,
+            };
+            if let Some(branch) = ref_name.strip_prefix("refs/heads/") {
+                check_branch_name(branch)?;
+            } else if ref_name != "HEAD" {
+                check_ref_format(ref_name, false)?;
+            }
+
+            let resolved = RefValue::resolve_ref_name(ref_name, true)?;
+            let old_oid = get_ref_oid(&resolved)?;
+            if old_oid.as_deref() != Some(oid.as_str()) {
+                if old_oid.is_some() && checked_out.as_ref() == Some(&resolved) {
+                    return Err(anyhow!(
+                        "Refusing to update the branch checked out in a working tree: {}",
+                        resolved.trim_start_matches("refs/heads/")
+                    ));
+                }
+                let forced = match ref_name.starts_with("refs/tags/") {
+                    true => old_oid.is_some(),
+                    false => is_forced(old_oid.as_deref(), oid)?,
+                };
+                if forced && !force {
+                    return Err(anyhow!(
+                        "Rejected a non-fast-forward update of {}, use --force to update it",
+                        ref_name
+                    ));
+                }
+                let expected_old = match &old_oid {
+                    Some(old_oid) => old_oid.to_owned(),
+                    None => oid.algorithm().zero_oid().into(),
+                };
+                let ref_value = RefValue::new(Some(oid), false, oid);
+                transaction.update(
+                    ref_name,
+                    ref_value,
+                    true,
+                    Some(&expected_old),
+                    "fast-import",
+                );
+            }
+            self.result.refs.push((ref_name.to_owned(), oid.to_owned()));
+        }
+        transaction.commit()
+    }
+}
+
+/// Read a fast-import stream of `blob`, `commit`, `reset` and `tag`
+/// commands into the current repository, and update the references it
+/// names at the end. `marks` gives marks defined by an earlier stream, and
+/// takes the marks defined by this one. References are only fast-forwarded
+/// unless `force` is given.
+pub fn fast_import(input: &mut impl BufRead, marks: &mut Marks, force: bool) -> Result<FastImport> {
+    let mut importer = Importer {
+        parser: Parser {
+            input,
+            peeked: None,
+        },
+        marks,
+        refs: BTreeMap::new(),
+        result: FastImport::default(),
+    };
+    while let Some(line) = importer.parser.next_line()? {
+        let (command, arg) = line.split_once(' ').unwrap_or((&line, ""));
+        match command {
+            "" | "checkpoint" | "progress" | "feature" | "option" => {}
+            "blob" => importer.import_blob()?,
+            "commit" => importer.import_commit(arg)?,
+            "reset" => importer.reset(arg)?,
+            "tag" => importer.import_tag(arg)?,
+            "done" => break,
+            _ => {
+                return Err(anyhow!(
+                    "A command is not supported by fast-import: {}",
+                    line
+                ))
+            }
+        }
+    }
+    importer.update_refs(force)?;
+    Ok(importer.result)
+}
@@ -0,0 +1,1105 @@
+use crate::commit::Commit;
+use crate::data::{
+    dsgit_dir, get_object, hash_object, object_exists, read_object, sha1_hash, TypeObject,
+};
+use crate::entry::Entry;
+use crate::hash::{HashAlgorithm, ObjectId};
+use crate::identity::{get_identity, get_timestamp};
+use crate::protocol::RefAdvertisement;
+use crate::reference::RefValue;
+use crate::tag::Tag;
+use crate::transaction::{LockFile, RefTransaction};
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::{TryFrom, TryInto};
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::{self, FromStr};
+
+use anyhow::{anyhow, Context, Result};
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use walkdir::WalkDir;
+
+// A tagger written for a Git tag which records none.
+const UNKNOWN_TAGGER: &str = "unknown <unknown> 0 +0000";
+
+// The longest chain of deltas in a Git pack, which Git keeps in 12 bits.
+const MAX_DELTA_DEPTH: usize = 4095;
+
+/// Hash an object as Git does, over `<type> <size>\0<contents>`.
+pub fn git_hash(obj_type: TypeObject, contents: &[u8]) -> String {
+    let header = format!("{} {}\0", obj_type, contents.len());
+    let mut hash = [0u8; 20];
+    sha1_hash([header.as_bytes(), contents].concat(), &mut hash);
+    hex::encode(hash)
+}
+
+fn pack_object_type(kind: u8) -> Result<TypeObject> {
+    match kind {
+        1 => Ok(TypeObject::Commit),
+        2 => Ok(TypeObject::Tree),
+        3 => Ok(TypeObject::Blob),
+        4 => Ok(TypeObject::Tag),
+        _ => Err(anyhow!("Unknown object type in a Git pack: {}", kind)),
+    }
+}
+
+// The object a deltified object in a pack is based on.
+enum DeltaBase {
+    Offset(u64),
+    Oid(String),
+}
+
+fn next_byte(data: &[u8], pos: &mut usize) -> Result<u8> {
+    let byte = *data
+        .get(*pos)
+        .ok_or_else(|| anyhow!("A delta ended unexpectedly."))?;
+    *pos += 1;
+    Ok(byte)
+}
+
+fn read_byte(reader: &mut impl Read) -> Result<u8> {
+    let mut byte = [0u8];
+    reader
+        .read_exact(&mut byte)
+        .map_err(|_| anyhow!("A Git pack ended unexpectedly."))?;
+    Ok(byte[0])
+}
+
+// Add 7 bits of a size read from the lowest, which must fit in 64 bits.
+fn add_size_bits(size: u64, bits: u8, shift: u32) -> Result<u64> {
+    if shift > 63 || (bits as u64) << shift >> shift != bits as u64 {
+        return Err(anyhow!("A size in a Git pack is too large."));
+    }
+    Ok(size | (bits as u64) << shift)
+}
+
+// Read a size at the start of a delta, 7 bits per byte from the lowest.
+fn delta_size(delta: &[u8], pos: &mut usize) -> Result<usize> {
+    let (mut size, mut shift) = (0u64, 0);
+    loop {
+        let byte = next_byte(delta, pos)?;
+        size = add_size_bits(size, byte & 0x7f, shift)?;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return usize::try_from(size).map_err(|_| anyhow!("A delta is too large."));
+        }
+    }
+}
+
+// Rebuild an object from its base and a delta of copy and insert
+// instructions.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut pos = 0;
+    if delta_size(delta, &mut pos)? != base.len() {
+        return Err(anyhow!("A delta does not match the size of its base."));
+    }
+    let size = delta_size(delta, &mut pos)?;
+    // The size is not trusted for more than the delta can make.
+    let mut out = Vec::with_capacity(size.min(base.len() + delta.len()));
+    while pos < delta.len() {
+        let op = next_byte(delta, &mut pos)?;
+        if op & 0x80 != 0 {
+            let (mut offset, mut len) = (0usize, 0usize);
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    offset |= (next_byte(delta, &mut pos)? as usize) << (8 * i);
+                }
+            }
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    len |= (next_byte(delta, &mut pos)? as usize) << (8 * i);
+                }
+            }
+            if len == 0 {
+                len = 0x10000;
+            }
+            let bytes = offset
+                .checked_add(len)
+                .and_then(|end| base.get(offset..end))
+                .ok_or_else(|| anyhow!("A delta copies beyond its base."))?;
+            out.extend_from_slice(bytes);
+        } else if op != 0 {
+            let bytes = delta
+                .get(pos..pos + op as usize)
+                .ok_or_else(|| anyhow!("A delta ended unexpectedly."))?;
+            out.extend_from_slice(bytes);
+            pos += op as usize;
+        } else {
+            return Err(anyhow!("A delta has an invalid instruction."));
+        }
+    }
+    if out.len() != size {
+        return Err(anyhow!("A delta does not match the size of its result."));
+    }
+    Ok(out)
+}
+
+// A Git pack with its index of version 2, which has a fan-out table of 256
+// counts, the sorted hashes of objects, their CRCs, and their offsets in the
+// pack with a table of large offsets. Objects are inflated when they are read.
+struct GitPack {
+    path: PathBuf,
+    index: Vec<u8>,
+    count: usize,
+}
+
+impl GitPack {
+    fn open(path: &Path) -> Result<Self> {
+        let mut header = [0u8; 12];
+        File::open(path)
+            .and_then(|mut file| file.read_exact(&mut header))
+            .with_context(|| format!("Failed to read file: {}", path.display()))?;
+        if &header[..4] != b"PACK" {
+            return Err(anyhow!("Not a Git pack: {}", path.display()));
+        }
+
+        let index_path = path.with_extension("idx");
+        let index = fs::read(&index_path)
+            .with_context(|| format!("Failed to read file: {}", index_path.display()))?;
+        if index.len() < 8 + 256 * 4 || index[..8] != [0xff, b't', b'O', b'c', 0, 0, 0, 2] {
+            return Err(anyhow!(
+                "Unsupported Git pack index: {}",
+                index_path.display()
+            ));
+        }
+        let count = be_u32(&index, 8 + 255 * 4).unwrap_or(0) as usize;
+        if index.len() < 8 + 256 * 4 + count * 28 + 40 {
+            return Err(anyhow!(
+                "A Git pack index is broken: {}",
+                index_path.display()
+            ));
+        }
+        Ok(GitPack {
+            path: path.to_path_buf(),
+            index,
+            count,
+        })
+    }
+
+    fn oid_at(&self, i: usize) -> &[u8] {
+        let start = 8 + 256 * 4 + i * 20;
+        &self.index[start..start + 20]
+    }
+
+    fn offset_at(&self, i: usize) -> Option<u64> {
+        let offset = be_u32(&self.index, 8 + 256 * 4 + self.count * 24 + i * 4)?;
+        if offset & 0x8000_0000 == 0 {
+            return Some(offset as u64);
+        }
+        let start = 8 + 256 * 4 + self.count * 28 + (offset & 0x7fff_ffff) as usize * 8;
+        let bytes = self.index.get(start..start + 8)?;
+        Some(u64::from_be_bytes(bytes.try_into().ok()?))
+    }
+
+    // The offset of an object in the pack, searched among the hashes which
+    // start with the same byte.
+    fn find(&self, oid: &str) -> Option<u64> {
+        let oid = hex::decode(oid).ok().filter(|oid| oid.len() == 20)?;
+        let fan_out = |byte: usize| be_u32(&self.index, 8 + byte * 4).map(|n| n as usize);
+        let mut lo = match oid[0] {
+            0 => 0,
+            byte => fan_out(byte as usize - 1)?,
+        };
+        let mut hi = fan_out(oid[0] as usize)?.min(self.count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.oid_at(mid).cmp(&oid[..]) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return self.offset_at(mid),
+            }
+        }
+        None
+    }
+
+    // Read the entry at `offset`, which is an object or a delta with its
+    // base, and inflate it.
+    fn read_entry(&self, offset: u64) -> Result<(u8, Option<DeltaBase>, Vec<u8>)> {
+        let mut file = File::open(&self.path)
+            .with_context(|| format!("Failed to read file: {}", self.path.display()))?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file);
+
+        let mut byte = read_byte(&mut reader)?;
+        let kind = (byte >> 4) & 0x07;
+        let (mut size, mut shift) = ((byte & 0x0f) as u64, 4);
+        while byte & 0x80 != 0 {
+            byte = read_byte(&mut reader)?;
+            size = add_size_bits(size, byte & 0x7f, shift)?;
+            shift += 7;
+        }
+        let base = match kind {
+            6 => {
+                let mut byte = read_byte(&mut reader)?;
+                let mut distance = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 {
+                    byte = read_byte(&mut reader)?;
+                    distance = distance
+                        .checked_add(1)
+                        .and_then(|distance| distance.checked_mul(1 << 7))
+                        .ok_or_else(|| anyhow!("A delta in a Git pack is too far."))?
+                        | (byte & 0x7f) as u64;
+                }
+                let base = offset
+                    .checked_sub(distance)
+                    .filter(|_| distance > 0)
+                    .ok_or_else(|| anyhow!("A delta refers outside the pack."))?;
+                Some(DeltaBase::Offset(base))
+            }
+            7 => {
+                let mut oid = [0u8; 20];
+                reader
+                    .read_exact(&mut oid)
+                    .map_err(|_| anyhow!("A Git pack ended unexpectedly."))?;
+                Some(DeltaBase::Oid(hex::encode(oid)))
+            }
+            _ => None,
+        };
+
+        // The size is known, so a broken pack can not inflate beyond it.
+        let mut contents = vec![];
+        ZlibDecoder::new(reader)
+            .take(size.saturating_add(1))
+            .read_to_end(&mut contents)
+            .with_context(|| format!("Failed to inflate an object at {}", offset))?;
+        if contents.len() as u64 != size {
+            return Err(anyhow!("An object in a Git pack does not match its size."));
+        }
+        Ok((kind, base, contents))
+    }
+}
+
+fn be_u32(data: &[u8], pos: usize) -> Option<u32> {
+    let bytes = data.get(pos..pos + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// A Git repository read and written as Git lays it out: zlib compressed
+/// loose objects, packs under `objects/pack`, and loose or packed refs.
+pub struct GitRepository {
+    git_dir: PathBuf,
+    packs: Vec<GitPack>,
+}
+
+impl GitRepository {
+    /// Open the Git repository at `path`, either a working tree with `.git`
+    /// or a bare repository.
+    pub fn open(path: &str) -> Result<Self> {
+        let root = Path::new(path);
+        let dot_git = root.join(".git");
+        let git_dir = if dot_git.is_dir() {
+            dot_git
+        } else if dot_git.is_file() {
+            // A linked working tree points to its repository by `gitdir:`.
+            let contents = fs::read_to_string(&dot_git)?;
+            let dir = contents
+                .trim_end()
+                .strip_prefix("gitdir: ")
+                .ok_or_else(|| anyhow!("Invalid gitdir file: {}", dot_git.display()))?;
+            root.join(dir)
+        } else {
+            root.to_path_buf()
+        };
+        if !git_dir.join("objects").is_dir() || !git_dir.join("HEAD").is_file() {
+            return Err(anyhow!("Not a Git repository: {}", path));
+        }
+
+        // Only the indexes of packs are read here, objects are read from
+        // the packs when they are needed.
+        let mut packs = vec![];
+        let pack_dir = git_dir.join("objects/pack");
+        if pack_dir.is_dir() {
+            let mut paths = vec![];
+            for entry in fs::read_dir(&pack_dir)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "pack") {
+                    paths.push(path);
+                }
+            }
+            paths.sort();
+            for path in paths.iter() {
+                packs.push(GitPack::open(path)?);
+            }
+        }
+        Ok(GitRepository { git_dir, packs })
+    }
+
+    /// Create a Git repository with a working tree at `path` unless one
+    /// exists, and open it.
+    pub fn init(path: &str) -> Result<Self> {
+        let git_dir = Path::new(path).join(".git");
+        if !git_dir.exists() {
+            for dir in ["objects/info", "objects/pack", "refs/heads", "refs/tags"].iter() {
+                let dir = git_dir.join(dir);
+                fs::create_dir_all(&dir)
+                    .with_context(|| format!("Failed to create a directory: {}", dir.display()))?;
+            }
+            fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n")?;
+            fs::write(
+                git_dir.join("config"),
+                "[core]\n\trepositoryformatversion = 0\n\tfilemode = true\n\tbare = false\n",
+            )?;
+        }
+        GitRepository::open(path)
+    }
+
+    // The pack which has an object, and the offset of the object in it.
+    fn find_packed(&self, oid: &str) -> Option<(&GitPack, u64)> {
+        self.packs
+            .iter()
+            .find_map(|pack| pack.find(oid).map(|offset| (pack, offset)))
+    }
+
+    // Read a packed object, following its chain of deltas down to an object
+    // stored whole, and then applying the deltas back up.
+    fn read_packed(&self, pack: &GitPack, offset: u64) -> Result<(TypeObject, Vec<u8>)> {
+        let (mut pack, mut offset) = (pack, offset);
+        let mut deltas = vec![];
+        let (obj_type, mut contents) = loop {
+            if deltas.len() > MAX_DELTA_DEPTH {
+                return Err(anyhow!("A chain of deltas in a Git pack is too long."));
+            }
+            let (kind, base, contents) = pack
+                .read_entry(offset)
+                .with_context(|| format!("Failed to read a Git pack: {}", pack.path.display()))?;
+            let base = match base {
+                None => break (pack_object_type(kind)?, contents),
+                Some(base) => base,
+            };
+            deltas.push(contents);
+            match base {
+                DeltaBase::Offset(base_offset) => offset = base_offset,
+                DeltaBase::Oid(oid) => match self.find_packed(&oid) {
+                    Some((base_pack, base_offset)) => {
+                        pack = base_pack;
+                        offset = base_offset;
+                    }
+                    None => break self.read_loose(&oid)?,
+                },
+            }
+        };
+        for delta in deltas.iter().rev() {
+            contents = apply_delta(&contents, delta)?;
+        }
+        Ok((obj_type, contents))
+    }
+
+    fn loose_path(&self, oid: &str) -> PathBuf {
+        self.git_dir
+            .join("objects")
+            .join(&oid[..2.min(oid.len())])
+            .join(oid.get(2..).unwrap_or(""))
+    }
+
+    pub fn has_object(&self, oid: &str) -> bool {
+        self.loose_path(oid).is_file() || self.find_packed(oid).is_some()
+    }
+
+    /// Read an object, either loose or packed.
+    pub fn read_object(&self, oid: &str) -> Result<(TypeObject, Vec<u8>)> {
+        match self.find_packed(oid) {
+            Some((pack, offset)) => self.read_packed(pack, offset),
+            None => self.read_loose(oid),
+        }
+    }
+
+    fn read_loose(&self, oid: &str) -> Result<(TypeObject, Vec<u8>)> {
+        let path = self.loose_path(oid);
+        let data = fs::read(&path).map_err(|_| anyhow!("A Git object is missing: {}", oid))?;
+        let mut raw = vec![];
+        ZlibDecoder::new(&data[..])
+            .read_to_end(&mut raw)
+            .with_context(|| format!("Failed to read a Git object: {}", oid))?;
+        let nul = raw
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(|| anyhow!("A Git object has no header: {}", oid))?;
+        let header = str::from_utf8(&raw[..nul])?;
+        let (obj_type, size) = header
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("A Git object has a broken header: {}", oid))?;
+        let obj_type = TypeObject::from_str(obj_type)
+            .map_err(|_| anyhow!("Unknown object type: {}", obj_type))?;
+        if size.parse::<usize>().ok() != Some(raw.len() - nul - 1) {
+            return Err(anyhow!("A Git object does not match its size: {}", oid));
+        }
+        Ok((obj_type, raw[nul + 1..].to_vec()))
+    }
+
+    /// Write an object as a loose object unless it exists, and return its
+    /// hash.
+    pub fn write_object(&self, obj_type: TypeObject, contents: &[u8]) -> Result<String> {
+        let oid = git_hash(obj_type, contents);
+        if self.has_object(&oid) {
+            return Ok(oid);
+        }
+        let path = self.loose_path(&oid);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let header = format!("{} {}\0", obj_type, contents.len());
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(header.as_bytes())?;
+        encoder.write_all(contents)?;
+        fs::write(&path, encoder.finish()?)
+            .with_context(|| format!("Failed to write a Git object: {}", oid))?;
+        Ok(oid)
+    }
+
+    /// Every reference under `refs/` which points to an object, packed ones
+    /// overridden by loose ones, in order of their names.
+    pub fn refs(&self) -> Result<Vec<(String, String)>> {
+        let mut refs = BTreeMap::new();
+        let packed_refs = self.git_dir.join("packed-refs");
+        if packed_refs.is_file() {
+            for line in fs::read_to_string(&packed_refs)?.lines() {
+                if line.starts_with('#') || line.starts_with('^') {
+                    continue;
+                }
+                if let Some((oid, name)) = line.split_once(' ') {
+                    refs.insert(name.to_owned(), oid.to_owned());
+                }
+            }
+        }
+        for entry in WalkDir::new(self.git_dir.join("refs"))
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_file())
+        {
+            let name = entry.path().strip_prefix(&self.git_dir)?;
+            let name = name.to_str().unwrap().replace('\\', "/");
+            if name.ends_with(".lock") {
+                continue;
+            }
+            let value = fs::read_to_string(entry.path())?;
+            let value = value.trim_end();
+            // Symbolic references like `refs/remotes/origin/HEAD` are left.
+            if !value.starts_with("ref: ") {
+                refs.insert(name, value.to_owned());
+            }
+        }
+        Ok(refs.into_iter().collect())
+    }
+
+    /// The branch HEAD points to, `None` if HEAD is detached.
+    pub fn head_branch(&self) -> Result<Option<String>> {
+        let head = fs::read_to_string(self.git_dir.join("HEAD"))?;
+        Ok(head
+            .trim_end()
+            .strip_prefix("ref: ")
+            .map(|name| name.to_owned()))
+    }
+
+    pub fn update_ref(&self, name: &str, oid: &str) -> Result<()> {
+        let path = self.git_dir.join(name);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, format!("{}\n", oid))
+            .with_context(|| format!("Failed to write a Git reference: {}", name))
+    }
+
+    /// Point HEAD to a branch, or detach it at a commit.
+    pub fn set_head(&self, value: &RefValue) -> Result<()> {
+        let contents = if value.symbolic {
+            format!("ref: {}\n", value.value)
+        } else {
+            format!("{}\n", value.value)
+        };
+        fs::write(self.git_dir.join("HEAD"), contents)?;
+        Ok(())
+    }
+}
+
+// Split a Git commit or tag into its headers and message. Continuation
+// lines of a header, like a signature, are left out.
+fn parse_headers(contents: &[u8]) -> (Vec<(String, String)>, String) {
+    let text = String::from_utf8_lossy(contents);
+    let (header, message) = text.split_once("\n\n").unwrap_or((&text, ""));
+    let headers = header
+        .lines()
+        .filter(|line| !line.starts_with(' '))
+        .filter_map(|line| line.split_once(' '))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect();
+    (headers, message.to_owned())
+}
+
+fn header<'a>(headers: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.as_str())
+}
+
+// Entries of a Git tree as `(mode, name, oid)`.
+fn parse_git_tree(contents: &[u8], hash: HashAlgorithm) -> Result<Vec<(String, String, String)>> {
+    let mut entries = vec![];
+    let mut pos = 0;
+    while pos < contents.len() {
+        let space = pos
+            + contents[pos..]
+                .iter()
+                .position(|byte| *byte == b' ')
+                .ok_or_else(|| anyhow!("A Git tree entry has no mode."))?;
+        let nul = space
+            + contents[space..]
+                .iter()
+                .position(|byte| *byte == 0)
+                .ok_or_else(|| anyhow!("A Git tree entry has no name."))?;
+        let oid = contents
+            .get(nul + 1..nul + 1 + hash.byte_len())
+            .ok_or_else(|| anyhow!("A Git tree ended unexpectedly."))?;
+        entries.push((
+            str::from_utf8(&contents[pos..space])?.to_owned(),
+            str::from_utf8(&contents[space + 1..nul])
+                .map_err(|_| anyhow!("A file name in a Git tree is not UTF-8."))?
+                .to_owned(),
+            hex::encode(oid),
+        ));
+        pos = nul + 1 + hash.byte_len();
+    }
+    Ok(entries)
+}
+
+// The name of a dsgit tree entry, which is the last component of its path.
+fn entry_name(entry: &Entry) -> Result<String> {
+    Path::new(&entry.path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.to_owned())
+        .ok_or_else(|| anyhow!("A tree entry has no name: {}", entry.path))
+}
+
+// Write the entries of a Git tree from `(name, object type, oid)`. Git sorts
+// entries by name, as if a tree were followed by `/`.
+fn write_git_tree(entries: &[(String, TypeObject, String)]) -> Result<Vec<u8>> {
+    let mut entries: Vec<(String, &str, &String, &String)> = entries
+        .iter()
+        .map(|(name, obj_type, oid)| match obj_type {
+            TypeObject::Tree => (format!("{}/", name), "40000", name, oid),
+            _ => (name.to_owned(), "100644", name, oid),
+        })
+        .collect();
+    entries.sort();
+    let mut tree = vec![];
+    for (_, mode, name, oid) in entries.into_iter() {
+        tree.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+        tree.extend_from_slice(&hex::decode(oid)?);
+    }
+    Ok(tree)
+}
+
+/// Encode the lines of a dsgit tree as a Git tree, whose entries have only
+/// the names of files without the directories they are in.
+pub(crate) fn encode_tree(contents: &str) -> Result<Vec<u8>> {
+    let mut entries = vec![];
+    for line in contents.lines() {
+        let entry: Entry = line.parse()?;
+        entries.push((entry_name(&entry)?, entry.obj_type, entry.oid.into()));
+    }
+    write_git_tree(&entries)
+}
+
+/// Decode a Git tree as the lines of a dsgit tree, with paths relative to
+/// the tree like `./foo.txt`.
+pub(crate) fn decode_tree(contents: &[u8], hash: HashAlgorithm) -> Result<String> {
+    let mut entries = vec![];
+    for (mode, name, oid) in parse_git_tree(contents, hash)?.into_iter() {
+        if name.contains(' ') || name.contains('\n') {
+            return Err(anyhow!("A tree entry has a space in the name: {}", name));
+        }
+        let obj_type = match mode.as_str() {
+            "40000" => TypeObject::Tree,
+            "100644" | "100755" => TypeObject::Blob,
+            _ => return Err(anyhow!("Unsupported mode of a tree entry: {}", mode)),
+        };
+        entries.push(Entry {
+            path: format!("./{}", name),
+            oid: ObjectId::parse(&oid)?,
+            obj_type,
+        });
+    }
+    entries.sort();
+    Ok(entries.iter().map(|entry| entry.to_string()).collect())
+}
+
+/// Pairs of a Git object and the dsgit object it corresponds to, kept in
+/// `.dsgit/git-map` as `<git oid> <dsgit oid>` lines, so that importing or
+/// exporting again converts only new objects.
+#[derive(Debug, Default)]
+pub struct OidMap {
+    oids: BTreeMap<String, String>,
+}
+
+impl OidMap {
+    fn path() -> String {
+        format!("{}/git-map", dsgit_dir())
+    }
+
+    pub fn read() -> Result<Self> {
+        let path = OidMap::path();
+        let mut map = OidMap::default();
+        if !Path::new(&path).is_file() {
+            return Ok(map);
+        }
+        let contents =
+            fs::read_to_string(&path).with_context(|| format!("Failed to read file: {}", path))?;
+        for line in contents.lines() {
+            let (git_oid, oid) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("Broken line in {}: {}", path, line))?;
+            map.insert(git_oid, oid);
+        }
+        Ok(map)
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let mut lock = LockFile::acquire(&OidMap::path())?;
+        let contents: String = self
+            .oids
+            .iter()
+            .map(|(git_oid, oid)| format!("{} {}\n", git_oid, oid))
+            .collect();
+        lock.write(&contents)?;
+        lock.commit()
+    }
+
+    pub fn insert(&mut self, git_oid: &str, oid: &str) {
+        self.oids.insert(git_oid.to_owned(), oid.to_owned());
+    }
+
+    /// The dsgit object a Git object corresponds to.
+    pub fn get(&self, git_oid: &str) -> Option<&str> {
+        self.oids.get(git_oid).map(|oid| oid.as_str())
+    }
+
+    /// Pairs of a Git object and a dsgit object in order of Git hashes.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.oids.iter()
+    }
+}
+
+/// References written by `import_git` with the dsgit objects they point
+/// to, paths which dsgit can not record, and Git commits whose messages are
+/// cut to their subject lines.
+#[derive(Debug, Default)]
+pub struct GitImport {
+    pub refs: Vec<(String, ObjectId)>,
+    pub skipped: Vec<String>,
+    pub truncated: Vec<String>,
+}
+
+struct Importer<'a> {
+    repository: &'a GitRepository,
+    map: OidMap,
+    // Trees depend on where they are, since dsgit entries keep full paths.
+    trees: HashMap<(String, String), ObjectId>,
+    skipped: BTreeSet<String>,
+    truncated: Vec<String>,
+}
+
+impl<'a> Importer<'a> {
+    fn converted(&self, git_oid: &str) -> Option<ObjectId> {
+        self.map
+            .get(git_oid)
+            .filter(|oid| object_exists(oid))
+            .and_then(|oid| ObjectId::parse(oid).ok())
+    }
+
+    fn import_object(&mut self, git_oid: &str) -> Result<ObjectId> {
+        if let Some(oid) = self.converted(git_oid) {
+            return Ok(oid);
+        }
+        let (obj_type, contents) = self.repository.read_object(git_oid)?;
+        match obj_type {
+            TypeObject::Commit => self.import_commit(git_oid),
+            TypeObject::Tree => self.import_tree(git_oid, "."),
+            TypeObject::Blob => self
+                .import_blob(git_oid, &contents)?
+                .ok_or_else(|| anyhow!("A binary file can not be imported: {}", git_oid)),
+            TypeObject::Tag => self.import_tag(git_oid, &contents),
+        }
+    }
+
+    // Import a commit after its parents, walking history with a stack
+    // instead of recursion since it can be long.
+    fn import_commit(&mut self, git_oid: &str) -> Result<ObjectId> {
+        let mut stack = vec![git_oid.to_owned()];
+        while let Some(cur) = stack.last().cloned() {
+            if self.converted(&cur).is_some() {
+                stack.pop();
+                continue;
+            }
+            let (obj_type, contents) = self.repository.read_object(&cur)?;
+            if obj_type != TypeObject::Commit {
+                return Err(anyhow!(
+                    "Expected a Git commit, but got {}: {}",
+                    obj_type,
+                    cur
+                ));
+            }
+            let (headers, message) = parse_headers(&contents);
+            let git_parents: Vec<&str> = headers
+                .iter()
+                .filter(|(key, _)| key == "parent")
+                .map(|(_, value)| value.as_str())
+                .collect();
+            if git_parents.len() > 2 {
+                return Err(anyhow!(
+                    "A merge of more than two parents can not be imported: {}",
+                    cur
+                ));
+            }
+            let unconverted: Vec<String> = git_parents
+                .iter()
+                .filter(|parent| self.converted(parent).is_none())
+                .map(|parent| parent.to_string())
+                .collect();
+            if !unconverted.is_empty() {
+                stack.extend(unconverted);
+                continue;
+            }
+            stack.pop();
+
+            let git_tree = header(&headers, "tree")
+                .ok_or_else(|| anyhow!("A Git commit has no tree: {}", cur))?;
+            let tree = self.import_tree(git_tree, ".")?;
+            let parents: Vec<ObjectId> = git_parents
+                .iter()
+                .filter_map(|parent| self.converted(parent))
+                .collect();
+            // A dsgit commit message is a single line, the subject.
+            let subject = message.lines().next().unwrap_or("");
+            if message.trim_end().contains('\n') {
+                self.truncated.push(cur.to_owned());
+            }
+            let oid = Commit::create_merge(
+                &tree,
+                parents.first().map(|oid| oid.as_str()),
+                parents.get(1).map(|oid| oid.as_str()),
+                subject,
+            )?;
+            self.map.insert(&cur, &oid);
+        }
+        self.converted(git_oid)
+            .ok_or_else(|| anyhow!("Failed to import a Git commit: {}", git_oid))
+    }
+
+    fn import_tree(&mut self, git_oid: &str, prefix: &str) -> Result<ObjectId> {
+        let key = (git_oid.to_owned(), prefix.to_owned());
+        if let Some(oid) = self.trees.get(&key) {
+            return Ok(oid.to_owned());
+        }
+        let (obj_type, contents) = self.repository.read_object(git_oid)?;
+        if obj_type != TypeObject::Tree {
+            return Err(anyhow!(
+                "Expected a Git tree, but got {}: {}",
+                obj_type,
+                git_oid
+            ));
+        }
+
+        let mut entries = vec![];
+        for (mode, name, entry_oid) in parse_git_tree(&contents, HashAlgorithm::Sha1)?.into_iter() {
+            let path = format!("{}/{}", prefix, name);
+            // A tree entry of dsgit is separated by spaces and newlines.
+            if name.contains(' ') || name.contains('\n') {
+                self.skipped
+                    .insert(format!("{} (a space in the name)", path));
+                continue;
+            }
+            match mode.as_str() {
+                "40000" => entries.push(Entry {
+                    oid: self.import_tree(&entry_oid, &path)?,
+                    path,
+                    obj_type: TypeObject::Tree,
+                }),
+                "120000" => {
+                    self.skipped.insert(format!("{} (a symbolic link)", path));
+                }
+                "160000" => {
+                    self.skipped.insert(format!("{} (a submodule)", path));
+                }
+                _ => {
+                    let oid = match self.converted(&entry_oid) {
+                        Some(oid) => Some(oid),
+                        None => {
+                            let (_, blob) = self.repository.read_object(&entry_oid)?;
+                            self.import_blob(&entry_oid, &blob)?
+                        }
+                    };
+                    match oid {
+                        Some(oid) => entries.push(Entry {
+                            path,
+                            oid,
+                            obj_type: TypeObject::Blob,
+                        }),
+                        None => {
+                            self.skipped.insert(format!("{} (a binary file)", path));
+                        }
+                    }
+                }
+            }
+        }
+
+        // Entries are sorted as `Tree::write_tree` does, so that a tree is
+        // the same as the one committed from the same files.
+        entries.sort();
+        let contents: String = entries.iter().map(|entry| entry.to_string()).collect();
+        let oid = hash_object(&contents, TypeObject::Tree)?;
+        self.trees.insert(key, oid.clone());
+        Ok(oid)
+    }
+
+    // Import a blob unless it is binary, which dsgit objects can not hold.
+    fn import_blob(&mut self, git_oid: &str, contents: &[u8]) -> Result<Option<ObjectId>> {
+        let text = match str::from_utf8(contents) {
+            Ok(text) => text,
+            Err(_) => return Ok(None),
+        };
+        let oid = hash_object(text, TypeObject::Blob)?;
+        self.map.insert(git_oid, &oid);
+        Ok(Some(oid))
+    }
+
+    fn import_tag(&mut self, git_oid: &str, contents: &[u8]) -> Result<ObjectId> {
+        let (headers, message) = parse_headers(contents);
+        let missing = |field: &str| anyhow!("A Git tag has no {}: {}", field, git_oid);
+        let object =
+            self.import_object(header(&headers, "object").ok_or_else(|| missing("object"))?)?;
+        let tag = format!(
+            "object {}\ntype {}\ntag {}\ntagger {}\n\n{}\n",
+            object,
+            header(&headers, "type").ok_or_else(|| missing("type"))?,
+            header(&headers, "tag").ok_or_else(|| missing("tag"))?,
+            header(&headers, "tagger").unwrap_or(UNKNOWN_TAGGER),
+            message.trim_end_matches('\n')
+        );
+        let oid = hash_object(&tag, TypeObject::Tag)?;
+        self.map.insert(git_oid, &oid);
+        Ok(oid)
+    }
+}
+
+/// Convert the branches and tags of the Git repository at `path` with the
+/// history they point to into the current repository. Files dsgit can not
+/// record, like binary files and symbolic links, are left out and reported,
+/// as are the bodies of commit messages.
+/// HEAD of a repository without commits follows the branch of Git's HEAD,
+/// while the working tree is left as it is.
+pub fn import_git(path: &str) -> Result<GitImport> {
+    let repository = GitRepository::open(path)?;
+    let mut importer = Importer {
+        repository: &repository,
+        map: OidMap::read()?,
+        trees: HashMap::new(),
+        skipped: BTreeSet::new(),
+        truncated: vec![],
+    };
+    let mut refs = vec![];
+    for (name, git_oid) in repository.refs()?.into_iter() {
+        if !name.starts_with("refs/heads/") && !name.starts_with("refs/tags/") {
+            continue;
+        }
+        let oid = importer
+            .import_object(&git_oid)
+            .with_context(|| format!("Failed to import {}", name))?;
+        refs.push((name, oid));
+    }
+    importer.map.write()?;
+
+    let unborn = RefValue::get_ref("HEAD", true)?.is_none();
+    let message = format!("import-git: from {}", path);
+    let mut transaction = RefTransaction::new();
+    for (name, oid) in refs.iter() {
+        let ref_value = RefValue::new(Some(oid), false, oid);
+        transaction.update(name, ref_value, false, None, &message);
+    }
+    transaction.commit()?;
+    if let Some(branch) = repository.head_branch()? {
+        if unborn && refs.iter().any(|(name, _)| *name == branch) {
+            RefValue::write_ref("HEAD", &RefValue::new(None, true, &branch))?;
+        }
+    }
+
+    Ok(GitImport {
+        refs,
+        skipped: importer.skipped.into_iter().collect(),
+        truncated: importer.truncated,
+    })
+}
+
+struct Exporter<'a> {
+    repository: &'a GitRepository,
+    map: OidMap,
+    // Git objects which dsgit objects were imported from or exported to.
+    known: HashMap<String, Vec<String>>,
+    exported: HashMap<String, String>,
+    signature: String,
+}
+
+impl<'a> Exporter<'a> {
+    // A Git object corresponding to a dsgit object, which exists in the
+    // target repository.
+    fn exported(&self, oid: &str) -> Option<String> {
+        if let Some(git_oid) = self.exported.get(oid) {
+            return Some(git_oid.to_owned());
+        }
+        self.known
+            .get(oid)?
+            .iter()
+            .find(|git_oid| self.repository.has_object(git_oid))
+            .cloned()
+    }
+
+    fn record(&mut self, oid: &str, git_oid: &str) {
+        self.exported.insert(oid.to_owned(), git_oid.to_owned());
+    }
+
+    fn export_object(&mut self, oid: &str) -> Result<String> {
+        if let Some(git_oid) = self.exported(oid) {
+            return Ok(git_oid);
+        }
+        let (obj_type, contents) = read_object(oid)?;
+        let git_oid = match obj_type {
+            TypeObject::Blob => {
+                let git_oid = self
+                    .repository
+                    .write_object(TypeObject::Blob, contents.as_bytes())?;
+                self.map.insert(&git_oid, oid);
+                git_oid
+            }
+            TypeObject::Tree => self.export_tree(&contents)?,
+            TypeObject::Commit => self.export_commit(oid)?,
+            TypeObject::Tag => self.export_tag(oid)?,
+        };
+        self.record(oid, &git_oid);
+        Ok(git_oid)
+    }
+
+    fn export_tree(&mut self, contents: &str) -> Result<String> {
+        let mut entries = vec![];
+        for line in contents.lines() {
+            let entry: Entry = line.parse()?;
+            let git_oid = self.export_object(&entry.oid)?;
+            entries.push((entry_name(&entry)?, entry.obj_type, git_oid));
+        }
+        let tree = write_git_tree(&entries)?;
+        self.repository.write_object(TypeObject::Tree, &tree)
+    }
+
+    // Export a commit after its parents, walking history with a stack
+    // instead of recursion since it can be long.
+    fn export_commit(&mut self, oid: &str) -> Result<String> {
+        let mut stack = vec![ObjectId::parse(oid)?];
+        while let Some(cur) = stack.last().cloned() {
+            if self.exported(&cur).is_some() {
+                stack.pop();
+                continue;
+            }
+            let commit = Commit::get_commit(&cur)?;
+            let unexported: Vec<ObjectId> = commit
+                .parents()
+                .into_iter()
+                .filter(|parent| self.exported(parent).is_none())
+                .collect();
+            if !unexported.is_empty() {
+                stack.extend(unexported);
+                continue;
+            }
+            stack.pop();
+
+            let mut git_commit = format!("tree {}\n", self.export_object(&commit.tree)?);
+            for parent in commit.parents().iter() {
+                git_commit += &format!("parent {}\n", self.export_object(parent)?);
+            }
+            let contents = get_object(&cur, TypeObject::Commit)?;
+            let (header, message) = contents.split_once("\n\n").unwrap_or((&contents, ""));
+            // A commit keeps its own author and committer if it records
+            // them, so that it is exported as the same Git commit.
+            let field = |name: &str| {
+                header
+                    .lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .unwrap_or(&self.signature)
+            };
+            git_commit += &format!(
+                "author {}\ncommitter {}\n",
+                field("author "),
+                field("committer ")
+            );
+            git_commit += &format!("\n{}\n", message.trim_end_matches('\n'));
+            let git_oid = self
+                .repository
+                .write_object(TypeObject::Commit, git_commit.as_bytes())?;
+            self.map.insert(&git_oid, &cur);
+            self.record(&cur, &git_oid);
+        }
+        self.exported(oid)
+            .ok_or_else(|| anyhow!("Failed to export a commit: {}", oid))
+    }
+
+    fn export_tag(&mut self, oid: &str) -> Result<String> {
+        let tag = Tag::get_tag(oid)?;
+        let git_tag = format!(
+            "object {}\ntype {}\ntag {}\ntagger {}\n\n{}\n",
+            self.export_object(&tag.object)?,
+            tag.obj_type,
+            tag.tag,
+            tag.tagger,
+            tag.message
+        );
+        let git_oid = self
+            .repository
+            .write_object(TypeObject::Tag, git_tag.as_bytes())?;
+        self.map.insert(&git_oid, oid);
+        Ok(git_oid)
+    }
+}
+
+/// Write the branches and tags of the current repository with their
+/// history into the Git repository at `path`, created unless it exists, and
+/// return the references written with the Git objects they point to.
+/// Commits are authored by the current user, and an object exported or
+/// imported before is reused if the Git repository has it. Git's index is
+/// not written, `git reset --hard` checks out the files.
+pub fn export_git(path: &str) -> Result<Vec<(String, String)>> {
+    let repository = GitRepository::init(path)?;
+    let map = OidMap::read()?;
+    let mut known: HashMap<String, Vec<String>> = HashMap::new();
+    for (git_oid, oid) in map.iter() {
+        known
+            .entry(oid.to_owned())
+            .or_default()
+            .push(git_oid.to_owned());
+    }
+    let mut exporter = Exporter {
+        repository: &repository,
+        map,
+        known,
+        exported: HashMap::new(),
+        signature: format!("{} {}", get_identity(), get_timestamp()),
+    };
+
+    let advertisement = RefAdvertisement::current()?;
+    let mut refs = vec![];
+    for (name, oid) in advertisement.refs.iter() {
+        let git_oid = exporter
+            .export_object(oid)
+            .with_context(|| format!("Failed to export {}", name))?;
+        repository.update_ref(name, &git_oid)?;
+        refs.push((name.to_owned(), git_oid));
+    }
+    match (advertisement.head_branch, advertisement.head_oid) {
+        (Some(branch), _) => repository.set_head(&RefValue::new(None, true, &branch))?,
+        (None, Some(oid)) => {
+            let git_oid = exporter.export_object(&oid)?;
+            repository.set_head(&RefValue::new(None, false, &git_oid))?
+        }
+        (None, None) => {}
+    }
+    exporter.map.write()?;
+    Ok(refs)
+}
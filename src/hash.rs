@@ -0,0 +1,196 @@
+use crate::data::sha1_hash;
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+
+fn sha256_hash(data: &[u8], out: &mut [u8]) {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    out.copy_from_slice(&hasher.finalize())
+}
+
+/// The hash function of objects, chosen when a repository is created and
+/// kept in `.dsgit/config` as `extensions.objectFormat` like Git.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// The number of bytes of a hash.
+    pub fn byte_len(&self) -> usize {
+        match *self {
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 => 32,
+        }
+    }
+
+    /// The number of hex digits of an object id.
+    pub fn hex_len(&self) -> usize {
+        self.byte_len() * 2
+    }
+
+    /// The algorithm which made the object id `oid`, judged by its length.
+    pub fn of(oid: &str) -> Self {
+        HashAlgorithm::from_hex_len(oid.len()).unwrap_or_default()
+    }
+
+    // The algorithm which makes object ids of `hex_len` digits.
+    fn from_hex_len(hex_len: usize) -> Option<Self> {
+        [HashAlgorithm::Sha1, HashAlgorithm::Sha256]
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.hex_len() == hex_len)
+    }
+
+    pub fn hash(&self, data: &[u8]) -> ObjectId {
+        let mut hash = vec![0u8; self.byte_len()];
+        match *self {
+            HashAlgorithm::Sha1 => sha1_hash(data, &mut hash),
+            HashAlgorithm::Sha256 => sha256_hash(data, &mut hash),
+        }
+        ObjectId(hex::encode(hash))
+    }
+
+    /// An object id of zeros, which stands for no object.
+    pub fn zero_oid(&self) -> ObjectId {
+        ObjectId("0".repeat(self.hex_len()))
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            _ => Err(anyhow!("Unknown hash algorithm: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HashAlgorithm::Sha1 => write!(f, "sha1"),
+            HashAlgorithm::Sha256 => write!(f, "sha256"),
+        }
+    }
+}
+
+/// The id of an object, which is the hash of the object in lowercase hex.
+/// It reads as a `&str`, so that it is passed to functions taking a name.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectId(String);
+
+impl ObjectId {
+    /// Parse an object id of any supported hash algorithm.
+    pub fn parse(s: &str) -> Result<Self> {
+        let is_hex = s
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+        if !is_hex || HashAlgorithm::from_hex_len(s.len()).is_none() {
+            return Err(anyhow!("Not an object id: {}", s));
+        }
+        Ok(ObjectId(s.to_owned()))
+    }
+
+    /// The hash algorithm which made this object id.
+    pub fn algorithm(&self) -> HashAlgorithm {
+        HashAlgorithm::of(&self.0)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The raw bytes of the hash.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        hex::decode(&self.0).unwrap_or_default()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.chars().all(|c| c == '0')
+    }
+}
+
+/// Whether `oid` is an object id of zeros, which stands for no object.
+pub fn is_zero_oid(oid: &str) -> bool {
+    ObjectId::parse(oid).is_ok_and(|oid| oid.is_zero())
+}
+
+impl FromStr for ObjectId {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        ObjectId::parse(s)
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Pass through width and precision, so that `{:.7}` is a short id.
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for ObjectId {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for ObjectId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for ObjectId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<ObjectId> for String {
+    fn from(oid: ObjectId) -> String {
+        oid.0
+    }
+}
+
+impl PartialEq<str> for ObjectId {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for ObjectId {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<String> for ObjectId {
+    fn eq(&self, other: &String) -> bool {
+        &self.0 == other
+    }
+}
+
+impl PartialEq<ObjectId> for String {
+    fn eq(&self, other: &ObjectId) -> bool {
+        *self == other.0
+    }
+}
+
+impl PartialEq<ObjectId> for &str {
+    fn eq(&self, other: &ObjectId) -> bool {
+        *self == other.0
+    }
+}
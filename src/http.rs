@@ -0,0 +1,164 @@
+use crate::data::set_dsgit_dir;
+use crate::protocol::{receive_pack, upload_pack, RefAdvertisement};
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use anyhow::{anyhow, Context, Result};
+
+/// Serve the repository of a `.dsgit` directory over HTTP, answering one
+/// connection at a time until the listener fails. A served repository is
+/// reached by `http://<address>/` with the paths below:
+///   GET  /info/refs?service=dsgit-upload-pack  : advertise references
+///   GET  /info/refs?service=dsgit-receive-pack : advertise references
+///   POST /dsgit-upload-pack                    : send a pack of wanted objects
+///   POST /dsgit-receive-pack                   : receive a pack and update references
+/// Any prefix before these paths is ignored. The current thread acts on the
+/// served repository.
+pub fn serve(listener: TcpListener, dsgit_dir: &str) -> Result<()> {
+    set_dsgit_dir(dsgit_dir);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        if let Err(e) = handle(stream) {
+            eprintln!("dsgit: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn write_response(stream: &mut TcpStream, status: &str, body: &[u8]) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/octet-stream\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+// Read a request line and headers, and return the method, the path and the
+// value of `Content-Length`.
+fn read_head(reader: &mut impl BufRead) -> Result<(String, String, usize)> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let fields: Vec<&str> = request_line.split_whitespace().collect();
+    let (method, path) = match fields[..] {
+        [method, path, _] => (method.to_owned(), path.to_owned()),
+        _ => return Err(anyhow!("Broken HTTP request: {}", request_line.trim_end())),
+    };
+    let content_length = read_headers(reader)?.unwrap_or(0);
+    Ok((method, path, content_length))
+}
+
+// Read headers until an empty line, and return the value of
+// `Content-Length` if given.
+fn read_headers(reader: &mut impl BufRead) -> Result<Option<usize>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+    Ok(content_length)
+}
+
+fn handle(mut stream: TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let (method, path, content_length) = read_head(&mut reader)?;
+    // The body is read as it is needed rather than buffered, so that the
+    // length a client claims is not allocated up front.
+    let mut body = reader.take(content_length as u64);
+
+    let mut response = vec![];
+    let result = match (method.as_str(), path.as_str()) {
+        ("GET", path)
+            if path.ends_with("/info/refs?service=dsgit-upload-pack")
+                || path.ends_with("/info/refs?service=dsgit-receive-pack") =>
+        {
+            RefAdvertisement::current()
+                .map(|advertisement| response = advertisement.to_string().into_bytes())
+        }
+        ("POST", path) if path.ends_with("/dsgit-upload-pack") => {
+            upload_pack(&mut body, &mut response)
+        }
+        ("POST", path) if path.ends_with("/dsgit-receive-pack") => {
+            receive_pack(&mut body, &mut response)
+        }
+        _ => {
+            let message = format!("Not found: {} {}", method, path);
+            return write_response(&mut stream, "404 Not Found", message.as_bytes());
+        }
+    };
+    match result {
+        Ok(()) => write_response(&mut stream, "200 OK", &response),
+        Err(e) => write_response(
+            &mut stream,
+            "500 Internal Server Error",
+            e.to_string().as_bytes(),
+        ),
+    }
+}
+
+/// Send an HTTP request to a URL like `http://localhost:8080/info/refs`,
+/// and return the body of a successful response.
+pub fn request(method: &str, url: &str, body: Option<&[u8]>) -> Result<Vec<u8>> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow!("Only http:// URLs are supported: {}", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    let mut stream =
+        TcpStream::connect(host).with_context(|| format!("Failed to connect to {}", host))?;
+    let body = body.unwrap_or_default();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        method,
+        path,
+        host,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow!("Broken HTTP response from {}", url))?;
+    let mut response = vec![];
+    match read_headers(&mut reader)? {
+        Some(length) => {
+            reader.take(length as u64).read_to_end(&mut response)?;
+            if response.len() != length {
+                return Err(anyhow!("A response from {} ended unexpectedly.", url));
+            }
+        }
+        None => {
+            reader.read_to_end(&mut response)?;
+        }
+    }
+    if status != "200" {
+        return Err(anyhow!(
+            "{} {} failed with {}: {}",
+            method,
+            url,
+            status,
+            String::from_utf8_lossy(&response)
+        ));
+    }
+    Ok(response)
+}
@@ -0,0 +1,25 @@
+use chrono::{DateTime, FixedOffset, Local};
+use std::env;
+
+/// Return `Name <email>` of a user running dsgit, taken from
+/// `DSGIT_AUTHOR_NAME` and `DSGIT_AUTHOR_EMAIL` environment variables.
+pub fn get_identity() -> String {
+    let name = env::var("DSGIT_AUTHOR_NAME")
+        .or_else(|_| env::var("USER"))
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| String::from("unknown"));
+    let email = env::var("DSGIT_AUTHOR_EMAIL").unwrap_or_else(|_| format!("{}@localhost", name));
+    format!("{} <{}>", name, email)
+}
+
+/// Return the current time as `<seconds since epoch> <timezone>`, where the
+/// timezone is the local offset from UTC like `+0900`.
+pub fn get_timestamp() -> String {
+    format_timestamp(Local::now().into())
+}
+
+/// Return `time` as `<seconds since epoch> <timezone>`, the way
+/// `get_timestamp` writes it.
+pub fn format_timestamp(time: DateTime<FixedOffset>) -> String {
+    time.format("%s %z").to_string()
+}
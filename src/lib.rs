@@ -1,5 +1,26 @@
+pub mod apply;
+pub mod bundle;
 pub mod commit;
+pub mod config;
 pub mod data;
 pub mod diff;
 pub mod entry;
+pub mod fastimport;
+pub mod git;
+pub mod hash;
+pub mod http;
+pub mod identity;
+pub mod mail;
+pub mod merge;
+pub mod pack;
+pub mod pktline;
+pub mod protocol;
+pub mod pull;
 pub mod reference;
+pub mod reflog;
+pub mod remote;
+pub mod shallow;
+pub mod stash;
+pub mod tag;
+pub mod transaction;
+pub mod transport;
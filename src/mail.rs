@@ -0,0 +1,353 @@
+use crate::apply::{apply_patch, Patch};
+use crate::commit::Commit;
+use crate::data::get_oid;
+use crate::diff::{diff_trees, diffstat, patch};
+use crate::entry::Tree;
+use crate::hash::ObjectId;
+use crate::identity::{get_identity, get_timestamp};
+use crate::merge::{ensure_clean_working_tree, write_file};
+use crate::reference::RefValue;
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+// Git writes this fixed date on the line starting each mail of a mailbox,
+// which tells the line from a `From ` line of some mail program.
+const MAILBOX_DATE: &str = "Mon Sep 17 00:00:00 2001";
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// The most characters of a subject in a file name of a patch.
+const MAX_FILE_NAME_SUBJECT: usize = 52;
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// A date of days since 1970-01-01 as year, month and day.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// The offset of a timezone like `+0900` in seconds.
+fn parse_timezone(timezone: &str) -> Option<i64> {
+    let sign = match timezone.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let hours: i64 = timezone.get(1..3)?.parse().ok()?;
+    let minutes: i64 = timezone.get(3..5)?.parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Format seconds since the epoch at a timezone like `+0900` as a date of a
+/// mail, `Sat, 17 Oct 2026 14:00:00 +0900`.
+pub fn format_date(seconds: i64, timezone: &str) -> String {
+    let local = seconds + parse_timezone(timezone).unwrap_or(0);
+    let days = local.div_euclid(86400);
+    let time = local.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} {}",
+        // 1970-01-01 was a Thursday.
+        WEEKDAYS[(days + 4).rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        timezone
+    )
+}
+
+/// Parse a date of a mail into seconds since the epoch and the timezone.
+pub fn parse_date(date: &str) -> Result<(i64, String)> {
+    let invalid = || anyhow!("Invalid date of a mail: {}", date);
+    // The day of the week is optional.
+    let date_time = date.split_once(", ").map_or(date, |(_, rest)| rest);
+    let fields: Vec<&str> = date_time.split_whitespace().collect();
+    if fields.len() != 5 {
+        return Err(invalid());
+    }
+    let day: i64 = fields[0].parse().map_err(|_| invalid())?;
+    let month = MONTHS
+        .iter()
+        .position(|month| *month == fields[1])
+        .ok_or_else(invalid)? as i64;
+    let year: i64 = fields[2].parse().map_err(|_| invalid())?;
+    let time: Vec<i64> = fields[3]
+        .split(':')
+        .map(|n| n.parse().map_err(|_| invalid()))
+        .collect::<Result<_>>()?;
+    if time.len() != 3 {
+        return Err(invalid());
+    }
+    let offset = parse_timezone(fields[4]).ok_or_else(invalid)?;
+    let local =
+        days_from_civil(year, month + 1, day) * 86400 + time[0] * 3600 + time[1] * 60 + time[2];
+    Ok((local - offset, fields[4].to_owned()))
+}
+
+// Split `Name <email> <seconds> <timezone>` into the identity and the time.
+fn split_signature(signature: &str) -> Option<(&str, i64, &str)> {
+    let mut fields = signature.rsplitn(3, ' ');
+    let timezone = fields.next()?;
+    let seconds = fields.next()?.parse().ok()?;
+    Some((fields.next()?, seconds, timezone))
+}
+
+// The commits of `A..B`, or of `A..HEAD` for `A`, following the first
+// parents from B and the oldest first. Merge commits have no single patch,
+// so they are left out.
+fn commits_in_range(range: &str) -> Result<Vec<ObjectId>> {
+    let (excluded, included) = match range.split_once("..") {
+        Some((excluded, "")) => (excluded, "HEAD"),
+        Some((excluded, included)) => (excluded, included),
+        None => (range, "HEAD"),
+    };
+    let excluded = Commit::ancestors(&get_oid(excluded)?)?;
+    let mut commits = vec![];
+    let mut cur = Some(get_oid(included)?);
+    while let Some(oid) = cur {
+        if excluded.contains(&oid) {
+            break;
+        }
+        let commit = Commit::get_commit(&oid)?;
+        cur = commit.parent;
+        if commit.merge_parent.is_none() {
+            commits.push(oid);
+        }
+    }
+    commits.reverse();
+    Ok(commits)
+}
+
+// A file name of a patch like `0001-add-a-lion.patch`.
+fn patch_file_name(number: usize, subject: &str) -> String {
+    let mut slug = String::new();
+    for c in subject.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug: String = slug.chars().take(MAX_FILE_NAME_SUBJECT).collect();
+    format!(
+        "{:04}-{}.patch",
+        number,
+        slug.trim_end_matches(&['-', '.'][..])
+    )
+}
+
+/// Write a mail for each commit in `range` into `dir`, the commits of
+/// `A..B` or, for a single commit `A`, the commits since `A` up to HEAD. A
+/// mail has the author, the message as the subject, a diffstat and a
+/// unified diff, and can be applied by `am`. Commits which record no author
+/// are sent by the current user. The paths written are returned.
+pub fn format_patch(range: &str, dir: &str) -> Result<Vec<String>> {
+    let commits = commits_in_range(range)?;
+    let mut paths = vec![];
+    for (index, oid) in commits.iter().enumerate() {
+        let commit = Commit::get_commit(oid)?;
+        let signature = commit
+            .author
+            .clone()
+            .unwrap_or_else(|| format!("{} {}", get_identity(), get_timestamp()));
+        let (identity, seconds, timezone) = split_signature(&signature)
+            .ok_or_else(|| anyhow!("Invalid author of a commit: {}", signature))?;
+
+        let from = match &commit.parent {
+            Some(parent) => Tree::get_commit_tree(parent)?,
+            None => Tree { entries: vec![] },
+        };
+        let diff = diff_trees(&from, &Tree::get_commit_tree(oid)?)?;
+        let mail = format!(
+            "From {} {}\n\
+             From: {}\n\
+             Date: {}\n\
+             Subject: [PATCH {}/{}] {}\n\
+             \n\
+             ---\n\
+             {}\n\
+             {}\
+             -- \n\
+             dsgit {}\n\n",
+            oid,
+            MAILBOX_DATE,
+            identity,
+            format_date(seconds, timezone),
+            index + 1,
+            commits.len(),
+            commit.message,
+            diffstat(&diff)?,
+            patch(&diff, 3)?,
+            env!("CARGO_PKG_VERSION"),
+        );
+
+        let path = Path::new(dir).join(patch_file_name(index + 1, &commit.message));
+        let path = path.to_string_lossy().into_owned();
+        write_file(&path, &mail)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// A mail of a patch read from a mailbox.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mail {
+    /// `Name <email> <seconds> <timezone>` of the author.
+    pub author: String,
+    /// The subject without a prefix like `[PATCH 1/2]`.
+    pub subject: String,
+    pub patch: Patch,
+}
+
+// Whether a line starts a mail, `From <oid> Mon Sep 17 00:00:00 2001` as
+// `format-patch` writes it.
+fn is_mail_start(line: &str) -> bool {
+    line.trim_end()
+        .strip_prefix("From ")
+        .and_then(|rest| rest.split_once(' '))
+        .is_some_and(|(oid, date)| ObjectId::parse(oid).is_ok() && date == MAILBOX_DATE)
+}
+
+// Split a mailbox into the mails, each of which starts at the line of
+// `is_mail_start`.
+fn split_mailbox(mailbox: &str) -> Vec<&str> {
+    let mut starts: Vec<usize> = vec![];
+    let mut pos = 0;
+    for line in mailbox.split_inclusive('\n') {
+        if is_mail_start(line) {
+            starts.push(pos);
+        }
+        pos += line.len();
+    }
+    let ends = starts.iter().skip(1).copied().chain(Some(mailbox.len()));
+    starts
+        .iter()
+        .zip(ends)
+        .map(|(start, end)| &mailbox[*start..end])
+        .collect()
+}
+
+fn parse_mail(mail: &str) -> Result<Mail> {
+    let (headers, body) = mail
+        .split_once("\n\n")
+        .ok_or_else(|| anyhow!("A mail has no body."))?;
+    // A long header continues on lines starting with a space.
+    let mut fields: Vec<(String, String)> = vec![];
+    for line in headers.lines().skip(1) {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            fields.push((name.to_ascii_lowercase(), value.trim().to_owned()));
+        }
+    }
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let from = field("from").ok_or_else(|| anyhow!("A mail has no From header."))?;
+    let time = match field("date") {
+        Some(date) => {
+            let (seconds, timezone) = parse_date(date)?;
+            format!("{} {}", seconds, timezone)
+        }
+        None => get_timestamp(),
+    };
+    let subject = field("subject").unwrap_or("");
+    let subject = match subject.strip_prefix('[') {
+        Some(prefixed) => prefixed.split_once(']').map_or(subject, |(_, rest)| rest),
+        None => subject,
+    };
+    let subject = subject.trim().to_owned();
+
+    let patch: Patch = body.parse()?;
+    if patch.files.is_empty() {
+        return Err(anyhow!("A mail has no patch: {}", subject));
+    }
+    Ok(Mail {
+        author: format!("{} {}", from, time),
+        subject,
+        patch,
+    })
+}
+
+/// Read the mails of patches in a mailbox, like files written by
+/// `format-patch` joined together.
+pub fn read_mailbox(path: &str) -> Result<Vec<Mail>> {
+    let mailbox =
+        fs::read_to_string(path).with_context(|| format!("Failed to read file: {}", path))?;
+    split_mailbox(&mailbox)
+        .into_iter()
+        .map(parse_mail)
+        .collect()
+}
+
+/// Apply the patches in a mailbox onto HEAD in order, recording each of them
+/// as a commit with the subject as the message and the author of the mail.
+/// A patch which does not apply stops the rest of them, with the rejected
+/// hunks written next to the files. The commits created are returned.
+pub fn am(path: &str, ignore_options: &[String]) -> Result<Vec<ObjectId>> {
+    let mails = read_mailbox(path)?;
+    if mails.is_empty() {
+        return Err(anyhow!("No mails of patches in a mailbox: {}", path));
+    }
+    ensure_clean_working_tree("am", ignore_options)?;
+
+    let mut oids = vec![];
+    for (index, mail) in mails.iter().enumerate() {
+        let reports = apply_patch(&mail.patch, false)?;
+        let rejects: Vec<&str> = reports
+            .iter()
+            .filter_map(|report| report.reject_file.as_deref())
+            .collect();
+        if !rejects.is_empty() {
+            return Err(anyhow!(
+                "Patch failed at {:04} {}\nRejected hunks are written to: {}",
+                index + 1,
+                mail.subject,
+                rejects.join(", ")
+            ));
+        }
+
+        let tree = Tree::write_tree(".", ignore_options)?;
+        let parent = RefValue::get_ref("HEAD", true)?.map(|ref_value| ref_value.value);
+        let oid = Commit::create_authored(&tree, parent.as_deref(), &mail.author, &mail.subject)?;
+        let ref_value = RefValue::new(Some(&oid), false, &oid);
+        RefValue::update_ref("HEAD", &ref_value, true, &format!("am: {}", mail.subject))?;
+        oids.push(oid);
+    }
+    Ok(oids)
+}
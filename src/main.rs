@@ -1,25 +1,76 @@
+pub mod apply;
+pub mod bundle;
 pub mod commit;
+pub mod config;
 pub mod data;
 pub mod diff;
 pub mod entry;
+pub mod fastimport;
+pub mod git;
+pub mod hash;
+pub mod http;
+pub mod identity;
+pub mod mail;
+pub mod merge;
+pub mod pack;
+pub mod pktline;
+pub mod protocol;
+pub mod pull;
 pub mod reference;
+pub mod reflog;
+pub mod remote;
+pub mod shallow;
+pub mod stash;
+pub mod tag;
+pub mod transaction;
+pub mod transport;
 
 use colored::*;
 use std::collections::HashMap;
 use std::env;
 use std::fs;
+use std::io;
+use std::net::TcpListener;
 use std::path::Path;
 use std::process::exit;
 
 use anyhow::{anyhow, Result};
 use commit::Commit;
-use data::TypeObject;
+use console::user_attended;
+use data::{ObjectEncoding, RepositoryFormat, TypeObject};
+use diff::{Change, DiffFormat};
 use entry::Tree;
+use hash::HashAlgorithm;
 use reference::RefValue;
 
+enum BranchCommand {
+    List(bool),
+    Create((String, Option<String>, bool)),
+    Delete((String, bool)),
+    Rename((Option<String>, String)),
+    SetUpstream((String, Option<String>)),
+}
+
+enum RemoteCommand {
+    List(bool),
+    Add((String, String)),
+    Remove(String),
+}
+
+enum BundleCommand {
+    Create((String, Vec<String>)),
+    Verify(String),
+}
+
+enum TagCommand {
+    List(Option<String>),
+    Create((String, Option<String>, Option<String>)),
+    Delete(String),
+}
+
 enum Commands {
     Help,
-    Init,
+    Init((Option<String>, Option<String>)),
     WriteTree,
     Log(Option<String>),
     Cat(String),
@@ -27,12 +78,34 @@ enum Commands {
     ReadTree(String),
     Commit(String),
     Switch(String),
-    Tag((String, Option<String>)),
-    Branch(Option<(String, Option<String>)>),
+    Tag(TagCommand),
+    Branch(BranchCommand),
     Status,
     Reset(String),
-    Show(Option<String>),
-    Diff(Option<String>),
+    Show((Option<String>, diff::DiffOptions)),
+    Diff((Option<String>, diff::DiffOptions)),
+    CherryPick((Vec<String>, bool)),
+    Revert((String, bool)),
+    Stash((String, Option<String>)),
+    Reflog(Option<String>),
+    PackRefs,
+    CheckRefFormat((String, bool, bool)),
+    Clone((String, Option<String>, bool, transport::FetchOptions)),
+    Remote(RemoteCommand),
+    Fetch((Option<String>, bool)),
+    Push((Option<String>, Option<String>, bool, bool)),
+    Pull((Option<String>, Option<String>, Option<pull::PullMode>)),
+    Serve((Option<String>, Option<String>)),
+    UploadPack(String),
+    ReceivePack(String),
+    Bundle(BundleCommand),
+    ImportGit(String),
+    ExportGit(String),
+    FastExport((Vec<String>, Option<String>, Option<String>)),
+    FastImport((Option<String>, Option<String>, bool)),
+    Apply((String, bool, bool)),
+    FormatPatch((String, Option<String>)),
+    Am(String),
 }
 
 fn check_args(args: &[String], expect_length: usize, err_msg: &'static str) -> Result<()> {
@@ -42,13 +115,59 @@ fn check_args(args: &[String], expect_length: usize, err_msg: &'static str) -> R
     Ok(())
 }
 
+// A diff is colored on a terminal, and a patch otherwise so that a diff
+// redirected to a file or a pipe can be applied.
+fn default_diff_options() -> diff::DiffOptions {
+    let mut options = diff::DiffOptions::default();
+    if user_attended() {
+        options.format = DiffFormat::Colored;
+    }
+    options
+}
+
+// Take `--patch` and `-U<n>` out of the arguments of a command printing a
+// diff. Like git, `-U<n>` implies a patch.
+fn parse_diff_options(args: &[String]) -> Result<(diff::DiffOptions, Vec<String>)> {
+    let mut options = default_diff_options();
+    let mut rest = vec![];
+    for arg in args.iter() {
+        if arg == "-p" || arg == "--patch" {
+            options.format = DiffFormat::Patch;
+        } else if let Some(n) = arg
+            .strip_prefix("--unified=")
+            .or_else(|| arg.strip_prefix("-U"))
+        {
+            options.format = DiffFormat::Patch;
+            options.context = n
+                .parse()
+                .map_err(|_| anyhow!("dsgit: `{}` required a number of context lines.", arg))?;
+        } else {
+            rest.push(arg.to_owned());
+        }
+    }
+    Ok((options, rest))
+}
+
 fn arg_parse() -> Result<Commands> {
     let args: Vec<String> = env::args().collect();
 
     if args.len() > 1 {
         let cmd: Commands = match args[1].as_str() {
             "--help" | "-h" => Commands::Help,
-            "init" => Commands::Init,
+            "init" => {
+                let mut encoding = None;
+                let mut format = None;
+                for arg in args[2..].iter() {
+                    if let Some(value) = arg.strip_prefix("--object-encoding=") {
+                        encoding = Some(value.to_owned());
+                    } else if let Some(value) = arg.strip_prefix("--object-format=") {
+                        format = Some(value.to_owned());
+                    } else {
+                        return Err(anyhow!("dsgit: `init` got an unknown option: {}", arg));
+                    }
+                }
+                Commands::Init((encoding, format))
+            }
             "write-tree" => Commands::WriteTree,
             "log" => {
                 if args.len() > 2 {
@@ -88,19 +207,40 @@ fn arg_parse() -> Result<Commands> {
                 }
             }
             "branch" => {
-                if args.len() == 2 {
-                    Commands::Branch(None)
-                } else if args.len() == 3 {
-                    let branch_name: String = args[2].to_owned();
-                    Commands::Branch(Some((branch_name, None)))
-                } else {
-                    let err_msg = "dsgit: `branch` required branch-name and commit-hash.";
-                    check_args(&args, 4, err_msg)?;
-
-                    let branch_name: String = args[2].to_owned();
-                    let oid: String = args[3].to_owned();
-                    Commands::Branch(Some((branch_name, Some(oid))))
-                }
+                let err_msg = "dsgit: `branch` required branch-name and (option) commit-hash.";
+                let cmd = match args.get(2).map(|a| a.as_str()) {
+                    None => BranchCommand::List(false),
+                    Some("-v") | Some("--verbose") => {
+                        check_args(&args, 3, err_msg)?;
+                        BranchCommand::List(true)
+                    }
+                    Some("-d") | Some("-D") => {
+                        let err_msg = "dsgit: `branch -d` required branch-name.";
+                        check_args(&args, 4, err_msg)?;
+                        BranchCommand::Delete((args[3].to_owned(), args[2] == "-D"))
+                    }
+                    Some("-m") => match args.len() {
+                        4 => BranchCommand::Rename((None, args[3].to_owned())),
+                        5 => BranchCommand::Rename((Some(args[3].to_owned()), args[4].to_owned())),
+                        _ => return Err(anyhow!("dsgit: `branch -m` required (option) old-branch-name and new-branch-name.")),
+                    },
+                    Some("-u") | Some("--set-upstream-to") => match args.len() {
+                        4 => BranchCommand::SetUpstream((args[3].to_owned(), None)),
+                        5 => BranchCommand::SetUpstream((args[3].to_owned(), Some(args[4].to_owned()))),
+                        _ => return Err(anyhow!("dsgit: `branch -u` required an upstream and (option) branch-name.")),
+                    },
+                    Some("-f") | Some("--force") => match args.len() {
+                        4 => BranchCommand::Create((args[3].to_owned(), None, true)),
+                        5 => BranchCommand::Create((args[3].to_owned(), Some(args[4].to_owned()), true)),
+                        _ => return Err(anyhow!(err_msg)),
+                    },
+                    Some(_) => match args.len() {
+                        3 => BranchCommand::Create((args[2].to_owned(), None, false)),
+                        4 => BranchCommand::Create((args[2].to_owned(), Some(args[3].to_owned()), false)),
+                        _ => return Err(anyhow!(err_msg)),
+                    },
+                };
+                Commands::Branch(cmd)
             }
             "switch" => {
                 let err_msg = "dsgit: `switch` required branch-name or commit-hash.";
@@ -109,19 +249,52 @@ fn arg_parse() -> Result<Commands> {
                 Commands::Switch(commit)
             }
             "tag" => {
-                if args.len() < 3 {
-                    return Err(anyhow!(
-                        "dsgit: `tag` required tag-name, and (option) commit-hash."
-                    ));
-                } else {
-                    let tag: String = args[2].to_owned();
-                    if args.len() == 3 {
-                        Commands::Tag((tag, None))
-                    } else {
-                        let oid: String = args[3].to_owned();
-                        Commands::Tag((tag, Some(oid)))
+                let err_msg = "dsgit: `tag` required tag-name, and (option) commit-hash.";
+                let cmd = match args.get(2).map(|a| a.as_str()) {
+                    None => TagCommand::List(None),
+                    Some("-l") | Some("--list") => match args.len() {
+                        3 => TagCommand::List(None),
+                        4 => TagCommand::List(Some(args[3].to_owned())),
+                        _ => return Err(anyhow!("dsgit: `tag -l` accepts only (option) pattern.")),
+                    },
+                    Some("-d") | Some("--delete") => {
+                        check_args(&args, 4, "dsgit: `tag -d` required tag-name.")?;
+                        TagCommand::Delete(args[3].to_owned())
                     }
-                }
+                    Some(_) => {
+                        // `-a` is implied by `-m`, as an annotated tag requires a message.
+                        let mut message = None;
+                        let mut names = vec![];
+                        let mut rest = args[2..].iter();
+                        while let Some(arg) = rest.next() {
+                            match arg.as_str() {
+                                "-a" | "--annotate" => continue,
+                                "-m" | "--message" => match rest.next() {
+                                    Some(msg) => message = Some(msg.to_owned()),
+                                    None => {
+                                        return Err(anyhow!("dsgit: `tag -m` required message."))
+                                    }
+                                },
+                                _ => names.push(arg.to_owned()),
+                            }
+                        }
+                        if args[2..].iter().any(|a| a == "-a" || a == "--annotate")
+                            && message.is_none()
+                        {
+                            return Err(anyhow!("dsgit: `tag -a` required '-m' and message."));
+                        }
+                        match names.len() {
+                            1 => TagCommand::Create((names[0].to_owned(), None, message)),
+                            2 => TagCommand::Create((
+                                names[0].to_owned(),
+                                Some(names[1].to_owned()),
+                                message,
+                            )),
+                            _ => return Err(anyhow!(err_msg)),
+                        }
+                    }
+                };
+                Commands::Tag(cmd)
             }
             "status" => Commands::Status,
             "reset" => {
@@ -131,20 +304,329 @@ fn arg_parse() -> Result<Commands> {
             }
             "show" => {
                 let err_msg = "dsgit: `show` required commit hash.";
-                if args.len() > 2 {
-                    check_args(&args, 3, err_msg)?;
-                    Commands::Show(Some(args[2].to_owned()))
-                } else {
-                    Commands::Show(None)
+                let (options, rest) = parse_diff_options(&args[2..])?;
+                if rest.len() > 1 {
+                    return Err(anyhow!(err_msg));
                 }
+                Commands::Show((rest.first().cloned(), options))
             }
             "diff" => {
                 let err_msg = "dsgit: `diff` required commit hash.";
+                let (options, rest) = parse_diff_options(&args[2..])?;
+                if rest.len() > 1 {
+                    return Err(anyhow!(err_msg));
+                }
+                Commands::Diff((rest.first().cloned(), options))
+            }
+            "cherry-pick" => {
+                let err_msg = "dsgit: `cherry-pick` required one or more commits.";
+                let no_commit = args[2..].iter().any(|a| a == "-n" || a == "--no-commit");
+                let commits: Vec<String> = args[2..]
+                    .iter()
+                    .filter(|a| *a != "-n" && *a != "--no-commit")
+                    .cloned()
+                    .collect();
+                if commits.is_empty() {
+                    return Err(anyhow!(err_msg));
+                }
+                Commands::CherryPick((commits, no_commit))
+            }
+            "revert" => {
+                let err_msg =
+                    "dsgit: `revert` required a commit, and (option) '-n' or '--no-commit'.";
+                match args.len() {
+                    3 => Commands::Revert((args[2].to_owned(), false)),
+                    4 => match args[2].as_str() {
+                        "-n" | "--no-commit" => Commands::Revert((args[3].to_owned(), true)),
+                        _ => return Err(anyhow!(err_msg)),
+                    },
+                    _ => return Err(anyhow!(err_msg)),
+                }
+            }
+            "pack-refs" => Commands::PackRefs,
+            "clone" => {
+                let err_msg =
+                    "dsgit: `clone` required a repository path, (option) a directory, '--bare', '--depth' with a positive number and '--filter=blob:none'.";
+                let bare = args[2..].iter().any(|a| a == "--bare");
+                let (depth, rest) = match args.iter().position(|a| a == "--depth") {
+                    Some(i) if i + 1 < args.len() => match args[i + 1].parse::<usize>() {
+                        Ok(depth) if depth > 0 => {
+                            (Some(depth), [&args[2..i], &args[i + 2..]].concat())
+                        }
+                        _ => return Err(anyhow!(err_msg)),
+                    },
+                    Some(_) => return Err(anyhow!(err_msg)),
+                    None => (None, args[2..].to_vec()),
+                };
+                let filter = match rest.iter().find_map(|a| a.strip_prefix("--filter=")) {
+                    Some(filter) => Some(filter.parse().map_err(|_| anyhow!(err_msg))?),
+                    None => None,
+                };
+                let options = transport::FetchOptions { depth, filter };
+                let paths: Vec<&String> = rest
+                    .iter()
+                    .filter(|a| *a != "--bare" && !a.starts_with("--filter="))
+                    .collect();
+                match paths.len() {
+                    1 => Commands::Clone((paths[0].to_owned(), None, bare, options)),
+                    2 => Commands::Clone((
+                        paths[0].to_owned(),
+                        Some(paths[1].to_owned()),
+                        bare,
+                        options,
+                    )),
+                    _ => return Err(anyhow!(err_msg)),
+                }
+            }
+            "remote" => {
+                let err_msg = "dsgit: `remote` required one of -v, add and remove.";
+                let cmd = match args.get(2).map(|a| a.as_str()) {
+                    None => RemoteCommand::List(false),
+                    Some("-v") | Some("--verbose") => {
+                        check_args(&args, 3, err_msg)?;
+                        RemoteCommand::List(true)
+                    }
+                    Some("add") => {
+                        check_args(&args, 5, "dsgit: `remote add` required a name and a path.")?;
+                        RemoteCommand::Add((args[3].to_owned(), args[4].to_owned()))
+                    }
+                    Some("remove") | Some("rm") => {
+                        check_args(&args, 4, "dsgit: `remote remove` required a name.")?;
+                        RemoteCommand::Remove(args[3].to_owned())
+                    }
+                    Some(_) => return Err(anyhow!(err_msg)),
+                };
+                Commands::Remote(cmd)
+            }
+            "fetch" => {
+                let err_msg =
+                    "dsgit: `fetch` accepts only (option) a remote name or a repository path, and '--unshallow'.";
+                let unshallow = args[2..].iter().any(|a| a == "--unshallow");
+                let rest: Vec<&String> = args[2..].iter().filter(|a| *a != "--unshallow").collect();
+                if rest.len() > 1 {
+                    return Err(anyhow!(err_msg));
+                }
+                Commands::Fetch((rest.first().map(|a| a.to_string()), unshallow))
+            }
+            "push" => {
+                let err_msg =
+                    "dsgit: `push` accepts (option) a remote name or a repository path, a branch, '-f' and '-u'.";
+                let is_force = |a: &String| a == "-f" || a == "--force";
+                let is_set_upstream = |a: &String| a == "-u" || a == "--set-upstream";
+                let force = args[2..].iter().any(is_force);
+                let set_upstream = args[2..].iter().any(is_set_upstream);
+                let rest: Vec<&String> = args[2..]
+                    .iter()
+                    .filter(|a| !is_force(a) && !is_set_upstream(a))
+                    .collect();
+                if rest.len() > 2 {
+                    return Err(anyhow!(err_msg));
+                }
+                Commands::Push((
+                    rest.first().map(|a| a.to_string()),
+                    rest.get(1).map(|a| a.to_string()),
+                    force,
+                    set_upstream,
+                ))
+            }
+            "pull" => {
+                let err_msg = "dsgit: `pull` accepts (option) a remote name or a repository path, a branch, and one of '--rebase', '--no-rebase' and '--ff-only'.";
+                let mode_of = |a: &str| match a {
+                    "--rebase" | "-r" => Some(pull::PullMode::Rebase),
+                    "--no-rebase" => Some(pull::PullMode::Merge),
+                    "--ff-only" => Some(pull::PullMode::FastForwardOnly),
+                    _ => None,
+                };
+                let modes: Vec<pull::PullMode> =
+                    args[2..].iter().filter_map(|a| mode_of(a)).collect();
+                let rest: Vec<&String> =
+                    args[2..].iter().filter(|a| mode_of(a).is_none()).collect();
+                if modes.len() > 1 || rest.len() > 2 {
+                    return Err(anyhow!(err_msg));
+                }
+                Commands::Pull((
+                    rest.first().map(|a| a.to_string()),
+                    rest.get(1).map(|a| a.to_string()),
+                    modes.first().copied(),
+                ))
+            }
+            "serve" => {
+                let err_msg =
+                    "dsgit: `serve` accepts (option) '--listen' and an address, and a repository path.";
+                let (listen, rest) = match args.iter().position(|a| a == "--listen") {
+                    Some(i) if i + 1 < args.len() => (
+                        Some(args[i + 1].to_owned()),
+                        [&args[2..i], &args[i + 2..]].concat(),
+                    ),
+                    Some(_) => return Err(anyhow!(err_msg)),
+                    None => (None, args[2..].to_vec()),
+                };
+                if rest.len() > 1 {
+                    return Err(anyhow!(err_msg));
+                }
+                Commands::Serve((listen, rest.first().cloned()))
+            }
+            "upload-pack" => {
+                check_args(&args, 3, "dsgit: `upload-pack` required a repository path.")?;
+                Commands::UploadPack(args[2].to_owned())
+            }
+            "receive-pack" => {
+                check_args(
+                    &args,
+                    3,
+                    "dsgit: `receive-pack` required a repository path.",
+                )?;
+                Commands::ReceivePack(args[2].to_owned())
+            }
+            "bundle" => {
+                let err_msg = "dsgit: `bundle` required one of create and verify.";
+                let cmd = match args.get(2).map(|a| a.as_str()) {
+                    Some("create") => {
+                        if args.len() < 5 {
+                            return Err(anyhow!(
+                                "dsgit: `bundle create` required a file and references."
+                            ));
+                        }
+                        BundleCommand::Create((args[3].to_owned(), args[4..].to_vec()))
+                    }
+                    Some("verify") => {
+                        check_args(&args, 4, "dsgit: `bundle verify` required a file.")?;
+                        BundleCommand::Verify(args[3].to_owned())
+                    }
+                    _ => return Err(anyhow!(err_msg)),
+                };
+                Commands::Bundle(cmd)
+            }
+            "import-git" => {
+                let err_msg = "dsgit: `import-git` required a path of a Git repository.";
+                check_args(&args, 3, err_msg)?;
+                Commands::ImportGit(args[2].to_owned())
+            }
+            "fast-export" => {
+                let mut revs = vec![];
+                let (mut import_marks, mut export_marks) = (None, None);
+                for arg in args[2..].iter() {
+                    if let Some(path) = arg.strip_prefix("--import-marks=") {
+                        import_marks = Some(path.to_owned());
+                    } else if let Some(path) = arg.strip_prefix("--export-marks=") {
+                        export_marks = Some(path.to_owned());
+                    } else {
+                        revs.push(arg.to_owned());
+                    }
+                }
+                if revs.is_empty() {
+                    return Err(anyhow!(
+                        "dsgit: `fast-export` required references or '--all'."
+                    ));
+                }
+                Commands::FastExport((revs, import_marks, export_marks))
+            }
+            "fast-import" => {
+                let (mut import_marks, mut export_marks) = (None, None);
+                let mut force = false;
+                for arg in args[2..].iter() {
+                    if arg == "-f" || arg == "--force" {
+                        force = true;
+                    } else if let Some(path) = arg.strip_prefix("--import-marks=") {
+                        import_marks = Some(path.to_owned());
+                    } else if let Some(path) = arg.strip_prefix("--export-marks=") {
+                        export_marks = Some(path.to_owned());
+                    } else {
+                        return Err(anyhow!(
+                            "dsgit: `fast-import` got an unknown option: {}",
+                            arg
+                        ));
+                    }
+                }
+                Commands::FastImport((import_marks, export_marks, force))
+            }
+            "apply" => {
+                let (mut check, mut reverse) = (false, false);
+                let mut paths = vec![];
+                for arg in args[2..].iter() {
+                    match arg.as_str() {
+                        "--check" => check = true,
+                        "-R" | "--reverse" => reverse = true,
+                        _ => paths.push(arg.to_owned()),
+                    }
+                }
+                if paths.len() != 1 {
+                    return Err(anyhow!("dsgit: `apply` required a patch file."));
+                }
+                Commands::Apply((paths.remove(0), check, reverse))
+            }
+            "format-patch" => {
+                let err_msg = "dsgit: `format-patch` required a range of commits.";
+                match args.len() {
+                    3 => Commands::FormatPatch((args[2].to_owned(), None)),
+                    5 if args[2] == "-o" || args[2] == "--output-directory" => {
+                        Commands::FormatPatch((args[4].to_owned(), Some(args[3].to_owned())))
+                    }
+                    _ => return Err(anyhow!(err_msg)),
+                }
+            }
+            "am" => {
+                let err_msg = "dsgit: `am` required a mailbox file.";
+                check_args(&args, 3, err_msg)?;
+                Commands::Am(args[2].to_owned())
+            }
+            "export-git" => {
+                let err_msg = "dsgit: `export-git` required a path to write a Git repository.";
+                check_args(&args, 3, err_msg)?;
+                Commands::ExportGit(args[2].to_owned())
+            }
+            "check-ref-format" => {
+                let err_msg = "dsgit: `check-ref-format` required a reference name, and (option) '--branch' or '--allow-onelevel'.";
+                let branch = args[2..].iter().any(|a| a == "--branch");
+                let allow_onelevel = args[2..].iter().any(|a| a == "--allow-onelevel");
+                let names: Vec<&String> = args[2..]
+                    .iter()
+                    .filter(|a| *a != "--branch" && *a != "--allow-onelevel")
+                    .collect();
+                if names.len() != 1 {
+                    return Err(anyhow!(err_msg));
+                }
+                Commands::CheckRefFormat((names[0].to_owned(), branch, allow_onelevel))
+            }
+            "reflog" => {
+                let err_msg = "dsgit: `reflog` accepts only (option) reference name.";
                 if args.len() > 2 {
                     check_args(&args, 3, err_msg)?;
-                    Commands::Diff(Some(args[2].to_owned()))
+                    Commands::Reflog(Some(args[2].to_owned()))
+                } else {
+                    Commands::Reflog(None)
+                }
+            }
+            "stash" => {
+                let err_msg =
+                    "dsgit: `stash` required one of push, list, show, pop, apply and drop.";
+                if args.len() == 2 {
+                    Commands::Stash((String::from("push"), None))
                 } else {
-                    Commands::Diff(None)
+                    match args[2].as_str() {
+                        "push" => match args.len() {
+                            3 => Commands::Stash((String::from("push"), None)),
+                            5 if args[3] == "-m" || args[3] == "--message" => {
+                                Commands::Stash((String::from("push"), Some(args[4].to_owned())))
+                            }
+                            _ => {
+                                return Err(anyhow!(
+                                    "dsgit: `stash push` accepts only '-m' and message."
+                                ))
+                            }
+                        },
+                        "list" => {
+                            check_args(&args, 3, err_msg)?;
+                            Commands::Stash((String::from("list"), None))
+                        }
+                        "show" | "pop" | "apply" | "drop" => {
+                            if args.len() > 4 {
+                                return Err(anyhow!(err_msg));
+                            }
+                            Commands::Stash((args[2].to_owned(), args.get(3).cloned()))
+                        }
+                        _ => return Err(anyhow!(err_msg)),
+                    }
                 }
             }
             _ => {
@@ -161,8 +643,22 @@ fn arg_parse() -> Result<Commands> {
     }
 }
 
-fn init() {
-    data::init().unwrap();
+fn repository_format(encoding: Option<String>, hash: Option<String>) -> Result<RepositoryFormat> {
+    let mut format = RepositoryFormat::default();
+    if let Some(encoding) = encoding {
+        format.encoding = encoding.parse::<ObjectEncoding>()?;
+    }
+    if let Some(hash) = hash {
+        format.hash = hash.parse::<HashAlgorithm>()?;
+    }
+    Ok(format)
+}
+
+fn init(encoding: Option<String>, hash: Option<String>) {
+    if let Err(e) = repository_format(encoding, hash).and_then(data::init_with_format) {
+        eprintln!("dsgit: {}", e);
+        exit(1);
+    }
     let path = env::current_dir().unwrap();
     println!(
         "Initialized empty DSGit repository in {}/.dsgit",
@@ -176,7 +672,7 @@ fn print_commit(oid: &str, commit: &Commit, refs: Option<&str>) {
         None => println!("commit {:#}", &oid),
     }
     println!("tree   {:#}", &commit.tree);
-    if let Some(parent_oid) = &commit.parent {
+    for parent_oid in commit.parents().iter() {
         println!("parent {:#}", parent_oid);
     }
     println!("\n{:ident$}{:#}", "", &commit.message, ident = 4);
@@ -187,7 +683,9 @@ fn log(tag_or_oid: Option<String>) {
     let mut refs = HashMap::new();
     let ref_values = RefValue::get_refs(None, ".").unwrap();
     for r in ref_values.iter() {
-        refs.insert(r, RefValue::get_ref(r, true).unwrap().unwrap());
+        let mut ref_value = RefValue::get_ref(r, true).unwrap().unwrap();
+        ref_value.value = data::peel_oid(&ref_value.value).unwrap().into();
+        refs.insert(r.as_str(), ref_value);
     }
 
     let mut oid = match tag_or_oid {
@@ -197,7 +695,7 @@ fn log(tag_or_oid: Option<String>) {
 
     loop {
         let commit = Commit::get_commit(&oid).unwrap();
-        match refs.get(&oid) {
+        match refs.get(oid.as_str()) {
             Some(ref_oid) => print_commit(&oid, &commit, Some(&ref_oid.value)),
             None => print_commit(&oid, &commit, None),
         }
@@ -208,9 +706,25 @@ fn log(tag_or_oid: Option<String>) {
     }
 }
 
-fn show(oid: Option<String>) {
-    let oid = match oid {
-        Some(oid) => oid,
+fn print_tag(oid: &str) {
+    let tag = tag::Tag::get_tag(oid).unwrap();
+    println!("tag    {:#}", &tag.tag);
+    println!("tagger {:#}", &tag.tagger);
+    println!("\n{:ident$}{:#}", "", &tag.message, ident = 4);
+    println!();
+}
+
+fn show(name: Option<String>, options: &diff::DiffOptions) {
+    let oid = match name {
+        Some(name) => {
+            // Display annotated tags on the way to the commit.
+            let mut oid = data::get_oid_unpeeled(&name).unwrap();
+            while let (TypeObject::Tag, _) = data::read_object(&oid).unwrap() {
+                print_tag(&oid);
+                oid = tag::Tag::get_tag(&oid).unwrap().object;
+            }
+            oid
+        }
         None => reference::get_head_oid(),
     };
 
@@ -221,31 +735,31 @@ fn show(oid: Option<String>) {
         let parent = Commit::get_commit(&oid).unwrap();
         let from_tree = data::get_object(&parent.tree, data::TypeObject::Tree).unwrap();
         let to_tree = data::get_object(&commit.tree, data::TypeObject::Tree).unwrap();
-        diff::diff_trees(
-            Tree::get_tree(&from_tree).unwrap(),
-            Tree::get_tree(&to_tree).unwrap(),
-            true,
+        let diff = diff::diff_trees(
+            &Tree::get_tree(&from_tree).unwrap(),
+            &Tree::get_tree(&to_tree).unwrap(),
         )
         .unwrap();
+        diff::print_diff(&diff, options).unwrap();
     };
 }
 
-fn diff(oid: Option<String>) {
+fn diff(oid: Option<String>, options: &diff::DiffOptions) {
     let ignore_files = read_ignore_file();
     let oid = match oid {
         Some(oid) => oid,
-        None => reference::get_head_oid(),
+        None => reference::get_head_oid().into(),
     };
     let pre_commit = Commit::get_commit(&oid).unwrap();
     let pre_tree = data::get_object(&pre_commit.tree, TypeObject::Tree).unwrap();
 
     // Diff between working tree and difference specified commit /or HEAD tree.
-    diff::diff_trees(
-        Tree::get_tree(&pre_tree).unwrap(),
-        Tree::get_working_tree(&ignore_files).unwrap(),
-        true,
+    let diff = diff::diff_trees(
+        &Tree::get_tree(&pre_tree).unwrap(),
+        &Tree::get_working_tree(&ignore_files).unwrap(),
     )
     .unwrap();
+    diff::print_diff(&diff, options).unwrap();
 }
 
 fn hash_object(file: &str) {
@@ -256,7 +770,8 @@ fn hash_object(file: &str) {
 
 fn cat_object(tag_or_oid: &str) {
     let oid = data::get_oid(tag_or_oid).unwrap();
-    let contents = data::get_object(&oid, data::TypeObject::Blob).unwrap();
+    remote::fetch_missing_objects(std::slice::from_ref(&oid)).unwrap();
+    let (_, contents) = data::read_object(&oid).unwrap();
     print!("{}", contents);
 }
 
@@ -292,82 +807,705 @@ fn switch(commit: &str, ignore_files: Vec<String>) {
     RefValue::switch(commit, &ignore_files).unwrap();
 }
 
-fn create_tag(tag: &str, tag_or_oid: &str) {
-    let oid = data::get_oid(tag_or_oid).unwrap();
-    reference::create_tag(tag, &oid);
+fn tag(cmd: TagCommand) {
+    let result = match cmd {
+        TagCommand::List(pattern) => tag::list_tags(pattern.as_deref()).map(|tags| {
+            for tag in tags.iter() {
+                println!("{}", tag);
+            }
+        }),
+        TagCommand::Create((name, oid_or_none, message)) => {
+            let oid = match oid_or_none {
+                Some(name) => data::get_oid(&name),
+                None => Ok(reference::get_head_oid()),
+            };
+            oid.and_then(|oid| match message {
+                Some(message) => tag::Tag::create(&name, &oid, &message).map(|_| ()),
+                None => reference::create_tag(&name, &oid),
+            })
+        }
+        TagCommand::Delete(name) => {
+            tag::delete_tag(&name).map(|oid| println!("Deleted tag '{}' (was {})", name, &oid[..7]))
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("dsgit: {}", e);
+        exit(1);
+    }
 }
 
-fn branch(pair_name_oid: Option<(&str, &str)>) {
-    match pair_name_oid {
-        Some((name, oid)) => {
-            reference::create_branch(name, oid);
-            println!("Created a branch: {} at {}", name, oid);
+fn clone(path: &str, dir: Option<String>, bare: bool, options: transport::FetchOptions) {
+    let result = (|| -> Result<String> {
+        let path = if transport::is_url(path) {
+            path.to_owned()
+        } else {
+            fs::canonicalize(path)?.to_str().unwrap().to_owned()
+        };
+        let path = path.as_str();
+        let dir = match dir {
+            Some(dir) => dir,
+            None => {
+                let name = path
+                    .trim_end_matches('/')
+                    .rsplit('/')
+                    .next()
+                    .map(|name| name.trim_end_matches(".bundle"))
+                    .filter(|name| !name.is_empty() && !name.contains(':'))
+                    .ok_or_else(|| anyhow!("Specify a directory to clone into."))?;
+                if bare {
+                    format!("{}.dsgit", name)
+                } else {
+                    name.to_owned()
+                }
+            }
+        };
+        if Path::new(&dir).exists() && fs::read_dir(&dir)?.next().is_some() {
+            return Err(anyhow!(
+                "Destination path '{}' already exists and is not an empty directory.",
+                dir
+            ));
         }
-        None => {
-            let cur_branch = RefValue::get_branch_name().unwrap().unwrap();
-            let branches = RefValue::get_refs(Some("."), "refs/heads/").unwrap();
-            for branch in branches.iter() {
-                if *branch == cur_branch {
-                    println!("* {}", branch);
+        if bare {
+            data::set_dsgit_dir(&dir);
+            remote::clone_with(path, true, &options)?;
+        } else {
+            fs::create_dir_all(&dir)?;
+            env::set_current_dir(&dir)?;
+            remote::clone_with(path, false, &options)?;
+        }
+        Ok(dir)
+    })();
+    match result {
+        Ok(dir) => println!("Cloned into '{}'", dir),
+        Err(e) => {
+            eprintln!("dsgit: {}", e);
+            exit(1);
+        }
+    }
+}
+
+fn remote(cmd: RemoteCommand) {
+    let result = match cmd {
+        RemoteCommand::List(verbose) => remote::Remote::list().map(|remotes| {
+            for remote in remotes.iter() {
+                if verbose {
+                    println!("{}\t{}", remote.name, remote.url);
                 } else {
-                    println!("  {}", branch);
+                    println!("{}", remote.name);
                 }
             }
+        }),
+        RemoteCommand::Add((name, path)) => remote::Remote::add(&name, &path).map(|_| ()),
+        RemoteCommand::Remove(name) => remote::Remote::remove(&name),
+    };
+    if let Err(e) = result {
+        eprintln!("dsgit: {}", e);
+        exit(1);
+    }
+}
+
+// The remote of the upstream of the current branch, or `origin`.
+fn default_remote() -> String {
+    let upstream = RefValue::get_branch_name().and_then(|branch| match branch {
+        Some(branch) => remote::Upstream::get(&branch),
+        None => Ok(None),
+    });
+    match upstream {
+        Ok(upstream) => upstream.map_or_else(|| String::from("origin"), |upstream| upstream.remote),
+        Err(e) => {
+            eprintln!("dsgit: {}", e);
+            exit(1);
         }
     }
 }
 
-fn status() {
-    let oid = reference::get_head_oid();
-    match RefValue::get_branch_name().unwrap() {
-        Some(branch) => println!("On branch {}", branch),
-        None => println!("HEAD detached at {}", &oid[10..]),
+fn fetch(name: Option<String>, unshallow: bool) {
+    let name = name.unwrap_or_else(default_remote);
+    let result = remote::Remote::resolve(&name).and_then(|remote| {
+        if unshallow {
+            remote::unshallow(&remote)
+        } else {
+            remote::fetch(&remote)
+        }
+    });
+    match result {
+        Ok(changes) => {
+            if !changes.is_empty() {
+                println!("From {}", name);
+            }
+            for change in changes.iter() {
+                println!("{}", change);
+            }
+        }
+        Err(e) => {
+            eprintln!("dsgit: {}", e);
+            exit(1);
+        }
     }
+}
 
-    let ignore_files = read_ignore_file();
-    let diffs = diff::diff_trees(
-        Tree::get_head_tree().unwrap(),
-        Tree::get_working_tree(&ignore_files).unwrap(),
-        false,
-    )
-    .unwrap();
+fn push(name: Option<String>, branch: Option<String>, force: bool, set_upstream: bool) {
+    let name = name.unwrap_or_else(default_remote);
+    let result = (|| -> Result<remote::RefChange> {
+        let branch = match branch {
+            Some(branch) => branch,
+            None => RefValue::get_branch_name()?
+                .ok_or_else(|| anyhow!("HEAD is detached, specify a branch to push."))?,
+        };
+        let remote = remote::Remote::resolve(&name)?;
+        let change = remote::push(&remote, &branch, force)?;
+        if set_upstream {
+            let tracking_ref = remote
+                .fetch
+                .iter()
+                .find_map(|refspec| refspec.map(&change.src))
+                .ok_or_else(|| anyhow!("'{}' does not track remote branches.", name))?;
+            remote::Upstream::set(&branch, &tracking_ref)?;
+            let tracking = tracking_ref.trim_start_matches("refs/remotes/");
+            println!("Branch '{}' set up to track '{}'.", branch, tracking);
+        }
+        Ok(change)
+    })();
+    match result {
+        Ok(change) if change.old_oid.as_ref() == Some(&change.new_oid) => {
+            println!("Everything up-to-date")
+        }
+        Ok(change) => println!("To {}\n{}", name, change),
+        Err(e) => {
+            eprintln!("dsgit: {}", e);
+            exit(1);
+        }
+    }
+}
 
-    if diffs.0.is_empty() && diffs.1.is_empty() && diffs.2.is_empty() {
-        println!("\nCurrent status is clean.");
-        exit(0);
+fn pull(name: Option<String>, branch: Option<String>, mode: Option<pull::PullMode>) {
+    let ignore_files = read_ignore_file();
+    match pull::pull(name.as_deref(), branch.as_deref(), mode, &ignore_files) {
+        Ok(pull::PullOutcome::UpToDate) => println!("Already up to date."),
+        Ok(pull::PullOutcome::FastForward(oid)) => println!("Fast-forward to {:#}", oid),
+        Ok(pull::PullOutcome::Merged(oid)) => println!("Merge made: {:#}", oid),
+        Ok(pull::PullOutcome::Rebased(oid)) => println!("Successfully rebased onto {:#}", oid),
+        Err(e) => {
+            eprintln!("dsgit: {}", e);
+            exit(1);
+        }
     }
-    println!("\nChanged to be commited:");
-    for m in diffs.0.iter() {
+}
+
+fn serve(listen: Option<String>, path: Option<String>) {
+    let listen = listen.unwrap_or_else(|| String::from("127.0.0.1:8080"));
+    let path = path.unwrap_or_else(|| String::from("."));
+    let result = (|| -> Result<()> {
+        let path = fs::canonicalize(&path)?;
+        let dsgit_dir = remote::remote_dsgit_dir(path.to_str().unwrap())?;
+        let listener = TcpListener::bind(&listen)?;
         println!(
-            "{:ident$}{}:   {:#}",
-            "",
-            "modified".green(),
-            &m.green(),
-            ident = 7
+            "Serving {} on http://{}",
+            path.display(),
+            listener.local_addr()?
         );
+        http::serve(listener, &dsgit_dir)
+    })();
+    if let Err(e) = result {
+        eprintln!("dsgit: {}", e);
+        exit(1);
     }
-    for c in diffs.1.iter() {
-        println!(
-            "{:ident$}{} :   {:#}",
-            "",
-            "created".green(),
-            &c.green(),
-            ident = 7
-        );
+}
+
+// Serve `upload-pack` or `receive-pack` of a repository over the standard
+// input and output.
+fn serve_pack(service: &str, path: &str) {
+    let result = remote::remote_dsgit_dir(path).and_then(|dsgit_dir| {
+        data::set_dsgit_dir(&dsgit_dir);
+        let mut stdin = io::stdin();
+        let mut stdout = io::stdout();
+        match service {
+            "upload-pack" => protocol::serve_upload_pack(&mut stdin, &mut stdout),
+            _ => protocol::serve_receive_pack(&mut stdin, &mut stdout),
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("dsgit: {}", e);
+        exit(1);
+    }
+}
+
+fn bundle(cmd: BundleCommand) {
+    let result = match cmd {
+        BundleCommand::Create((file, revs)) => bundle::Bundle::create(&file, &revs).map(|bundle| {
+            println!(
+                "Created {} with {} references",
+                bundle.path,
+                bundle.refs.len()
+            )
+        }),
+        BundleCommand::Verify(file) => bundle::Bundle::open(&file).and_then(|bundle| {
+            println!("The bundle contains these references:");
+            for (name, oid) in bundle.refs.iter() {
+                println!("{} {}", oid, name);
+            }
+            if bundle.prerequisites.is_empty() {
+                println!("The bundle records a complete history.");
+            } else {
+                println!("The bundle requires these commits:");
+                for oid in bundle.prerequisites.iter() {
+                    println!("{}", oid);
+                }
+            }
+            bundle.verify()?;
+            println!("{} is okay", file);
+            Ok(())
+        }),
+    };
+    if let Err(e) = result {
+        eprintln!("dsgit: {}", e);
+        exit(1);
     }
-    for r in diffs.2.iter() {
+}
+
+fn import_git(path: &str) {
+    match git::import_git(path) {
+        Ok(import) => {
+            for path in import.skipped.iter() {
+                eprintln!("warning: skipped {}", path);
+            }
+            for git_oid in import.truncated.iter() {
+                eprintln!(
+                    "warning: kept only the subject of the message of {}",
+                    git_oid
+                );
+            }
+            for (name, oid) in import.refs.iter() {
+                println!("{} {}", oid, name);
+            }
+            println!("Imported {} references from {}", import.refs.len(), path);
+        }
+        Err(e) => {
+            eprintln!("dsgit: {}", e);
+            exit(1);
+        }
+    }
+}
+
+fn export_git(path: &str) {
+    match git::export_git(path) {
+        Ok(refs) => {
+            for (name, oid) in refs.iter() {
+                println!("{} {}", oid, name);
+            }
+            println!("Exported {} references to {}", refs.len(), path);
+        }
+        Err(e) => {
+            eprintln!("dsgit: {}", e);
+            exit(1);
+        }
+    }
+}
+
+fn fast_export(revs: &[String], import_marks: Option<String>, export_marks: Option<String>) {
+    let result = import_marks
+        .map_or_else(
+            || Ok(fastimport::Marks::default()),
+            |path| fastimport::Marks::read(&path),
+        )
+        .and_then(|mut marks| {
+            let mut out = io::BufWriter::new(io::stdout().lock());
+            fastimport::fast_export(&mut out, revs, &mut marks)?;
+            io::Write::flush(&mut out)?;
+            match export_marks {
+                Some(path) => marks.write(&path),
+                None => Ok(()),
+            }
+        });
+    if let Err(e) = result {
+        eprintln!("dsgit: {}", e);
+        exit(1);
+    }
+}
+
+fn fast_import(import_marks: Option<String>, export_marks: Option<String>, force: bool) {
+    let result = import_marks
+        .map_or_else(
+            || Ok(fastimport::Marks::default()),
+            |path| fastimport::Marks::read(&path),
+        )
+        .and_then(|mut marks| {
+            let imported = fastimport::fast_import(&mut io::stdin().lock(), &mut marks, force)?;
+            if let Some(path) = export_marks {
+                marks.write(&path)?;
+            }
+            Ok(imported)
+        });
+    match result {
+        Ok(imported) => {
+            for (name, oid) in imported.refs.iter() {
+                println!("{} {}", oid, name);
+            }
+            println!(
+                "Imported {} blobs, {} commits and {} tags",
+                imported.blobs, imported.commits, imported.tags
+            );
+        }
+        Err(e) => {
+            eprintln!("dsgit: {}", e);
+            exit(1);
+        }
+    }
+}
+
+fn apply(path: &str, check: bool, reverse: bool) {
+    let result = fs::read_to_string(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|contents| contents.parse::<apply::Patch>())
+        .and_then(|patch| match reverse {
+            true => apply::apply_patch(&patch.reversed(), check),
+            false => apply::apply_patch(&patch, check),
+        });
+    let reports = match result {
+        Ok(reports) => reports,
+        Err(e) => {
+            eprintln!("dsgit: {}", e);
+            exit(1);
+        }
+    };
+    for report in reports.iter() {
+        println!("Checking patch {}...", report.name);
+        for (index, hunk) in report.hunks.iter().enumerate() {
+            match *hunk {
+                apply::HunkOutcome::Applied { line, offset, fuzz } => {
+                    if offset == 0 && fuzz == 0 {
+                        continue;
+                    }
+                    let mut message = format!("Hunk #{} succeeded at {}", index + 1, line);
+                    if fuzz > 0 {
+                        message.push_str(&format!(" with fuzz {}", fuzz));
+                    }
+                    if offset != 0 {
+                        let lines = if offset.abs() == 1 { "line" } else { "lines" };
+                        message.push_str(&format!(" (offset {} {})", offset, lines));
+                    }
+                    println!("{}.", message);
+                }
+                apply::HunkOutcome::Failed { line } => {
+                    println!("Hunk #{} FAILED at {}.", index + 1, line)
+                }
+            }
+        }
+        if check {
+            continue;
+        }
+        match &report.reject_file {
+            Some(reject_file) => println!(
+                "Applied patch {} with rejects, saving to {}",
+                report.name, reject_file
+            ),
+            None => println!("Applied patch {} cleanly.", report.name),
+        }
+    }
+    if reports.iter().any(|report| !report.is_clean()) {
+        exit(1);
+    }
+}
+
+fn format_patch(range: &str, dir: Option<String>) {
+    match mail::format_patch(range, dir.as_deref().unwrap_or(".")) {
+        Ok(paths) => {
+            for path in paths.iter() {
+                println!("{}", path);
+            }
+        }
+        Err(e) => {
+            eprintln!("dsgit: {}", e);
+            exit(1);
+        }
+    }
+}
+
+fn am(path: &str) {
+    let ignore_files = read_ignore_file();
+    match mail::am(path, &ignore_files) {
+        Ok(oids) => {
+            for oid in oids.iter() {
+                let commit = Commit::get_commit(oid).unwrap();
+                println!("Applying: {}", commit.message);
+            }
+        }
+        Err(e) => {
+            eprintln!("dsgit: {}", e);
+            exit(1);
+        }
+    }
+}
+
+fn check_ref_format(name: &str, branch: bool, allow_onelevel: bool) {
+    let result = if branch {
+        reference::check_branch_name(name).map(|_| println!("{}", name))
+    } else {
+        reference::check_ref_format(name, allow_onelevel)
+    };
+    if let Err(e) = result {
+        eprintln!("dsgit: {}", e);
+        exit(1);
+    }
+}
+
+// Describe a tracking status like `origin/main: ahead 1, behind 2`.
+fn format_tracking_status(status: &remote::TrackingStatus) -> String {
+    match (status.ahead, status.behind) {
+        (0, 0) => status.tracking.to_owned(),
+        (ahead, 0) => format!("{}: ahead {}", status.tracking, ahead),
+        (0, behind) => format!("{}: behind {}", status.tracking, behind),
+        (ahead, behind) => format!("{}: ahead {}, behind {}", status.tracking, ahead, behind),
+    }
+}
+
+fn branch(cmd: BranchCommand) {
+    let result = match cmd {
+        BranchCommand::List(verbose) => (|| {
+            let cur_branch = RefValue::get_branch_name()?;
+            if cur_branch.is_none() {
+                let oid = reference::get_head_oid();
+                println!("* (HEAD detached at {})", &oid[..7]);
+            }
+            for branch in reference::get_branches()?.iter() {
+                let mark = if Some(branch) == cur_branch.as_ref() {
+                    "*"
+                } else {
+                    " "
+                };
+                if verbose {
+                    let oid = data::get_oid(&format!("refs/heads/{}", branch))?;
+                    let commit = Commit::get_commit(&oid)?;
+                    let tracking = match remote::tracking_status(branch)? {
+                        Some(status) => format!("[{}] ", format_tracking_status(&status)).blue(),
+                        None => "".normal(),
+                    };
+                    println!(
+                        "{} {} {} {}{}",
+                        mark,
+                        branch,
+                        &oid[..7].yellow(),
+                        tracking,
+                        commit.message
+                    );
+                } else {
+                    println!("{} {}", mark, branch);
+                }
+            }
+            Ok(())
+        })(),
+        BranchCommand::Create((name, oid_or_none, force)) => (|| {
+            let oid = match oid_or_none {
+                Some(name) => data::get_oid(&name)?,
+                None => reference::get_head_oid(),
+            };
+            let exists = RefValue::get_ref(&format!("refs/heads/{}", name), false)?.is_some();
+            if exists && force {
+                reference::move_branch(&name, &oid)
+                    .map(|_| println!("Reset a branch: {} to {}", name, oid))
+            } else if exists {
+                Err(anyhow!("A branch named '{}' already exists.", name))
+            } else {
+                reference::create_branch(&name, &oid)
+                    .map(|_| println!("Created a branch: {} at {}", name, oid))
+            }
+        })(),
+        BranchCommand::Delete((name, force)) => reference::delete_branch(&name, force)
+            .map(|oid| println!("Deleted branch {} (was {}).", name, &oid[..7])),
+        BranchCommand::Rename((old_name, new_name)) => {
+            let old_name = match old_name {
+                Some(old_name) => Ok(old_name),
+                None => RefValue::get_branch_name().and_then(|name| {
+                    name.ok_or_else(|| anyhow!("HEAD is detached, specify a branch to rename."))
+                }),
+            };
+            old_name.and_then(|old_name| reference::rename_branch(&old_name, &new_name))
+        }
+        BranchCommand::SetUpstream((upstream, name)) => {
+            let name = match name {
+                Some(name) => Ok(name),
+                None => RefValue::get_branch_name().and_then(|name| {
+                    name.ok_or_else(|| anyhow!("HEAD is detached, specify a branch to track."))
+                }),
+            };
+            name.and_then(|name| {
+                remote::Upstream::set(&name, &upstream)
+                    .map(|_| println!("Branch '{}' set up to track '{}'.", name, upstream))
+            })
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("dsgit: {}", e);
+        exit(1);
+    }
+}
+
+fn status() {
+    let result = (|| -> Result<()> {
+        let oid = reference::get_head_oid();
+        match RefValue::get_branch_name()? {
+            Some(branch) => {
+                println!("On branch {}", branch);
+                if let Some(status) = remote::tracking_status(&branch)? {
+                    let plural = |n: usize| if n == 1 { "commit" } else { "commits" };
+                    match (status.ahead, status.behind) {
+                        (0, 0) => println!("Your branch is up to date with '{}'.", status.tracking),
+                        (ahead, 0) => println!(
+                            "Your branch is ahead of '{}' by {} {}.",
+                            status.tracking,
+                            ahead,
+                            plural(ahead)
+                        ),
+                        (0, behind) => println!(
+                            "Your branch is behind '{}' by {} {}.",
+                            status.tracking,
+                            behind,
+                            plural(behind)
+                        ),
+                        (ahead, behind) => println!(
+                            "Your branch and '{}' have diverged, ahead {}, behind {}.",
+                            status.tracking, ahead, behind
+                        ),
+                    }
+                }
+            }
+            None => println!("HEAD detached at {}", &oid[10..]),
+        }
+        if RefValue::get_ref(commit::MERGE_HEAD, false)?.is_some() {
+            println!("You have unmerged paths, record the merge with `dsgit commit -m`.");
+        }
+
+        let ignore_files = read_ignore_file();
+        let diff = diff::diff_trees(
+            &Tree::get_head_tree()?,
+            &Tree::get_working_tree(&ignore_files)?,
+        )?;
+
+        if diff.is_empty() {
+            println!("\nCurrent status is clean.");
+            return Ok(());
+        }
+        println!("\nChanged to be commited:");
+        for change in diff.changes.iter() {
+            let kind = format!("{:<8}", change.kind());
+            let path = match change {
+                Change::Renamed {
+                    old, similarity, ..
+                }
+                | Change::Copied {
+                    old, similarity, ..
+                } => format!("{} -> {} ({}%)", old.path, change.path(), similarity),
+                _ => change.path().to_owned(),
+            };
+            let (kind, path) = match change {
+                Change::Removed(_) => (kind.red(), path.red()),
+                _ => (kind.green(), path.green()),
+            };
+            println!("{:ident$}{}:   {:#}", "", kind, path, ident = 7);
+        }
+        Ok(())
+    })();
+    if let Err(e) = result {
+        eprintln!("dsgit: {}", e);
+        exit(1);
+    }
+}
+
+fn cherry_pick(commits: &[String], no_commit: bool) {
+    let ignore_files = read_ignore_file();
+    match Commit::cherry_pick(commits, no_commit, &ignore_files) {
+        Ok(oids) => {
+            for oid in oids.iter() {
+                println!("{:#}", oid);
+            }
+        }
+        Err(e) => {
+            eprintln!("dsgit: {}", e);
+            exit(1);
+        }
+    }
+}
+
+fn revert(commit: &str, no_commit: bool) {
+    let ignore_files = read_ignore_file();
+    match Commit::revert(commit, no_commit, &ignore_files) {
+        Ok(Some(oid)) => println!("{:#}", oid),
+        Ok(None) => (),
+        Err(e) => {
+            eprintln!("dsgit: {}", e);
+            exit(1);
+        }
+    }
+}
+
+fn stash(subcommand: &str, arg: Option<String>) {
+    let ignore_files = read_ignore_file();
+    let index = match subcommand {
+        "push" | "list" => 0,
+        _ => match arg.as_deref() {
+            Some(name) => match stash::parse_stash_index(name) {
+                Ok(index) => index,
+                Err(e) => {
+                    eprintln!("dsgit: {}", e);
+                    exit(1);
+                }
+            },
+            None => 0,
+        },
+    };
+
+    let result = match subcommand {
+        "push" => stash::push(arg.as_deref(), &ignore_files).map(|oid| {
+            println!("Saved working directory: {:#}", oid);
+        }),
+        "list" => stash::list().map(|entries| {
+            for (i, entry) in entries.iter().enumerate() {
+                println!("stash@{{{}}}: {}", i, entry.message);
+            }
+        }),
+        "show" => stash::show(index, &default_diff_options()),
+        "apply" => stash::apply(index, &ignore_files),
+        "pop" => stash::pop(index, &ignore_files).map(|oid| {
+            println!("Dropped stash@{{{}}} ({:#})", index, oid);
+        }),
+        "drop" => stash::drop(index).map(|oid| {
+            println!("Dropped stash@{{{}}} ({:#})", index, oid);
+        }),
+        _ => unreachable!(),
+    };
+    if let Err(e) = result {
+        eprintln!("dsgit: {}", e);
+        exit(1);
+    }
+}
+
+fn reflog(name: Option<String>) {
+    let name = name.unwrap_or_else(|| String::from("HEAD"));
+    let refs = match data::ref_candidates(&name)
+        .iter()
+        .find(|path| reflog::exists(path))
+    {
+        Some(refs) => refs.to_owned(),
+        None => {
+            eprintln!("dsgit: No reflog for reference: {}", name);
+            exit(1);
+        }
+    };
+
+    for (i, entry) in reflog::read(&refs).unwrap().iter().enumerate() {
         println!(
-            "{:ident$}{} :   {:#}",
-            "",
-            "removed".red(),
-            &r.red(),
-            ident = 7
+            "{} {}@{{{}}}: {}",
+            &entry.new_oid[..7].yellow(),
+            name,
+            i,
+            entry.message
         );
     }
 }
 
 fn reset(commit: &str) {
-    reference::reset(commit);
+    let oid = data::get_oid(commit).unwrap();
+    reference::reset(&oid);
 }
 
 fn help() {
@@ -381,6 +1519,8 @@ USAGE:
 COMMANDS:
     --help | -h                   : Show this help.
     init                          : Initialize dsgit, creating `.dsgit` directory.
+    init [--object-encoding=git]  : Initialize dsgit, writing objects with Git's encoding.
+    init [--object-format=sha256] : Initialize dsgit, naming objects by SHA-256 hashes.
     hash-object [FILE NAME]       : Given file, calculate hash object.
     cat-object [OID]              : Given object id, display object's contents.
     read-tree [OID]               : Read a tree objects from specified tree oid.
@@ -388,12 +1528,63 @@ COMMANDS:
     commit [MESSAGE]              : Record changes to the repository.
     switch [COMMIT]               : Switch branch or restore working tree's files.
     tag [TAG NAME] [COMMIT]       : Set a mark to commit hash.
+    tag -a [TAG NAME] -m [MESSAGE] [COMMIT]
+                                  : Create an annotated tag object with a message.
+    tag -l [PATTERN]              : List tags, filtered by a glob pattern if given.
+    tag -d [TAG NAME]             : Delete a tag.
     branch [BRANCH NAME] [COMMIT] : Diverge from the main line of development and \
 continue to do work without messing with that main line.
+    branch -v                     : List branches with the hash, upstream status and subject of each tip commit.
+    branch -f [BRANCH NAME] [COMMIT]
+                                  : Move an existing branch to the commit.
+    branch -d | -D [BRANCH NAME]  : Delete a merged branch, or any branch with `-D`.
+    branch -m [OLD NAME] [NEW NAME]
+                                  : Rename a branch, the current branch if old name is omitted.
+    branch -u [UPSTREAM] [BRANCH NAME]
+                                  : Set a remote-tracking branch like `origin/main` as the upstream.
     status                        : Display a current status of version management.
     reset [COMMIT]                : Reset to HEAD from specified commit hash.
-    show [COMMIT]                 : Display a commit object's contents.
-    diff [COMMIT]                 : Display a difference between working tree and specified commit tree.
+    reflog [REF]                  : Display the update history of a reference, HEAD by default.
+    pack-refs                     : Pack loose references into a single `.dsgit/packed-refs` file.
+    check-ref-format [--branch] [--allow-onelevel] [NAME]
+                                  : Check whether a reference name is acceptable.
+    clone [--bare] [--depth N] [--filter=blob:none] [PATH | URL] [DIRECTORY]
+                                  : Clone a repository or a bundle file into a new directory, URL is one of http://, ssh:// and ext::<COMMAND>. \
+`--depth` takes only the latest N commits, and `--filter=blob:none` fetches file contents only when they are needed.
+    remote [-v]                   : List remotes, with their paths if `-v` is given.
+    remote add [NAME] [PATH]      : Add a remote tracking branches under `refs/remotes/<NAME>/`.
+    remote remove [NAME]          : Remove a remote with its remote-tracking branches.
+    fetch [--unshallow] [REMOTE]  : Download objects and branches of a remote, or of a repository path, `--unshallow` completes a shallow clone.
+    push [-f] [-u] [REMOTE] [BRANCH]
+                                  : Update a branch of a remote, `-u` sets it as the upstream.
+    pull [--rebase | --no-rebase | --ff-only] [REMOTE] [BRANCH]
+                                  : Fetch a branch of a remote and integrate it into the current branch.
+    serve [--listen ADDRESS] [PATH]
+                                  : Serve a repository over HTTP for clone, fetch and push, on 127.0.0.1:8080 by default.
+    upload-pack [PATH]            : Send objects of a repository over standard input and output, run by fetch.
+    receive-pack [PATH]           : Receive objects into a repository over standard input and output, run by push.
+    bundle create [FILE] [REV]... : Write references and their objects into a file, `A..B` or `^A` leaves out history of A, `--all` takes every reference.
+    bundle verify [FILE]          : Check a bundle file can be unbundled into the current repository.
+    import-git [PATH]             : Convert branches and tags of a Git repository with their history into the current repository.
+    export-git [PATH]             : Write branches and tags with their history into a Git repository, created unless it exists.
+    fast-export [--all | REF...] [--import-marks=FILE] [--export-marks=FILE]
+                                  : Write references with their history as a fast-import stream to standard output.
+    fast-import [--force] [--import-marks=FILE] [--export-marks=FILE]
+                                  : Read a fast-import stream from standard input into commits, tags and references.
+    show [--patch] [-U<N>] [COMMIT]
+                                  : Display a commit object's contents.
+    diff [--patch] [-U<N>] [COMMIT]
+                                  : Display a difference between working tree and specified commit tree. \
+`--patch` prints a unified diff with N lines of context, the default when the output is not a terminal.
+    apply [--check] [-R] [PATCH]  : Apply a unified diff to the working tree, writing hunks which do not apply to `<FILE>.rej`. \
+`--check` only reports whether it applies, and `-R` reverses it.
+    format-patch [-o DIRECTORY] [RANGE]
+                                  : Write a mail for each commit in `A..B`, or since a commit up to HEAD, with a diffstat and a unified diff.
+    am [MAILBOX]                  : Apply the patches of mails written by `format-patch` as commits, keeping their authors and subjects.
+    cherry-pick [-n] [COMMIT]...  : Apply the changes introduced by existing commits onto HEAD.
+    revert [-n] [COMMIT]          : Record a new commit reverting the changes of a commit.
+    stash [SUBCOMMAND]            : Stash the changes in a working tree, subcommands are \
+push [-m MESSAGE], list, show, pop, apply and drop.
 "
     );
     exit(0);
@@ -402,7 +1593,7 @@ continue to do work without messing with that main line.
 fn main() {
     match arg_parse().unwrap() {
         Commands::Help => help(),
-        Commands::Init => init(),
+        Commands::Init((encoding, format)) => init(encoding, format),
         Commands::Log(oid) => log(oid),
         Commands::Cat(file) => cat_object(&file),
         Commands::HashObject(file) => hash_object(&file),
@@ -422,26 +1613,44 @@ fn main() {
             let ignore_files = read_ignore_file();
             switch(&commit, ignore_files);
         }
-        Commands::Tag((tag, oid_or_none)) => {
-            let oid = match oid_or_none {
-                Some(oid) => oid,
-                None => RefValue::get_ref("HEAD", true).unwrap().unwrap().value,
-            };
-            create_tag(&tag, &oid);
-        }
-        Commands::Branch(args) => match args {
-            Some((name, oid_or_none)) => {
-                let oid = match oid_or_none {
-                    Some(oid) => oid,
-                    None => RefValue::get_ref("HEAD", true).unwrap().unwrap().value,
-                };
-                branch(Some((&name, &oid)));
-            }
-            None => branch(None),
-        },
+        Commands::Tag(cmd) => tag(cmd),
+        Commands::Branch(cmd) => branch(cmd),
         Commands::Status => status(),
         Commands::Reset(commit) => reset(&commit),
-        Commands::Show(oid) => show(oid),
-        Commands::Diff(oid) => diff(oid),
+        Commands::Show((oid, options)) => show(oid, &options),
+        Commands::Diff((oid, options)) => diff(oid, &options),
+        Commands::CherryPick((commits, no_commit)) => cherry_pick(&commits, no_commit),
+        Commands::Revert((commit, no_commit)) => revert(&commit, no_commit),
+        Commands::Stash((subcommand, arg)) => stash(&subcommand, arg),
+        Commands::Reflog(name) => reflog(name),
+        Commands::PackRefs => {
+            let count = reference::pack_refs().unwrap();
+            println!("Packed {} references into .dsgit/packed-refs", count);
+        }
+        Commands::Clone((path, dir, bare, options)) => clone(&path, dir, bare, options),
+        Commands::Remote(cmd) => remote(cmd),
+        Commands::Fetch((name, unshallow)) => fetch(name, unshallow),
+        Commands::Push((name, branch, force, set_upstream)) => {
+            push(name, branch, force, set_upstream)
+        }
+        Commands::Pull((name, branch, mode)) => pull(name, branch, mode),
+        Commands::Serve((listen, path)) => serve(listen, path),
+        Commands::UploadPack(path) => serve_pack("upload-pack", &path),
+        Commands::ReceivePack(path) => serve_pack("receive-pack", &path),
+        Commands::Bundle(cmd) => bundle(cmd),
+        Commands::ImportGit(path) => import_git(&path),
+        Commands::ExportGit(path) => export_git(&path),
+        Commands::FastExport((revs, import_marks, export_marks)) => {
+            fast_export(&revs, import_marks, export_marks)
+        }
+        Commands::FastImport((import_marks, export_marks, force)) => {
+            fast_import(import_marks, export_marks, force)
+        }
+        Commands::Apply((path, check, reverse)) => apply(&path, check, reverse),
+        Commands::FormatPatch((range, dir)) => format_patch(&range, dir),
+        Commands::Am(path) => am(&path),
+        Commands::CheckRefFormat((name, branch, allow_onelevel)) => {
+            check_ref_format(&name, branch, allow_onelevel)
+        }
     }
 }
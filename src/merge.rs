@@ -0,0 +1,275 @@
+use crate::data::{get_object, TypeObject};
+use crate::diff::{convert_dict, diff_trees, Change};
+use crate::entry::Tree;
+use crate::hash::ObjectId;
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+
+// A replacement of `base[start..end]` lines by `lines`.
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    lines: Vec<&'a str>,
+}
+
+fn collect_hunks<'a>(base: &[&'a str], other: &[&'a str]) -> Vec<Hunk<'a>> {
+    capture_diff_slices(Algorithm::Myers, base, other)
+        .iter()
+        .filter_map(|op| match *op {
+            DiffOp::Equal { .. } => None,
+            DiffOp::Delete {
+                old_index, old_len, ..
+            } => Some(Hunk {
+                start: old_index,
+                end: old_index + old_len,
+                lines: vec![],
+            }),
+            DiffOp::Insert {
+                old_index,
+                new_index,
+                new_len,
+            } => Some(Hunk {
+                start: old_index,
+                end: old_index,
+                lines: other[new_index..new_index + new_len].to_vec(),
+            }),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => Some(Hunk {
+                start: old_index,
+                end: old_index + old_len,
+                lines: other[new_index..new_index + new_len].to_vec(),
+            }),
+        })
+        .collect()
+}
+
+fn apply_hunks<'a>(
+    base: &[&'a str],
+    hunks: &[&Hunk<'a>],
+    start: usize,
+    end: usize,
+) -> Vec<&'a str> {
+    let mut lines = vec![];
+    let mut pos = start;
+    for hunk in hunks.iter() {
+        lines.extend_from_slice(&base[pos..hunk.start]);
+        lines.extend_from_slice(&hunk.lines);
+        pos = hunk.end;
+    }
+    lines.extend_from_slice(&base[pos..end]);
+    lines
+}
+
+fn push_lines(merged: &mut String, lines: &[&str]) {
+    for line in lines.iter() {
+        merged.push_str(line);
+    }
+}
+
+fn push_marker(merged: &mut String, marker: &str) {
+    if !merged.is_empty() && !merged.ends_with('\n') {
+        merged.push('\n');
+    }
+    merged.push_str(marker);
+    merged.push('\n');
+}
+
+/// Merge the changes made from `base` to `ours` and from `base` to `theirs`
+/// line by line. Overlapping changes are written with conflict markers and
+/// the second value of the result tells whether any conflict happened.
+pub fn merge_contents(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    ours_label: &str,
+    theirs_label: &str,
+) -> (String, bool) {
+    let base_lines: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours_lines: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs_lines: Vec<&str> = theirs.split_inclusive('\n').collect();
+
+    let ours_hunks = collect_hunks(&base_lines, &ours_lines);
+    let theirs_hunks = collect_hunks(&base_lines, &theirs_lines);
+
+    let mut merged = String::new();
+    let mut conflict = false;
+    let mut pos = 0;
+    let (mut i, mut j) = (0, 0);
+    while i < ours_hunks.len() || j < theirs_hunks.len() {
+        // Start a group from the hunk which comes first in base, then pull in
+        // every hunk from both sides touching the group.
+        let start = match (ours_hunks.get(i), theirs_hunks.get(j)) {
+            (Some(o), Some(t)) => o.start.min(t.start),
+            (Some(o), None) => o.start,
+            (None, Some(t)) => t.start,
+            (None, None) => break,
+        };
+        let mut end = start;
+        let mut ours_group = vec![];
+        let mut theirs_group = vec![];
+        loop {
+            if let Some(hunk) = ours_hunks.get(i).filter(|h| h.start <= end) {
+                end = end.max(hunk.end);
+                ours_group.push(hunk);
+                i += 1;
+            } else if let Some(hunk) = theirs_hunks.get(j).filter(|h| h.start <= end) {
+                end = end.max(hunk.end);
+                theirs_group.push(hunk);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        push_lines(&mut merged, &base_lines[pos..start]);
+        let ours_part = apply_hunks(&base_lines, &ours_group, start, end);
+        let theirs_part = apply_hunks(&base_lines, &theirs_group, start, end);
+        if theirs_group.is_empty() || ours_part == theirs_part {
+            push_lines(&mut merged, &ours_part);
+        } else if ours_group.is_empty() {
+            push_lines(&mut merged, &theirs_part);
+        } else {
+            conflict = true;
+            push_marker(&mut merged, &format!("<<<<<<< {}", ours_label));
+            push_lines(&mut merged, &ours_part);
+            push_marker(&mut merged, "=======");
+            push_lines(&mut merged, &theirs_part);
+            push_marker(&mut merged, &format!(">>>>>>> {}", theirs_label));
+        }
+        pos = end;
+    }
+    push_lines(&mut merged, &base_lines[pos..]);
+    (merged, conflict)
+}
+
+fn read_blob(oid: Option<&ObjectId>) -> Result<String> {
+    match oid {
+        Some(oid) => get_object(oid, TypeObject::Blob),
+        None => Ok(String::new()),
+    }
+}
+
+pub(crate) fn write_file(path: &str, contents: &str) -> Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+pub(crate) fn remove_file(path: &str) -> Result<()> {
+    fs::remove_file(path)?;
+    // Trees never record empty directories, so clean them up as well.
+    let mut parent = Path::new(path).parent();
+    while let Some(dir) = parent {
+        if dir == Path::new(".") || dir.as_os_str().is_empty() || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+    Ok(())
+}
+
+/// Fail when the working tree has changes which are not committed to HEAD,
+/// the error lists every changed path.
+pub fn ensure_clean_working_tree(operation: &str, ignore_options: &[String]) -> Result<()> {
+    let diff = diff_trees(
+        &Tree::get_head_tree()?,
+        &Tree::get_working_tree(ignore_options)?,
+    )?;
+    if diff.is_empty() {
+        return Ok(());
+    }
+    let mut message = format!(
+        "Your local changes would be overwritten by {}, commit or stash them first:",
+        operation
+    );
+    for change in diff.changes.iter() {
+        let path = match change.old_entry() {
+            Some(old) if old.path != change.path() => format!("{} -> {}", old.path, change.path()),
+            _ => change.path().to_owned(),
+        };
+        message.push_str(&format!("\n    {}: {}", change.kind(), path));
+    }
+    Err(anyhow!(message))
+}
+
+/// Apply the changes between `from` and `to` trees onto the working tree.
+/// Paths changed on both sides are merged with `merge_contents`, and the
+/// paths which could not be merged cleanly are returned.
+pub fn apply_tree_changes(
+    from: Tree,
+    to: Tree,
+    theirs_label: &str,
+    ignore_options: &[String],
+) -> Result<Vec<String>> {
+    let from_dict = convert_dict(&from);
+    let to_dict = convert_dict(&to);
+    let ours_dict = convert_dict(&Tree::get_working_tree(ignore_options)?);
+    // Paths are merged one by one, so a rename is applied as a removal and
+    // a creation, and a copy as a creation.
+    let mut changed = vec![];
+    let mut removed = vec![];
+    for change in diff_trees(&from, &to)?.changes.into_iter() {
+        match change {
+            Change::Removed(old) => removed.push(old.path),
+            Change::Renamed { old, new, .. } => {
+                removed.push(old.path);
+                changed.push(new.path);
+            }
+            change => changed.extend(change.new_entry().map(|new| new.path.to_owned())),
+        }
+    }
+
+    let mut conflicts = vec![];
+    for path in changed.iter() {
+        let base_oid = from_dict.get(path);
+        let their_oid = to_dict.get(path);
+        let our_oid = ours_dict.get(path);
+        if our_oid == their_oid {
+            continue;
+        }
+        if our_oid == base_oid {
+            write_file(path, &read_blob(their_oid)?)?;
+            continue;
+        }
+        if our_oid.is_none() {
+            // Removed on our side but modified on theirs, keep their version.
+            write_file(path, &read_blob(their_oid)?)?;
+            conflicts.push(path.to_owned());
+            continue;
+        }
+
+        let (merged, conflict) = merge_contents(
+            &read_blob(base_oid)?,
+            &read_blob(our_oid)?,
+            &read_blob(their_oid)?,
+            "HEAD",
+            theirs_label,
+        );
+        write_file(path, &merged)?;
+        if conflict {
+            conflicts.push(path.to_owned());
+        }
+    }
+
+    for path in removed.iter() {
+        match ours_dict.get(path) {
+            None => continue,
+            Some(our_oid) if Some(our_oid) == from_dict.get(path) => remove_file(path)?,
+            // Modified on our side but removed on theirs, keep our version.
+            Some(_) => conflicts.push(path.to_owned()),
+        }
+    }
+
+    conflicts.sort();
+    Ok(conflicts)
+}
@@ -0,0 +1,99 @@
+use crate::data::{dsgit_dir, object_exists};
+use crate::hash::ObjectId;
+
+use std::fs;
+use std::io::{BufRead, Read, Write};
+
+use anyhow::{anyhow, Context, Result};
+
+// The first line of a pack.
+const PACK_SIGNATURE: &str = "DSGITPACK";
+
+/// Write objects of the current repository as a pack, which carries objects
+/// between repositories as a single stream:
+///   DSGITPACK <number of objects>
+///   <oid> <size>
+///   <object file of size bytes>
+///   ...
+/// Objects are written in the given order, so that referenced objects given
+/// first are also stored first on the other side.
+pub fn write_pack(out: &mut impl Write, oids: &[ObjectId]) -> Result<()> {
+    writeln!(out, "{} {}", PACK_SIGNATURE, oids.len())?;
+    for oid in oids.iter() {
+        let path = format!("{}/objects/{}", dsgit_dir(), oid);
+        let object = fs::read(&path).with_context(|| format!("Failed to read object: {}", oid))?;
+        writeln!(out, "{} {}", oid, object.len())?;
+        out.write_all(&object)?;
+    }
+    Ok(())
+}
+
+fn read_line(input: &mut impl BufRead) -> Result<String> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Err(anyhow!("A pack ended unexpectedly."));
+    }
+    Ok(line.trim_end_matches('\n').to_owned())
+}
+
+// Read every object of a pack checked against its hash, and pass it to `f`.
+fn for_each_object(
+    input: &mut impl BufRead,
+    mut f: impl FnMut(&str, &[u8]) -> Result<()>,
+) -> Result<usize> {
+    let header = read_line(input)?;
+    let count = header
+        .strip_prefix(PACK_SIGNATURE)
+        .and_then(|count| count.trim().parse::<usize>().ok())
+        .ok_or_else(|| anyhow!("Not a pack: {}", header))?;
+
+    for _ in 0..count {
+        let line = read_line(input)?;
+        let (oid, size) = line
+            .split_once(' ')
+            .and_then(|(oid, size)| Some((oid, size.parse::<usize>().ok()?)))
+            .ok_or_else(|| anyhow!("Broken pack entry: {}", line))?;
+        // The size is not trusted before the object is read.
+        let mut object = vec![];
+        input.by_ref().take(size as u64).read_to_end(&mut object)?;
+        if object.len() != size {
+            return Err(anyhow!("A pack ended in the middle of object: {}", oid));
+        }
+
+        // An object id tells the hash algorithm it is made by.
+        let expected = ObjectId::parse(oid)?;
+        if expected.algorithm().hash(&object) != expected {
+            return Err(anyhow!(
+                "An object in a pack does not match its hash: {}",
+                oid
+            ));
+        }
+        f(oid, &object)?;
+    }
+    Ok(count)
+}
+
+/// Store objects of a pack into the current repository, and return the
+/// number of objects which were missing. Every object is checked against
+/// its hash before being written.
+pub fn read_pack(input: &mut impl BufRead) -> Result<usize> {
+    let mut written = 0;
+    for_each_object(input, |oid, object| {
+        if object_exists(oid) {
+            return Ok(());
+        }
+        let path = format!("{}/objects/{}", dsgit_dir(), oid);
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, object).with_context(|| format!("Failed to write object: {}", oid))?;
+        fs::rename(&tmp, &path).with_context(|| format!("Failed to write object: {}", oid))?;
+        written += 1;
+        Ok(())
+    })?;
+    Ok(written)
+}
+
+/// Check every object of a pack against its hash without storing them, and
+/// return the number of objects.
+pub fn verify_pack(input: &mut impl BufRead) -> Result<usize> {
+    for_each_object(input, |_, _| Ok(()))
+}
@@ -0,0 +1,106 @@
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, Context, Result};
+
+// The largest payload of a packet, which keeps the length in 4 hex digits.
+const MAX_PAYLOAD: usize = 65516;
+
+/// Write a packet, which is the payload prefixed by its length plus 4 in
+/// 4 hex digits, like `000bhello` followed by a newline.
+pub fn write_pkt(out: &mut (impl Write + ?Sized), payload: &[u8]) -> Result<()> {
+    if payload.len() > MAX_PAYLOAD {
+        return Err(anyhow!("A packet is too long: {} bytes", payload.len()));
+    }
+    write!(out, "{:04x}", payload.len() + 4)?;
+    out.write_all(payload)?;
+    Ok(())
+}
+
+/// Write a flush packet `0000`, which ends a section of packets.
+pub fn write_flush(out: &mut (impl Write + ?Sized)) -> Result<()> {
+    out.write_all(b"0000")?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Write each line as a packet with a trailing newline, then a flush packet.
+pub fn write_lines(out: &mut (impl Write + ?Sized), lines: &[String]) -> Result<()> {
+    for line in lines.iter() {
+        write_pkt(out, format!("{}\n", line).as_bytes())?;
+    }
+    write_flush(out)
+}
+
+/// Write binary data split into packets, then a flush packet.
+pub fn write_data(out: &mut (impl Write + ?Sized), data: &[u8]) -> Result<()> {
+    for chunk in data.chunks(MAX_PAYLOAD) {
+        write_pkt(out, chunk)?;
+    }
+    write_flush(out)
+}
+
+/// Write an error for the other side, which is reported when it reads the
+/// packet as a line or as the beginning of data.
+pub fn write_error(out: &mut (impl Write + ?Sized), message: &str) -> Result<()> {
+    write_pkt(
+        out,
+        format!("ERR {}", message.replace('\n', " ")).as_bytes(),
+    )?;
+    write_flush(out)
+}
+
+/// Read a packet, `None` for a flush packet.
+pub fn read_pkt(input: &mut (impl Read + ?Sized)) -> Result<Option<Vec<u8>>> {
+    let mut length = [0u8; 4];
+    input
+        .read_exact(&mut length)
+        .context("The other side hung up unexpectedly.")?;
+    let length = std::str::from_utf8(&length)
+        .ok()
+        .and_then(|length| usize::from_str_radix(length, 16).ok())
+        .ok_or_else(|| anyhow!("Broken packet length: {:?}", length))?;
+    match length {
+        0 => Ok(None),
+        1..=3 => Err(anyhow!("Broken packet length: {}", length)),
+        _ => {
+            let mut payload = vec![0u8; length - 4];
+            input
+                .read_exact(&mut payload)
+                .context("The other side hung up in the middle of a packet.")?;
+            Ok(Some(payload))
+        }
+    }
+}
+
+fn check_error(payload: &[u8]) -> Result<()> {
+    match payload.strip_prefix(b"ERR ") {
+        Some(message) => Err(anyhow!(
+            "The remote failed: {}",
+            String::from_utf8_lossy(message)
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Read packets as lines until a flush packet, without trailing newlines.
+pub fn read_lines(input: &mut (impl Read + ?Sized)) -> Result<Vec<String>> {
+    let mut lines = vec![];
+    while let Some(payload) = read_pkt(input)? {
+        check_error(&payload)?;
+        let line = String::from_utf8(payload)?;
+        lines.push(line.trim_end_matches('\n').to_owned());
+    }
+    Ok(lines)
+}
+
+/// Read packets as binary data until a flush packet.
+pub fn read_data(input: &mut (impl Read + ?Sized)) -> Result<Vec<u8>> {
+    let mut data = vec![];
+    while let Some(payload) = read_pkt(input)? {
+        if data.is_empty() {
+            check_error(&payload)?;
+        }
+        data.extend_from_slice(&payload);
+    }
+    Ok(data)
+}
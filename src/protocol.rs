@@ -0,0 +1,450 @@
+use crate::data::{dsgit_dir, object_exists, RepositoryFormat};
+use crate::hash::{is_zero_oid, HashAlgorithm, ObjectId};
+use crate::pack::{read_pack, write_pack};
+use crate::pktline;
+use crate::reference::{check_ref_format, get_branches, RefValue};
+use crate::remote::{get_ref_oid, is_bare, reachable_objects_within};
+use crate::shallow;
+use crate::tag::list_tags;
+use crate::transaction::RefTransaction;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{BufRead, Read, Write};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+/// References which a repository offers to others, written one per line:
+///   <oid> HEAD [<branch HEAD points to>]
+///   format <hash algorithm> <object encoding>
+///   <oid> refs/heads/main
+///   <oid> refs/tags/v1
+/// HEAD of an empty repository is written with the zero hash. The format
+/// line is left out for the default format.
+#[derive(Debug, Default, PartialEq)]
+pub struct RefAdvertisement {
+    // The branch HEAD points to, like `refs/heads/main`, `None` if detached.
+    pub head_branch: Option<String>,
+    pub head_oid: Option<String>,
+    // Branches followed by tags, with the hashes they point to.
+    pub refs: Vec<(String, String)>,
+    // The format of objects, which a pack carries as they are stored.
+    pub format: RepositoryFormat,
+}
+
+impl RefAdvertisement {
+    /// Advertise branches and tags of the current repository.
+    pub fn current() -> Result<Self> {
+        let mut refs = vec![];
+        for branch in get_branches()?.iter() {
+            let name = format!("refs/heads/{}", branch);
+            if let Some(oid) = get_ref_oid(&name)? {
+                refs.push((name, oid));
+            }
+        }
+        for tag in list_tags(None)?.iter() {
+            let name = format!("refs/tags/{}", tag);
+            if let Some(oid) = get_ref_oid(&name)? {
+                refs.push((name, oid));
+            }
+        }
+        Ok(RefAdvertisement {
+            head_branch: RefValue::get_branch_name()?
+                .map(|branch| format!("refs/heads/{}", branch)),
+            head_oid: get_ref_oid("HEAD")?,
+            refs,
+            format: RepositoryFormat::read()?,
+        })
+    }
+
+    /// The hash of an advertised reference given by its full name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.refs
+            .iter()
+            .find(|(ref_name, _)| ref_name == name)
+            .map(|(_, oid)| oid.as_str())
+    }
+
+    pub fn branches(&self) -> impl Iterator<Item = &(String, String)> {
+        self.refs
+            .iter()
+            .filter(|(name, _)| name.starts_with("refs/heads/"))
+    }
+
+    pub fn tags(&self) -> impl Iterator<Item = &(String, String)> {
+        self.refs
+            .iter()
+            .filter(|(name, _)| name.starts_with("refs/tags/"))
+    }
+
+    /// Hashes of every advertised reference.
+    pub fn oids(&self) -> Vec<String> {
+        self.refs.iter().map(|(_, oid)| oid.to_owned()).collect()
+    }
+}
+
+impl fmt::Display for RefAdvertisement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let head_oid = match &self.head_oid {
+            Some(head_oid) => head_oid.to_owned(),
+            None => self.format.hash.zero_oid().into(),
+        };
+        match &self.head_branch {
+            Some(branch) => writeln!(f, "{} HEAD {}", head_oid, branch)?,
+            None => writeln!(f, "{} HEAD", head_oid)?,
+        }
+        if self.format != RepositoryFormat::default() {
+            writeln!(f, "format {} {}", self.format.hash, self.format.encoding)?;
+        }
+        for (name, oid) in self.refs.iter() {
+            writeln!(f, "{} {}", oid, name)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RefAdvertisement {
+    type Err = anyhow::Error;
+
+    fn from_str(contents: &str) -> Result<Self> {
+        let mut advertisement = RefAdvertisement::default();
+        for line in contents.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields[..] {
+                ["format", hash, encoding] => {
+                    advertisement.format = RepositoryFormat {
+                        encoding: encoding.parse()?,
+                        hash: hash.parse()?,
+                    }
+                }
+                [oid, "HEAD"] | [oid, "HEAD", _] => {
+                    advertisement.head_oid = Some(oid.to_owned()).filter(|oid| !is_zero_oid(oid));
+                    advertisement.head_branch = fields.get(2).map(|branch| branch.to_string());
+                }
+                [oid, name] => advertisement.refs.push((name.to_owned(), oid.to_owned())),
+                _ => return Err(anyhow!("Broken reference advertisement: {}", line)),
+            }
+        }
+        Ok(advertisement)
+    }
+}
+
+/// Objects left out of a fetch by a partial clone, which are fetched from
+/// the remote later when needed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectFilter {
+    // Every blob except ones wanted explicitly, written as `blob:none`.
+    BlobNone,
+}
+
+impl fmt::Display for ObjectFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectFilter::BlobNone => write!(f, "blob:none"),
+        }
+    }
+}
+
+impl FromStr for ObjectFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(filter: &str) -> Result<Self> {
+        match filter {
+            "blob:none" => Ok(ObjectFilter::BlobNone),
+            _ => Err(anyhow!("Unknown object filter: {}", filter)),
+        }
+    }
+}
+
+/// A request of objects from a remote, written one per line:
+///   want <oid>      an object to be sent with objects reachable from it
+///   have <oid>      a commit the requester already has
+///   shallow <oid>   a commit of the requester whose parents are missing
+///   deepen <depth>  send commits only up to `depth` commits from each want
+///   filter <filter> leave out objects reachable from wants by the filter
+#[derive(Debug, Default, PartialEq)]
+pub struct FetchRequest {
+    pub wants: Vec<String>,
+    pub haves: Vec<String>,
+    pub shallows: Vec<String>,
+    pub depth: Option<usize>,
+    pub filter: Option<ObjectFilter>,
+}
+
+impl FetchRequest {
+    /// Objects reachable from `wants` in the current repository, except the
+    /// ones reachable from `haves` which the requester already has. Hashes
+    /// in `haves` unknown to the current repository are ignored, and the
+    /// requester is assumed to lack parents of its shallow commits.
+    pub fn objects(&self) -> Result<Vec<ObjectId>> {
+        let common: Vec<String> = self
+            .haves
+            .iter()
+            .filter(|oid| object_exists(oid))
+            .cloned()
+            .collect();
+        let known: HashSet<ObjectId> = reachable_objects_within(
+            &common,
+            None,
+            |oid| self.shallows.iter().any(|s| s == oid),
+            |_| false,
+        )?
+        .into_iter()
+        .collect();
+        let boundary = match self.depth {
+            Some(depth) => shallow::boundary(&self.wants, depth)?,
+            None => HashSet::new(),
+        };
+        reachable_objects_within(
+            &self.wants,
+            self.filter,
+            |oid| boundary.contains(oid),
+            |oid| known.contains(oid),
+        )
+    }
+}
+
+impl fmt::Display for FetchRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for oid in self.wants.iter() {
+            writeln!(f, "want {}", oid)?;
+        }
+        for oid in self.haves.iter() {
+            writeln!(f, "have {}", oid)?;
+        }
+        for oid in self.shallows.iter() {
+            writeln!(f, "shallow {}", oid)?;
+        }
+        if let Some(depth) = self.depth {
+            writeln!(f, "deepen {}", depth)?;
+        }
+        if let Some(filter) = self.filter {
+            writeln!(f, "filter {}", filter)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for FetchRequest {
+    type Err = anyhow::Error;
+
+    fn from_str(contents: &str) -> Result<Self> {
+        let mut request = FetchRequest::default();
+        for line in contents.lines() {
+            match line.split_once(' ') {
+                Some(("want", oid)) => request.wants.push(oid.to_owned()),
+                Some(("have", oid)) => request.haves.push(oid.to_owned()),
+                Some(("shallow", oid)) => request.shallows.push(oid.to_owned()),
+                Some(("deepen", depth)) => match depth.parse::<usize>() {
+                    Ok(depth) if depth > 0 => request.depth = Some(depth),
+                    _ => return Err(anyhow!("Broken depth of a request: {}", depth)),
+                },
+                Some(("filter", filter)) => request.filter = Some(filter.parse()?),
+                _ if line == "done" => break,
+                _ => return Err(anyhow!("Broken upload-pack request: {}", line)),
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// Objects reachable from `wants` in the current repository, except the
+/// ones reachable from `haves` which the other side already has. Hashes in
+/// `haves` unknown to the current repository are ignored.
+pub fn objects_to_send(wants: &[String], haves: &[String]) -> Result<Vec<ObjectId>> {
+    FetchRequest {
+        wants: wants.to_vec(),
+        haves: haves.to_vec(),
+        ..Default::default()
+    }
+    .objects()
+}
+
+/// Answer a request of objects from the current repository with a pack.
+/// The request is a `FetchRequest`, optionally ended by `done`.
+pub fn upload_pack(request: &mut impl BufRead, out: &mut impl Write) -> Result<()> {
+    let mut contents = String::new();
+    request.read_to_string(&mut contents)?;
+    let request: FetchRequest = contents.parse()?;
+    for oid in request.wants.iter() {
+        if !object_exists(oid) {
+            return Err(anyhow!("Not our object: {}", oid));
+        }
+    }
+    write_pack(out, &request.objects()?)
+}
+
+/// An update of a reference requested by a push, `old_oid` is the value the
+/// pusher expects the reference to have.
+#[derive(Debug, PartialEq)]
+pub struct RefUpdate {
+    pub name: String,
+    pub old_oid: Option<String>,
+    pub new_oid: String,
+}
+
+impl fmt::Display for RefUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let old_oid = match &self.old_oid {
+            Some(old_oid) => old_oid.to_owned(),
+            None => HashAlgorithm::of(&self.new_oid).zero_oid().into(),
+        };
+        write!(f, "{} {} {}", old_oid, self.new_oid, self.name)
+    }
+}
+
+impl FromStr for RefUpdate {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        match line.split(' ').collect::<Vec<&str>>()[..] {
+            [old_oid, new_oid, name] => Ok(RefUpdate {
+                name: name.to_owned(),
+                old_oid: Some(old_oid.to_owned()).filter(|oid| !is_zero_oid(oid)),
+                new_oid: new_oid.to_owned(),
+            }),
+            _ => Err(anyhow!("Broken reference update: {}", line)),
+        }
+    }
+}
+
+/// Apply pushed updates to the current repository in a single transaction.
+/// The branch checked out in a working tree is refused, since its files
+/// would no longer match the branch. Updates to the current value are
+/// accepted without being recorded.
+pub fn update_refs(updates: &[RefUpdate]) -> Result<()> {
+    let checked_out = RefValue::get_branch_name()?.map(|branch| format!("refs/heads/{}", branch));
+    let mut transaction = RefTransaction::new();
+    for update in updates.iter() {
+        // Pseudo-references like `HEAD` are not written by a push.
+        if !update.name.starts_with("refs/") {
+            return Err(anyhow!(
+                "A pushed reference must be under refs/: {}",
+                update.name
+            ));
+        }
+        check_ref_format(&update.name, false)?;
+        if !is_bare(&dsgit_dir()) && checked_out.as_ref() == Some(&update.name) {
+            return Err(anyhow!(
+                "Refusing to update the branch checked out in a working tree: {}",
+                update.name.trim_start_matches("refs/heads/")
+            ));
+        }
+        if !object_exists(&update.new_oid) {
+            return Err(anyhow!("A pushed object is missing: {}", update.new_oid));
+        }
+        if update.old_oid.as_ref() == Some(&update.new_oid) {
+            continue;
+        }
+        let ref_value = RefValue::new(Some(&update.new_oid), false, &update.new_oid);
+        let expected_old = match &update.old_oid {
+            Some(old_oid) => old_oid.to_owned(),
+            None => HashAlgorithm::of(&update.new_oid).zero_oid().into(),
+        };
+        transaction.update(&update.name, ref_value, false, Some(&expected_old), "push");
+    }
+    transaction.commit()
+}
+
+/// Receive a push into the current repository. The request lists updates
+/// as `<old oid> <new oid> <ref>` followed by an empty line and a pack, and
+/// the reply has `ok <ref>` or `ng <ref> <reason>` for each update.
+pub fn receive_pack(request: &mut impl BufRead, out: &mut impl Write) -> Result<()> {
+    let mut updates = vec![];
+    loop {
+        let mut line = String::new();
+        if request.read_line(&mut line)? == 0 {
+            return Err(anyhow!("A receive-pack request ended unexpectedly."));
+        }
+        let line = line.trim_end_matches('\n');
+        if line.is_empty() {
+            break;
+        }
+        updates.push(line.parse::<RefUpdate>()?);
+    }
+    let result = read_pack(request).and_then(|_| update_refs(&updates));
+    for update in updates.iter() {
+        match &result {
+            Ok(()) => writeln!(out, "ok {}", update.name)?,
+            Err(e) => writeln!(
+                out,
+                "ng {} {}",
+                update.name,
+                e.to_string().replace('\n', " ")
+            )?,
+        }
+    }
+    Ok(())
+}
+
+/// Check a reply of `receive_pack`, failing with the first rejected update.
+pub fn check_report(report: &str) -> Result<()> {
+    for line in report.lines() {
+        match line.split_once(' ') {
+            Some(("ok", _)) => (),
+            Some(("ng", rest)) => {
+                let (name, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+                return Err(anyhow!("The remote rejected {}: {}", name, reason));
+            }
+            _ => return Err(anyhow!("Broken receive-pack report: {}", line)),
+        }
+    }
+    Ok(())
+}
+
+// Send the advertisement of the current repository as packets.
+fn write_advertisement(out: &mut impl Write) -> Result<()> {
+    let advertisement = RefAdvertisement::current()?.to_string();
+    let lines: Vec<String> = advertisement.lines().map(|l| l.to_owned()).collect();
+    pktline::write_lines(out, &lines)
+}
+
+/// Serve a fetch from the current repository over a pipe, like the standard
+/// input and output of `dsgit upload-pack`. The references are advertised
+/// first, then a request of `want` and `have` lines is answered with a pack.
+/// An empty request only lists references.
+pub fn serve_upload_pack(input: &mut impl Read, out: &mut impl Write) -> Result<()> {
+    write_advertisement(out)?;
+    let request = pktline::read_lines(input)?;
+    if request.is_empty() {
+        return Ok(());
+    }
+    let mut pack = vec![];
+    let request = request.join("\n") + "\n";
+    match upload_pack(&mut request.as_bytes(), &mut pack) {
+        Ok(()) => pktline::write_data(out, &pack),
+        Err(e) => {
+            pktline::write_error(out, &e.to_string())?;
+            Err(e)
+        }
+    }
+}
+
+/// Serve a push into the current repository over a pipe, like the standard
+/// input and output of `dsgit receive-pack`. The references are advertised
+/// first, then updates and a pack are received, and a report of the updates
+/// is sent back. An empty list of updates only lists references.
+pub fn serve_receive_pack(input: &mut impl Read, out: &mut impl Write) -> Result<()> {
+    write_advertisement(out)?;
+    let updates = pktline::read_lines(input)?;
+    if updates.is_empty() {
+        return Ok(());
+    }
+    let mut request = (updates.join("\n") + "\n\n").into_bytes();
+    request.extend(pktline::read_data(input)?);
+    let mut report = vec![];
+    match receive_pack(&mut request.as_slice(), &mut report) {
+        Ok(()) => {
+            let lines: Vec<String> = String::from_utf8(report)?
+                .lines()
+                .map(|l| l.to_owned())
+                .collect();
+            pktline::write_lines(out, &lines)
+        }
+        Err(e) => {
+            pktline::write_error(out, &e.to_string())?;
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use crate::commit::{Commit, MERGE_HEAD};
+use crate::config::Config;
+use crate::entry::Tree;
+use crate::hash::ObjectId;
+use crate::merge::{apply_tree_changes, ensure_clean_working_tree};
+use crate::reference::RefValue;
+use crate::remote::{self, get_ref_oid, Remote, Upstream};
+use crate::transport::Transport;
+
+use anyhow::{anyhow, Result};
+
+/// How a pull integrates the remote branch when it is not a fast-forward.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PullMode {
+    // Refuse anything but a fast-forward.
+    FastForwardOnly,
+    // Record a merge commit of the local and the remote branches.
+    Merge,
+    // Replay the local commits on top of the remote branch.
+    Rebase,
+}
+
+impl PullMode {
+    /// The mode configured for `branch`, looking up `branch.<name>.rebase`,
+    /// `pull.rebase` and `pull.ff = only` in order. A merge by default.
+    pub fn configured(branch: &str) -> Result<Self> {
+        let config = Config::read()?;
+        let rebase = match config.get(&format!("branch.{}.rebase", branch))? {
+            Some(rebase) => Some(rebase),
+            None => config.get("pull.rebase")?,
+        };
+        match rebase.as_deref() {
+            Some("true") => return Ok(PullMode::Rebase),
+            Some("false") | None => (),
+            Some(value) => return Err(anyhow!("Invalid value of rebase: {}", value)),
+        }
+        match config.get("pull.ff")?.as_deref() {
+            Some("only") => Ok(PullMode::FastForwardOnly),
+            _ => Ok(PullMode::Merge),
+        }
+    }
+}
+
+/// The result of a pull, with the new commit of HEAD if it moved.
+#[derive(Debug, PartialEq)]
+pub enum PullOutcome {
+    UpToDate,
+    FastForward(ObjectId),
+    Merged(ObjectId),
+    Rebased(ObjectId),
+}
+
+// Choose the remote and its branch to pull. They default to the upstream of
+// the current branch, or to `origin` and the branch of the same name.
+fn resolve_target(
+    branch: &str,
+    remote: Option<&str>,
+    remote_branch: Option<&str>,
+) -> Result<(Remote, String)> {
+    let upstream = Upstream::get(branch)?;
+    let name = match (remote, &upstream) {
+        (Some(name), _) => name.to_owned(),
+        (None, Some(upstream)) => upstream.remote.to_owned(),
+        (None, None) => String::from("origin"),
+    };
+    let merge = match (remote_branch, &upstream) {
+        (Some(remote_branch), _) if remote_branch.starts_with("refs/") => remote_branch.to_owned(),
+        (Some(remote_branch), _) => format!("refs/heads/{}", remote_branch),
+        (None, Some(upstream)) if upstream.remote == name => upstream.merge.to_owned(),
+        (None, _) => format!("refs/heads/{}", branch),
+    };
+    Ok((Remote::resolve(&name)?, merge))
+}
+
+// Record the merge of `theirs` into HEAD, stopping with `MERGE_HEAD` written
+// when some paths conflict.
+fn merge(ours: &str, theirs: &str, message: &str, ignore_options: &[String]) -> Result<ObjectId> {
+    let base = Commit::merge_base(ours, theirs)?
+        .ok_or_else(|| anyhow!("Refusing to merge unrelated histories."))?;
+    let conflicts = apply_tree_changes(
+        Tree::get_commit_tree(&base)?,
+        Tree::get_commit_tree(theirs)?,
+        &theirs[..7],
+        ignore_options,
+    )?;
+    if !conflicts.is_empty() {
+        RefValue::write_ref(MERGE_HEAD, &RefValue::new(None, false, theirs))?;
+        return Err(anyhow!(
+            "Automatic merge failed.\nConflicts in: {}\n\
+             Resolve them and record the result with `dsgit commit -m`.",
+            conflicts.join(", ")
+        ));
+    }
+
+    let tree = Tree::write_tree(".", ignore_options)?;
+    let oid = Commit::create_merge(&tree, Some(ours), Some(theirs), message)?;
+    let ref_value = RefValue::new(Some(&oid), false, &oid);
+    RefValue::update_ref("HEAD", &ref_value, true, &format!("pull: {}", message))?;
+    Ok(oid)
+}
+
+// Replay the commits from their merge base to `ours` following first
+// parents on top of `theirs`, then move HEAD to the last of them at once. On
+// a conflict the working tree is restored to `ours`, and the branch is left
+// as it was.
+fn rebase(ours: &str, theirs: &str, ignore_options: &[String]) -> Result<ObjectId> {
+    let base = Commit::merge_base(ours, theirs)?
+        .ok_or_else(|| anyhow!("Refusing to rebase onto unrelated histories."))?;
+    let mut commits = vec![];
+    let mut oid = ObjectId::parse(ours)?;
+    while oid != base {
+        let commit = Commit::get_commit(&oid)?;
+        commits.push(oid);
+        oid = commit
+            .parent
+            .ok_or_else(|| anyhow!("A merge base is not found on the first parents."))?;
+    }
+    commits.reverse();
+
+    Tree::read_tree(&Commit::get_commit(theirs)?.tree, ignore_options)?;
+    let mut onto = ObjectId::parse(theirs)?;
+    for oid in commits.iter() {
+        let commit = Commit::get_commit(oid)?;
+        let parent = commit.parent.as_deref().unwrap_or_default();
+        let label = format!("{} ({})", &oid[..7], commit.message);
+        let conflicts = apply_tree_changes(
+            Tree::get_commit_tree(parent)?,
+            Tree::get_commit_tree(oid)?,
+            &label,
+            ignore_options,
+        )?;
+        if !conflicts.is_empty() {
+            Tree::read_tree(&Commit::get_commit(ours)?.tree, ignore_options)?;
+            return Err(anyhow!(
+                "pull --rebase stopped, could not apply {}... {}\nConflicts in: {}\n\
+                 The branch is left as it was, pull with `--no-rebase` to merge instead.",
+                &oid[..7],
+                commit.message,
+                conflicts.join(", ")
+            ));
+        }
+        let tree = Tree::write_tree(".", ignore_options)?;
+        onto = match &commit.author {
+            Some(author) => Commit::create_authored(&tree, Some(&onto), author, &commit.message)?,
+            None => Commit::create(&tree, Some(&onto), &commit.message)?,
+        };
+    }
+
+    let ref_value = RefValue::new(Some(&onto), false, &onto);
+    let message = format!("pull --rebase: onto {}", theirs);
+    RefValue::update_ref("HEAD", &ref_value, true, &message)?;
+    Ok(onto)
+}
+
+/// Fetch a remote and integrate its branch into the current branch. The
+/// remote and the branch default to the upstream of the current branch, and
+/// `mode` defaults to the one configured for the branch. The working tree
+/// must not have changes against HEAD.
+pub fn pull(
+    remote: Option<&str>,
+    remote_branch: Option<&str>,
+    mode: Option<PullMode>,
+    ignore_options: &[String],
+) -> Result<PullOutcome> {
+    let branch = RefValue::get_branch_name()?
+        .ok_or_else(|| anyhow!("HEAD is detached, switch to a branch to pull into."))?;
+    let ours = get_ref_oid("HEAD")?
+        .ok_or_else(|| anyhow!("The branch '{}' has no commits yet.", branch))?;
+    if RefValue::get_ref(MERGE_HEAD, false)?.is_some() {
+        return Err(anyhow!(
+            "A merge is not concluded, record the result with `dsgit commit -m` first."
+        ));
+    }
+    ensure_clean_working_tree("pull", ignore_options)?;
+    let mode = match mode {
+        Some(mode) => mode,
+        None => PullMode::configured(&branch)?,
+    };
+
+    let (remote, merge_ref) = resolve_target(&branch, remote, remote_branch)?;
+    remote::fetch(&remote)?;
+    let theirs = Transport::open(&remote.url)?
+        .list_refs()?
+        .get(&merge_ref)
+        .map(|oid| oid.to_owned())
+        .ok_or_else(|| {
+            anyhow!(
+                "A branch '{}' is not found in '{}'.",
+                merge_ref.trim_start_matches("refs/heads/"),
+                remote.url
+            )
+        })?;
+
+    if Commit::is_ancestor(&theirs, &ours)? {
+        return Ok(PullOutcome::UpToDate);
+    }
+    if Commit::is_ancestor(&ours, &theirs)? {
+        Tree::read_tree(&Commit::get_commit(&theirs)?.tree, ignore_options)?;
+        let ref_value = RefValue::new(Some(&theirs), false, &theirs);
+        RefValue::update_ref("HEAD", &ref_value, true, "pull: Fast-forward")?;
+        return Ok(PullOutcome::FastForward(ObjectId::parse(&theirs)?));
+    }
+
+    match mode {
+        PullMode::FastForwardOnly => Err(anyhow!(
+            "Not possible to fast-forward, the branch '{}' and '{}' have diverged. \
+             Pull with `--rebase` or `--no-rebase`.",
+            branch,
+            remote.url
+        )),
+        PullMode::Merge => {
+            let message = format!(
+                "Merge branch '{}' of {}",
+                merge_ref.trim_start_matches("refs/heads/"),
+                remote.url
+            );
+            merge(&ours, &theirs, &message, ignore_options).map(PullOutcome::Merged)
+        }
+        PullMode::Rebase => rebase(&ours, &theirs, ignore_options).map(PullOutcome::Rebased),
+    }
+}
@@ -1,16 +1,25 @@
 use crate::commit::Commit;
-use crate::data::get_oid;
+use crate::config::Config;
+use crate::data::{dsgit_dir, get_oid, peel_oid, FileCache};
 use crate::entry::Tree;
+use crate::hash::{HashAlgorithm, ObjectId};
+use crate::reflog;
+use crate::transaction::{remove_empty_dirs, LockFile, RefTransaction};
 use anyhow::{anyhow, Context, Result};
-use std::fs::{create_dir_all, OpenOptions};
-use std::io::{Read, Write};
-use std::path::Path;
+use std::fs::{self, OpenOptions};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::str;
 use walkdir::WalkDir;
 
-const DSGIT_DIR: &str = ".dsgit";
+thread_local! {
+    // Packed references of repositories by the paths of their packed-refs
+    // files.
+    static PACKED_REFS: FileCache<Vec<PackedRef>> = FileCache::new();
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RefValue {
     pub ref_oid: Option<String>,
     pub symbolic: bool,
@@ -26,42 +35,62 @@ impl RefValue {
         }
     }
 
-    pub fn update_ref<'a>(refs: &'a str, ref_value: &'a RefValue, deref: bool) -> Result<String> {
-        let refs = match RefValue::get_ref_internal(refs, deref)? {
-            Some(ref_value) => ref_value.ref_oid.unwrap(),
-            // At first commit case, this returns None.
-            None => refs.to_owned(),
-        };
+    // Follow symbolic references from `refs` and return the name of the
+    // reference to be written, even if the target does not exist yet.
+    pub(crate) fn resolve_ref_name(refs: &str, deref: bool) -> Result<String> {
+        match RefValue::get_ref_internal(refs, false)? {
+            Some(ref_value) if deref && ref_value.symbolic => {
+                RefValue::resolve_ref_name(&ref_value.value, true)
+            }
+            _ => Ok(refs.to_owned()),
+        }
+    }
 
-        assert!(!ref_value.value.is_empty());
-        let value: String = if ref_value.symbolic {
-            String::from("ref:") + &ref_value.value
+    // Contents of a reference file.
+    pub(crate) fn contents(&self) -> String {
+        assert!(!self.value.is_empty());
+        if self.symbolic {
+            String::from("ref:") + &self.value
         } else {
-            ref_value.value.to_owned()
-        };
-
-        let ref_path: String = format!("{}/{}", DSGIT_DIR, refs);
-        let parent_path = Path::new(&ref_path).parent().unwrap();
+            self.value.to_owned()
+        }
+    }
 
-        create_dir_all(parent_path)?;
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&ref_path)
-            .with_context(|| format!("Failed to open object file: {}", ref_path))?;
+    pub fn update_ref(
+        refs: &str,
+        ref_value: &RefValue,
+        deref: bool,
+        message: &str,
+    ) -> Result<String> {
+        let mut transaction = RefTransaction::new();
+        transaction.update(refs, ref_value.clone(), deref, None, message);
+        transaction.commit()?;
+        Ok(ref_value.contents())
+    }
 
-        file.write_all(value.as_bytes()).unwrap();
-        file.flush().unwrap();
+    // Write a reference file as it is, without recording the reflog.
+    pub(crate) fn write_ref(refs: &str, ref_value: &RefValue) -> Result<String> {
+        let value = ref_value.contents();
+        let mut lock = LockFile::acquire(&format!("{}/{}", dsgit_dir(), refs))?;
+        lock.write(&value)?;
+        lock.commit()?;
         Ok(value)
     }
 
+    /// Delete a reference from both of loose and packed references, so that
+    /// a packed value does not appear again after removing the loose one.
+    pub fn delete_ref(refs: &str) -> Result<()> {
+        let mut transaction = RefTransaction::new();
+        transaction.delete(refs, None);
+        transaction.commit()
+    }
+
     pub fn get_ref(refs: &str, deref: bool) -> Result<Option<RefValue>> {
         RefValue::get_ref_internal(refs, deref)
     }
 
     fn get_ref_internal(refs: &str, deref: bool) -> Result<Option<RefValue>> {
-        let ref_path = &format!("{}/{}", DSGIT_DIR, refs);
+        let ref_path = &format!("{}/{}", dsgit_dir(), refs);
         if Path::new(ref_path).is_file() {
             let mut file = OpenOptions::new()
                 .read(true)
@@ -81,33 +110,57 @@ impl RefValue {
             };
             Ok(Some(RefValue::new(Some(refs), symbolic, &value)))
         } else {
-            Ok(None)
+            // A loose reference takes priority over a packed one.
+            let packed_ref = cached_packed_refs()?
+                .iter()
+                .find(|packed_ref| packed_ref.name == refs)
+                .map(|packed_ref| RefValue::new(Some(refs), false, &packed_ref.oid));
+            Ok(packed_ref)
         }
     }
 
     pub fn get_refs(prefix: Option<&str>, rel_path: &str) -> Result<Vec<String>> {
-        let mut refs = vec![String::from("HEAD")];
-        let prefix_root = format!("{}/{}", DSGIT_DIR, rel_path);
-        let prefix_rel_path = Path::new(&prefix_root);
-        for entry in WalkDir::new(format!("{}/refs/", DSGIT_DIR))
+        let mut ref_paths: Vec<PathBuf> = WalkDir::new(format!("{}/refs/", dsgit_dir()))
             .into_iter()
             .filter_map(Result::ok)
             .filter(|e| !e.file_type().is_dir())
-        {
+            .map(|e| e.path().to_path_buf())
+            .collect();
+        for packed_ref in cached_packed_refs()?.iter() {
+            let path = Path::new(&dsgit_dir()).join(&packed_ref.name);
+            if !path.is_file() {
+                ref_paths.push(path);
+            }
+        }
+
+        let mut refs = vec![String::from("HEAD")];
+        let prefix_root = format!("{}/{}", dsgit_dir(), rel_path);
+        let prefix_rel_path = Path::new(&prefix_root);
+        for path in ref_paths.iter() {
+            let file_name = path.file_name().unwrap().to_str().unwrap();
+            // Skip lock files of references being updated.
+            if file_name.ends_with(".lock") {
+                continue;
+            }
             if let Some(p) = prefix {
-                let file_name = entry.file_name().to_str().unwrap();
                 if file_name.starts_with(p) {
                     continue;
                 }
             }
-            let ref_path = entry.path().strip_prefix(prefix_rel_path)?;
-            refs.push(ref_path.to_str().unwrap().to_owned());
+            // Skip references outside of a given relative path.
+            if let Ok(ref_path) = path.strip_prefix(prefix_rel_path) {
+                refs.push(ref_path.to_str().unwrap().to_owned());
+            }
         }
 
         Ok(refs)
     }
 
     pub fn switch(name: &str, ignore_options: &[String]) -> Result<()> {
+        let from = match RefValue::get_branch_name()? {
+            Some(branch) => branch,
+            None => get_head_oid().into(),
+        };
         let oid = get_oid(name).unwrap();
         let commit = Commit::get_commit(&oid).unwrap();
         Tree::read_tree(&commit.tree, ignore_options)?;
@@ -119,7 +172,8 @@ impl RefValue {
             RefValue::new(Some(&oid), false, &oid)
         };
 
-        RefValue::update_ref("HEAD", &head_ref, false).unwrap();
+        let message = format!("switch: moving from {} to {}", from, name);
+        RefValue::update_ref("HEAD", &head_ref, false, &message).unwrap();
         Ok(())
     }
 
@@ -151,25 +205,305 @@ impl RefValue {
     }
 }
 
-pub fn create_tag(tag: &str, oid: &str) {
+/// Check a reference name against below rules, which follow git's
+/// `check-ref-format`:
+///   - components separated by `/` must not be empty, must not begin with
+///     `.` and must not end with `.lock`.
+///   - it must not contain `..`, `@{`, `\`, ASCII control characters, a
+///     space, or any of `~ ^ : ? * [`.
+///   - it must not begin or end with `/`, nor end with `.`.
+///   - it must not be `@`.
+///   - it must have two components or more like `refs/heads/main`, unless
+///     `allow_onelevel` is given or it is an uppercase name like `HEAD`.
+pub fn check_ref_format(name: &str, allow_onelevel: bool) -> Result<()> {
+    let invalid = |reason: &str| {
+        Err(anyhow!(
+            "'{}' is not a valid reference name: {}",
+            name,
+            reason
+        ))
+    };
+
+    if name.is_empty() || name == "@" {
+        return invalid("it must not be empty or `@`");
+    }
+    if name.contains("..") {
+        return invalid("it must not contain `..`");
+    }
+    if name.contains("@{") {
+        return invalid("it must not contain `@{`");
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| c.is_ascii_control() || " ~^:?*[\\".contains(*c))
+    {
+        return invalid(&format!("it must not contain {:?}", c));
+    }
+    if name.ends_with('.') {
+        return invalid("it must not end with `.`");
+    }
+    for component in name.split('/') {
+        if component.is_empty() {
+            return invalid("it must not have an empty component");
+        }
+        if component.starts_with('.') {
+            return invalid("a component must not begin with `.`");
+        }
+        if component.ends_with(".lock") {
+            return invalid("a component must not end with `.lock`");
+        }
+    }
+
+    let is_pseudo_ref = name.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+    if !name.contains('/') && !allow_onelevel && !is_pseudo_ref {
+        return invalid("it must contain at least one `/`");
+    }
+    Ok(())
+}
+
+/// Check a branch name, which must be a valid name under `refs/heads/`.
+pub fn check_branch_name(name: &str) -> Result<()> {
+    if name.starts_with('-') || name == "HEAD" {
+        return Err(anyhow!("'{}' is not a valid branch name", name));
+    }
+    check_ref_format(&format!("refs/heads/{}", name), false)
+}
+
+pub fn create_tag(tag: &str, oid: &str) -> Result<()> {
+    check_ref_format(&format!("refs/tags/{}", tag), false)?;
     let ref_value = RefValue::new(Some(oid), false, oid);
-    RefValue::update_ref(&format!("refs/tags/{}", tag), &ref_value, true).unwrap();
+    let message = format!("tag: tagging {}", oid);
+    RefValue::update_ref(&format!("refs/tags/{}", tag), &ref_value, true, &message)?;
+    Ok(())
 }
 
-pub fn create_branch(name: &str, oid: &str) {
+pub fn create_branch(name: &str, oid: &str) -> Result<()> {
+    check_branch_name(name)?;
     let ref_name = String::from("refs/heads/") + name;
     let ref_value = RefValue::new(Some(oid), false, oid);
-    RefValue::update_ref(&ref_name, &ref_value, true).unwrap();
+    let message = format!("branch: Created from {}", oid);
+    RefValue::update_ref(&ref_name, &ref_value, true, &message)?;
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PackedRef {
+    pub name: String,
+    pub oid: String,
+    // The object an annotated tag points to.
+    pub peeled: Option<String>,
+}
+
+fn packed_refs_path() -> String {
+    format!("{}/packed-refs", dsgit_dir())
+}
+
+/// Read `.dsgit/packed-refs` which has below format:
+///   # pack-refs with: peeled
+///   [object hash] [reference name]
+///   ^[peeled object hash] // only after an annotated tag.
+pub fn read_packed_refs() -> Result<Vec<PackedRef>> {
+    let path = packed_refs_path();
+    if !Path::new(&path).is_file() {
+        return Ok(vec![]);
+    }
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read file: {}", path))?;
+
+    let mut packed_refs: Vec<PackedRef> = vec![];
+    for line in contents.lines() {
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        if let Some(peeled) = line.strip_prefix('^') {
+            match packed_refs.last_mut() {
+                Some(packed_ref) => packed_ref.peeled = Some(peeled.to_owned()),
+                None => return Err(anyhow!("Broken packed-refs line: {}", line)),
+            }
+            continue;
+        }
+        match line.split_once(' ') {
+            Some((oid, name)) => packed_refs.push(PackedRef {
+                name: name.to_owned(),
+                oid: oid.to_owned(),
+                peeled: None,
+            }),
+            None => return Err(anyhow!("Broken packed-refs line: {}", line)),
+        }
+    }
+    Ok(packed_refs)
+}
+
+// The packed references, read once while `.dsgit/packed-refs` is unchanged
+// since every lookup of a reference which is not loose reads them.
+fn cached_packed_refs() -> Result<Rc<Vec<PackedRef>>> {
+    PACKED_REFS.with(|cache| cache.get(&packed_refs_path(), read_packed_refs))
+}
+
+fn write_packed_refs(packed_refs: &[PackedRef]) -> Result<()> {
+    let mut contents = String::from("# pack-refs with: peeled\n");
+    for packed_ref in packed_refs.iter() {
+        contents = contents + &packed_ref.oid + " " + &packed_ref.name + "\n";
+        if let Some(peeled) = &packed_ref.peeled {
+            contents = contents + "^" + peeled + "\n";
+        }
+    }
+    let path = packed_refs_path();
+    let mut lock = LockFile::acquire(&path)?;
+    lock.write(&contents)?;
+    lock.commit()?;
+    PACKED_REFS.with(|cache| cache.forget(&path));
+    Ok(())
+}
+
+// Remove references from `.dsgit/packed-refs` if they are packed.
+pub(crate) fn remove_packed_refs(names: &[&str]) -> Result<()> {
+    let mut packed_refs = read_packed_refs()?;
+    let packed_len = packed_refs.len();
+    packed_refs.retain(|packed_ref| !names.contains(&packed_ref.name.as_str()));
+    if packed_refs.len() != packed_len {
+        write_packed_refs(&packed_refs)?;
+    }
+    Ok(())
+}
+
+/// Move every loose reference under `refs/` into `.dsgit/packed-refs`, and
+/// return the number of packed references.
+pub fn pack_refs() -> Result<usize> {
+    let mut packed_refs = read_packed_refs()?;
+    let mut locks = vec![];
+    for name in RefValue::get_refs(None, ".")?.iter() {
+        let ref_path = format!("{}/{}", dsgit_dir(), name);
+        if name == "HEAD" || !Path::new(&ref_path).is_file() {
+            continue;
+        }
+        // Keep the loose reference locked until it is removed.
+        let lock = LockFile::acquire(&ref_path)?;
+        let ref_value = RefValue::get_ref(name, false)?.unwrap();
+        if ref_value.symbolic {
+            continue;
+        }
+
+        let peeled = Some(peel_oid(&ref_value.value)?.into()).filter(|oid| *oid != ref_value.value);
+        packed_refs.retain(|packed_ref| packed_ref.name != *name);
+        packed_refs.push(PackedRef {
+            name: name.to_owned(),
+            oid: ref_value.value,
+            peeled,
+        });
+        locks.push((ref_path, lock));
+    }
+    packed_refs.sort_by(|a, b| a.name.cmp(&b.name));
+    write_packed_refs(&packed_refs)?;
+
+    for (ref_path, lock) in locks.into_iter() {
+        lock.commit_delete()?;
+        remove_empty_dirs(&ref_path);
+    }
+    Ok(packed_refs.len())
+}
+
+pub fn get_branches() -> Result<Vec<String>> {
+    let mut branches: Vec<String> = RefValue::get_refs(Some("."), "refs/heads/")?
+        .into_iter()
+        .filter(|name| name != "HEAD")
+        .collect();
+    branches.sort();
+    Ok(branches)
+}
+
+fn branch_oid(name: &str) -> Result<String> {
+    match RefValue::get_ref(&format!("refs/heads/{}", name), true)? {
+        Some(ref_value) => Ok(ref_value.value),
+        None => Err(anyhow!("A branch is not found: {}", name)),
+    }
+}
+
+/// Move an existing branch to `oid`, the branch checked out can not be moved.
+pub fn move_branch(name: &str, oid: &str) -> Result<()> {
+    branch_oid(name)?;
+    if RefValue::get_branch_name()?.as_deref() == Some(name) {
+        return Err(anyhow!("Cannot force update the current branch: {}", name));
+    }
+    let ref_value = RefValue::new(Some(oid), false, oid);
+    let message = format!("branch: Reset to {}", oid);
+    RefValue::update_ref(&format!("refs/heads/{}", name), &ref_value, false, &message)?;
+    Ok(())
+}
+
+/// Delete a branch and its reflog, and return the commit it pointed to.
+/// Without `force`, a branch not merged into HEAD is kept.
+pub fn delete_branch(name: &str, force: bool) -> Result<String> {
+    let oid = branch_oid(name)?;
+    if RefValue::get_branch_name()?.as_deref() == Some(name) {
+        return Err(anyhow!("Cannot delete the branch checked out: {}", name));
+    }
+    if !force && !Commit::is_ancestor(&oid, &get_head_oid())? {
+        return Err(anyhow!(
+            "The branch '{}' is not fully merged, use `-D` to delete it anyway.",
+            name
+        ));
+    }
+
+    let ref_name = format!("refs/heads/{}", name);
+    RefValue::delete_ref(&ref_name)?;
+    reflog::delete(&ref_name)?;
+
+    let mut config = Config::read()?;
+    if config.remove_section(&format!("branch.{}", name)) {
+        config.write()?;
+    }
+    Ok(oid)
+}
+
+/// Rename a branch with its reflog, HEAD follows the branch if checked out.
+/// The new branch, the old one and HEAD are updated in a single transaction
+/// which fails if any of them changed since they were read.
+pub fn rename_branch(old_name: &str, new_name: &str) -> Result<()> {
+    check_branch_name(new_name)?;
+    let oid = branch_oid(old_name)?;
+    let old_ref = format!("refs/heads/{}", old_name);
+    let new_ref = format!("refs/heads/{}", new_name);
+    if RefValue::get_ref(&new_ref, false)?.is_some() {
+        return Err(anyhow!("A branch named '{}' already exists.", new_name));
+    }
+
+    let message = format!("Branch: renamed {} to {}", old_ref, new_ref);
+    let zero_oid = HashAlgorithm::of(&oid).zero_oid();
+    let mut transaction = RefTransaction::new();
+    let ref_value = RefValue::new(Some(&oid), false, &oid);
+    transaction
+        .update(&new_ref, ref_value, false, Some(&zero_oid), &message)
+        .delete(&old_ref, Some(&oid));
+    if RefValue::get_branch_name()?.as_deref() == Some(old_name) {
+        let head_value = RefValue::new(Some(&oid), true, &new_ref);
+        transaction.update("HEAD", head_value, false, Some(&oid), &message);
+    }
+    // The reflog moves first, so that the entry of the rename follows it.
+    reflog::rename(&old_ref, &new_ref)?;
+    if let Err(e) = transaction.commit() {
+        reflog::rename(&new_ref, &old_ref)?;
+        return Err(e);
+    }
+
+    // Keep the upstream of the branch.
+    let mut config = Config::read()?;
+    config.rename_section(
+        &format!("branch.{}", old_name),
+        &format!("branch.{}", new_name),
+    )?;
+    config.write()
 }
 
 pub fn reset(commit: &str) {
     let ref_value = RefValue::new(Some(commit), false, commit);
-    RefValue::update_ref("HEAD", &ref_value, true).unwrap();
+    let message = format!("reset: moving to {}", commit);
+    RefValue::update_ref("HEAD", &ref_value, true, &message).unwrap();
 }
 
-pub fn get_head_oid() -> String {
+pub fn get_head_oid() -> ObjectId {
     match RefValue::get_ref("HEAD", true).unwrap() {
-        Some(ref_value) => ref_value.value,
+        Some(ref_value) => ObjectId::parse(&ref_value.value).unwrap(),
         None => panic!("HEAD reference is missing"),
     }
 }
@@ -0,0 +1,155 @@
+use crate::data::dsgit_dir;
+use crate::hash::HashAlgorithm;
+use crate::identity::{get_identity, get_timestamp};
+
+use std::fs::{self, create_dir_all, remove_file, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+#[derive(Debug, PartialEq)]
+pub struct ReflogEntry {
+    pub old_oid: String,
+    pub new_oid: String,
+    pub identity: String,
+    pub timestamp: String,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    // Parse a reflog line of the below format:
+    //   [old oid] [new oid] [name] <[email]> [seconds] [timezone]\t[message]
+    fn parse(line: &str) -> Result<Self> {
+        let broken = || anyhow!("Broken reflog line: {}", line);
+        let (header, message) = line.split_once('\t').ok_or_else(broken)?;
+        let (old_oid, rest) = header.split_once(' ').ok_or_else(broken)?;
+        let (new_oid, rest) = rest.split_once(' ').ok_or_else(broken)?;
+        let email_end = rest.rfind('>').ok_or_else(broken)?;
+        Ok(ReflogEntry {
+            old_oid: old_oid.to_owned(),
+            new_oid: new_oid.to_owned(),
+            identity: rest[..email_end + 1].to_owned(),
+            timestamp: rest[email_end + 1..].trim().to_owned(),
+            message: message.to_owned(),
+        })
+    }
+}
+
+impl std::fmt::Display for ReflogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}\t{}",
+            self.old_oid, self.new_oid, self.identity, self.timestamp, self.message
+        )
+    }
+}
+
+fn log_path(refs: &str) -> String {
+    format!("{}/logs/{}", dsgit_dir(), refs)
+}
+
+/// Append an entry to the reflog of `refs`, a reference without old value
+/// is recorded with the zero oid of the hash algorithm of `new_oid`.
+pub fn append(refs: &str, old_oid: Option<&str>, new_oid: &str, message: &str) -> Result<()> {
+    let path = log_path(refs);
+    create_dir_all(Path::new(&path).parent().unwrap())?;
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open file: {}", path))?;
+
+    let entry = ReflogEntry {
+        old_oid: match old_oid {
+            Some(old_oid) => old_oid.to_owned(),
+            None => HashAlgorithm::of(new_oid).zero_oid().into(),
+        },
+        new_oid: new_oid.to_owned(),
+        identity: get_identity(),
+        timestamp: get_timestamp(),
+        // A message must keep an entry in one line.
+        message: message.lines().next().unwrap_or("").to_owned(),
+    };
+    writeln!(file, "{}", entry)?;
+    Ok(())
+}
+
+/// Read the reflog of `refs`, the newest entry comes first as `refs@{0}`.
+pub fn read(refs: &str) -> Result<Vec<ReflogEntry>> {
+    let path = log_path(refs);
+    if !Path::new(&path).is_file() {
+        return Ok(vec![]);
+    }
+
+    let mut contents = String::new();
+    OpenOptions::new()
+        .read(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open file: {}", path))?
+        .read_to_string(&mut contents)?;
+
+    let mut entries = contents
+        .lines()
+        .map(ReflogEntry::parse)
+        .collect::<Result<Vec<ReflogEntry>>>()?;
+    entries.reverse();
+    Ok(entries)
+}
+
+pub fn exists(refs: &str) -> bool {
+    Path::new(&log_path(refs)).is_file()
+}
+
+/// Rewrite the reflog of `refs` with given entries, newest first.
+pub fn write(refs: &str, entries: &[ReflogEntry]) -> Result<()> {
+    let path = log_path(refs);
+    if entries.is_empty() {
+        return delete(refs);
+    }
+
+    create_dir_all(Path::new(&path).parent().unwrap())?;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open file: {}", path))?;
+    for entry in entries.iter().rev() {
+        writeln!(file, "{}", entry)?;
+    }
+    Ok(())
+}
+
+pub fn delete(refs: &str) -> Result<()> {
+    let path = log_path(refs);
+    if Path::new(&path).is_file() {
+        remove_file(&path).with_context(|| format!("Failed to remove file: {}", path))?;
+    }
+    Ok(())
+}
+
+pub fn rename(old_refs: &str, new_refs: &str) -> Result<()> {
+    let old_path = log_path(old_refs);
+    if !Path::new(&old_path).is_file() {
+        return Ok(());
+    }
+    let new_path = log_path(new_refs);
+    create_dir_all(Path::new(&new_path).parent().unwrap())?;
+    fs::rename(&old_path, &new_path)
+        .with_context(|| format!("Failed to rename file: {} to {}", old_path, new_path))
+}
+
+/// Return the value of `refs` which was `index` updates before.
+pub fn get_nth_oid(refs: &str, index: usize) -> Result<String> {
+    let entries = read(refs)?;
+    match entries.get(index) {
+        Some(entry) => Ok(entry.new_oid.to_owned()),
+        None => Err(anyhow!(
+            "Log for '{}' only has {} entries.",
+            refs,
+            entries.len()
+        )),
+    }
+}
@@ -0,0 +1,748 @@
+use crate::bundle::Bundle;
+use crate::commit::Commit;
+use crate::config::Config;
+use crate::data::{
+    get_object, init_with_format, object_exists, read_object, ref_candidates, TypeObject,
+};
+use crate::entry::{Entry, Tree};
+use crate::hash::{HashAlgorithm, ObjectId};
+use crate::protocol::{ObjectFilter, RefAdvertisement, RefUpdate};
+use crate::reference::{check_ref_format, RefValue};
+use crate::shallow;
+use crate::tag::Tag;
+use crate::transaction::RefTransaction;
+use crate::transport::{is_url, FetchOptions, Transport};
+
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+
+/// A reference updated by `fetch` or `push`, `src` is a reference of the
+/// repository objects came from and `dst` is the one written.
+#[derive(Debug, PartialEq)]
+pub struct RefChange {
+    pub src: String,
+    pub dst: String,
+    pub old_oid: Option<String>,
+    pub new_oid: String,
+    pub forced: bool,
+}
+
+fn short_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+impl fmt::Display for RefChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (src, dst) = (short_ref_name(&self.src), short_ref_name(&self.dst));
+        match &self.old_oid {
+            None if self.dst.starts_with("refs/tags/") => {
+                write!(f, " * [new tag]         {} -> {}", src, dst)
+            }
+            None => write!(f, " * [new branch]      {} -> {}", src, dst),
+            Some(old_oid) if self.forced => write!(
+                f,
+                " + {}...{} {} -> {} (forced update)",
+                &old_oid[..7],
+                &self.new_oid[..7],
+                src,
+                dst
+            ),
+            Some(old_oid) => write!(
+                f,
+                "   {}..{}  {} -> {}",
+                &old_oid[..7],
+                &self.new_oid[..7],
+                src,
+                dst
+            ),
+        }
+    }
+}
+
+/// Find the `.dsgit` directory of a repository at `path`, which is either a
+/// working tree containing `.dsgit` or a bare repository itself.
+pub fn remote_dsgit_dir(path: &str) -> Result<String> {
+    let dsgit_dir = Path::new(path).join(".dsgit");
+    if dsgit_dir.is_dir() {
+        return Ok(dsgit_dir.to_str().unwrap().to_owned());
+    }
+    let dir = Path::new(path);
+    if dir.join("objects").is_dir() && dir.join("HEAD").is_file() {
+        return Ok(path.to_owned());
+    }
+    Err(anyhow!(
+        "'{}' does not appear to be a dsgit repository",
+        path
+    ))
+}
+
+// A bare repository has no working tree, so its directory is not `.dsgit`.
+pub(crate) fn is_bare(dsgit_dir: &str) -> bool {
+    !Path::new(dsgit_dir).ends_with(".dsgit")
+}
+
+/// Collect objects reachable from `oids` in the current repository, except
+/// objects which `has_object` reports. An object already present is assumed
+/// to come with every object reachable from it, so the walk stops there.
+/// Referenced objects come before the objects referring to them.
+pub fn reachable_objects(
+    oids: &[String],
+    has_object: impl Fn(&str) -> bool,
+) -> Result<Vec<ObjectId>> {
+    reachable_objects_within(oids, None, |_| false, has_object)
+}
+
+/// Collect objects like `reachable_objects`, without objects left out by
+/// `filter` and parents of commits which `is_boundary` reports. Blobs in
+/// trees are not read, so that blobs missing in a partial clone are not
+/// fetched by the walk.
+pub fn reachable_objects_within(
+    oids: &[String],
+    filter: Option<ObjectFilter>,
+    is_boundary: impl Fn(&str) -> bool,
+    has_object: impl Fn(&str) -> bool,
+) -> Result<Vec<ObjectId>> {
+    let mut visited = HashSet::new();
+    let mut objects = vec![];
+    // Objects to visit with their types if known from a tree.
+    let mut stack: Vec<(ObjectId, Option<TypeObject>)> = vec![];
+    for oid in oids.iter() {
+        stack.push((ObjectId::parse(oid)?, None));
+    }
+    while let Some((oid, obj_type)) = stack.pop() {
+        if !visited.insert(oid.clone()) || has_object(&oid) {
+            continue;
+        }
+        let obj_type = match obj_type {
+            Some(obj_type) => obj_type,
+            None => read_object(&oid)?.0,
+        };
+        match obj_type {
+            TypeObject::Commit => {
+                let commit = Commit::get_commit(&oid)?;
+                if !is_boundary(&oid) {
+                    stack.extend(commit.parents().into_iter().map(|oid| (oid, None)));
+                }
+                stack.push((commit.tree, Some(TypeObject::Tree)));
+            }
+            TypeObject::Tree => {
+                for line in get_object(&oid, TypeObject::Tree)?.lines() {
+                    let entry: Entry = line.parse()?;
+                    if entry.obj_type == TypeObject::Blob && filter == Some(ObjectFilter::BlobNone)
+                    {
+                        continue;
+                    }
+                    stack.push((entry.oid, Some(entry.obj_type)));
+                }
+            }
+            TypeObject::Tag => stack.push((Tag::get_tag(&oid)?.object, None)),
+            TypeObject::Blob => (),
+        }
+        objects.push(oid);
+    }
+    objects.reverse();
+    Ok(objects)
+}
+
+pub(crate) fn get_ref_oid(name: &str) -> Result<Option<String>> {
+    Ok(RefValue::get_ref(name, true)?.map(|ref_value| ref_value.value))
+}
+
+/// Resolve a name like `main` or `v1` to a full reference name and the hash
+/// it points to, annotated tags are kept unpeeled.
+pub(crate) fn resolve_ref(name: &str) -> Result<Option<(String, String)>> {
+    for ref_name in ref_candidates(name).iter() {
+        if let Some(oid) = get_ref_oid(ref_name)? {
+            return Ok(Some((ref_name.to_owned(), oid)));
+        }
+    }
+    Ok(None)
+}
+
+// Whether updating a reference from `old_oid` to `new_oid` loses commits,
+// which is the case unless the old commit is known to be an ancestor.
+pub(crate) fn is_forced(old_oid: Option<&str>, new_oid: &str) -> Result<bool> {
+    match old_oid {
+        Some(old_oid) => Ok(!object_exists(old_oid) || !Commit::is_ancestor(old_oid, new_oid)?),
+        None => Ok(false),
+    }
+}
+
+/// A mapping of references between repositories, written as
+/// `[+]<src>:<dst>` like `+refs/heads/*:refs/remotes/origin/*`. A `*` in
+/// both sides matches any part of a name, and `+` allows updates which are
+/// not fast-forward.
+#[derive(Debug, PartialEq)]
+pub struct Refspec {
+    pub force: bool,
+    pub src: String,
+    pub dst: String,
+}
+
+impl FromStr for Refspec {
+    type Err = anyhow::Error;
+
+    fn from_str(refspec: &str) -> Result<Self> {
+        let (force, spec) = match refspec.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, refspec),
+        };
+        let (src, dst) = spec
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid refspec: {}", refspec))?;
+        if src.matches('*').count() > 1 || src.matches('*').count() != dst.matches('*').count() {
+            return Err(anyhow!("Invalid refspec: {}", refspec));
+        }
+        Ok(Refspec {
+            force,
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Refspec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let force = if self.force { "+" } else { "" };
+        write!(f, "{}{}:{}", force, self.src, self.dst)
+    }
+}
+
+// Match `name` with `pattern` and substitute the matched part for `*` in
+// `replacement`.
+fn map_pattern(pattern: &str, replacement: &str, name: &str) -> Option<String> {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            if matched.is_empty() {
+                return None;
+            }
+            Some(replacement.replacen('*', matched, 1))
+        }
+        None if pattern == name => Some(replacement.to_owned()),
+        None => None,
+    }
+}
+
+impl Refspec {
+    /// Map a reference of the source side to the destination side.
+    pub fn map(&self, name: &str) -> Option<String> {
+        map_pattern(&self.src, &self.dst, name)
+    }
+
+    /// Map a reference of the destination side back to the source side.
+    pub fn map_reverse(&self, name: &str) -> Option<String> {
+        map_pattern(&self.dst, &self.src, name)
+    }
+}
+
+/// A named remote repository configured in `.dsgit/config`.
+#[derive(Debug, PartialEq)]
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+    pub fetch: Vec<Refspec>,
+    // Objects left out by fetches from a promisor remote of a partial clone.
+    pub filter: Option<ObjectFilter>,
+}
+
+impl Remote {
+    /// A remote which tracks every branch under `refs/remotes/<name>/`.
+    pub fn new(name: &str, url: &str) -> Self {
+        Remote {
+            name: name.to_owned(),
+            url: url.to_owned(),
+            fetch: vec![Refspec {
+                force: true,
+                src: String::from("refs/heads/*"),
+                dst: format!("refs/remotes/{}/*", name),
+            }],
+            filter: None,
+        }
+    }
+
+    pub fn get(name: &str) -> Result<Option<Self>> {
+        let config = Config::read()?;
+        let url = match config.get(&format!("remote.{}.url", name))? {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+        let mut fetch = vec![];
+        for refspec in config.get_all(&format!("remote.{}.fetch", name))?.iter() {
+            fetch.push(refspec.parse()?);
+        }
+        let filter = match config.get(&format!("remote.{}.partialCloneFilter", name))? {
+            Some(filter) => Some(filter.parse()?),
+            None => None,
+        };
+        Ok(Some(Remote {
+            name: name.to_owned(),
+            url,
+            fetch,
+            filter,
+        }))
+    }
+
+    /// Find a configured remote by name, or a repository by path or URL which
+    /// has no remote-tracking references.
+    pub fn resolve(name_or_path: &str) -> Result<Self> {
+        if let Some(remote) = Remote::get(name_or_path)? {
+            return Ok(remote);
+        }
+        if !is_url(name_or_path) && !Bundle::is_bundle(name_or_path) {
+            remote_dsgit_dir(name_or_path).map_err(|_| {
+                anyhow!(
+                    "'{}' is neither a remote name nor a dsgit repository",
+                    name_or_path
+                )
+            })?;
+        }
+        Ok(Remote {
+            name: name_or_path.to_owned(),
+            url: name_or_path.to_owned(),
+            fetch: vec![],
+            filter: None,
+        })
+    }
+
+    pub fn list() -> Result<Vec<Self>> {
+        let mut remotes = vec![];
+        for name in Config::read()?.subsections("remote").iter() {
+            if let Some(remote) = Remote::get(name)? {
+                remotes.push(remote);
+            }
+        }
+        Ok(remotes)
+    }
+
+    pub fn add(name: &str, url: &str) -> Result<Self> {
+        check_ref_format(&format!("refs/remotes/{}", name), false)
+            .map_err(|_| anyhow!("'{}' is not a valid remote name", name))?;
+        let mut config = Config::read()?;
+        if config.get(&format!("remote.{}.url", name))?.is_some() {
+            return Err(anyhow!("A remote named '{}' already exists.", name));
+        }
+        let remote = Remote::new(name, url);
+        config.set(&format!("remote.{}.url", name), url)?;
+        for refspec in remote.fetch.iter() {
+            config.add(&format!("remote.{}.fetch", name), &refspec.to_string())?;
+        }
+        config.write()?;
+        Ok(remote)
+    }
+
+    /// Remove a remote with its remote-tracking references, and stop
+    /// branches tracking it.
+    pub fn remove(name: &str) -> Result<()> {
+        let remote = Remote::get(name)?.ok_or_else(|| anyhow!("No such remote: {}", name))?;
+        let mut transaction = RefTransaction::new();
+        for refs in RefValue::get_refs(None, ".")?.iter() {
+            if remote
+                .fetch
+                .iter()
+                .any(|refspec| refspec.map_reverse(refs).is_some())
+            {
+                transaction.delete(refs, None);
+            }
+        }
+        transaction.commit()?;
+
+        let mut config = Config::read()?;
+        config.remove_section(&format!("remote.{}", name));
+        for branch in config.subsections("branch").iter() {
+            if config.get(&format!("branch.{}.remote", branch))?.as_deref() == Some(name) {
+                config.remove_section(&format!("branch.{}", branch));
+            }
+        }
+        config.write()
+    }
+}
+
+/// The branch of a remote which a local branch is based on.
+#[derive(Debug, PartialEq)]
+pub struct Upstream {
+    pub remote: String,
+    // A branch name in the remote, like `refs/heads/main`.
+    pub merge: String,
+}
+
+impl Upstream {
+    pub fn get(branch: &str) -> Result<Option<Self>> {
+        let config = Config::read()?;
+        let remote = config.get(&format!("branch.{}.remote", branch))?;
+        let merge = config.get(&format!("branch.{}.merge", branch))?;
+        Ok(match (remote, merge) {
+            (Some(remote), Some(merge)) => Some(Upstream { remote, merge }),
+            _ => None,
+        })
+    }
+
+    /// Let `branch` track a remote-tracking branch like `origin/main`.
+    pub fn set(branch: &str, tracking: &str) -> Result<Self> {
+        let tracking_ref = if tracking.starts_with("refs/") {
+            tracking.to_owned()
+        } else {
+            format!("refs/remotes/{}", tracking)
+        };
+        if RefValue::get_ref(&tracking_ref, false)?.is_none() {
+            return Err(anyhow!(
+                "A remote-tracking branch is not found: {}",
+                tracking
+            ));
+        }
+        let upstream = Remote::list()?
+            .into_iter()
+            .find_map(|remote| {
+                let merge = remote
+                    .fetch
+                    .iter()
+                    .find_map(|refspec| refspec.map_reverse(&tracking_ref))?;
+                Some(Upstream {
+                    remote: remote.name,
+                    merge,
+                })
+            })
+            .ok_or_else(|| anyhow!("'{}' is not a remote-tracking branch", tracking))?;
+        upstream.write(branch)?;
+        Ok(upstream)
+    }
+
+    fn write(&self, branch: &str) -> Result<()> {
+        let mut config = Config::read()?;
+        config.set(&format!("branch.{}.remote", branch), &self.remote)?;
+        config.set(&format!("branch.{}.merge", branch), &self.merge)?;
+        config.write()
+    }
+
+    /// The local reference which follows the upstream branch, like
+    /// `refs/remotes/origin/main`.
+    pub fn tracking_ref(&self) -> Result<Option<String>> {
+        Ok(Remote::get(&self.remote)?.and_then(|remote| {
+            remote
+                .fetch
+                .iter()
+                .find_map(|refspec| refspec.map(&self.merge))
+        }))
+    }
+}
+
+/// How far a branch is from its upstream, `tracking` is a short name of the
+/// remote-tracking branch like `origin/main`.
+#[derive(Debug, PartialEq)]
+pub struct TrackingStatus {
+    pub tracking: String,
+    pub ahead: usize,
+    pub behind: usize,
+}
+
+/// Compare a branch with its upstream, `None` if the branch has no upstream
+/// or the remote-tracking branch does not exist.
+pub fn tracking_status(branch: &str) -> Result<Option<TrackingStatus>> {
+    let tracking_ref = match Upstream::get(branch)? {
+        Some(upstream) => upstream.tracking_ref()?,
+        None => None,
+    };
+    let tracking_ref = match tracking_ref {
+        Some(tracking_ref) => tracking_ref,
+        None => return Ok(None),
+    };
+    let (oid, tracking_oid) = match (
+        get_ref_oid(&format!("refs/heads/{}", branch))?,
+        get_ref_oid(&tracking_ref)?,
+    ) {
+        (Some(oid), Some(tracking_oid)) => (oid, tracking_oid),
+        _ => return Ok(None),
+    };
+    let (ahead, behind) = Commit::count_ahead_behind(&oid, &tracking_oid)?;
+    Ok(Some(TrackingStatus {
+        tracking: short_ref_name(&tracking_ref).to_owned(),
+        ahead,
+        behind,
+    }))
+}
+
+/// Copy branches and tags of a remote repository into the current one.
+/// Branches are written to the references mapped by the fetch refspecs of
+/// the remote, and tags are created only if missing. References already up
+/// to date are not returned.
+pub fn fetch(remote: &Remote) -> Result<Vec<RefChange>> {
+    fetch_with(remote, None)
+}
+
+// Fetch with commits only up to `depth`, and without objects the filter of
+// a promisor remote leaves out.
+fn fetch_with(remote: &Remote, depth: Option<usize>) -> Result<Vec<RefChange>> {
+    let transport = Transport::open(&remote.url)?;
+    let advertisement = transport.list_refs()?;
+    fetch_advertised(remote, &transport, &advertisement, depth)
+}
+
+// Fetch the references of an advertisement already read from `transport`.
+fn fetch_advertised(
+    remote: &Remote,
+    transport: &Transport,
+    advertisement: &RefAdvertisement,
+    depth: Option<usize>,
+) -> Result<Vec<RefChange>> {
+    let options = FetchOptions {
+        depth,
+        filter: remote.filter,
+    };
+    transport.fetch_with(&advertisement.oids(), &options)?;
+    update_fetched_refs(remote, advertisement)
+}
+
+/// Fetch like `fetch`, together with the history a shallow clone left out,
+/// so that the current repository is no longer shallow.
+pub fn unshallow(remote: &Remote) -> Result<Vec<RefChange>> {
+    let shallows = shallow::read()?;
+    if shallows.is_empty() {
+        return Err(anyhow!("The repository is not shallow."));
+    }
+    let transport = Transport::open(&remote.url)?;
+    let advertisement = transport.list_refs()?;
+    let mut wants = advertisement.oids();
+    for oid in shallows.iter() {
+        wants.extend(
+            Commit::read_commit(oid)?
+                .parents()
+                .into_iter()
+                .map(String::from),
+        );
+    }
+    let options = FetchOptions {
+        depth: None,
+        filter: remote.filter,
+    };
+    transport.fetch_with(&wants, &options)?;
+    update_fetched_refs(remote, &advertisement)
+}
+
+// Update references of the current repository by fetched ones.
+fn update_fetched_refs(
+    remote: &Remote,
+    advertisement: &RefAdvertisement,
+) -> Result<Vec<RefChange>> {
+    let mut changes = vec![];
+    for (src, new_oid) in advertisement.branches() {
+        let (refspec, dst) = match remote
+            .fetch
+            .iter()
+            .find_map(|refspec| refspec.map(src).map(|dst| (refspec, dst)))
+        {
+            Some(mapped) => mapped,
+            None => continue,
+        };
+        let old_oid = get_ref_oid(&dst)?;
+        if old_oid.as_ref() == Some(new_oid) {
+            continue;
+        }
+        let forced = is_forced(old_oid.as_deref(), new_oid)?;
+        if forced && !refspec.force {
+            return Err(anyhow!(
+                "Rejected a non-fast-forward update of {} by refspec: {}",
+                dst,
+                refspec
+            ));
+        }
+        changes.push(RefChange {
+            src: src.to_owned(),
+            dst,
+            old_oid,
+            new_oid: new_oid.to_owned(),
+            forced,
+        });
+    }
+    for (src, new_oid) in advertisement.tags() {
+        if RefValue::get_ref(src, false)?.is_some() {
+            continue;
+        }
+        changes.push(RefChange {
+            src: src.to_owned(),
+            dst: src.to_owned(),
+            old_oid: None,
+            new_oid: new_oid.to_owned(),
+            forced: false,
+        });
+    }
+
+    let mut transaction = RefTransaction::new();
+    for change in changes.iter() {
+        let ref_value = RefValue::new(Some(&change.new_oid), false, &change.new_oid);
+        let message = match (&change.old_oid, change.forced) {
+            (None, _) => String::from("fetch: storing head"),
+            (Some(_), false) => String::from("fetch: fast-forward"),
+            (Some(_), true) => String::from("fetch: forced-update"),
+        };
+        let expected_old = match &change.old_oid {
+            Some(old_oid) => old_oid.to_owned(),
+            None => HashAlgorithm::of(&change.new_oid).zero_oid().into(),
+        };
+        transaction.update(&change.dst, ref_value, false, Some(&expected_old), &message);
+    }
+    transaction.commit()?;
+    Ok(changes)
+}
+
+/// Send the local `branch` to a remote repository. The remote branch must
+/// be an ancestor of the local one unless `force` is given, and the branch
+/// checked out in the remote working tree can not be updated.
+pub fn push(remote: &Remote, branch: &str, force: bool) -> Result<RefChange> {
+    let transport = Transport::open(&remote.url)?;
+    let ref_name = format!("refs/heads/{}", branch);
+    let new_oid =
+        get_ref_oid(&ref_name)?.ok_or_else(|| anyhow!("A branch is not found: {}", branch))?;
+
+    let old_oid = transport
+        .list_refs()?
+        .get(&ref_name)
+        .map(|oid| oid.to_owned());
+    let change = RefChange {
+        src: ref_name.to_owned(),
+        dst: ref_name.to_owned(),
+        forced: is_forced(old_oid.as_deref(), &new_oid)?,
+        old_oid,
+        new_oid,
+    };
+    if change.forced && !force {
+        return Err(anyhow!(
+            "Updates were rejected because the remote branch '{}' is not an ancestor \
+             of the local one (non-fast-forward). Fetch first, or push with `--force`.",
+            branch
+        ));
+    }
+    let update = RefUpdate {
+        name: ref_name.to_owned(),
+        old_oid: change.old_oid.to_owned(),
+        new_oid: change.new_oid.to_owned(),
+    };
+    transport.push(&[update])?;
+
+    if let Some(tracking_ref) = remote.fetch.iter().find_map(|r| r.map(&ref_name)) {
+        if get_ref_oid(&tracking_ref)?.as_ref() == Some(&change.new_oid) {
+            return Ok(change);
+        }
+        let ref_value = RefValue::new(Some(&change.new_oid), false, &change.new_oid);
+        RefValue::update_ref(&tracking_ref, &ref_value, false, "update by push")?;
+    }
+    Ok(change)
+}
+
+// Record a remote as the promisor remote of a partial clone, from which
+// objects left out by `filter` are fetched.
+fn set_promisor(name: &str, filter: ObjectFilter) -> Result<()> {
+    let mut config = Config::read()?;
+    config.set(&format!("remote.{}.promisor", name), "true")?;
+    config.set(
+        &format!("remote.{}.partialCloneFilter", name),
+        &filter.to_string(),
+    )?;
+    config.set("extensions.partialClone", name)?;
+    config.write()
+}
+
+/// Fetch the objects among `oids` which a partial clone left out from the
+/// promisor remote in a single request, and return the number of objects
+/// copied. Nothing is fetched unless the current repository is a partial
+/// clone, so that objects are read from the current repository only.
+pub fn fetch_missing_objects(oids: &[ObjectId]) -> Result<usize> {
+    let mut missing = vec![];
+    for oid in oids.iter() {
+        if !object_exists(oid) && !missing.contains(&oid.to_string()) {
+            missing.push(oid.to_string());
+        }
+    }
+    if missing.is_empty() {
+        return Ok(0);
+    }
+    let name = match Config::read()?.get("extensions.partialClone")? {
+        Some(name) => name,
+        None => return Ok(0),
+    };
+    let remote =
+        Remote::get(&name)?.ok_or_else(|| anyhow!("The promisor remote is missing: {}", name))?;
+    Transport::open(&remote.url)?
+        .fetch_objects(&missing, remote.filter)
+        .with_context(|| format!("Failed to fetch missing objects from {}", name))
+}
+
+/// Clone the repository at `path` into the current repository, which must
+/// not exist yet. A working tree clone adds the remote `origin` tracking
+/// remote branches under `refs/remotes/origin/`, and checks out the branch of
+/// the remote HEAD with the remote branch as its upstream. A bare clone
+/// copies branches as they are without a working tree.
+pub fn clone(path: &str, bare: bool) -> Result<()> {
+    clone_with(path, bare, &FetchOptions::default())
+}
+
+/// Clone like `clone`, with objects limited by `options`. A depth leaves out
+/// older commits as a shallow clone, and a filter makes a partial clone
+/// which fetches left out objects from `origin` when they are needed.
+pub fn clone_with(path: &str, bare: bool, options: &FetchOptions) -> Result<()> {
+    let transport = Transport::open(path)?;
+    let advertisement = transport.list_refs()?;
+    // Objects are copied as they are stored, so the clone takes the format
+    // of the remote.
+    init_with_format(advertisement.format)?;
+    if let Some(filter) = options.filter {
+        set_promisor("origin", filter)?;
+    }
+    let message = format!("clone: from {}", path);
+    if bare {
+        transport.fetch_with(&advertisement.oids(), options)?;
+
+        let mut transaction = RefTransaction::new();
+        for (name, oid) in advertisement.branches().chain(advertisement.tags()) {
+            let ref_value = RefValue::new(Some(oid), false, oid);
+            let zero_oid = HashAlgorithm::of(oid).zero_oid();
+            transaction.update(name, ref_value, false, Some(&zero_oid), &message);
+        }
+        transaction.commit()?;
+
+        let mut config = Config::read()?;
+        config.set("remote.origin.url", path)?;
+        config.write()?;
+    } else {
+        let mut origin = Remote::add("origin", path)?;
+        origin.filter = options.filter;
+        fetch_advertised(&origin, &transport, &advertisement, options.depth)?;
+    }
+
+    let head_oid = match advertisement.head_oid {
+        Some(oid) => oid,
+        // An empty repository, nothing to check out.
+        None => return Ok(()),
+    };
+
+    let value = RefValue::new(Some(&head_oid), false, &head_oid);
+    match advertisement.head_branch {
+        // HEAD follows the branch, so that the branch is logged as created.
+        Some(ref_name) => {
+            RefValue::write_ref("HEAD", &RefValue::new(None, true, &ref_name))?;
+            if !bare {
+                RefValue::update_ref("HEAD", &value, true, &message)?;
+                let branch = ref_name.trim_start_matches("refs/heads/").to_owned();
+                let upstream = Upstream {
+                    remote: String::from("origin"),
+                    merge: ref_name,
+                };
+                upstream.write(&branch)?;
+            }
+        }
+        None => {
+            RefValue::update_ref("HEAD", &value, false, &message)?;
+        }
+    }
+
+    if !bare {
+        Tree::read_tree(&Commit::get_commit(&head_oid)?.tree, &[])?;
+    }
+    Ok(())
+}
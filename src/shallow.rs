@@ -0,0 +1,121 @@
+use crate::commit::Commit;
+use crate::data::{dsgit_dir, object_exists, peel_oid, read_object, FileCache, TypeObject};
+use crate::hash::ObjectId;
+use crate::transaction::LockFile;
+
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+thread_local! {
+    // Shallow commits of repositories by the paths of their shallow files.
+    static SHALLOWS: FileCache<BTreeSet<ObjectId>> = FileCache::new();
+}
+
+fn shallow_path() -> String {
+    format!("{}/shallow", dsgit_dir())
+}
+
+/// Read `.dsgit/shallow`, which lists commits of a shallow clone whose
+/// parents are missing, one hash per line.
+pub fn read() -> Result<BTreeSet<ObjectId>> {
+    let path = shallow_path();
+    if !Path::new(&path).is_file() {
+        return Ok(BTreeSet::new());
+    }
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read file: {}", path))?;
+    contents.lines().map(ObjectId::parse).collect()
+}
+
+/// Write `.dsgit/shallow`, the file is removed when no commit is shallow.
+pub fn write(shallows: &BTreeSet<ObjectId>) -> Result<()> {
+    let path = shallow_path();
+    let mut lock = LockFile::acquire(&path)?;
+    if shallows.is_empty() {
+        lock.commit_delete()?;
+    } else {
+        let contents: String = shallows.iter().map(|oid| format!("{}\n", oid)).collect();
+        lock.write(&contents)?;
+        lock.commit()?;
+    }
+    SHALLOWS.with(|cache| cache.forget(&path));
+    Ok(())
+}
+
+/// Whether the current repository is a shallow clone.
+pub fn is_shallow_repository() -> bool {
+    Path::new(&shallow_path()).is_file()
+}
+
+/// Whether the parents of a commit are cut off by a shallow clone. The
+/// shallow file is read once while it is unchanged, since every commit read
+/// is checked.
+pub fn is_shallow(oid: &str) -> Result<bool> {
+    if !is_shallow_repository() {
+        return Ok(false);
+    }
+    let shallows = SHALLOWS.with(|cache| cache.get(&shallow_path(), read))?;
+    Ok(shallows.contains(oid))
+}
+
+// Commits which `oids` point to, following annotated tags.
+fn peel_commits(oids: &[String]) -> Result<Vec<ObjectId>> {
+    let mut commits = vec![];
+    for oid in oids.iter() {
+        let oid = peel_oid(oid)?;
+        if let (TypeObject::Commit, _) = read_object(&oid)? {
+            commits.push(oid);
+        }
+    }
+    Ok(commits)
+}
+
+/// Commits `depth` commits away from `oids`, counting the commits of `oids`
+/// as 1, whose parents are left out by a fetch limited to `depth`.
+pub fn boundary(oids: &[String], depth: usize) -> Result<HashSet<ObjectId>> {
+    if depth == 0 {
+        return Err(anyhow!("A depth must be a positive number."));
+    }
+    let mut visited = HashSet::new();
+    let mut boundary = HashSet::new();
+    let mut queue: VecDeque<(ObjectId, usize)> = peel_commits(oids)?
+        .into_iter()
+        .map(|oid| (oid, 1))
+        .collect();
+    while let Some((oid, distance)) = queue.pop_front() {
+        if !visited.insert(oid.clone()) {
+            continue;
+        }
+        let parents = Commit::get_commit(&oid)?.parents();
+        if distance < depth {
+            queue.extend(parents.into_iter().map(|parent| (parent, distance + 1)));
+        } else if !parents.is_empty() {
+            boundary.insert(oid);
+        }
+    }
+    Ok(boundary)
+}
+
+/// Record commits reachable from `oids` whose parents are missing as
+/// shallow, and forget shallow commits whose parents have been fetched.
+pub fn update(oids: &[String]) -> Result<()> {
+    let mut shallows = read()?;
+    let mut visited = HashSet::new();
+    let mut stack = peel_commits(oids)?;
+    while let Some(oid) = stack.pop() {
+        if !visited.insert(oid.clone()) {
+            continue;
+        }
+        let parents = Commit::read_commit(&oid)?.parents();
+        if parents.iter().all(|parent| object_exists(parent)) {
+            shallows.remove(&oid);
+            stack.extend(parents);
+        } else {
+            shallows.insert(oid);
+        }
+    }
+    write(&shallows)
+}
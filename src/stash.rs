@@ -0,0 +1,164 @@
+use crate::commit::Commit;
+use crate::data::{get_object, TypeObject};
+use crate::diff::{diff_trees, print_diff, DiffOptions};
+use crate::entry::Tree;
+use crate::hash::{HashAlgorithm, ObjectId};
+use crate::merge::{apply_tree_changes, ensure_clean_working_tree};
+use crate::reference::RefValue;
+use crate::reflog;
+
+use anyhow::{anyhow, Result};
+
+const STASH_REF: &str = "refs/stash";
+
+#[derive(Debug, PartialEq)]
+pub struct StashEntry {
+    pub oid: String,
+    pub message: String,
+}
+
+// Each stash entry is kept in the reflog of `refs/stash`, the newest entry
+// is referred as `stash@{0}`.
+fn read_log() -> Result<Vec<StashEntry>> {
+    Ok(reflog::read(STASH_REF)?
+        .into_iter()
+        .map(|entry| StashEntry {
+            oid: entry.new_oid,
+            message: entry.message,
+        })
+        .collect())
+}
+
+/// Parse a stash name given as `stash@{N}` or `N`.
+pub fn parse_stash_index(name: &str) -> Result<usize> {
+    let index = match name.strip_prefix("stash@{") {
+        Some(rest) => rest.strip_suffix('}').unwrap_or(""),
+        None => name,
+    };
+    index
+        .parse::<usize>()
+        .map_err(|_| anyhow!("Not a valid stash name: {}", name))
+}
+
+pub fn list() -> Result<Vec<StashEntry>> {
+    read_log()
+}
+
+pub fn get_stash(index: usize) -> Result<StashEntry> {
+    read_log()?
+        .into_iter()
+        .nth(index)
+        .ok_or_else(|| anyhow!("stash@{{{}}} is not a valid stash entry.", index))
+}
+
+/// Save the working tree as a stash commit on top of HEAD, and restore the
+/// working tree to HEAD.
+pub fn push(message: Option<&str>, ignore_options: &[String]) -> Result<ObjectId> {
+    let head_oid = RefValue::get_ref("HEAD", true)?
+        .map(|head| head.value)
+        .filter(|oid| !oid.is_empty())
+        .ok_or_else(|| anyhow!("You do not have the initial commit yet."))?;
+    let head_commit = Commit::get_commit(&head_oid)?;
+    let diff = diff_trees(
+        &Tree::get_commit_tree(&head_oid)?,
+        &Tree::get_working_tree(ignore_options)?,
+    )?;
+    if diff.is_empty() {
+        return Err(anyhow!("No local changes to save."));
+    }
+
+    let branch = match RefValue::get_branch_name()? {
+        Some(branch) => branch,
+        None => String::from("(no branch)"),
+    };
+    let message = match message {
+        Some(message) => format!("On {}: {}", branch, message),
+        None => format!(
+            "WIP on {}: {} {}",
+            branch,
+            &head_oid[..7],
+            head_commit.message
+        ),
+    };
+
+    let tree_oid = Tree::write_tree(".", ignore_options)?;
+    let oid = Commit::create(&tree_oid, Some(&head_oid), &message)?;
+
+    let ref_value = RefValue::new(Some(&oid), false, &oid);
+    RefValue::update_ref(STASH_REF, &ref_value, false, &message)?;
+
+    Tree::read_tree(&head_commit.tree, ignore_options)?;
+    Ok(oid)
+}
+
+/// Display the changes recorded in a stash entry.
+pub fn show(index: usize, options: &DiffOptions) -> Result<()> {
+    let entry = get_stash(index)?;
+    let commit = Commit::get_commit(&entry.oid)?;
+    let parent = commit
+        .parent
+        .ok_or_else(|| anyhow!("A stash commit must have a parent."))?;
+    let tree = get_object(&commit.tree, TypeObject::Tree)?;
+    let diff = diff_trees(&Tree::get_commit_tree(&parent)?, &Tree::get_tree(&tree)?)?;
+    print_diff(&diff, options)
+}
+
+/// Apply a stash entry onto the working tree with a three-way merge between
+/// the commit the stash was created on, HEAD and the stashed tree.
+pub fn apply(index: usize, ignore_options: &[String]) -> Result<()> {
+    ensure_clean_working_tree("stash apply", ignore_options)?;
+
+    let entry = get_stash(index)?;
+    let commit = Commit::get_commit(&entry.oid)?;
+    let parent = commit
+        .parent
+        .ok_or_else(|| anyhow!("A stash commit must have a parent."))?;
+    let tree = get_object(&commit.tree, TypeObject::Tree)?;
+    let label = format!("stash@{{{}}}", index);
+    let conflicts = apply_tree_changes(
+        Tree::get_commit_tree(&parent)?,
+        Tree::get_tree(&tree)?,
+        &label,
+        ignore_options,
+    )?;
+    if !conflicts.is_empty() {
+        return Err(anyhow!(
+            "Conflicts in: {}\nThe stash entry is kept in case you need it again.",
+            conflicts.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Remove a stash entry and return its commit hash.
+pub fn drop(index: usize) -> Result<String> {
+    let mut entries = reflog::read(STASH_REF)?;
+    if index >= entries.len() {
+        return Err(anyhow!("stash@{{{}}} is not a valid stash entry.", index));
+    }
+    let entry = entries.remove(index);
+
+    // Keep old values chained to the remaining entries.
+    for i in 0..entries.len() {
+        entries[i].old_oid = match entries.get(i + 1) {
+            Some(older) => older.new_oid.to_owned(),
+            None => HashAlgorithm::of(&entries[i].new_oid).zero_oid().into(),
+        };
+    }
+    reflog::write(STASH_REF, &entries)?;
+
+    // Point `refs/stash` at the newest entry, or remove it when nothing is left.
+    match entries.first() {
+        Some(newest) => {
+            let ref_value = RefValue::new(Some(&newest.new_oid), false, &newest.new_oid);
+            RefValue::write_ref(STASH_REF, &ref_value)?;
+        }
+        None => RefValue::delete_ref(STASH_REF)?,
+    }
+    Ok(entry.new_oid)
+}
+
+pub fn pop(index: usize, ignore_options: &[String]) -> Result<String> {
+    apply(index, ignore_options)?;
+    drop(index)
+}
@@ -0,0 +1,135 @@
+use crate::data::{get_object, hash_object, read_object, TypeObject};
+use crate::hash::ObjectId;
+use crate::identity::{get_identity, get_timestamp};
+use crate::reference::{check_ref_format, RefValue};
+use crate::reflog;
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq)]
+pub struct Tag {
+    pub object: ObjectId,
+    pub obj_type: TypeObject,
+    pub tag: String,
+    pub tagger: String,
+    pub message: String,
+}
+
+impl Tag {
+    pub fn get_tag(oid: &str) -> Result<Self> {
+        let tag_obj = get_object(oid, TypeObject::Tag)?;
+
+        // Parse each line from below tag format:
+        //   object [object hash]
+        //   type [object type]
+        //   tag [tag name]
+        //   tagger [name] <[email]> [seconds] [timezone]
+        //
+        //   [tag message]
+        let (header, message) = tag_obj
+            .split_once("\n\n")
+            .ok_or_else(|| anyhow!("Tag object must have a message: {}", oid))?;
+
+        let mut object = None;
+        let mut obj_type = None;
+        let mut tag = None;
+        let mut tagger = None;
+        for line in header.lines() {
+            match line.split_once(' ') {
+                Some(("object", value)) => object = Some(ObjectId::parse(value)?),
+                Some(("type", value)) => {
+                    obj_type = Some(
+                        TypeObject::from_str(value)
+                            .map_err(|_| anyhow!("Unknown object type: {}", value))?,
+                    )
+                }
+                Some(("tag", value)) => tag = Some(value.to_owned()),
+                Some(("tagger", value)) => tagger = Some(value.to_owned()),
+                _ => return Err(anyhow!("Unknown tag header: {}", line)),
+            }
+        }
+
+        let missing = |field: &str| anyhow!("Tag object expected including {}: {}", field, oid);
+        Ok(Tag {
+            object: object.ok_or_else(|| missing("object"))?,
+            obj_type: obj_type.ok_or_else(|| missing("type"))?,
+            tag: tag.ok_or_else(|| missing("tag"))?,
+            tagger: tagger.ok_or_else(|| missing("tagger"))?,
+            message: message.trim_end_matches('\n').to_owned(),
+        })
+    }
+
+    /// Write the tag as a tag object, and return its hash.
+    pub fn write(&self) -> Result<ObjectId> {
+        let tag = format!(
+            "object {}\ntype {}\ntag {}\ntagger {}\n\n{}\n",
+            self.object, self.obj_type, self.tag, self.tagger, self.message
+        );
+        hash_object(&tag, TypeObject::Tag)
+    }
+
+    /// Write an annotated tag object pointing `oid`, and create a reference
+    /// `refs/tags/<name>` to the tag object.
+    pub fn create(name: &str, oid: &str, message: &str) -> Result<ObjectId> {
+        check_ref_format(&format!("refs/tags/{}", name), false)?;
+        let (obj_type, _) = read_object(oid)?;
+        let tag = Tag {
+            object: ObjectId::parse(oid)?,
+            obj_type,
+            tag: name.to_owned(),
+            tagger: format!("{} {}", get_identity(), get_timestamp()),
+            message: message.to_owned(),
+        };
+        let tag_oid = tag.write()?;
+
+        let ref_value = RefValue::new(Some(&tag_oid), false, &tag_oid);
+        let log_message = format!("tag: tagging {} ({})", oid, name);
+        RefValue::update_ref(
+            &format!("refs/tags/{}", name),
+            &ref_value,
+            false,
+            &log_message,
+        )?;
+        Ok(tag_oid)
+    }
+}
+
+// Match a name with a shell glob pattern, supporting `*` and `?`.
+fn match_pattern(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|i| match_pattern(&pattern[1..], &name[i..])),
+        Some('?') => !name.is_empty() && match_pattern(&pattern[1..], &name[1..]),
+        Some(c) => name.first() == Some(c) && match_pattern(&pattern[1..], &name[1..]),
+    }
+}
+
+/// List tag names in sorted order, filtered by a glob pattern if given.
+pub fn list_tags(pattern: Option<&str>) -> Result<Vec<String>> {
+    let mut tags: Vec<String> = RefValue::get_refs(Some("."), "refs/tags/")?
+        .into_iter()
+        .filter(|name| name != "HEAD")
+        .filter(|name| match pattern {
+            Some(pattern) => {
+                let pattern: Vec<char> = pattern.chars().collect();
+                let name: Vec<char> = name.chars().collect();
+                match_pattern(&pattern, &name)
+            }
+            None => true,
+        })
+        .collect();
+    tags.sort();
+    Ok(tags)
+}
+
+/// Delete a tag reference and return the hash it pointed to.
+pub fn delete_tag(name: &str) -> Result<String> {
+    let ref_name = format!("refs/tags/{}", name);
+    let oid = match RefValue::get_ref(&ref_name, false)? {
+        Some(ref_value) => ref_value.value,
+        None => return Err(anyhow!("A tag is not found: {}", name)),
+    };
+    RefValue::delete_ref(&ref_name)?;
+    reflog::delete(&ref_name)?;
+    Ok(oid)
+}
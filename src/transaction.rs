@@ -0,0 +1,245 @@
+use crate::data::dsgit_dir;
+use crate::hash::is_zero_oid;
+use crate::reference::{check_ref_format, remove_packed_refs, RefValue};
+use crate::reflog;
+
+use std::collections::HashSet;
+use std::fs::{self, create_dir_all, remove_file, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+/// An exclusive lock of a file taken by creating `<path>.lock`. New contents
+/// are written into the lock file and renamed into place on `commit`, the
+/// lock file is removed if dropped without committing.
+#[derive(Debug)]
+pub struct LockFile {
+    path: String,
+    lock_path: String,
+    committed: bool,
+}
+
+impl LockFile {
+    pub fn acquire(path: &str) -> Result<Self> {
+        let lock_path = format!("{}.lock", path);
+        create_dir_all(Path::new(path).parent().unwrap())?;
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(_) => Ok(LockFile {
+                path: path.to_owned(),
+                lock_path,
+                committed: false,
+            }),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(anyhow!(
+                "Unable to create '{}': File exists.\n\
+                 Another dsgit process seems to be running, or a previous one crashed. \
+                 Remove the file manually to continue if no other process is running.",
+                lock_path
+            )),
+            Err(e) => Err(e).with_context(|| format!("Failed to create lock file: {}", lock_path)),
+        }
+    }
+
+    pub fn write(&mut self, contents: &str) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&self.lock_path)
+            .with_context(|| format!("Failed to open lock file: {}", self.lock_path))?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Replace the locked file with the contents written to the lock file.
+    pub fn commit(mut self) -> Result<()> {
+        fs::rename(&self.lock_path, &self.path)
+            .with_context(|| format!("Failed to rename lock file: {}", self.lock_path))?;
+        self.committed = true;
+        Ok(())
+    }
+
+    /// Remove the locked file, and release the lock.
+    pub fn commit_delete(mut self) -> Result<()> {
+        if Path::new(&self.path).is_file() {
+            remove_file(&self.path)
+                .with_context(|| format!("Failed to remove file: {}", self.path))?;
+        }
+        remove_file(&self.lock_path)
+            .with_context(|| format!("Failed to remove lock file: {}", self.lock_path))?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = remove_file(&self.lock_path);
+        }
+    }
+}
+
+// Clean up directories left empty under `refs/` after removing a reference.
+pub(crate) fn remove_empty_dirs(ref_path: &str) {
+    let mut parent = Path::new(ref_path).parent();
+    while let Some(dir) = parent {
+        if dir.ends_with("refs") || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+}
+
+#[derive(Debug)]
+struct RefUpdate {
+    refs: String,
+    // A new value of the reference, `None` means deletion.
+    new_value: Option<RefValue>,
+    deref: bool,
+    expected_old: Option<String>,
+    message: String,
+}
+
+/// A set of reference updates applied all-or-nothing. Every reference is
+/// locked and compared with its expected old value before any of them is
+/// written, so a failure leaves all references untouched.
+#[derive(Debug, Default)]
+pub struct RefTransaction {
+    updates: Vec<RefUpdate>,
+}
+
+impl RefTransaction {
+    pub fn new() -> Self {
+        RefTransaction { updates: vec![] }
+    }
+
+    /// Queue an update of `refs` to `new_value`. With `expected_old`, the
+    /// reference must currently have that value, and a zero oid means the
+    /// reference must not exist yet.
+    pub fn update(
+        &mut self,
+        refs: &str,
+        new_value: RefValue,
+        deref: bool,
+        expected_old: Option<&str>,
+        message: &str,
+    ) -> &mut Self {
+        self.updates.push(RefUpdate {
+            refs: refs.to_owned(),
+            new_value: Some(new_value),
+            deref,
+            expected_old: expected_old.map(|oid| oid.to_owned()),
+            message: message.to_owned(),
+        });
+        self
+    }
+
+    /// Queue a deletion of `refs`, from both loose and packed references.
+    pub fn delete(&mut self, refs: &str, expected_old: Option<&str>) -> &mut Self {
+        self.updates.push(RefUpdate {
+            refs: refs.to_owned(),
+            new_value: None,
+            deref: false,
+            expected_old: expected_old.map(|oid| oid.to_owned()),
+            message: String::new(),
+        });
+        self
+    }
+
+    pub fn commit(self) -> Result<()> {
+        // Resolve symbolic references, and lock in a stable order.
+        let mut resolved = vec![];
+        let mut names = HashSet::new();
+        for update in self.updates.into_iter() {
+            check_ref_format(&update.refs, false)?;
+            let ref_name = RefValue::resolve_ref_name(&update.refs, update.deref)?;
+            if !names.insert(ref_name.to_owned()) {
+                return Err(anyhow!(
+                    "A reference is updated twice in a transaction: {}",
+                    ref_name
+                ));
+            }
+            resolved.push((ref_name, update));
+        }
+        resolved.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut locks = vec![];
+        for (ref_name, _) in resolved.iter() {
+            locks.push(LockFile::acquire(&format!("{}/{}", dsgit_dir(), ref_name))?);
+        }
+
+        // Compare and prepare every reference before touching any of them.
+        let mut old_oids = vec![];
+        for ((ref_name, update), lock) in resolved.iter().zip(locks.iter_mut()) {
+            let old_oid = RefValue::get_ref(ref_name, true)?.map(|old| old.value);
+            if let Some(expected) = &update.expected_old {
+                let matched = match &old_oid {
+                    Some(old_oid) => old_oid == expected,
+                    None => is_zero_oid(expected),
+                };
+                if !matched {
+                    return Err(anyhow!(
+                        "Cannot update {}: expected {}, but it is {}",
+                        ref_name,
+                        expected,
+                        old_oid.as_deref().unwrap_or("missing")
+                    ));
+                }
+            }
+            match &update.new_value {
+                Some(new_value) => lock.write(&new_value.contents())?,
+                None => {
+                    if RefValue::get_ref(ref_name, false)?.is_none() {
+                        return Err(anyhow!("A reference is not found: {}", ref_name));
+                    }
+                }
+            }
+            old_oids.push(old_oid);
+        }
+
+        let deleted: Vec<&str> = resolved
+            .iter()
+            .filter(|(_, update)| update.new_value.is_none())
+            .map(|(ref_name, _)| ref_name.as_str())
+            .collect();
+        if !deleted.is_empty() {
+            remove_packed_refs(&deleted)?;
+        }
+
+        for ((ref_name, update), lock) in resolved.iter().zip(locks) {
+            match &update.new_value {
+                Some(_) => lock.commit()?,
+                None => {
+                    lock.commit_delete()?;
+                    remove_empty_dirs(&format!("{}/{}", dsgit_dir(), ref_name));
+                }
+            }
+        }
+
+        // Record the updates into reflogs of the written references and of
+        // the symbolic references pointing to them, like HEAD.
+        for ((ref_name, update), old_oid) in resolved.iter().zip(old_oids.iter()) {
+            let new_value = match &update.new_value {
+                Some(new_value) => new_value,
+                None => continue,
+            };
+            let new_oid = if new_value.symbolic {
+                RefValue::get_ref(&new_value.value, true)?.map(|new| new.value)
+            } else {
+                Some(new_value.value.to_owned())
+            };
+            if let Some(new_oid) = new_oid {
+                reflog::append(ref_name, old_oid.as_deref(), &new_oid, &update.message)?;
+                if *ref_name != update.refs {
+                    reflog::append(&update.refs, old_oid.as_deref(), &new_oid, &update.message)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,367 @@
+use crate::bundle::Bundle;
+use crate::data::{object_exists, set_dsgit_dir};
+use crate::http;
+use crate::pack::{read_pack, write_pack};
+use crate::pktline;
+use crate::protocol::{
+    check_report, objects_to_send, serve_receive_pack, serve_upload_pack, FetchRequest,
+    ObjectFilter, RefAdvertisement, RefUpdate,
+};
+use crate::reference::RefValue;
+use crate::remote::{get_ref_oid, remote_dsgit_dir};
+use crate::shallow;
+
+use std::env;
+use std::io::{self, BufRead, BufReader, PipeReader, PipeWriter, Write};
+use std::process::{ChildStdin, ChildStdout, Command, Stdio};
+use std::thread;
+
+use anyhow::{anyhow, Context, Result};
+
+/// Whether a remote is given by a URL rather than a local path.
+pub fn is_url(url: &str) -> bool {
+    ["http://", "ssh://", "ext::"]
+        .iter()
+        .any(|prefix| url.starts_with(prefix))
+}
+
+/// A repository of `ssh://[user@]host[:port]/path`.
+#[derive(Debug, PartialEq)]
+pub struct SshUrl {
+    pub host: String,
+    pub port: Option<String>,
+    pub path: String,
+}
+
+impl SshUrl {
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url.trim_start_matches("ssh://");
+        let (host, path) = rest
+            .find('/')
+            .map(|index| rest.split_at(index))
+            .ok_or_else(|| anyhow!("A repository path is missing in URL: {}", url))?;
+        let (host, port) = match host.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host, None),
+        };
+        // ssh would take such a host as an option.
+        if host.is_empty() || host.starts_with('-') {
+            return Err(anyhow!("Invalid host in URL: {}", url));
+        }
+        if let Some(port) = port {
+            if port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(anyhow!("Invalid port in URL: {}", url));
+            }
+        }
+        Ok(SshUrl {
+            host: host.to_owned(),
+            port: port.map(|port| port.to_owned()),
+            path: path.to_owned(),
+        })
+    }
+
+    // Run `dsgit <service> <path>` on the host. The ssh program can be
+    // replaced by `DSGIT_SSH_COMMAND`, split at whitespace without a shell.
+    fn command(&self, service: &str) -> Command {
+        let ssh = env::var("DSGIT_SSH_COMMAND").unwrap_or_else(|_| String::from("ssh"));
+        let mut words = ssh.split_whitespace();
+        let mut command = Command::new(words.next().unwrap_or("ssh"));
+        command.args(words);
+        if let Some(port) = &self.port {
+            command.args(["-p", port]);
+        }
+        // ssh joins the arguments into a command line of a shell on the host,
+        // so the path is quoted for it.
+        command.args([&self.host, "dsgit", service, &shell_quote(&self.path)]);
+        command
+    }
+}
+
+// Quote a word for a POSIX shell.
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+// Run a command of an `ext::` URL through the shell, which the user wrote
+// on purpose.
+fn shell_command(command: &str) -> Command {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut shell = Command::new(shell);
+    shell.arg(flag).arg(command);
+    shell
+}
+
+/// A way to reach a remote repository. A local repository is served on
+/// another thread, a repository served by `dsgit serve` is reached over
+/// HTTP, and any other repository is reached through a command running
+/// `dsgit upload-pack` or `dsgit receive-pack` on the other side.
+#[derive(Debug, PartialEq)]
+pub enum Transport {
+    // The `.dsgit` directory of a repository on this filesystem.
+    Local(String),
+    // The URL of a served repository, without a trailing slash.
+    Http(String),
+    // A shell command talking packets over its standard input and output,
+    // with `%s` replaced by `upload-pack` or `receive-pack`.
+    Command(String),
+    // A repository on another host, reached by running `dsgit` through ssh.
+    Ssh(SshUrl),
+    // The path of a bundle file, which can only be fetched from.
+    Bundle(String),
+}
+
+// Run a transport command, and talk to it with `f`. The command must exit
+// successfully after its standard input is closed.
+fn run_command<T>(
+    mut command: Command,
+    f: impl FnOnce(&mut ChildStdin, &mut BufReader<ChildStdout>) -> Result<T>,
+) -> Result<T> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run: {:?}", command))?;
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let result = f(&mut stdin, &mut stdout);
+    drop(stdin);
+    let status = child.wait()?;
+    let value = result?;
+    if !status.success() {
+        return Err(anyhow!("`{:?}` failed with {}", command, status));
+    }
+    Ok(value)
+}
+
+// Serve `service` of the repository of the `.dsgit` directory `dir` on
+// another thread, which acts on that repository, and talk to it with `f`
+// like to a transport command.
+fn run_local<T>(
+    dir: &str,
+    service: &str,
+    f: impl FnOnce(&mut PipeWriter, &mut BufReader<PipeReader>) -> Result<T>,
+) -> Result<T> {
+    let (mut input, mut stdin) = io::pipe()?;
+    let (stdout, mut output) = io::pipe()?;
+    let server = {
+        let dir = dir.to_owned();
+        let service = service.to_owned();
+        thread::spawn(move || {
+            set_dsgit_dir(&dir);
+            match service.as_str() {
+                "upload-pack" => serve_upload_pack(&mut input, &mut output),
+                _ => serve_receive_pack(&mut input, &mut output),
+            }
+        })
+    };
+    let mut stdout = BufReader::new(stdout);
+    let result = f(&mut stdin, &mut stdout);
+    // The service reads the end of its input, and fails to write what is
+    // left unread instead of blocking.
+    drop(stdin);
+    drop(stdout);
+    let served = server
+        .join()
+        .unwrap_or_else(|_| Err(anyhow!("The {} of {} panicked", service, dir)));
+    let value = result?;
+    served?;
+    Ok(value)
+}
+
+/// Limits of objects copied by a fetch.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FetchOptions {
+    // Commits only up to this number of commits from each wanted object.
+    pub depth: Option<usize>,
+    // Objects left out, which a partial clone fetches later when needed.
+    pub filter: Option<ObjectFilter>,
+}
+
+// A request for objects reachable from `wants`. Every reference of the
+// current repository tells what the remote does not need to send.
+fn fetch_request(wants: &[String], options: &FetchOptions) -> Result<FetchRequest> {
+    let mut haves = vec![];
+    for name in RefValue::get_refs(None, ".")?.iter() {
+        if let Some(oid) = get_ref_oid(name)? {
+            haves.push(oid);
+        }
+    }
+    Ok(FetchRequest {
+        wants: wants.to_vec(),
+        haves,
+        shallows: shallow::read()?.into_iter().map(String::from).collect(),
+        depth: options.depth,
+        filter: options.filter,
+    })
+}
+
+impl Transport {
+    // The process of `service` for a transport talking over a command.
+    fn service_command(&self, service: &str) -> Option<Command> {
+        match self {
+            Transport::Command(command) => Some(shell_command(&command.replace("%s", service))),
+            Transport::Ssh(url) => Some(url.command(service)),
+            _ => None,
+        }
+    }
+
+    // Talk to `service` of a remote reached over a pipe with `f`, which is
+    // a local repository or a transport command.
+    fn run_service<T>(
+        &self,
+        service: &str,
+        f: impl FnOnce(&mut dyn Write, &mut dyn BufRead) -> Result<T>,
+    ) -> Result<T> {
+        match self {
+            Transport::Local(dir) => run_local(dir, service, |stdin, stdout| f(stdin, stdout)),
+            _ => {
+                let command = self.service_command(service).unwrap();
+                run_command(command, |stdin, stdout| f(stdin, stdout))
+            }
+        }
+    }
+
+    /// Choose a transport by a URL or a path. `ext::<command>` runs the
+    /// command with `%s` in it replaced by the service through the shell,
+    /// and `ssh://host/path` runs `dsgit` on the host through ssh without a
+    /// local shell. A path to a
+    /// bundle file is read as a remote.
+    pub fn open(url: &str) -> Result<Self> {
+        if url.starts_with("http://") {
+            Ok(Transport::Http(url.trim_end_matches('/').to_owned()))
+        } else if let Some(command) = url.strip_prefix("ext::") {
+            if !command.contains("%s") {
+                return Err(anyhow!(
+                    "A command must contain '%s' for a service: {}",
+                    url
+                ));
+            }
+            Ok(Transport::Command(command.to_owned()))
+        } else if url.starts_with("ssh://") {
+            Ok(Transport::Ssh(SshUrl::parse(url)?))
+        } else if Bundle::is_bundle(url) {
+            Ok(Transport::Bundle(url.to_owned()))
+        } else {
+            Ok(Transport::Local(remote_dsgit_dir(url)?))
+        }
+    }
+
+    /// Branches, tags and HEAD of the remote.
+    pub fn list_refs(&self) -> Result<RefAdvertisement> {
+        match self {
+            Transport::Http(url) => {
+                let url = format!("{}/info/refs?service=dsgit-upload-pack", url);
+                String::from_utf8(http::request("GET", &url, None)?)?.parse()
+            }
+            Transport::Bundle(path) => Ok(Bundle::open(path)?.advertisement()),
+            Transport::Local(_) | Transport::Command(_) | Transport::Ssh(_) => {
+                self.run_service("upload-pack", |stdin, stdout| {
+                    let advertisement = pktline::read_lines(stdout)?;
+                    pktline::write_flush(stdin)?;
+                    (advertisement.join("\n") + "\n").parse()
+                })
+            }
+        }
+    }
+
+    /// Copy objects reachable from `wants` which are missing in the current
+    /// repository, and return the number of copied objects.
+    pub fn fetch(&self, wants: &[String]) -> Result<usize> {
+        self.fetch_with(wants, &FetchOptions::default())
+    }
+
+    /// Copy objects like `fetch`, limited by `options`. Commits whose
+    /// parents are left missing by a depth are recorded as shallow.
+    pub fn fetch_with(&self, wants: &[String], options: &FetchOptions) -> Result<usize> {
+        let missing: Vec<String> = wants
+            .iter()
+            .filter(|oid| !object_exists(oid))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            return Ok(0);
+        }
+        let count = self.send_request(&fetch_request(&missing, options)?)?;
+        if options.depth.is_some() || shallow::is_shallow_repository() {
+            shallow::update(wants)?;
+        }
+        Ok(count)
+    }
+
+    /// Copy objects left out of a partial clone. The remote is not told
+    /// references of the current repository, since objects reachable from
+    /// them may be the ones missing.
+    pub fn fetch_objects(&self, oids: &[String], filter: Option<ObjectFilter>) -> Result<usize> {
+        self.send_request(&FetchRequest {
+            wants: oids.to_vec(),
+            filter,
+            ..Default::default()
+        })
+    }
+
+    // Copy objects of a request from the remote, and return the number of
+    // copied objects.
+    fn send_request(&self, request: &FetchRequest) -> Result<usize> {
+        match self {
+            Transport::Http(url) => {
+                let request = format!("{}done\n", request);
+                let url = format!("{}/dsgit-upload-pack", url);
+                let pack = http::request("POST", &url, Some(request.as_bytes()))?;
+                read_pack(&mut pack.as_slice())
+            }
+            Transport::Local(_) | Transport::Command(_) | Transport::Ssh(_) => {
+                self.run_service("upload-pack", |stdin, stdout| {
+                    pktline::read_lines(stdout)?;
+                    let request = request.to_string();
+                    let lines: Vec<String> = request.lines().map(|l| l.to_owned()).collect();
+                    pktline::write_lines(stdin, &lines)?;
+                    let pack = pktline::read_data(stdout)?;
+                    read_pack(&mut pack.as_slice())
+                })
+            }
+            Transport::Bundle(path) => {
+                if request.depth.is_some() || request.filter.is_some() {
+                    return Err(anyhow!("A bundle can only be fetched as a whole: {}", path));
+                }
+                Bundle::open(path)?.unbundle()
+            }
+        }
+    }
+
+    /// Send objects of the current repository needed by `updates`, and
+    /// update references of the remote.
+    pub fn push(&self, updates: &[RefUpdate]) -> Result<()> {
+        let oids: Vec<String> = updates.iter().map(|u| u.new_oid.to_owned()).collect();
+        match self {
+            Transport::Http(url) => {
+                let haves = self.list_refs()?.oids();
+                let mut request = vec![];
+                for update in updates.iter() {
+                    request.extend_from_slice(format!("{}\n", update).as_bytes());
+                }
+                request.push(b'\n');
+                write_pack(&mut request, &objects_to_send(&oids, &haves)?)?;
+                let url = format!("{}/dsgit-receive-pack", url);
+                let report = http::request("POST", &url, Some(&request))?;
+                check_report(&String::from_utf8(report)?)
+            }
+            Transport::Local(_) | Transport::Command(_) | Transport::Ssh(_) => {
+                self.run_service("receive-pack", |stdin, stdout| {
+                    let advertisement: RefAdvertisement =
+                        (pktline::read_lines(stdout)?.join("\n") + "\n").parse()?;
+                    let lines: Vec<String> = updates.iter().map(|u| u.to_string()).collect();
+                    pktline::write_lines(stdin, &lines)?;
+                    let mut pack = vec![];
+                    write_pack(&mut pack, &objects_to_send(&oids, &advertisement.oids())?)?;
+                    pktline::write_data(stdin, &pack)?;
+                    check_report(&pktline::read_lines(stdout)?.join("\n"))
+                })
+            }
+            Transport::Bundle(path) => Err(anyhow!("Unable to push into a bundle: {}", path)),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use serial_test::serial;
+use std::fs;
+use std::path::Path;
+
+use common::setup;
+use dsgit::apply::{apply_patch, HunkOutcome, Patch};
+use dsgit::diff::{diff_trees, patch};
+use dsgit::entry::Tree;
+
+#[test]
+#[serial]
+fn test_apply_diff_patch() {
+    setup();
+    let poem: String = (1..=10).map(|n| format!("line {}\n", n)).collect();
+    fs::write("./poem.txt", &poem).unwrap();
+    fs::write("./tiger.txt", "Tora").unwrap();
+    let from_tree = Tree::new(".", &[]).unwrap();
+    let f_oid = Tree::write_tree(".", &[]).unwrap();
+
+    fs::remove_file("./cat.txt").unwrap();
+    fs::write("./poem.txt", poem.replace("line 5\n", "line five\n")).unwrap();
+    fs::rename("./poem.txt", "./verse.txt").unwrap();
+    fs::write("./tiger.txt", "ToraTora").unwrap();
+    fs::write("./dragon.txt", "Ryuu\n").unwrap();
+    let to_tree = Tree::new(".", &[]).unwrap();
+    let diff = diff_trees(&from_tree, &to_tree).unwrap();
+    let patch: Patch = patch(&diff, 3).unwrap().parse().unwrap();
+    assert_eq!(patch.files.len(), 4);
+    assert_eq!(patch.files[0].new_path, None);
+    assert_eq!(patch.files[1].old_path, None);
+    assert_eq!(patch.files[3].name(), "poem.txt => verse.txt");
+
+    // Undo the changes with the reversed patch.
+    let reports = apply_patch(&patch.reversed(), false).unwrap();
+    assert!(reports.iter().all(|report| report.is_clean()));
+    assert_eq!(fs::read_to_string("./cat.txt").unwrap(), "cat cat\n");
+    assert_eq!(fs::read_to_string("./poem.txt").unwrap(), poem);
+    assert_eq!(fs::read_to_string("./tiger.txt").unwrap(), "Tora");
+    assert!(!Path::new("./dragon.txt").exists());
+    assert!(!Path::new("./verse.txt").exists());
+
+    // Checking does not touch the working tree.
+    let reports = apply_patch(&patch, true).unwrap();
+    assert!(reports.iter().all(|report| report.is_clean()));
+    assert!(Path::new("./cat.txt").exists());
+
+    // Redo the changes, then they can not be applied again.
+    apply_patch(&patch, false).unwrap();
+    let redone = diff_trees(&to_tree, &Tree::new(".", &[]).unwrap()).unwrap();
+    assert!(redone.is_empty());
+    assert!(apply_patch(&patch, true).is_err());
+
+    // Teardown, restore the files.
+    fs::remove_file("./dragon.txt").unwrap();
+    fs::remove_file("./verse.txt").unwrap();
+    Tree::read_tree(&f_oid, &[]).unwrap();
+    fs::remove_file("./poem.txt").unwrap();
+    fs::remove_file("./tiger.txt").unwrap();
+}
+
+#[test]
+#[serial]
+fn test_apply_offset_fuzz_and_reject() {
+    setup();
+    let poem: String = (1..=20).map(|n| format!("line {}\n", n)).collect();
+    // Two lines are added at the top, and the context of the second hunk is
+    // changed since the patch was made.
+    let changed = format!("line 0\nline 00\n{}", poem).replace("line 16\n", "line sixteen\n");
+    fs::write("./poem.txt", &changed).unwrap();
+    let patch: Patch = "\
+--- a/poem.txt
++++ b/poem.txt
+@@ -2,3 +2,3 @@
+ line 2
+-line 3
++line three
+ line 4
+@@ -13,3 +13,3 @@
+ line 13
+-line 14
++line fourteen
+ line 15
+@@ -15,3 +15,3 @@
+ line 15
+-line 16
++line 16!
+ line 17
+@@ -18,3 +18,3 @@
+ line 18
+-line 19
++line nineteen
+ line 19
+"
+    .parse()
+    .unwrap();
+
+    let reports = apply_patch(&patch, false).unwrap();
+    assert_eq!(reports.len(), 1);
+    assert_eq!(
+        reports[0].hunks,
+        vec![
+            HunkOutcome::Applied {
+                line: 4,
+                offset: 2,
+                fuzz: 0
+            },
+            HunkOutcome::Applied {
+                line: 15,
+                offset: 2,
+                fuzz: 0
+            },
+            HunkOutcome::Failed { line: 17 },
+            HunkOutcome::Applied {
+                line: 20,
+                offset: 2,
+                fuzz: 1
+            },
+        ]
+    );
+    assert_eq!(reports[0].reject_file.as_deref(), Some("poem.txt.rej"));
+    let expected = changed
+        .replace("line 3\n", "line three\n")
+        .replace("line 14\n", "line fourteen\n")
+        .replace("line 19\n", "line nineteen\n");
+    assert_eq!(fs::read_to_string("./poem.txt").unwrap(), expected);
+    assert_eq!(
+        fs::read_to_string("./poem.txt.rej").unwrap(),
+        "\
+--- a/poem.txt
++++ b/poem.txt
+@@ -15,3 +15,3 @@
+ line 15
+-line 16
++line 16!
+ line 17
+"
+    );
+
+    // Teardown, remove the files.
+    fs::remove_file("./poem.txt").unwrap();
+    fs::remove_file("./poem.txt.rej").unwrap();
+}
+
+#[test]
+#[serial]
+fn test_apply_refuses_paths_outside() {
+    setup();
+    for header in [
+        "--- /dev/null\n+++ b//tmp/dsgit_apply_x\n",
+        "--- /dev/null\n+++ b/../dsgit_apply_x\n",
+        "--- /dev/null\n+++ b/.dsgit/HEAD\n",
+        "diff --git a/cat.txt b/cat.txt\nrename from cat.txt\nrename to ../../dsgit_apply_x\n",
+    ]
+    .iter()
+    {
+        let patch: Patch = format!("{}@@ -0,0 +1 @@\n+x\n", header).parse().unwrap();
+        assert!(apply_patch(&patch, true).is_err(), "{}", header);
+        assert!(apply_patch(&patch, false).is_err(), "{}", header);
+    }
+    assert!(!Path::new("/tmp/dsgit_apply_x").exists());
+    assert!(!Path::new("../dsgit_apply_x").exists());
+    assert!(!Path::new("../../../dsgit_apply_x").exists());
+    assert_eq!(fs::read_to_string("./cat.txt").unwrap(), "cat cat\n");
+}
+
+#[test]
+fn test_parse_broken_hunk_line() {
+    // A line of a hunk which starts with a multibyte character is broken.
+    let patch = "--- a/f.txt\n+++ b/f.txt\n@@ -1,2 +1,2 @@\n a\n\u{e9}\n";
+    let err = patch.parse::<Patch>().unwrap_err();
+    assert_eq!(err.to_string(), "A hunk is broken at a line: \u{e9}");
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use serial_test::serial;
+use std::fs;
+
+use common::{setup, with_dsgit_dir};
+use dsgit::bundle::Bundle;
+use dsgit::commit::Commit;
+use dsgit::data::{get_oid, object_exists};
+use dsgit::reference::{self, RefValue};
+use dsgit::remote::{self, Remote};
+use dsgit::transport::Transport;
+
+const BUNDLE_FILE: &str = "../test.bundle";
+const REMOTE_DIR: &str = "../test_bundle_remote.dsgit";
+
+fn cleanup() {
+    fs::remove_file(BUNDLE_FILE).unwrap();
+    let _ = fs::remove_dir_all(REMOTE_DIR);
+}
+
+#[test]
+#[serial]
+fn bundle_create_and_clone() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    reference::create_tag("tag1", &oid1).unwrap();
+    let revs = vec!["main".to_owned(), "HEAD".to_owned(), "tag1".to_owned()];
+    let bundle = Bundle::create(BUNDLE_FILE, &revs).unwrap();
+    assert!(Bundle::is_bundle(BUNDLE_FILE));
+    assert_eq!(Bundle::open(BUNDLE_FILE).unwrap(), bundle);
+    assert!(bundle.prerequisites.is_empty());
+    assert_eq!(
+        bundle.refs,
+        vec![
+            ("refs/heads/main".to_owned(), oid1.to_string()),
+            ("HEAD".to_owned(), oid1.to_string()),
+            ("refs/tags/tag1".to_owned(), oid1.to_string()),
+        ]
+    );
+    bundle.verify().unwrap();
+
+    let advertisement = bundle.advertisement();
+    assert_eq!(
+        advertisement.head_branch.as_deref(),
+        Some("refs/heads/main")
+    );
+    assert_eq!(advertisement.head_oid.as_deref(), Some(oid1.as_str()));
+    assert_eq!(
+        Transport::open(BUNDLE_FILE).unwrap(),
+        Transport::Bundle(BUNDLE_FILE.to_owned())
+    );
+
+    let _ = fs::remove_dir_all(REMOTE_DIR);
+    with_dsgit_dir(REMOTE_DIR, || {
+        remote::clone(BUNDLE_FILE, true).unwrap();
+        assert_eq!(get_oid("main").unwrap(), oid1);
+        assert_eq!(get_oid("tag1").unwrap(), oid1);
+        assert_eq!(
+            RefValue::get_branch_name().unwrap().as_deref(),
+            Some("main")
+        );
+        let commit = Commit::get_commit(&oid1).unwrap();
+        assert!(object_exists(&commit.tree));
+    });
+
+    // A bundle is read only.
+    let origin = Remote::add("origin", BUNDLE_FILE).unwrap();
+    assert!(remote::push(&origin, "main", false).is_err());
+    cleanup();
+}
+
+#[test]
+#[serial]
+fn bundle_incremental_fetch() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    Bundle::create(BUNDLE_FILE, &["--all".to_owned()]).unwrap();
+    let _ = fs::remove_dir_all(REMOTE_DIR);
+    with_dsgit_dir(REMOTE_DIR, || remote::clone(BUNDLE_FILE, true)).unwrap();
+
+    fs::write("./foo.txt", "foo\n").unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    let range = format!("{}..main", oid1);
+    let bundle = Bundle::create(BUNDLE_FILE, &[range]).unwrap();
+    assert_eq!(bundle.prerequisites, vec![oid1.to_owned()]);
+    assert!(Bundle::create(BUNDLE_FILE, &["^main".to_owned()]).is_err());
+
+    with_dsgit_dir(REMOTE_DIR, || {
+        bundle.verify().unwrap();
+        let remote = Remote::add("bundle", BUNDLE_FILE).unwrap();
+        let changes = remote::fetch(&remote).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(get_oid("bundle/main").unwrap(), oid2);
+        let tree = Commit::get_commit(&oid2).unwrap().tree;
+        assert!(object_exists(&tree));
+        // The commit before the range did not come with the bundle.
+        assert_eq!(get_oid("main").unwrap(), oid1);
+    });
+
+    // An empty repository lacks the commit the bundle is based on.
+    let empty_dir = "../test_bundle_empty.dsgit";
+    let _ = fs::remove_dir_all(empty_dir);
+    with_dsgit_dir(empty_dir, || {
+        dsgit::data::init().unwrap();
+        let err = bundle.verify().unwrap_err();
+        assert!(err.to_string().contains(oid1.as_str()), "{}", err);
+        assert!(bundle.unbundle().is_err());
+        assert!(!object_exists(&oid2));
+    });
+    fs::remove_dir_all(empty_dir).unwrap();
+    fs::remove_file("./foo.txt").unwrap();
+    cleanup();
+}
+
+#[test]
+#[serial]
+fn broken_bundle() {
+    setup();
+    Commit::commit("1st commit", &[]).unwrap();
+    fs::write(BUNDLE_FILE, "not a bundle\n\n").unwrap();
+    assert!(!Bundle::is_bundle(BUNDLE_FILE));
+    assert!(Bundle::open(BUNDLE_FILE).is_err());
+
+    // A bundle cut in the middle of its objects fails to verify.
+    let bundle = Bundle::create(BUNDLE_FILE, &["main".to_owned()]).unwrap();
+    let contents = fs::read(BUNDLE_FILE).unwrap();
+    fs::write(BUNDLE_FILE, &contents[..contents.len() - 10]).unwrap();
+    assert!(Bundle::open(BUNDLE_FILE).is_ok());
+    assert!(bundle.verify().is_err());
+
+    assert!(Bundle::create(BUNDLE_FILE, &["nothing".to_owned()]).is_err());
+    cleanup();
+}
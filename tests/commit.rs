@@ -1,10 +1,14 @@
 mod common;
 
 use serial_test::serial;
+use std::fs;
 
 use common::setup;
 use dsgit::commit::Commit;
 use dsgit::data::{get_object, TypeObject};
+use dsgit::entry::Tree;
+use dsgit::reference;
+use dsgit::reflog;
 
 #[test]
 #[serial]
@@ -58,3 +62,119 @@ fn get_commit() {
     ));
     assert_eq!(commit2.message, "second commit".to_string());
 }
+
+#[test]
+#[serial]
+fn cherry_pick() {
+    setup();
+    let base_oid = Commit::commit("base", &[]).unwrap();
+    reference::create_branch("topic", &base_oid).unwrap();
+
+    fs::write("./lion.txt", "gao\n").unwrap();
+    let picked_oid = Commit::commit("add lion", &[]).unwrap();
+
+    // Go back to the base commit and apply the change on top of another one.
+    reference::RefValue::switch("topic", &[]).unwrap();
+    fs::write("./cat.txt", "cat cat\nmeow\n").unwrap();
+    Commit::commit("meow", &[]).unwrap();
+
+    let oids = Commit::cherry_pick(&[picked_oid.into()], false, &[]).unwrap();
+    assert_eq!(oids.len(), 1);
+    let commit = Commit::get_commit(&oids[0]).unwrap();
+    assert_eq!(commit.message, "add lion");
+    assert_eq!(
+        reflog::read("HEAD").unwrap()[0].message,
+        "cherry-pick: add lion"
+    );
+    assert_eq!(fs::read_to_string("./lion.txt").unwrap(), "gao\n");
+    assert_eq!(fs::read_to_string("./cat.txt").unwrap(), "cat cat\nmeow\n");
+
+    // Teardown, restore the original files.
+    reference::RefValue::switch(&base_oid, &[]).unwrap();
+}
+
+#[test]
+#[serial]
+fn cherry_pick_conflict() {
+    setup();
+    let base_oid = Commit::commit("base", &[]).unwrap();
+    reference::create_branch("topic", &base_oid).unwrap();
+
+    fs::write("./cat.txt", "cat\n").unwrap();
+    let picked_oid = Commit::commit("one cat", &[]).unwrap();
+
+    reference::RefValue::switch("topic", &[]).unwrap();
+    fs::write("./cat.txt", "cat cat cat\n").unwrap();
+    let head_oid = Commit::commit("three cats", &[]).unwrap();
+
+    assert!(Commit::cherry_pick(&[picked_oid.into()], false, &[]).is_err());
+    assert_eq!(reference::get_head_oid(), head_oid);
+    let contents = fs::read_to_string("./cat.txt").unwrap();
+    assert!(contents.starts_with("<<<<<<< HEAD\ncat cat cat\n=======\ncat\n>>>>>>>"));
+
+    // Teardown, restore the original files.
+    reference::RefValue::switch(&base_oid, &[]).unwrap();
+}
+
+#[test]
+#[serial]
+fn revert() {
+    setup();
+    let base_oid = Commit::commit("base", &[]).unwrap();
+    fs::write("./lion.txt", "gao\n").unwrap();
+    let lion_oid = Commit::commit("add lion", &[]).unwrap();
+    fs::write("./cat.txt", "cat cat\nmeow\n").unwrap();
+    Commit::commit("meow", &[]).unwrap();
+
+    // `--no-commit` only updates the working tree.
+    let head_oid = reference::get_head_oid();
+    assert!(Commit::revert(&lion_oid, true, &[]).unwrap().is_none());
+    assert_eq!(reference::get_head_oid(), head_oid);
+    assert!(!std::path::Path::new("./lion.txt").exists());
+    fs::write("./lion.txt", "gao\n").unwrap();
+
+    let oid = Commit::revert(&lion_oid, false, &[]).unwrap().unwrap();
+    let commit = Commit::get_commit(&oid).unwrap();
+    assert_eq!(commit.message, "Revert \"add lion\"");
+    assert_eq!(
+        reflog::read("HEAD").unwrap()[0].message,
+        "revert: Revert \"add lion\""
+    );
+    assert!(!std::path::Path::new("./lion.txt").exists());
+    assert_eq!(fs::read_to_string("./cat.txt").unwrap(), "cat cat\nmeow\n");
+
+    // Teardown, restore the original files.
+    reference::RefValue::switch(&base_oid, &[]).unwrap();
+}
+
+#[test]
+#[serial]
+fn merge_base_best_ancestor() {
+    setup();
+    let tree = Tree::write_tree(".", &[]).unwrap();
+    let commit = |parent: &str, merge_parent: Option<&str>, message: &str| {
+        Commit::create_merge(&tree, Some(parent), merge_parent, message).unwrap()
+    };
+    let root = Commit::create(&tree, None, "root").unwrap();
+    let y = commit(&root, None, "y");
+    let c = commit(&y, None, "c");
+    let ours = commit(&c, None, "ours");
+    let x = commit(&y, None, "x");
+    let m = commit(&c, None, "m");
+    // `y` is found first from `theirs`, but it is an ancestor of `c`.
+    let theirs = commit(&x, Some(&m), "theirs");
+    assert_eq!(Commit::merge_base(&ours, &theirs).unwrap(), Some(c.clone()));
+    assert_eq!(
+        Commit::merge_bases(&ours, &theirs).unwrap(),
+        vec![c.clone()]
+    );
+
+    // Criss-cross merges have two best common ancestors.
+    let left = commit(&c, Some(&x), "left");
+    let right = commit(&x, Some(&c), "right");
+    let mut bases = Commit::merge_bases(&left, &right).unwrap();
+    bases.sort();
+    let mut expected = vec![c, x];
+    expected.sort();
+    assert_eq!(bases, expected);
+}
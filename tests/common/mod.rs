@@ -3,7 +3,7 @@ use std::fs;
 use std::io;
 use std::io::BufRead;
 
-use dsgit::data::init;
+use dsgit::data::{dsgit_dir, init, set_dsgit_dir};
 
 pub const DSGIT_DIR: &str = ".dsgit";
 
@@ -14,10 +14,29 @@ pub fn setup() {
 }
 
 #[allow(dead_code)]
-pub fn assert_file_contents(path: &str, expects: Vec<String>) {
+pub fn assert_file_contents<S: AsRef<str>>(path: &str, expects: Vec<S>) {
     let f1 = fs::File::open(path).unwrap();
     let f1_contents = io::BufReader::new(f1);
     for (got, expect) in f1_contents.lines().zip(expects) {
-        assert_eq!(got.unwrap(), expect);
+        assert_eq!(got.unwrap(), expect.as_ref());
     }
 }
+
+// Restore the previous `.dsgit` directory, even when unwinding from a panic.
+struct RestoreDsgitDir(String);
+
+impl Drop for RestoreDsgitDir {
+    fn drop(&mut self) {
+        set_dsgit_dir(&self.0);
+    }
+}
+
+/// Run `f` against the repository whose `.dsgit` directory is `dir`, so that
+/// objects and references of another repository are read or written with
+/// the same functions. The working tree is not affected.
+#[allow(dead_code)]
+pub fn with_dsgit_dir<T>(dir: &str, f: impl FnOnce() -> T) -> T {
+    let _restore = RestoreDsgitDir(dsgit_dir());
+    set_dsgit_dir(dir);
+    f()
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use serial_test::serial;
+
+use common::{assert_file_contents, setup, DSGIT_DIR};
+use dsgit::config::Config;
+
+#[test]
+fn parse_config() {
+    let contents = "\
+# comment
+[core]
+\tbare = false
+[remote \"origin\"]
+\turl = ../repository
+\tfetch = +refs/heads/*:refs/remotes/origin/*
+\tfetch = +refs/tags/*:refs/tags/*
+[branch \"feature.x\"]
+\tremote = origin
+";
+    let config: Config = contents.parse().unwrap();
+    assert_eq!(config.get("core.bare").unwrap().unwrap(), "false");
+    assert_eq!(
+        config.get("remote.origin.url").unwrap().unwrap(),
+        "../repository"
+    );
+    assert_eq!(config.get_all("remote.origin.fetch").unwrap().len(), 2);
+    assert_eq!(
+        config.get("branch.feature.x.remote").unwrap().unwrap(),
+        "origin"
+    );
+    assert_eq!(config.get("remote.upstream.url").unwrap(), None);
+    assert_eq!(config.subsections("remote"), vec!["origin"]);
+    assert_eq!(config.to_string(), contents.replace("# comment\n", ""));
+
+    assert!("key = value".parse::<Config>().is_err());
+    assert!("[remote origin]\n".parse::<Config>().is_err());
+}
+
+#[test]
+#[serial]
+fn write_config() {
+    setup();
+    let mut config = Config::read().unwrap();
+    assert_eq!(config, Config::default());
+
+    config.set("remote.origin.url", "../a").unwrap();
+    config.set("remote.origin.url", "../b").unwrap();
+    config.add("branch.main.remote", "origin").unwrap();
+    config.write().unwrap();
+    assert_file_contents(
+        &format!("{}/config", DSGIT_DIR),
+        vec![
+            String::from("[remote \"origin\"]"),
+            String::from("\turl = ../b"),
+            String::from("[branch \"main\"]"),
+            String::from("\tremote = origin"),
+        ],
+    );
+
+    let mut config = Config::read().unwrap();
+    config
+        .rename_section("branch.main", "branch.trunk")
+        .unwrap();
+    assert_eq!(
+        config.get("branch.trunk.remote").unwrap().unwrap(),
+        "origin"
+    );
+    assert!(config.remove_section("remote.origin"));
+    assert!(!config.remove_section("remote.origin"));
+    config.unset("branch.trunk.remote").unwrap();
+    assert_eq!(config.get("branch.trunk.remote").unwrap(), None);
+}
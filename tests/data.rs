@@ -5,8 +5,12 @@ use std::assert;
 use std::fs;
 use std::path::Path;
 
-use common::{assert_file_contents, setup, DSGIT_DIR};
-use dsgit::data;
+use common::{assert_file_contents, setup, with_dsgit_dir, DSGIT_DIR};
+use dsgit::commit::Commit;
+use dsgit::config::Config;
+use dsgit::data::{self, RepositoryFormat};
+use dsgit::hash::HashAlgorithm;
+use dsgit::remote;
 
 const TEST_DATA: [(&str, &str, &str, &str, &str); 3] = [
     (
@@ -80,3 +84,69 @@ fn get_object() {
         }
     }
 }
+
+#[test]
+#[serial]
+fn sha256_object_format() {
+    setup();
+    assert_eq!(
+        HashAlgorithm::Sha256.hash(b""),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(
+        HashAlgorithm::Sha256.hash(&[b'a'; 200]),
+        "c2a908d98f5df987ade41b5fce213067efbcc21ef2240212a41e54b5e7c28ae5"
+    );
+
+    fs::remove_dir_all(DSGIT_DIR).unwrap();
+    data::init_with_format(RepositoryFormat {
+        hash: HashAlgorithm::Sha256,
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(
+        RepositoryFormat::read().unwrap().hash,
+        HashAlgorithm::Sha256
+    );
+    let blob = data::hash_object("Hello World!\n", data::TypeObject::Blob).unwrap();
+    assert_eq!(
+        blob,
+        "a112c35a79e45258731f39c5a61cf8b7a4d9603d9c63c38b95c77d2b1cc80b7d"
+    );
+    assert_eq!(blob.algorithm(), HashAlgorithm::Sha256);
+
+    let oid = Commit::commit("1st commit", &[]).unwrap();
+    assert_eq!(oid.len(), 64);
+    assert_eq!(data::get_oid("main").unwrap(), oid);
+    let commit = Commit::get_commit(&oid).unwrap();
+    assert_eq!(commit.tree.algorithm(), HashAlgorithm::Sha256);
+    // An object id of another hash algorithm names nothing here.
+    assert!(data::get_oid("4963f4ed0612f7242d9d92bf59b4fb8ac8d29ec2").is_err());
+
+    // A clone takes the hash algorithm of the remote.
+    let clone_dir = "../test_sha256_clone.dsgit";
+    let _ = fs::remove_dir_all(clone_dir);
+    with_dsgit_dir(clone_dir, || {
+        remote::clone(".", true).unwrap();
+        assert_eq!(
+            RepositoryFormat::read().unwrap().hash,
+            HashAlgorithm::Sha256
+        );
+        assert_eq!(data::get_oid("main").unwrap(), oid);
+        assert!(data::object_exists(&blob));
+    });
+    fs::remove_dir_all(clone_dir).unwrap();
+
+    // The format is kept while the config is unchanged, and read again
+    // when the config is written or the repository is created again.
+    fs::remove_dir_all(DSGIT_DIR).unwrap();
+    data::init().unwrap();
+    assert_eq!(RepositoryFormat::read().unwrap().hash, HashAlgorithm::Sha1);
+    let mut config = Config::read().unwrap();
+    config.set(data::OBJECT_FORMAT_KEY, "sha256").unwrap();
+    config.write().unwrap();
+    assert_eq!(
+        RepositoryFormat::read().unwrap().hash,
+        HashAlgorithm::Sha256
+    );
+}
@@ -1,13 +1,18 @@
+#![allow(clippy::len_zero)]
+
 mod common;
 
+use serial_test::serial;
 use std::fs;
 use std::io::Write;
 
 use common::setup;
-use dsgit::diff::diff_trees;
-use dsgit::entry::Tree;
+use dsgit::config::Config;
+use dsgit::diff::{diff_trees, patch, Change};
+use dsgit::entry::{Entry, Tree};
 
 #[test]
+#[serial]
 fn test_diff_trees() {
     setup();
     let from_tree = Tree::new(".", &[]).unwrap();
@@ -19,18 +24,23 @@ fn test_diff_trees() {
     fs::write("./tiger.txt", "ToraTora").unwrap();
     let to_tree = Tree::new(".", &[]).unwrap();
 
-    let mut diffs = diff_trees(from_tree, to_tree, false).unwrap();
-    diffs.1.sort();
+    let diff = diff_trees(&from_tree, &to_tree).unwrap();
+    assert_eq!(diff.changes.len(), 3);
 
     if cfg!(target_os = "windows") {
-        assert!(diffs.0.len() == 0);
-        assert_eq!(diffs.1, vec![".\\dragon.txt", ".\\tiger.txt"]);
-        assert_eq!(diffs.2, vec![".\\cat.txt"]);
+        assert!(diff.modified().len() == 0);
+        assert_eq!(diff.added(), vec![".\\dragon.txt", ".\\tiger.txt"]);
+        assert_eq!(diff.removed(), vec![".\\cat.txt"]);
     } else {
-        assert!(diffs.0.len() == 0);
-        assert_eq!(diffs.1, vec!["./dragon.txt", "./tiger.txt"]);
-        assert_eq!(diffs.2, vec!["./cat.txt"]);
+        assert!(diff.modified().len() == 0);
+        assert_eq!(diff.added(), vec!["./dragon.txt", "./tiger.txt"]);
+        assert_eq!(diff.removed(), vec!["./cat.txt"]);
     }
+    // Changes are in the order of their paths.
+    let paths: Vec<&str> = diff.changes.iter().map(|change| change.path()).collect();
+    let mut sorted = paths.clone();
+    sorted.sort_unstable();
+    assert_eq!(paths, sorted);
 
     // This diff pattern: a update file.
     let from_tree = Tree::new(".", &[]).unwrap();
@@ -42,18 +52,220 @@ fn test_diff_trees() {
     f.write_all(b"gaoo").unwrap();
     f.flush().unwrap();
     let to_tree = Tree::new(".", &[]).unwrap();
-    let diffs = diff_trees(from_tree, to_tree, false).unwrap();
+    let diff = diff_trees(&from_tree, &to_tree).unwrap();
 
     if cfg!(target_os = "windows") {
-        assert_eq!(diffs.0, vec![".\\tiger.txt"]);
-        assert!(diffs.1.len() == 0);
-        assert!(diffs.2.len() == 0);
+        assert_eq!(diff.modified(), vec![".\\tiger.txt"]);
+        assert!(diff.added().len() == 0);
+        assert!(diff.removed().len() == 0);
     } else {
-        assert_eq!(diffs.0, vec!["./tiger.txt"]);
-        assert!(diffs.1.len() == 0);
-        assert!(diffs.2.len() == 0);
+        assert_eq!(diff.modified(), vec!["./tiger.txt"]);
+        assert!(diff.added().len() == 0);
+        assert!(diff.removed().len() == 0);
+    }
+    match &diff.changes[..] {
+        [Change::Modified { old, new }] => {
+            assert_eq!(old, entry(&from_tree, &new.path));
+            assert_eq!(new, entry(&to_tree, &new.path));
+            assert_ne!(old.oid, new.oid);
+        }
+        changes => panic!("Unexpected changes: {:?}", changes),
     }
 
     // Teardown, restore removed file.
     Tree::read_tree(&f_oid, &[]).unwrap();
 }
+
+fn set_config(key: &str, value: &str) {
+    let mut config = Config::read().unwrap();
+    config.set(key, value).unwrap();
+    config.write().unwrap();
+}
+
+fn entry<'a>(tree: &'a Tree, path: &str) -> &'a Entry {
+    tree.entries
+        .iter()
+        .find(|entry| entry.path == path)
+        .unwrap()
+}
+
+#[test]
+#[serial]
+fn test_diff_trees_renames() {
+    setup();
+    let poem: String = (1..=10).map(|n| format!("line {}\n", n)).collect();
+    fs::write("./poem.txt", &poem).unwrap();
+    let from_tree = Tree::new(".", &[]).unwrap();
+    let f_oid = Tree::write_tree(".", &[]).unwrap();
+
+    // An exact rename, and a rename changing one line of ten.
+    fs::rename("./cat.txt", "./kitten.txt").unwrap();
+    fs::remove_file("./poem.txt").unwrap();
+    fs::write("./verse.txt", poem.replace("line 5\n", "line five\n")).unwrap();
+    let to_tree = Tree::new(".", &[]).unwrap();
+    let diff = diff_trees(&from_tree, &to_tree).unwrap();
+    let renames = vec![
+        Change::Renamed {
+            old: entry(&from_tree, "./cat.txt").clone(),
+            new: entry(&to_tree, "./kitten.txt").clone(),
+            similarity: 100,
+        },
+        Change::Renamed {
+            old: entry(&from_tree, "./poem.txt").clone(),
+            new: entry(&to_tree, "./verse.txt").clone(),
+            similarity: 90,
+        },
+    ];
+    assert_eq!(diff.changes, renames);
+    assert_eq!(
+        renames[1].to_string(),
+        "Renamed: ./poem.txt -> ./verse.txt (90%)"
+    );
+
+    // Files less similar than the threshold are removed and created.
+    set_config("diff.renameThreshold", "95%");
+    let diff = diff_trees(&from_tree, &to_tree).unwrap();
+    assert_eq!(diff.added(), vec!["./verse.txt"]);
+    assert_eq!(diff.removed(), vec!["./poem.txt"]);
+    assert_eq!(diff.changes[0], renames[0]);
+
+    set_config("diff.renames", "false");
+    let diff = diff_trees(&from_tree, &to_tree).unwrap();
+    assert_eq!(diff.added(), vec!["./kitten.txt", "./verse.txt"]);
+    assert_eq!(diff.removed(), vec!["./cat.txt", "./poem.txt"]);
+
+    // A copy keeps its source in the tree.
+    set_config("diff.renames", "copies");
+    fs::copy("./hello.txt", "./hello2.txt").unwrap();
+    let to_tree = Tree::new(".", &[]).unwrap();
+    let diff = diff_trees(&from_tree, &to_tree).unwrap();
+    assert!(diff.changes.contains(&Change::Copied {
+        old: entry(&from_tree, "./hello.txt").clone(),
+        new: entry(&to_tree, "./hello2.txt").clone(),
+        similarity: 100,
+    }));
+    assert!(!diff.added().contains(&"./hello2.txt"));
+
+    // Teardown, restore the files.
+    fs::remove_file("./hello2.txt").unwrap();
+    Tree::read_tree(&f_oid, &[]).unwrap();
+    fs::remove_file("./poem.txt").unwrap();
+}
+
+#[test]
+#[serial]
+fn test_diff_trees_file_to_directory() {
+    setup();
+    let from_tree = Tree::new(".", &[]).unwrap();
+    fs::remove_file("./cat.txt").unwrap();
+    fs::create_dir("./cat.txt").unwrap();
+    fs::write("./cat.txt/nya.txt", "nya nya\n").unwrap();
+    let to_tree = Tree::new(".", &[]).unwrap();
+
+    // A tree is flattened to its files, so the file is removed and the file
+    // below the directory is added.
+    let diff = diff_trees(&from_tree, &to_tree).unwrap();
+    assert_eq!(
+        diff.changes,
+        vec![
+            Change::Removed(entry(&from_tree, "./cat.txt").clone()),
+            Change::Added(entry(&to_tree, "./cat.txt/nya.txt").clone()),
+        ]
+    );
+
+    // Teardown, restore the file.
+    fs::remove_dir_all("./cat.txt").unwrap();
+    fs::write("./cat.txt", "cat cat\n").unwrap();
+}
+
+#[test]
+#[serial]
+fn test_patch() {
+    setup();
+    let poem: String = (1..=10).map(|n| format!("line {}\n", n)).collect();
+    fs::write("./poem.txt", &poem).unwrap();
+    fs::write("./tiger.txt", "Tora").unwrap();
+    let from_tree = Tree::new(".", &[]).unwrap();
+    let f_oid = Tree::write_tree(".", &[]).unwrap();
+
+    fs::remove_file("./cat.txt").unwrap();
+    fs::write("./poem.txt", poem.replace("line 5\n", "line five\n")).unwrap();
+    fs::write("./tiger.txt", "ToraTora").unwrap();
+    fs::write("./dragon.txt", "Ryuu\n").unwrap();
+    let to_tree = Tree::new(".", &[]).unwrap();
+    let diff = diff_trees(&from_tree, &to_tree).unwrap();
+    let abbrev = |tree: &Tree, path: &str| entry(tree, path).oid[..7].to_owned();
+
+    let expected = format!(
+        "diff --git a/cat.txt b/cat.txt
+deleted file mode 100644
+index {}..0000000
+--- a/cat.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-cat cat
+diff --git a/dragon.txt b/dragon.txt
+new file mode 100644
+index 0000000..{}
+--- /dev/null
++++ b/dragon.txt
+@@ -0,0 +1 @@
++Ryuu
+diff --git a/poem.txt b/poem.txt
+index {}..{} 100644
+--- a/poem.txt
++++ b/poem.txt
+@@ -4,3 +4,3 @@
+ line 4
+-line 5
++line five
+ line 6
+diff --git a/tiger.txt b/tiger.txt
+index {}..{} 100644
+--- a/tiger.txt
++++ b/tiger.txt
+@@ -1 +1 @@
+-Tora
+\\ No newline at end of file
++ToraTora
+\\ No newline at end of file
+",
+        abbrev(&from_tree, "./cat.txt"),
+        abbrev(&to_tree, "./dragon.txt"),
+        abbrev(&from_tree, "./poem.txt"),
+        abbrev(&to_tree, "./poem.txt"),
+        abbrev(&from_tree, "./tiger.txt"),
+        abbrev(&to_tree, "./tiger.txt"),
+    );
+    if !cfg!(target_os = "windows") {
+        assert_eq!(patch(&diff, 1).unwrap(), expected);
+    }
+    // The context widens to every line of the file.
+    let patch_all = patch(&diff, 10).unwrap();
+    assert!(patch_all.contains("@@ -1,10 +1,10 @@\n line 1\n"));
+
+    // A renamed file keeps the diff of its contents.
+    fs::rename("./poem.txt", "./verse.txt").unwrap();
+    let to_tree = Tree::new(".", &[]).unwrap();
+    let diff = diff_trees(&from_tree, &to_tree).unwrap();
+    let renamed = patch(&diff, 0).unwrap();
+    assert!(
+        renamed.contains(
+            "diff --git a/poem.txt b/verse.txt
+similarity index 90%
+rename from poem.txt
+rename to verse.txt
+"
+        ),
+        "{}",
+        renamed
+    );
+    assert!(renamed.contains("@@ -5 +5 @@\n-line 5\n+line five\n"));
+
+    // Teardown, restore the files.
+    fs::remove_file("./dragon.txt").unwrap();
+    fs::remove_file("./verse.txt").unwrap();
+    Tree::read_tree(&f_oid, &[]).unwrap();
+    fs::remove_file("./poem.txt").unwrap();
+    fs::remove_file("./tiger.txt").unwrap();
+}
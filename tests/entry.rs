@@ -11,28 +11,29 @@ use common::setup;
 use dsgit::data::{get_object, TypeObject};
 use dsgit::entry;
 use dsgit::entry::{Entry, Tree};
+use dsgit::hash::ObjectId;
 
 fn test_data(target_os: &str) -> [entry::Entry; 4] {
     match target_os {
         "windows" => [
             Entry {
                 path: ".\\cat.txt".to_string(),
-                oid: "738355a2d1dda0b9f26feb6bb8e2de8f735bcd19".to_string(),
+                oid: ObjectId::parse("738355a2d1dda0b9f26feb6bb8e2de8f735bcd19").unwrap(),
                 obj_type: TypeObject::Blob,
             },
             Entry {
                 path: ".\\dogs.txt".to_string(),
-                oid: "45ce866627173403d0a0406d7c3f4cb54708ec1c".to_string(),
+                oid: ObjectId::parse("45ce866627173403d0a0406d7c3f4cb54708ec1c").unwrap(),
                 obj_type: TypeObject::Blob,
             },
             Entry {
                 path: ".\\hello.txt".to_string(),
-                oid: "f0981ab57ce65e2716df953d09c80478fd7dec1c".to_string(),
+                oid: ObjectId::parse("f0981ab57ce65e2716df953d09c80478fd7dec1c").unwrap(),
                 obj_type: TypeObject::Blob,
             },
             Entry {
                 path: ".\\other".to_string(),
-                oid: "7716369a392b192a80b7766c99f2d310d056a807".to_string(),
+                oid: ObjectId::parse("7716369a392b192a80b7766c99f2d310d056a807").unwrap(),
                 obj_type: TypeObject::Tree,
             },
         ],
@@ -40,22 +41,22 @@ fn test_data(target_os: &str) -> [entry::Entry; 4] {
         _ => [
             Entry {
                 path: "./cat.txt".to_string(),
-                oid: "38d458fa6e384e24e7f15c5d17be0e9cee67f823".to_string(),
+                oid: ObjectId::parse("38d458fa6e384e24e7f15c5d17be0e9cee67f823").unwrap(),
                 obj_type: TypeObject::Blob,
             },
             Entry {
                 path: "./dogs.txt".to_string(),
-                oid: "bdb10d71fac51e4952b37042faa62640cd7847db".to_string(),
+                oid: ObjectId::parse("bdb10d71fac51e4952b37042faa62640cd7847db").unwrap(),
                 obj_type: TypeObject::Blob,
             },
             Entry {
                 path: "./hello.txt".to_string(),
-                oid: "4963f4ed0612f7242d9d92bf59b4fb8ac8d29ec2".to_string(),
+                oid: ObjectId::parse("4963f4ed0612f7242d9d92bf59b4fb8ac8d29ec2").unwrap(),
                 obj_type: TypeObject::Blob,
             },
             Entry {
                 path: "./other".to_string(),
-                oid: "b19cdcfa8e09aed887a25d11d73fbe68261dbfc3".to_string(),
+                oid: ObjectId::parse("b19cdcfa8e09aed887a25d11d73fbe68261dbfc3").unwrap(),
                 obj_type: TypeObject::Tree,
             },
         ],
@@ -83,7 +84,7 @@ fn write_tree() {
 
         let obj = get_object(&oid, TypeObject::Tree).unwrap();
         for (i, line) in obj.lines().enumerate() {
-            let entry = Entry::from(line);
+            let entry: Entry = line.parse().unwrap();
             assert_eq!(entry, expect_result[i]);
         }
     } else {
@@ -93,10 +94,20 @@ fn write_tree() {
 
         let obj = get_object(&oid, TypeObject::Tree).unwrap();
         for (i, line) in obj.lines().enumerate() {
-            let entry = Entry::from(line);
+            let entry: Entry = line.parse().unwrap();
             assert_eq!(entry, expect_result[i]);
         }
     }
+
+    // A tree read back has the paths of the working tree, and writes the
+    // same tree again.
+    let oid = Tree::write_tree(".", &[]).unwrap();
+    let mut tree = Tree::get_tree(&get_object(&oid, TypeObject::Tree).unwrap()).unwrap();
+    let mut working_tree = Tree::new(".", &[]).unwrap();
+    tree.entries.sort();
+    working_tree.entries.sort();
+    assert_eq!(tree.entries, working_tree.entries);
+    assert_eq!(working_tree.write().unwrap(), oid);
 }
 
 #[test]
@@ -133,3 +144,12 @@ fn read_tree() {
         assert_read_tree("cfafd0b3d132774e6c44b39d2e2bfc3635ec49ef", &expect_paths);
     }
 }
+
+#[test]
+fn parse_broken_entry() {
+    assert!("blob ./cat.txt".parse::<Entry>().is_err());
+    assert!("blob xyz ./cat.txt".parse::<Entry>().is_err());
+    assert!("file 3f786850e387550fdab836ed7e6dc881de23001b ./cat.txt"
+        .parse::<Entry>()
+        .is_err());
+}
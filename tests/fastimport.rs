@@ -0,0 +1,277 @@
+mod common;
+
+use serial_test::serial;
+use std::fs;
+
+use common::{setup, with_dsgit_dir};
+use dsgit::commit::Commit;
+use dsgit::data::{get_oid, get_oid_unpeeled, hash_object, init, TypeObject};
+use dsgit::entry::Tree;
+use dsgit::fastimport::{self, FastImport, Marks};
+use dsgit::hash::ObjectId;
+use dsgit::reflog;
+use dsgit::tag::Tag;
+
+const IMPORT_DIR: &str = "../test_fast_import.dsgit";
+const MARKS_FILE: &str = "../test_fast_import.marks";
+
+// Paths and blobs of the files in a commit.
+fn files(oid: &str) -> Vec<(String, ObjectId)> {
+    Tree::get_commit_tree(oid)
+        .unwrap()
+        .entries
+        .into_iter()
+        .map(|entry| (entry.path, entry.oid))
+        .collect()
+}
+
+fn blob(contents: &str) -> ObjectId {
+    hash_object(contents, TypeObject::Blob).unwrap()
+}
+
+fn mark_of(marks: &Marks, oid: &str) -> usize {
+    (1..100)
+        .find(|mark| marks.get(*mark).map(|mark| mark.as_str()) == Some(oid))
+        .unwrap()
+}
+
+#[test]
+#[serial]
+fn fast_export_and_import() {
+    setup();
+    fs::create_dir_all("./dir").unwrap();
+    fs::write("./dir/foo.txt", "foo\n").unwrap();
+    fs::write("./bar.txt", "bar\n").unwrap();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    fs::remove_file("./bar.txt").unwrap();
+    fs::write("./dir/foo.txt", "foo foo\n").unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    let tag = Tag::create("v1", &oid1, "version 1").unwrap();
+
+    let mut stream = vec![];
+    let mut marks = Marks::default();
+    fastimport::fast_export(&mut stream, &["--all".to_owned()], &mut marks).unwrap();
+    let stream = String::from_utf8(stream).unwrap();
+    let (mark1, mark2) = (mark_of(&marks, &oid1), mark_of(&marks, &oid2));
+    let foo = mark_of(&marks, &blob("foo foo\n"));
+    let bar = mark_of(&marks, &blob("bar\n"));
+    assert!(
+        stream.contains(&format!("blob\nmark :{}\ndata 4\nbar\n\n", bar)),
+        "{}",
+        stream
+    );
+    assert!(
+        stream.contains(&format!("M 100644 :{} bar.txt\n", bar)),
+        "{}",
+        stream
+    );
+    let changes = format!(
+        "from :{}\nM 100644 :{} dir/foo.txt\nD bar.txt\n",
+        mark1, foo
+    );
+    assert!(stream.contains(&changes), "{}", stream);
+    assert!(mark1 < mark2);
+    assert!(
+        stream.contains(&format!("tag v1\nfrom :{}\n", mark1)),
+        "{}",
+        stream
+    );
+    assert!(
+        stream.ends_with("data 10\nversion 1\n\ndone\n"),
+        "{}",
+        stream
+    );
+
+    // The same files and messages make the same commits again.
+    let _ = fs::remove_dir_all(IMPORT_DIR);
+    with_dsgit_dir(IMPORT_DIR, || {
+        init().unwrap();
+        let imported =
+            fastimport::fast_import(&mut stream.as_bytes(), &mut Marks::default(), false);
+        assert_eq!(
+            imported.unwrap(),
+            FastImport {
+                blobs: mark2 - 2,
+                commits: 2,
+                tags: 1,
+                refs: vec![
+                    ("refs/heads/main".to_owned(), oid2.to_owned()),
+                    ("refs/tags/v1".to_owned(), tag.to_owned()),
+                ],
+            }
+        );
+        assert_eq!(get_oid("main").unwrap(), oid2);
+        assert_eq!(Tag::get_tag(&tag).unwrap().message, "version 1");
+    });
+
+    fs::remove_dir_all("./dir").unwrap();
+    fs::remove_dir_all(IMPORT_DIR).unwrap();
+}
+
+#[test]
+#[serial]
+fn fast_import_stream() {
+    setup();
+    let stream = "\
+# A comment is skipped.
+blob
+mark :1
+data 4
+foo
+
+commit refs/heads/main
+mark :2
+committer someone <someone@localhost> 0 +0000
+data <<EOF
+1st commit
+with a body
+EOF
+M 100644 :1 dir/foo.txt
+M 644 inline \"dir/b\\141r.txt\"
+data 4
+bar
+C dir copied
+R dir/foo.txt moved.txt
+
+commit refs/heads/topic
+data 10
+2nd commit
+from :2
+D copied
+M 100755 :1 copied/foo.txt
+
+reset refs/heads/empty
+
+commit refs/heads/main
+mark :3
+data 11
+merge topic
+merge refs/heads/topic
+tag v1
+from :3
+tagger someone <someone@localhost> 0 +0000
+data 10
+version 1
+reset refs/heads/old
+from :2
+done
+ignored after done
+";
+    let mut marks = Marks::default();
+    let imported = fastimport::fast_import(&mut stream.as_bytes(), &mut marks, false).unwrap();
+    assert_eq!((imported.blobs, imported.commits, imported.tags), (1, 3, 1));
+    let names: Vec<&str> = imported
+        .refs
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            "refs/heads/main",
+            "refs/heads/old",
+            "refs/heads/topic",
+            "refs/tags/v1"
+        ]
+    );
+
+    let oid1 = marks.get(2).unwrap().to_owned();
+    let commit1 = Commit::get_commit(&oid1).unwrap();
+    assert_eq!(commit1.message, "1st commit");
+    assert_eq!(commit1.parent, None);
+    let (foo, bar) = (blob("foo\n"), blob("bar\n"));
+    let path = |path: &str| path.to_owned();
+    assert_eq!(
+        files(&oid1),
+        vec![
+            (path("./copied/bar.txt"), bar.to_owned()),
+            (path("./copied/foo.txt"), foo.to_owned()),
+            (path("./dir/bar.txt"), bar.to_owned()),
+            (path("./moved.txt"), foo.to_owned()),
+        ]
+    );
+
+    let topic = get_oid("topic").unwrap();
+    assert_eq!(
+        Commit::get_commit(&topic).unwrap().parent,
+        Some(oid1.to_owned())
+    );
+    assert_eq!(get_oid("old").unwrap(), oid1);
+    assert!(get_oid("empty").is_err());
+
+    // The merge follows the branch, and takes its files.
+    let merge_oid = get_oid("main").unwrap();
+    let merge = Commit::get_commit(&merge_oid).unwrap();
+    assert_eq!(merge.parent, Some(oid1.to_owned()));
+    assert_eq!(merge.merge_parent, Some(topic.to_owned()));
+    assert_eq!(merge.tree, Commit::get_commit(&oid1).unwrap().tree);
+    let tag = Tag::get_tag(&get_oid_unpeeled("v1").unwrap()).unwrap();
+    assert_eq!(&tag.object, marks.get(3).unwrap());
+    assert_eq!(tag.tagger, "someone <someone@localhost> 0 +0000");
+    // The same files make the same tree as a commit of a working tree.
+    let tree = Tree {
+        entries: Tree::get_commit_tree(&oid1).unwrap().entries,
+    };
+    assert_eq!(tree.write().unwrap(), commit1.tree);
+
+    for stream in [
+        "commit refs/heads/main\ndata 0\nM 120000 :1 link\n",
+        "commit refs/heads/main\ndata 0\nM 100644 :9 foo.txt\n",
+        "commit refs/heads/main\ndata 0\nM 100644 :1 foo bar.txt\n",
+        "blob\ndata 10\nfoo\n",
+        "ls foo.txt\n",
+    ]
+    .iter()
+    {
+        assert!(
+            fastimport::fast_import(&mut stream.as_bytes(), &mut marks, false).is_err(),
+            "{}",
+            stream
+        );
+    }
+
+    // Moving a branch back loses commits, so it needs `force`.
+    assert_eq!(
+        reflog::read("refs/heads/topic").unwrap()[0].message,
+        "fast-import"
+    );
+    let stream = "reset refs/heads/topic\nfrom :2\n";
+    let err = fastimport::fast_import(&mut stream.as_bytes(), &mut marks, false).unwrap_err();
+    assert!(err.to_string().contains("non-fast-forward"), "{}", err);
+    assert_eq!(get_oid("topic").unwrap(), topic);
+    fastimport::fast_import(&mut stream.as_bytes(), &mut marks, true).unwrap();
+    assert_eq!(get_oid("topic").unwrap(), oid1);
+
+    // The checked out branch is never moved.
+    let stream = "reset refs/heads/main\nfrom :2\n";
+    let err = fastimport::fast_import(&mut stream.as_bytes(), &mut marks, true).unwrap_err();
+    assert!(err.to_string().contains("checked out"), "{}", err);
+    assert_eq!(get_oid("main").unwrap(), merge_oid);
+}
+
+#[test]
+#[serial]
+fn fast_export_marks() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let mut marks = Marks::default();
+    fastimport::fast_export(&mut vec![], &["main".to_owned()], &mut marks).unwrap();
+    marks.write(MARKS_FILE).unwrap();
+
+    fs::write("./foo.txt", "foo\n").unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    let mut marks = Marks::read(MARKS_FILE).unwrap();
+    let mark1 = mark_of(&marks, &oid1);
+    let mut stream = vec![];
+    fastimport::fast_export(&mut stream, &["main".to_owned()], &mut marks).unwrap();
+    let stream = String::from_utf8(stream).unwrap();
+    // Only the new commit is written, based on the commit marked before.
+    assert_eq!(stream.matches("commit refs/heads/main\n").count(), 1);
+    let changes = format!("from :{}\nM 100644 :{} foo.txt\n", mark1, mark1 + 1);
+    assert!(stream.contains(&changes), "{}", stream);
+    assert_eq!(marks.get(mark1 + 2), Some(&oid2));
+
+    assert!(fastimport::fast_export(&mut vec![], &["nothing".to_owned()], &mut marks).is_err());
+    fs::remove_file("./foo.txt").unwrap();
+    fs::remove_file(MARKS_FILE).unwrap();
+}
@@ -0,0 +1,252 @@
+mod common;
+
+use serial_test::serial;
+use std::fs;
+use std::process::Command;
+
+use common::{setup, with_dsgit_dir, DSGIT_DIR};
+use dsgit::commit::Commit;
+use dsgit::data::{
+    get_object, get_oid, get_oid_unpeeled, hash_object, init, init_with_format, ObjectEncoding,
+    RepositoryFormat, TypeObject,
+};
+use dsgit::entry::{Entry, Tree};
+use dsgit::git::{self, GitRepository, OidMap};
+use dsgit::hash::ObjectId;
+use dsgit::reference::RefValue;
+use dsgit::tag::Tag;
+
+const GIT_DIR: &str = "../test_git_repo";
+const IMPORT_DIR: &str = "../test_git_import.dsgit";
+
+fn cleanup() {
+    let _ = fs::remove_dir_all(GIT_DIR);
+    let _ = fs::remove_dir_all(IMPORT_DIR);
+}
+
+// Run git in `GIT_DIR`, false if git is not installed.
+fn run_git(args: &[&str]) -> bool {
+    match Command::new("git")
+        .args(["-c", "user.name=dsgit", "-c", "user.email=dsgit@localhost"])
+        .args(args)
+        .current_dir(GIT_DIR)
+        .output()
+    {
+        Ok(output) => {
+            assert!(output.status.success(), "git {:?}: {:?}", args, output);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[test]
+#[serial]
+fn export_and_import_git() {
+    setup();
+    cleanup();
+    fs::create_dir_all("./dir").unwrap();
+    fs::write("./dir/foo.txt", "foo\n").unwrap();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    fs::write("./bar.txt", "bar\n").unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    let tag = Tag::create("v1", &oid1, "version 1").unwrap();
+
+    let refs = git::export_git(GIT_DIR).unwrap();
+    let names: Vec<&str> = refs.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["refs/heads/main", "refs/tags/v1"]);
+    let repository = GitRepository::open(GIT_DIR).unwrap();
+    assert_eq!(repository.refs().unwrap(), refs);
+    assert_eq!(
+        repository.head_branch().unwrap().as_deref(),
+        Some("refs/heads/main")
+    );
+    let (obj_type, contents) = repository.read_object(&refs[0].1).unwrap();
+    assert_eq!(obj_type, TypeObject::Commit);
+    let contents = String::from_utf8(contents).unwrap();
+    assert!(contents.starts_with("tree "), "{}", contents);
+    assert!(contents.ends_with("\n\n2nd commit\n"), "{}", contents);
+    assert_eq!(OidMap::read().unwrap().get(&refs[0].1), Some(oid2.as_str()));
+
+    // Exporting again reuses the objects written before.
+    assert_eq!(git::export_git(GIT_DIR).unwrap(), refs);
+
+    // The same files and messages make the same commits in dsgit again.
+    with_dsgit_dir(IMPORT_DIR, || {
+        init().unwrap();
+        let import = git::import_git(GIT_DIR).unwrap();
+        assert!(import.skipped.is_empty());
+        assert!(import.truncated.is_empty());
+        assert_eq!(
+            import.refs,
+            vec![
+                ("refs/heads/main".to_owned(), oid2.to_owned()),
+                ("refs/tags/v1".to_owned(), tag.to_owned()),
+            ]
+        );
+        assert_eq!(get_oid("main").unwrap(), oid2);
+        assert_eq!(Tag::get_tag(&tag).unwrap().object, oid1);
+        assert_eq!(
+            RefValue::get_branch_name().unwrap().as_deref(),
+            Some("main")
+        );
+    });
+
+    fs::remove_dir_all("./dir").unwrap();
+    fs::remove_file("./bar.txt").unwrap();
+    cleanup();
+}
+
+#[test]
+#[serial]
+fn import_git_packs() {
+    setup();
+    cleanup();
+    fs::create_dir_all(GIT_DIR).unwrap();
+    if !run_git(&["init", "-q", "-b", "trunk"]) {
+        eprintln!("git is not installed, skipped");
+        return;
+    }
+    let git_dir = |path: &str| format!("{}/{}", GIT_DIR, path);
+    for i in 1..=10 {
+        let lines: String = (0..i * 100).map(|n| format!("line {}\n", n)).collect();
+        fs::write(git_dir("lines.txt"), lines).unwrap();
+        run_git(&["add", "-A"]);
+        run_git(&["commit", "-q", "-m", &format!("commit {}\n\nbody", i)]);
+    }
+    std::os::unix::fs::symlink("lines.txt", git_dir("link")).unwrap();
+    fs::write(git_dir("binary"), [0xff, 0xfe]).unwrap();
+    run_git(&["add", "-A"]);
+    run_git(&["commit", "-q", "-m", "links"]);
+    run_git(&["tag", "-a", "v1", "-m", "version 1"]);
+    // Pack objects with deltas, and leave a commit loose.
+    run_git(&["gc", "-q", "--aggressive"]);
+    fs::write(git_dir("loose.txt"), "loose\n").unwrap();
+    run_git(&["add", "-A"]);
+    run_git(&["commit", "-q", "-m", "loose"]);
+
+    with_dsgit_dir(IMPORT_DIR, || {
+        init().unwrap();
+        let import = git::import_git(GIT_DIR).unwrap();
+        assert_eq!(
+            import.skipped,
+            vec!["./binary (a binary file)", "./link (a symbolic link)"]
+        );
+        // The bodies of `commit 1` to `commit 10` are left out.
+        assert_eq!(import.truncated.len(), 10);
+        assert_eq!(
+            RefValue::get_branch_name().unwrap().as_deref(),
+            Some("trunk")
+        );
+        let head = Commit::get_commit(&get_oid("trunk").unwrap()).unwrap();
+        assert_eq!(head.message, "loose");
+        let tagged = Tag::get_tag(&get_oid_unpeeled("refs/tags/v1").unwrap()).unwrap();
+        let links = Commit::get_commit(&tagged.object).unwrap();
+        assert_eq!(links.message, "links");
+        let commit10 = Commit::get_commit(&links.parent.unwrap()).unwrap();
+        assert_eq!(commit10.message, "commit 10");
+
+        let tree = get_object(&commit10.tree, TypeObject::Tree).unwrap();
+        let (_, blob) = tree.trim_end().split_once(' ').unwrap();
+        let (blob, path) = blob.split_once(' ').unwrap();
+        assert_eq!(path, "./lines.txt");
+        let lines = get_object(blob, TypeObject::Blob).unwrap();
+        assert_eq!(lines.lines().count(), 1000);
+
+        // Importing again converts nothing new.
+        let map = fs::read_to_string(format!("{}/git-map", IMPORT_DIR)).unwrap();
+        git::import_git(GIT_DIR).unwrap();
+        assert_eq!(
+            fs::read_to_string(format!("{}/git-map", IMPORT_DIR)).unwrap(),
+            map
+        );
+    });
+
+    assert!(git::import_git("./nothing").is_err());
+    cleanup();
+}
+
+#[test]
+#[serial]
+fn git_object_encoding() {
+    setup();
+    cleanup();
+    fs::remove_dir_all(DSGIT_DIR).unwrap();
+    init_with_format(RepositoryFormat {
+        encoding: ObjectEncoding::Git,
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(
+        RepositoryFormat::read().unwrap().encoding,
+        ObjectEncoding::Git
+    );
+
+    // Blobs and trees are hashed as Git does.
+    let (foo, bar) = (
+        hash_object("foo\n", TypeObject::Blob).unwrap(),
+        hash_object("bar\n", TypeObject::Blob).unwrap(),
+    );
+    assert_eq!(foo, "257cc5642cb1a054f08cc83f2d943e56fd3ebe99");
+    assert_eq!(foo, git::git_hash(TypeObject::Blob, b"foo\n"));
+    let blob = |path: &str, oid: &str| Entry {
+        path: path.to_owned(),
+        oid: ObjectId::parse(oid).unwrap(),
+        obj_type: TypeObject::Blob,
+    };
+    let tree = Tree {
+        entries: vec![blob("./bar.txt", &bar), blob("./dir/foo.txt", &foo)],
+    };
+    let tree_oid = tree.write().unwrap();
+    assert_eq!(tree_oid, "b4400f845b9b9ddd73ca66109e2973d08b19ffd7");
+
+    // A tree is read with the paths of its files as a dsgit tree.
+    fs::create_dir_all("./dir").unwrap();
+    fs::write("./dir/foo.txt", "foo\n").unwrap();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    fs::write("./dir/foo.txt", "foo foo\n").unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    let commit = Commit::get_commit(&oid2).unwrap();
+    assert_eq!(commit.parent, Some(oid1.to_owned()));
+    assert_eq!(commit.message, "2nd commit");
+    let contents = get_object(&oid2, TypeObject::Commit).unwrap();
+    assert!(contents.contains("\nauthor "), "{}", contents);
+    let files = Tree::get_commit_tree(&oid2).unwrap();
+    let foo_foo = hash_object("foo foo\n", TypeObject::Blob).unwrap();
+    assert!(files.entries.contains(&blob("./dir/foo.txt", &foo_foo)));
+    assert_eq!(files.write().unwrap(), commit.tree);
+    let tag = Tag::create("v1", &oid1, "version 1").unwrap();
+
+    // Exported objects keep their hashes in Git.
+    let refs = git::export_git(GIT_DIR).unwrap();
+    assert_eq!(
+        refs,
+        vec![
+            ("refs/heads/main".to_owned(), oid2.to_string()),
+            ("refs/tags/v1".to_owned(), tag.to_string()),
+        ]
+    );
+    let repository = GitRepository::open(GIT_DIR).unwrap();
+    assert!(repository.has_object(&commit.tree));
+
+    // A repository of the default encoding still reads these objects.
+    with_dsgit_dir(IMPORT_DIR, || {
+        init().unwrap();
+        assert_eq!(
+            RepositoryFormat::read().unwrap().encoding,
+            ObjectEncoding::Dsgit
+        );
+    });
+    fs::copy(
+        format!("{}/objects/{}", DSGIT_DIR, commit.tree),
+        format!("{}/objects/{}", IMPORT_DIR, commit.tree),
+    )
+    .unwrap();
+    with_dsgit_dir(IMPORT_DIR, || {
+        let tree = get_object(&commit.tree, TypeObject::Tree).unwrap();
+        assert!(tree.contains(" ./dir\n"), "{}", tree);
+    });
+
+    fs::remove_dir_all("./dir").unwrap();
+    cleanup();
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use serial_test::serial;
+use std::env::set_current_dir;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::Path;
+use std::thread;
+
+use common::{setup, with_dsgit_dir};
+use dsgit::commit::Commit;
+use dsgit::data::{get_oid, object_exists};
+use dsgit::http;
+use dsgit::reference::{self, RefValue};
+use dsgit::remote::{self, remote_dsgit_dir, Remote, Upstream};
+
+const REMOTE_DIR: &str = "../test_http_remote.dsgit";
+
+// Serve a repository on a free loopback port in the background, and return
+// its URL.
+fn serve(path: &str) -> String {
+    let path = fs::canonicalize(path).unwrap();
+    let dsgit_dir = remote_dsgit_dir(path.to_str().unwrap()).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || http::serve(listener, &dsgit_dir));
+    url
+}
+
+fn setup_remote() -> String {
+    let _ = fs::remove_dir_all(REMOTE_DIR);
+    with_dsgit_dir(REMOTE_DIR, || remote::clone(".", true)).unwrap();
+    serve(REMOTE_DIR)
+}
+
+fn remote_oid(name: &str) -> String {
+    with_dsgit_dir(REMOTE_DIR, || get_oid(name)).unwrap().into()
+}
+
+#[test]
+#[serial]
+fn http_fetch_and_push() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    reference::create_tag("tag1", &oid1).unwrap();
+    let url = setup_remote();
+
+    let origin = Remote::add("origin", &url).unwrap();
+    let changes = remote::fetch(&origin).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(get_oid("origin/main").unwrap(), oid1);
+
+    fs::write("./foo.txt", "foo bar").unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    let change = remote::push(&origin, "main", false).unwrap();
+    assert_eq!(change.old_oid, Some(oid1.to_string()));
+    assert_eq!(remote_oid("main"), oid2);
+    assert!(with_dsgit_dir(REMOTE_DIR, || object_exists(&oid2)));
+    assert_eq!(get_oid("origin/main").unwrap(), oid2);
+
+    // A commit made in the remote comes with fetch.
+    let oid3 = with_dsgit_dir(REMOTE_DIR, || {
+        let tree = Commit::get_commit(&oid2).unwrap().tree;
+        let oid = Commit::create(&tree, Some(&oid2), "remote commit").unwrap();
+        let ref_value = RefValue::new(Some(&oid), false, &oid);
+        RefValue::update_ref("refs/heads/main", &ref_value, false, "commit").unwrap();
+        oid
+    });
+    assert!(!object_exists(&oid3));
+    remote::fetch(&origin).unwrap();
+    assert!(object_exists(&oid3));
+    assert_eq!(get_oid("origin/main").unwrap(), oid3);
+
+    // The local branch is behind the remote one now.
+    assert!(remote::push(&origin, "main", false).is_err());
+    assert_eq!(remote_oid("main"), oid3);
+    remote::push(&origin, "main", true).unwrap();
+    assert_eq!(remote_oid("main"), oid2);
+
+    fs::remove_file("./foo.txt").unwrap();
+    fs::remove_dir_all(REMOTE_DIR).unwrap();
+}
+
+#[test]
+#[serial]
+fn http_clone() {
+    // Go back to the test directory even if the test fails.
+    struct Restore;
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            set_current_dir("../test_files").unwrap();
+        }
+    }
+
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let url = serve(".");
+    let clone_dir = "../test_http_clone";
+    let _ = fs::remove_dir_all(clone_dir);
+    fs::create_dir(clone_dir).unwrap();
+
+    {
+        set_current_dir(clone_dir).unwrap();
+        let _restore = Restore;
+        remote::clone(&url, false).unwrap();
+
+        assert_eq!(fs::read_to_string("hello.txt").unwrap(), "Hello World!\n");
+        assert!(Path::new("other").is_dir());
+        assert_eq!(get_oid("main").unwrap(), oid1);
+        assert_eq!(get_oid("origin/main").unwrap(), oid1);
+        assert_eq!(Remote::get("origin").unwrap().unwrap().url, url);
+        assert_eq!(Upstream::get("main").unwrap().unwrap().remote, "origin");
+
+        // The branch checked out in the served working tree is refused.
+        let origin = Remote::get("origin").unwrap().unwrap();
+        fs::write("./foo.txt", "foo bar").unwrap();
+        Commit::commit("2nd commit", &[]).unwrap();
+        let err = remote::push(&origin, "main", false).unwrap_err();
+        assert!(err.to_string().contains("checked out"), "{}", err);
+    }
+    assert_eq!(get_oid("main").unwrap(), oid1);
+    fs::remove_dir_all(clone_dir).unwrap();
+}
+
+#[test]
+#[serial]
+fn http_body_longer_than_sent() {
+    setup();
+    let url = setup_remote();
+
+    // A client claiming a huge body gets an error instead of the server
+    // allocating it.
+    let mut stream = TcpStream::connect(url.trim_start_matches("http://")).unwrap();
+    write!(
+        stream,
+        "POST /dsgit-receive-pack HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+        u64::MAX / 2
+    )
+    .unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"),
+        "{}",
+        response
+    );
+    fs::remove_dir_all(REMOTE_DIR).unwrap();
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use serial_test::serial;
+use std::fs;
+
+use common::setup;
+use dsgit::commit::Commit;
+use dsgit::entry::Tree;
+use dsgit::mail::{self, format_date, parse_date};
+use dsgit::reference::RefValue;
+
+const OUTPUT_DIR: &str = "../mail_out";
+const AUTHOR: &str = "Alice <alice@example.com> 1700000000 +0900";
+
+#[test]
+fn mail_date() {
+    assert_eq!(
+        format_date(1700000000, "+0900"),
+        "Wed, 15 Nov 2023 07:13:20 +0900"
+    );
+    assert_eq!(format_date(0, "-0130"), "Wed, 31 Dec 1969 22:30:00 -0130");
+    assert_eq!(
+        parse_date("Wed, 15 Nov 2023 07:13:20 +0900").unwrap(),
+        (1700000000, String::from("+0900"))
+    );
+    assert_eq!(
+        parse_date("29 Feb 2024 00:00:00 +0000").unwrap(),
+        (1709164800, String::from("+0000"))
+    );
+    assert!(parse_date("Wed, 15 Nov 2023").is_err());
+}
+
+#[test]
+#[serial]
+fn format_patch_and_am() {
+    setup();
+    let _ = fs::remove_dir_all(OUTPUT_DIR);
+    let base_oid = Commit::commit("base", &[]).unwrap();
+
+    fs::write("./lion.txt", "gao\n").unwrap();
+    let tree = Tree::write_tree(".", &[]).unwrap();
+    let lion_oid = Commit::create_authored(&tree, Some(&base_oid), AUTHOR, "Add a lion").unwrap();
+    let ref_value = RefValue::new(Some(&lion_oid), false, &lion_oid);
+    RefValue::update_ref("HEAD", &ref_value, true, "commit: Add a lion").unwrap();
+    fs::write("./cat.txt", "cat cat\nmeow\n").unwrap();
+    let cat_oid = Commit::commit("Teach the cat to meow", &[]).unwrap();
+
+    let paths = mail::format_patch(&base_oid, OUTPUT_DIR).unwrap();
+    assert_eq!(
+        paths,
+        vec![
+            format!("{}/0001-Add-a-lion.patch", OUTPUT_DIR),
+            format!("{}/0002-Teach-the-cat-to-meow.patch", OUTPUT_DIR),
+        ]
+    );
+    let first = fs::read_to_string(&paths[0]).unwrap();
+    let expected = format!(
+        "From {} Mon Sep 17 00:00:00 2001
+From: Alice <alice@example.com>
+Date: Wed, 15 Nov 2023 07:13:20 +0900
+Subject: [PATCH 1/2] Add a lion
+
+---
+ lion.txt | 1 +
+ 1 file changed, 1 insertion(+)
+
+diff --git a/lion.txt b/lion.txt
+new file mode 100644
+",
+        lion_oid
+    );
+    assert!(first.starts_with(&expected), "{}", first);
+    assert!(first.ends_with("+gao\n-- \ndsgit 0.1.0\n\n"));
+    let second = fs::read_to_string(&paths[1]).unwrap();
+    assert!(second.contains("Subject: [PATCH 2/2] Teach the cat to meow\n"));
+    assert!(second.contains(" cat.txt | 1 +\n"));
+
+    // Apply both mails in a mailbox onto the base commit. A line starting
+    // with `From ` in a message does not start another mail.
+    let mailbox = format!("{}/mbox", OUTPUT_DIR);
+    let first = first.replacen("\n\n---\n", "\n\nFrom the zoo.\n---\n", 1);
+    fs::write(&mailbox, first + &second).unwrap();
+    let mails = mail::read_mailbox(&mailbox).unwrap();
+    assert_eq!(mails.len(), 2);
+    assert_eq!(mails[0].author, AUTHOR);
+    assert_eq!(mails[1].subject, "Teach the cat to meow");
+
+    RefValue::switch(&base_oid, &[]).unwrap();
+    let oids = mail::am(&mailbox, &[]).unwrap();
+    assert_eq!(oids.len(), 2);
+    let lion = Commit::get_commit(&oids[0]).unwrap();
+    assert_eq!(lion.author.as_deref(), Some(AUTHOR));
+    assert_eq!(lion.message, "Add a lion");
+    assert_eq!(lion.parent.as_ref(), Some(&base_oid));
+    assert_eq!(lion.tree, Commit::get_commit(&lion_oid).unwrap().tree);
+    let cat = Commit::get_commit(&oids[1]).unwrap();
+    assert_eq!(cat.message, "Teach the cat to meow");
+    assert_eq!(cat.tree, Commit::get_commit(&cat_oid).unwrap().tree);
+    assert_eq!(fs::read_to_string("./cat.txt").unwrap(), "cat cat\nmeow\n");
+
+    // The patches are already applied.
+    assert!(mail::am(&mailbox, &[]).is_err());
+
+    // Teardown, restore the original files.
+    let _ = fs::remove_file("./lion.txt.rej");
+    let _ = fs::remove_file("./cat.txt.rej");
+    RefValue::switch(&base_oid, &[]).unwrap();
+    fs::remove_dir_all(OUTPUT_DIR).unwrap();
+}
@@ -0,0 +1,31 @@
+use dsgit::merge::merge_contents;
+
+#[test]
+fn merge_contents_without_conflict() {
+    let base = "one\ntwo\nthree\nfour\nfive\n";
+    let ours = "ONE\ntwo\nthree\nfour\nfive\n";
+    let theirs = "one\ntwo\nthree\nfour\nFIVE\n";
+
+    let (merged, conflict) = merge_contents(base, ours, theirs, "HEAD", "other");
+    assert!(!conflict);
+    assert_eq!(merged, "ONE\ntwo\nthree\nfour\nFIVE\n");
+
+    // Same change on both sides is taken once.
+    let (merged, conflict) = merge_contents(base, ours, ours, "HEAD", "other");
+    assert!(!conflict);
+    assert_eq!(merged, ours);
+}
+
+#[test]
+fn merge_contents_with_conflict() {
+    let base = "one\ntwo\nthree\n";
+    let ours = "one\n2\nthree\n";
+    let theirs = "one\nzwei\nthree\n";
+
+    let (merged, conflict) = merge_contents(base, ours, theirs, "HEAD", "other");
+    assert!(conflict);
+    assert_eq!(
+        merged,
+        "one\n<<<<<<< HEAD\n2\n=======\nzwei\n>>>>>>> other\nthree\n"
+    );
+}
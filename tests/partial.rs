@@ -0,0 +1,142 @@
+mod common;
+
+use serial_test::serial;
+use std::fs;
+use std::net::TcpListener;
+use std::slice;
+use std::thread;
+
+use common::{setup, with_dsgit_dir};
+use dsgit::commit::Commit;
+use dsgit::config::Config;
+use dsgit::data::{get_object, hash_object, object_exists, TypeObject};
+use dsgit::diff::diff_trees;
+use dsgit::entry::Tree;
+use dsgit::hash::ObjectId;
+use dsgit::http;
+use dsgit::protocol::{FetchRequest, ObjectFilter};
+use dsgit::remote::{self, remote_dsgit_dir, Remote};
+use dsgit::transport::FetchOptions;
+
+const PARTIAL_DIR: &str = "../test_partial_clone.dsgit";
+
+const BLOB_NONE: FetchOptions = FetchOptions {
+    depth: None,
+    filter: Some(ObjectFilter::BlobNone),
+};
+
+// Commit `contents` as `foo.txt`, and return the commit and the blob.
+fn commit_foo(contents: &str) -> (ObjectId, ObjectId) {
+    fs::write("./foo.txt", contents).unwrap();
+    let oid = Commit::commit(contents, &[]).unwrap();
+    (oid, hash_object(contents, TypeObject::Blob).unwrap())
+}
+
+#[test]
+#[serial]
+fn partial_clone_fetches_blobs_lazily() {
+    setup();
+    let (oid1, blob1) = commit_foo("foo\n");
+    let _ = fs::remove_dir_all(PARTIAL_DIR);
+    with_dsgit_dir(PARTIAL_DIR, || {
+        remote::clone_with(".", true, &BLOB_NONE).unwrap();
+        let config = Config::read().unwrap();
+        assert_eq!(
+            config.get("extensions.partialClone").unwrap().as_deref(),
+            Some("origin")
+        );
+        assert_eq!(
+            Remote::get("origin").unwrap().unwrap().filter,
+            Some(ObjectFilter::BlobNone)
+        );
+        assert!(object_exists(&oid1));
+        assert!(object_exists(&Commit::get_commit(&oid1).unwrap().tree));
+        assert!(!object_exists(&blob1));
+
+        // Reading an object never reaches the network, a missing blob is
+        // fetched on request and kept afterwards.
+        assert!(get_object(&blob1, TypeObject::Blob).is_err());
+        assert_eq!(
+            remote::fetch_missing_objects(slice::from_ref(&blob1)).unwrap(),
+            1
+        );
+        assert_eq!(get_object(&blob1, TypeObject::Blob).unwrap(), "foo\n");
+        assert_eq!(
+            remote::fetch_missing_objects(slice::from_ref(&blob1)).unwrap(),
+            0
+        );
+    });
+
+    // Later fetches leave out blobs as well.
+    let (oid2, blob2) = commit_foo("foo bar\n");
+    with_dsgit_dir(PARTIAL_DIR, || {
+        let origin = Remote::get("origin").unwrap().unwrap();
+        remote::fetch(&origin).unwrap();
+        assert!(object_exists(&oid2));
+        assert!(!object_exists(&blob2));
+
+        // Comparing trees fetches the blobs it needs.
+        let from = Tree::get_commit_tree(&oid1).unwrap();
+        let to = Tree::get_commit_tree(&oid2).unwrap();
+        assert_eq!(diff_trees(&from, &to).unwrap().changes.len(), 1);
+        assert_eq!(get_object(&blob2, TypeObject::Blob).unwrap(), "foo bar\n");
+
+        // An object the promisor remote does not have is still an error.
+        let zero_oid = ObjectId::parse(&"0".repeat(40)).unwrap();
+        assert!(remote::fetch_missing_objects(&[zero_oid]).is_err());
+    });
+
+    fs::remove_file("./foo.txt").unwrap();
+    fs::remove_dir_all(PARTIAL_DIR).unwrap();
+}
+
+#[test]
+#[serial]
+fn partial_clone_over_http() {
+    setup();
+    let (oid1, blob1) = commit_foo("foo\n");
+    let path = fs::canonicalize(".").unwrap();
+    let dsgit_dir = remote_dsgit_dir(path.to_str().unwrap()).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || http::serve(listener, &dsgit_dir));
+
+    let _ = fs::remove_dir_all(PARTIAL_DIR);
+    with_dsgit_dir(PARTIAL_DIR, || {
+        remote::clone_with(&url, true, &BLOB_NONE).unwrap();
+        assert!(object_exists(&oid1));
+        assert!(!object_exists(&blob1));
+        remote::fetch_missing_objects(slice::from_ref(&blob1)).unwrap();
+        assert_eq!(get_object(&blob1, TypeObject::Blob).unwrap(), "foo\n");
+    });
+
+    fs::remove_file("./foo.txt").unwrap();
+    fs::remove_dir_all(PARTIAL_DIR).unwrap();
+}
+
+#[test]
+#[serial]
+fn filter_request() {
+    setup();
+    let (oid1, blob1) = commit_foo("foo\n");
+    let request = FetchRequest {
+        wants: vec![oid1.to_string()],
+        filter: Some(ObjectFilter::BlobNone),
+        ..Default::default()
+    };
+    let text = request.to_string();
+    assert_eq!(text, format!("want {}\nfilter blob:none\n", oid1));
+    assert_eq!(text.parse::<FetchRequest>().unwrap(), request);
+    let objects = request.objects().unwrap();
+    assert!(objects.contains(&oid1));
+    assert!(!objects.contains(&blob1));
+
+    // A blob wanted by its hash is sent in spite of the filter.
+    let request = FetchRequest {
+        wants: vec![blob1.to_string()],
+        ..request
+    };
+    assert_eq!(request.objects().unwrap(), vec![blob1]);
+    assert!("filter tree:0\n".parse::<FetchRequest>().is_err());
+    fs::remove_file("./foo.txt").unwrap();
+}
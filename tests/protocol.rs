@@ -0,0 +1,143 @@
+mod common;
+
+use serial_test::serial;
+use std::fs;
+
+use common::{setup, with_dsgit_dir};
+use dsgit::commit::Commit;
+use dsgit::data::{init, object_exists, ObjectEncoding, RepositoryFormat};
+use dsgit::hash::HashAlgorithm;
+use dsgit::pack::{read_pack, write_pack};
+use dsgit::protocol::{check_report, objects_to_send, RefAdvertisement, RefUpdate};
+use dsgit::reference;
+
+#[test]
+#[serial]
+fn advertise_refs() {
+    setup();
+    let advertisement = RefAdvertisement::current().unwrap();
+    assert_eq!(
+        advertisement.head_branch.as_deref(),
+        Some("refs/heads/main")
+    );
+    assert_eq!(advertisement.head_oid, None);
+    assert_eq!(
+        advertisement
+            .to_string()
+            .parse::<RefAdvertisement>()
+            .unwrap(),
+        advertisement
+    );
+
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    reference::create_tag("tag1", &oid1).unwrap();
+    let advertisement = RefAdvertisement::current().unwrap();
+    assert_eq!(
+        advertisement.to_string(),
+        format!(
+            "{0} HEAD refs/heads/main\n{0} refs/heads/main\n{0} refs/tags/tag1\n",
+            oid1
+        )
+    );
+    assert_eq!(
+        advertisement
+            .to_string()
+            .parse::<RefAdvertisement>()
+            .unwrap(),
+        advertisement
+    );
+    assert_eq!(advertisement.get("refs/tags/tag1"), Some(oid1.as_str()));
+    assert_eq!(advertisement.branches().count(), 1);
+    assert!("broken".parse::<RefAdvertisement>().is_err());
+
+    let advertisement = RefAdvertisement {
+        format: RepositoryFormat {
+            encoding: ObjectEncoding::Git,
+            hash: HashAlgorithm::Sha256,
+        },
+        ..Default::default()
+    };
+    assert_eq!(
+        advertisement.to_string(),
+        format!(
+            "{} HEAD\nformat sha256 git\n",
+            HashAlgorithm::Sha256.zero_oid()
+        )
+    );
+    assert_eq!(
+        advertisement
+            .to_string()
+            .parse::<RefAdvertisement>()
+            .unwrap(),
+        advertisement
+    );
+}
+
+#[test]
+fn ref_update() {
+    let zero_oid = HashAlgorithm::Sha1.zero_oid();
+    let update: RefUpdate = format!("{} {} refs/heads/main", zero_oid, "a".repeat(40))
+        .parse()
+        .unwrap();
+    assert_eq!(update.old_oid, None);
+    assert_eq!(update.to_string().parse::<RefUpdate>().unwrap(), update);
+
+    // The zero oid is as long as the new object id.
+    let update = RefUpdate {
+        name: String::from("refs/heads/main"),
+        old_oid: None,
+        new_oid: "b".repeat(64),
+    };
+    let zero_oid = HashAlgorithm::Sha256.zero_oid();
+    assert!(update.to_string().starts_with(&format!("{} ", zero_oid)));
+    assert_eq!(update.to_string().parse::<RefUpdate>().unwrap(), update);
+    assert!("refs/heads/main".parse::<RefUpdate>().is_err());
+
+    assert!(check_report("ok refs/heads/main\n").is_ok());
+    let err = check_report("ok refs/heads/a\nng refs/heads/b stale info\n").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "The remote rejected refs/heads/b: stale info"
+    );
+}
+
+#[test]
+#[serial]
+fn pack_objects() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    fs::write("./foo.txt", "foo bar").unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+
+    // Only the objects introduced by the 2nd commit are sent to a side
+    // having the 1st commit.
+    let objects = objects_to_send(&[oid2.to_string()], &[oid1.to_string()]).unwrap();
+    assert_eq!(objects.len(), 3);
+    assert_eq!(objects.last(), Some(&oid2));
+    let all_objects = objects_to_send(&[oid2.to_string()], &[]).unwrap();
+    assert!(all_objects.len() > objects.len());
+
+    let mut pack = vec![];
+    write_pack(&mut pack, &all_objects).unwrap();
+    let other_dir = "../test_pack.dsgit";
+    let _ = fs::remove_dir_all(other_dir);
+    with_dsgit_dir(other_dir, || {
+        init().unwrap();
+        assert_eq!(read_pack(&mut pack.as_slice()).unwrap(), all_objects.len());
+        assert!(object_exists(&oid2));
+        // Objects already present are not written again.
+        assert_eq!(read_pack(&mut pack.as_slice()).unwrap(), 0);
+
+        // A broken object is detected by its hash.
+        let broken = String::from_utf8(pack.clone())
+            .unwrap()
+            .replace("foo bar", "foo baz");
+        assert!(read_pack(&mut broken.as_bytes()).is_err());
+        assert!(read_pack(&mut "DSGITPACK 1\n".as_bytes()).is_err());
+        // A size larger than the pack is an error, not an allocation.
+        let lying = format!("DSGITPACK 1\n{} 99999999999999\n", oid2);
+        assert!(read_pack(&mut lying.as_bytes()).is_err());
+    });
+    fs::remove_file("./foo.txt").unwrap();
+    fs::remove_dir_all(other_dir).unwrap();
+}
@@ -0,0 +1,175 @@
+mod common;
+
+use serial_test::serial;
+use std::fs;
+use std::path::Path;
+
+use common::{setup, with_dsgit_dir};
+use dsgit::commit::{Commit, MERGE_HEAD};
+use dsgit::config::Config;
+use dsgit::data::get_oid;
+use dsgit::hash::ObjectId;
+use dsgit::pull::{pull, PullMode, PullOutcome};
+use dsgit::reference::{self, RefValue};
+use dsgit::remote::{self, Remote, Upstream};
+
+const REMOTE_DIR: &str = "../test_pull_remote.dsgit";
+
+// Create a bare remote `origin` from the current repository, and let `main`
+// track it.
+fn setup_origin() -> Remote {
+    let _ = fs::remove_dir_all(REMOTE_DIR);
+    with_dsgit_dir(REMOTE_DIR, || remote::clone(".", true)).unwrap();
+    let origin = Remote::add("origin", REMOTE_DIR).unwrap();
+    remote::fetch(&origin).unwrap();
+    Upstream::set("main", "origin/main").unwrap();
+    origin
+}
+
+// Push a commit writing `file` to the remote, then move the local branch
+// back to `oid` so that the remote is ahead.
+fn commit_ahead_on_remote(origin: &Remote, oid: &str, file: &str, contents: &str) -> ObjectId {
+    fs::write(file, contents).unwrap();
+    let remote_oid = Commit::commit("remote commit", &[]).unwrap();
+    remote::push(origin, "main", false).unwrap();
+    reference::reset(oid);
+    fs::remove_file(file).unwrap();
+    remote_oid
+}
+
+#[test]
+#[serial]
+fn pull_fast_forward() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let origin = setup_origin();
+    assert_eq!(pull(None, None, None, &[]).unwrap(), PullOutcome::UpToDate);
+
+    let oid2 = commit_ahead_on_remote(&origin, &oid1, "./foo.txt", "foo\n");
+    assert!(!Path::new("./foo.txt").exists());
+    assert_eq!(
+        pull(None, None, None, &[]).unwrap(),
+        PullOutcome::FastForward(oid2.to_owned())
+    );
+    assert_eq!(get_oid("main").unwrap(), oid2);
+    assert_eq!(fs::read_to_string("./foo.txt").unwrap(), "foo\n");
+    assert_eq!(pull(None, None, None, &[]).unwrap(), PullOutcome::UpToDate);
+
+    // A repository path and a branch can be given instead of the upstream.
+    assert_eq!(
+        pull(Some(REMOTE_DIR), Some("main"), None, &[]).unwrap(),
+        PullOutcome::UpToDate
+    );
+    assert!(pull(Some(REMOTE_DIR), Some("nothing"), None, &[]).is_err());
+    fs::remove_file("./foo.txt").unwrap();
+    fs::remove_dir_all(REMOTE_DIR).unwrap();
+}
+
+#[test]
+#[serial]
+fn pull_merge() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let origin = setup_origin();
+    let oid2 = commit_ahead_on_remote(&origin, &oid1, "./foo.txt", "foo\n");
+    fs::write("./bar.txt", "bar\n").unwrap();
+    let oid3 = Commit::commit("local commit", &[]).unwrap();
+
+    assert!(pull(None, None, Some(PullMode::FastForwardOnly), &[]).is_err());
+    let merged = match pull(None, None, None, &[]).unwrap() {
+        PullOutcome::Merged(oid) => oid,
+        outcome => panic!("Unexpected outcome: {:?}", outcome),
+    };
+    let commit = Commit::get_commit(&merged).unwrap();
+    assert_eq!(commit.parents(), vec![oid3.to_owned(), oid2.to_owned()]);
+    assert_eq!(
+        commit.message,
+        format!("Merge branch 'main' of {}", REMOTE_DIR)
+    );
+    assert_eq!(fs::read_to_string("./foo.txt").unwrap(), "foo\n");
+    assert_eq!(fs::read_to_string("./bar.txt").unwrap(), "bar\n");
+    assert!(Commit::is_ancestor(&oid2, &merged).unwrap());
+    assert_eq!(Commit::merge_base(&merged, &oid2).unwrap(), Some(oid2));
+    assert_eq!(pull(None, None, None, &[]).unwrap(), PullOutcome::UpToDate);
+
+    fs::remove_file("./foo.txt").unwrap();
+    fs::remove_file("./bar.txt").unwrap();
+    fs::remove_dir_all(REMOTE_DIR).unwrap();
+}
+
+#[test]
+#[serial]
+fn pull_rebase() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let origin = setup_origin();
+    let oid2 = commit_ahead_on_remote(&origin, &oid1, "./foo.txt", "foo\n");
+    fs::write("./bar.txt", "bar\n").unwrap();
+    Commit::commit("local commit", &[]).unwrap();
+
+    // The mode configured for the branch is used by default.
+    let mut config = Config::read().unwrap();
+    config.set("branch.main.rebase", "true").unwrap();
+    config.write().unwrap();
+    assert_eq!(PullMode::configured("main").unwrap(), PullMode::Rebase);
+
+    let rebased = match pull(None, None, None, &[]).unwrap() {
+        PullOutcome::Rebased(oid) => oid,
+        outcome => panic!("Unexpected outcome: {:?}", outcome),
+    };
+    let commit = Commit::get_commit(&rebased).unwrap();
+    assert_eq!(commit.parents(), vec![oid2]);
+    assert_eq!(commit.message, "local commit");
+    assert_eq!(get_oid("main").unwrap(), rebased);
+    assert_eq!(fs::read_to_string("./foo.txt").unwrap(), "foo\n");
+    assert_eq!(fs::read_to_string("./bar.txt").unwrap(), "bar\n");
+
+    fs::remove_file("./foo.txt").unwrap();
+    fs::remove_file("./bar.txt").unwrap();
+    fs::remove_dir_all(REMOTE_DIR).unwrap();
+}
+
+#[test]
+#[serial]
+fn pull_conflict_and_dirty_tree() {
+    setup();
+    fs::write("./foo.txt", "base\n").unwrap();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let origin = setup_origin();
+    let oid2 = commit_ahead_on_remote(&origin, &oid1, "./foo.txt", "theirs\n");
+
+    fs::write("./foo.txt", "ours\n").unwrap();
+    let err = pull(None, None, None, &[]).unwrap_err().to_string();
+    assert!(err.contains("modified: ./foo.txt"), "{}", err);
+
+    let oid3 = Commit::commit("local commit", &[]).unwrap();
+    // A rebase stopped by a conflict leaves the branch as it was.
+    let err = pull(None, None, Some(PullMode::Rebase), &[])
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("Conflicts in: ./foo.txt"), "{}", err);
+    assert_eq!(get_oid("main").unwrap(), oid3);
+    assert_eq!(fs::read_to_string("./foo.txt").unwrap(), "ours\n");
+
+    assert!(pull(None, None, None, &[]).is_err());
+    assert!(fs::read_to_string("./foo.txt")
+        .unwrap()
+        .contains("<<<<<<< HEAD"));
+    assert_eq!(
+        RefValue::get_ref(MERGE_HEAD, false).unwrap().unwrap().value,
+        oid2
+    );
+    // Pulling again is refused until the merge is concluded.
+    assert!(pull(None, None, None, &[]).is_err());
+
+    fs::write("./foo.txt", "resolved\n").unwrap();
+    let merged = Commit::commit("merge", &[]).unwrap();
+    assert_eq!(
+        Commit::get_commit(&merged).unwrap().parents(),
+        vec![oid3, oid2]
+    );
+    assert!(RefValue::get_ref(MERGE_HEAD, false).unwrap().is_none());
+
+    fs::remove_file("./foo.txt").unwrap();
+    fs::remove_dir_all(REMOTE_DIR).unwrap();
+}
@@ -7,8 +7,11 @@ use std::io;
 use std::path::Path;
 
 use common::{assert_file_contents, setup, DSGIT_DIR};
+use dsgit::data::get_oid;
 use dsgit::reference;
 use dsgit::reference::RefValue;
+use dsgit::reflog;
+use dsgit::tag::Tag;
 
 #[test]
 #[serial]
@@ -35,7 +38,7 @@ fn switch() {
     assert_number_files(5);
 
     // Switch branch.
-    reference::create_branch("branch1", &oid1);
+    reference::create_branch("branch1", &oid1).unwrap();
     RefValue::switch("branch1", &[]).unwrap();
     let head_path = format!("{}/HEAD", DSGIT_DIR);
     let expect_val = "ref:refs/heads/branch1".to_string();
@@ -49,12 +52,12 @@ fn create_tag() {
     let oid1 = Commit::commit("1st commit", &[]).unwrap();
     let oid2 = Commit::commit("2nd commit", &[]).unwrap();
 
-    reference::create_tag("tag1", &oid1);
+    reference::create_tag("tag1", &oid1).unwrap();
     let f1_path = format!("{}/refs/tags/tag1", DSGIT_DIR);
     assert!(Path::new(&f1_path).exists());
     assert_file_contents(&f1_path, vec![oid1]);
 
-    reference::create_tag("tag2", &oid2);
+    reference::create_tag("tag2", &oid2).unwrap();
     let f2_path = format!("{}/refs/tags/tag2", DSGIT_DIR);
     assert!(Path::new(&f2_path).exists());
     assert_file_contents(&f2_path, vec![oid2]);
@@ -67,12 +70,12 @@ fn create_branch() {
     let oid1 = Commit::commit("1st commit", &[]).unwrap();
     let oid2 = Commit::commit("2nd commit", &[]).unwrap();
 
-    reference::create_branch("branch1", &oid1);
+    reference::create_branch("branch1", &oid1).unwrap();
     let b1_path = format!("{}/refs/heads/branch1", DSGIT_DIR);
     assert!(Path::new(&b1_path).exists());
     assert_file_contents(&b1_path, vec![oid1]);
 
-    reference::create_branch("branch2", &oid2);
+    reference::create_branch("branch2", &oid2).unwrap();
     let b2_path = format!("{}/refs/heads/branch2", DSGIT_DIR);
     assert!(Path::new(&b2_path).exists());
     assert_file_contents(&b2_path, vec![oid2]);
@@ -84,12 +87,12 @@ fn get_all_branches() {
     setup();
     let oid1 = Commit::commit("1st commit", &[]).unwrap();
     let oid2 = Commit::commit("2nd commit", &[]).unwrap();
-    reference::create_branch("branch1", &oid1);
-    reference::create_branch("branch2", &oid2);
+    reference::create_branch("branch1", &oid1).unwrap();
+    reference::create_branch("branch2", &oid2).unwrap();
 
     let mut branches = RefValue::get_refs(Some("."), "refs/heads").unwrap();
     branches.sort();
-    assert_eq!(branches, vec!["HEAD", "branch1", "branch2"]);
+    assert_eq!(branches, vec!["HEAD", "branch1", "branch2", "main"]);
 }
 
 #[test]
@@ -100,13 +103,193 @@ fn reset() {
     let _ = Commit::commit("2nd commit", &[]).unwrap();
 
     let head_path = format!("{}/HEAD", DSGIT_DIR);
+    let branch_path = format!("{}/refs/heads/main", DSGIT_DIR);
 
+    // HEAD keeps pointing the branch, and the branch moves to the commit.
     reference::reset(&oid1);
+    assert_file_contents(&head_path, vec!["ref:refs/heads/main".to_owned()]);
     if cfg!(target_os = "windows") {
         let expect_val = "49db91fcd51c7f6e04916cf6679a2055882c5c7d".to_owned();
-        assert_file_contents(&head_path, vec![expect_val]);
+        assert_file_contents(&branch_path, vec![expect_val]);
     } else {
         let expect_val = "924a1ce93c755545d46c95bb2ae8e3ea15367587".to_owned();
-        assert_file_contents(&head_path, vec![expect_val]);
+        assert_file_contents(&branch_path, vec![expect_val]);
     }
 }
+
+#[test]
+#[serial]
+fn delete_branch() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    reference::create_branch("merged", &oid1).unwrap();
+    reference::create_branch("topic", &oid1).unwrap();
+    RefValue::switch("topic", &[]).unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    RefValue::switch("main", &[]).unwrap();
+
+    // The branch checked out can not be deleted.
+    assert!(reference::delete_branch("main", true).is_err());
+
+    // Unmerged branch is deleted only by force.
+    assert!(reference::delete_branch("topic", false).is_err());
+    assert_eq!(reference::delete_branch("topic", true).unwrap(), oid2);
+    assert!(!Path::new(&format!("{}/refs/heads/topic", DSGIT_DIR)).exists());
+    assert!(!Path::new(&format!("{}/logs/refs/heads/topic", DSGIT_DIR)).exists());
+
+    assert_eq!(reference::delete_branch("merged", false).unwrap(), oid1);
+    assert_eq!(reference::get_branches().unwrap(), vec!["main"]);
+    assert!(reference::delete_branch("nothing", true).is_err());
+}
+
+#[test]
+#[serial]
+fn rename_branch() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    reference::create_branch("topic", &oid1).unwrap();
+    assert!(reference::rename_branch("topic", "main").is_err());
+
+    reference::rename_branch("main", "trunk").unwrap();
+    assert_eq!(reference::get_branches().unwrap(), vec!["topic", "trunk"]);
+    assert_eq!(RefValue::get_branch_name().unwrap().unwrap(), "trunk");
+    assert!(!Path::new(&format!("{}/logs/refs/heads/main", DSGIT_DIR)).exists());
+
+    let entries = reflog::read("refs/heads/trunk").unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(
+        entries[0].message,
+        "Branch: renamed refs/heads/main to refs/heads/trunk"
+    );
+    assert_eq!(entries[0].new_oid, oid1);
+    assert_eq!(entries[1].message, "commit (initial): 1st commit");
+    assert_eq!(reflog::read("HEAD").unwrap()[0].message, entries[0].message);
+
+    // A failed rename leaves the branch and its reflog as they were.
+    assert!(reference::rename_branch("topic", "trunk").is_err());
+    assert_eq!(reflog::read("refs/heads/topic").unwrap().len(), 1);
+}
+
+#[test]
+#[serial]
+fn move_branch() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    reference::create_branch("topic", &oid1).unwrap();
+
+    assert!(reference::move_branch("main", &oid1).is_err());
+    reference::move_branch("topic", &oid2).unwrap();
+    let b_path = format!("{}/refs/heads/topic", DSGIT_DIR);
+    assert_file_contents(&b_path, vec![oid2]);
+    assert!(reference::move_branch("nothing", &oid1).is_err());
+}
+
+#[test]
+#[serial]
+fn pack_refs() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    reference::create_branch("branch1", &oid1).unwrap();
+    reference::create_tag("tag1", &oid1).unwrap();
+    let tag_oid = Tag::create("tag2", &oid2, "annotated").unwrap();
+
+    assert_eq!(reference::pack_refs().unwrap(), 4);
+    assert!(!Path::new(&format!("{}/refs/heads/branch1", DSGIT_DIR)).exists());
+    let packed_refs = reference::read_packed_refs().unwrap();
+    assert_eq!(packed_refs.len(), 4);
+    assert_eq!(packed_refs[3].name, "refs/tags/tag2");
+    assert_eq!(packed_refs[3].oid, tag_oid);
+    assert_eq!(packed_refs[3].peeled, Some(oid2.to_string()));
+
+    // Packed references are still resolved and listed.
+    assert_eq!(get_oid("branch1").unwrap(), oid1);
+    assert_eq!(get_oid("tag2").unwrap(), oid2);
+    assert_eq!(reference::get_head_oid(), oid2);
+    assert_eq!(reference::get_branches().unwrap(), vec!["branch1", "main"]);
+
+    // A loose reference takes priority over the packed one.
+    reference::create_branch("branch1", &oid2).unwrap();
+    assert_eq!(get_oid("branch1").unwrap(), oid2);
+    assert_eq!(reference::get_branches().unwrap(), vec!["branch1", "main"]);
+
+    // Deletion removes both, so that the packed value does not come back.
+    RefValue::delete_ref("refs/heads/branch1").unwrap();
+    assert!(RefValue::get_ref("refs/heads/branch1", false)
+        .unwrap()
+        .is_none());
+    assert_eq!(reference::read_packed_refs().unwrap().len(), 3);
+    assert!(RefValue::delete_ref("refs/heads/branch1").is_err());
+
+    // Packing again is seen at once, even when the file keeps its size.
+    reference::create_branch("branch2", &oid1).unwrap();
+    reference::pack_refs().unwrap();
+    assert_eq!(get_oid("branch2").unwrap(), oid1);
+    let ref_value = RefValue::new(Some(&oid2), false, &oid2);
+    RefValue::update_ref("refs/heads/branch2", &ref_value, false, "move").unwrap();
+    reference::pack_refs().unwrap();
+    assert_eq!(get_oid("branch2").unwrap(), oid2);
+}
+
+#[test]
+fn check_ref_format() {
+    for name in [
+        "refs/heads/main",
+        "refs/tags/v1.0",
+        "refs/heads/feature/a-b_c",
+        "HEAD",
+    ] {
+        assert!(reference::check_ref_format(name, false).is_ok(), "{}", name);
+    }
+    assert!(reference::check_ref_format("main", false).is_err());
+    assert!(reference::check_ref_format("main", true).is_ok());
+
+    for name in [
+        "",
+        "@",
+        "refs/heads/../../objects/x",
+        "refs/heads/.hidden",
+        "refs/heads/main.lock",
+        "refs/heads//main",
+        "/refs/heads/main",
+        "refs/heads/main/",
+        "refs/heads/main.",
+        "refs/heads/a b",
+        "refs/heads/a~1",
+        "refs/heads/a^",
+        "refs/heads/a:b",
+        "refs/heads/a?",
+        "refs/heads/a*",
+        "refs/heads/a[b",
+        "refs/heads/a\\b",
+        "refs/heads/a\tb",
+        "refs/heads/a@{1}",
+    ] {
+        assert!(reference::check_ref_format(name, true).is_err(), "{}", name);
+    }
+
+    assert!(reference::check_branch_name("feature/x").is_ok());
+    assert!(reference::check_branch_name("-x").is_err());
+    assert!(reference::check_branch_name("HEAD").is_err());
+}
+
+#[test]
+#[serial]
+fn reject_invalid_ref_names() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+
+    assert!(reference::create_branch("../../objects/x", &oid1).is_err());
+    assert!(reference::create_tag("..", &oid1).is_err());
+    assert!(Tag::create("a:b", &oid1, "annotated").is_err());
+    let ref_value = RefValue::new(Some(&oid1), false, &oid1);
+    assert!(RefValue::update_ref("refs/heads/a..b", &ref_value, false, "").is_err());
+    assert!(RefValue::get_ref("refs/heads/a..b", false)
+        .unwrap()
+        .is_none());
+
+    reference::create_branch("branch1", &oid1).unwrap();
+    assert!(reference::rename_branch("branch1", "bad name").is_err());
+    assert_eq!(get_oid("branch1").unwrap(), oid1);
+}
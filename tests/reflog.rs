@@ -0,0 +1,60 @@
+mod common;
+
+use serial_test::serial;
+
+use common::setup;
+use dsgit::commit::Commit;
+use dsgit::data::get_oid;
+use dsgit::hash::HashAlgorithm;
+use dsgit::reference;
+use dsgit::reference::RefValue;
+use dsgit::reflog;
+
+#[test]
+#[serial]
+fn record_ref_updates() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    reference::reset(&oid1);
+
+    let entries = reflog::read("refs/heads/main").unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0].old_oid, oid2);
+    assert_eq!(entries[0].new_oid, oid1);
+    assert_eq!(entries[0].message, format!("reset: moving to {}", oid1));
+    assert_eq!(entries[1].message, "commit: 2nd commit");
+    assert_eq!(entries[2].old_oid, HashAlgorithm::Sha1.zero_oid());
+    assert_eq!(entries[2].message, "commit (initial): 1st commit");
+
+    // HEAD records updates of the branch it points to.
+    assert_eq!(reflog::read("HEAD").unwrap(), entries);
+
+    // A switch is recorded only for HEAD.
+    reference::create_branch("branch1", &oid2).unwrap();
+    RefValue::switch("branch1", &[]).unwrap();
+    let entries = reflog::read("HEAD").unwrap();
+    assert_eq!(entries.len(), 4);
+    assert_eq!(entries[0].message, "switch: moving from main to branch1");
+    assert_eq!(entries[0].new_oid, oid2);
+    assert_eq!(reflog::read("refs/heads/main").unwrap().len(), 3);
+    assert_eq!(reflog::read("refs/heads/branch1").unwrap().len(), 1);
+
+    // Teardown, restore the original files.
+    RefValue::switch("main", &[]).unwrap();
+}
+
+#[test]
+#[serial]
+fn resolve_reflog_syntax() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    reference::reset(&oid1);
+
+    assert_eq!(get_oid("HEAD@{0}").unwrap(), oid1);
+    assert_eq!(get_oid("@{1}").unwrap(), oid2);
+    assert_eq!(get_oid("main@{2}").unwrap(), oid1);
+    assert!(get_oid("main@{3}").is_err());
+    assert!(get_oid("nothing@{0}").is_err());
+}
@@ -0,0 +1,296 @@
+mod common;
+
+use serial_test::serial;
+use std::env::set_current_dir;
+use std::fs;
+use std::path::Path;
+
+use common::{setup, with_dsgit_dir};
+use dsgit::commit::Commit;
+use dsgit::data::{
+    get_object, get_oid, init_with_format, object_exists, ObjectEncoding, RepositoryFormat,
+    TypeObject,
+};
+use dsgit::protocol::RefUpdate;
+use dsgit::reference::{self, RefValue};
+use dsgit::remote::{self, Refspec, Remote, TrackingStatus, Upstream};
+use dsgit::transport::Transport;
+
+const REMOTE_DIR: &str = "../test_remote.dsgit";
+
+// Create a bare repository cloned from the one of the current directory.
+fn setup_remote() {
+    let _ = fs::remove_dir_all(REMOTE_DIR);
+    with_dsgit_dir(REMOTE_DIR, || remote::clone(".", true)).unwrap();
+}
+
+fn origin() -> Remote {
+    Remote::new("origin", REMOTE_DIR)
+}
+
+fn remote_oid(name: &str) -> String {
+    with_dsgit_dir(REMOTE_DIR, || get_oid(name)).unwrap().into()
+}
+
+// Record a commit on top of `parent` in the remote, reusing its tree.
+fn commit_on_remote(parent: &str, message: &str) -> String {
+    with_dsgit_dir(REMOTE_DIR, || {
+        let tree = Commit::get_commit(parent).unwrap().tree;
+        let oid = Commit::create(&tree, Some(parent), message).unwrap();
+        let ref_value = RefValue::new(Some(&oid), false, &oid);
+        RefValue::update_ref("refs/heads/main", &ref_value, false, message).unwrap();
+        oid.into()
+    })
+}
+
+#[test]
+#[serial]
+fn clone_bare() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    reference::create_tag("tag1", &oid1).unwrap();
+    reference::create_branch("branch1", &oid1).unwrap();
+    setup_remote();
+
+    assert_eq!(remote_oid("main"), oid1);
+    assert_eq!(remote_oid("branch1"), oid1);
+    assert_eq!(remote_oid("tag1"), oid1);
+    assert_eq!(remote_oid("HEAD"), oid1);
+    let head = with_dsgit_dir(REMOTE_DIR, || RefValue::get_ref("HEAD", false)).unwrap();
+    assert_eq!(head.unwrap().value, "refs/heads/main");
+    assert!(!Path::new(&format!("{}/refs/remotes", REMOTE_DIR)).exists());
+    fs::remove_dir_all(REMOTE_DIR).unwrap();
+}
+
+#[test]
+#[serial]
+fn clone_bare_with_format() {
+    setup();
+    fs::remove_dir_all(common::DSGIT_DIR).unwrap();
+    let format = RepositoryFormat {
+        encoding: ObjectEncoding::Git,
+        ..Default::default()
+    };
+    init_with_format(format).unwrap();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    setup_remote();
+
+    assert_eq!(
+        with_dsgit_dir(REMOTE_DIR, RepositoryFormat::read).unwrap(),
+        format
+    );
+    assert_eq!(remote_oid("main"), oid1);
+    let commit = with_dsgit_dir(REMOTE_DIR, || get_object(&oid1, TypeObject::Commit));
+    assert!(commit.unwrap().contains("1st commit"));
+    fs::remove_dir_all(REMOTE_DIR).unwrap();
+}
+
+#[test]
+#[serial]
+fn clone_working_tree() {
+    // Go back to the test directory even if the test fails.
+    struct Restore;
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            set_current_dir("../test_files").unwrap();
+        }
+    }
+
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let clone_dir = "../test_clone";
+    let _ = fs::remove_dir_all(clone_dir);
+    fs::create_dir(clone_dir).unwrap();
+
+    {
+        set_current_dir(clone_dir).unwrap();
+        let _restore = Restore;
+        remote::clone("../test_files", false).unwrap();
+
+        assert_eq!(fs::read_to_string("hello.txt").unwrap(), "Hello World!\n");
+        assert!(Path::new("other").is_dir());
+        assert_eq!(RefValue::get_branch_name().unwrap().unwrap(), "main");
+        assert_eq!(get_oid("main").unwrap(), oid1);
+        assert_eq!(get_oid("origin/main").unwrap(), oid1);
+        assert_eq!(reference::get_branches().unwrap(), vec!["main"]);
+        assert_eq!(Remote::get("origin").unwrap().unwrap().url, "../test_files");
+        let upstream = Upstream::get("main").unwrap().unwrap();
+        assert_eq!(upstream.remote, "origin");
+        assert_eq!(upstream.merge, "refs/heads/main");
+    }
+    fs::remove_dir_all(clone_dir).unwrap();
+}
+
+#[test]
+#[serial]
+fn fetch() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    setup_remote();
+
+    let changes = remote::fetch(&origin()).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].dst, "refs/remotes/origin/main");
+    assert_eq!(changes[0].old_oid, None);
+    assert_eq!(get_oid("origin/main").unwrap(), oid1);
+
+    let oid2 = commit_on_remote(&oid1, "remote commit");
+    assert!(!object_exists(&oid2));
+    let changes = remote::fetch(&origin()).unwrap();
+    assert_eq!(changes[0].old_oid, Some(oid1.to_string()));
+    assert!(!changes[0].forced);
+    assert!(object_exists(&oid2));
+    assert_eq!(get_oid("origin/main").unwrap(), oid2);
+    // Local branches are not touched.
+    assert_eq!(get_oid("main").unwrap(), oid1);
+
+    assert!(remote::fetch(&origin()).unwrap().is_empty());
+    fs::remove_dir_all(REMOTE_DIR).unwrap();
+}
+
+#[test]
+#[serial]
+fn push() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    setup_remote();
+
+    fs::write("./foo.txt", "foo bar").unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    let change = remote::push(&origin(), "main", false).unwrap();
+    assert_eq!(change.old_oid, Some(oid1.to_string()));
+    assert!(!change.forced);
+    assert_eq!(remote_oid("main"), oid2);
+    assert!(with_dsgit_dir(REMOTE_DIR, || object_exists(&oid2)));
+    assert_eq!(get_oid("origin/main").unwrap(), oid2);
+
+    // Pushing the same commit again changes nothing.
+    let change = remote::push(&origin(), "main", false).unwrap();
+    assert_eq!(change.old_oid, Some(oid2.to_string()));
+
+    // A diverged remote branch is not overwritten without force.
+    let remote_oid3 = commit_on_remote(&oid2, "remote commit");
+    fs::remove_file("./foo.txt").unwrap();
+    let oid3 = Commit::commit("3rd commit", &[]).unwrap();
+    assert!(remote::push(&origin(), "main", false).is_err());
+    assert_eq!(remote_oid("main"), remote_oid3);
+
+    let change = remote::push(&origin(), "main", true).unwrap();
+    assert!(change.forced);
+    assert_eq!(remote_oid("main"), oid3);
+
+    // The branch checked out in a working tree is not updated.
+    assert!(remote::push(&Remote::new("self", "."), "main", false).is_err());
+    // Not a repository.
+    assert!(remote::push(&Remote::new("other", "./other"), "main", false).is_err());
+
+    // A pseudo-reference is not pushed, HEAD stays on the branch.
+    let update = RefUpdate {
+        name: String::from("HEAD"),
+        old_oid: Some(oid3.to_string()),
+        new_oid: oid1.to_string(),
+    };
+    assert!(Transport::open(REMOTE_DIR)
+        .unwrap()
+        .push(&[update])
+        .is_err());
+    let head = with_dsgit_dir(REMOTE_DIR, || RefValue::get_ref("HEAD", false)).unwrap();
+    assert_eq!(head.unwrap().value, "refs/heads/main");
+    fs::remove_dir_all(REMOTE_DIR).unwrap();
+}
+
+#[test]
+fn refspec() {
+    let refspec: Refspec = "+refs/heads/*:refs/remotes/origin/*".parse().unwrap();
+    assert!(refspec.force);
+    assert_eq!(refspec.to_string(), "+refs/heads/*:refs/remotes/origin/*");
+    assert_eq!(
+        refspec.map("refs/heads/feature/a"),
+        Some(String::from("refs/remotes/origin/feature/a"))
+    );
+    assert_eq!(refspec.map("refs/tags/v1"), None);
+    assert_eq!(
+        refspec.map_reverse("refs/remotes/origin/main"),
+        Some(String::from("refs/heads/main"))
+    );
+
+    let refspec: Refspec = "refs/heads/main:refs/remotes/origin/main".parse().unwrap();
+    assert!(!refspec.force);
+    assert_eq!(refspec.map("refs/heads/main2"), None);
+    assert!("refs/heads/*:refs/remotes/origin/main"
+        .parse::<Refspec>()
+        .is_err());
+    assert!("refs/heads/main".parse::<Refspec>().is_err());
+}
+
+#[test]
+#[serial]
+fn remote_config() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    setup_remote();
+
+    let remote = Remote::add("origin", REMOTE_DIR).unwrap();
+    assert!(Remote::add("origin", REMOTE_DIR).is_err());
+    assert!(Remote::add("bad name", REMOTE_DIR).is_err());
+    assert_eq!(Remote::get("origin").unwrap().unwrap(), remote);
+    assert_eq!(Remote::list().unwrap(), vec![remote]);
+    assert_eq!(Remote::resolve("origin").unwrap().url, REMOTE_DIR);
+    assert!(Remote::resolve(REMOTE_DIR).unwrap().fetch.is_empty());
+    assert!(Remote::resolve("nothing").is_err());
+
+    // A repository path fetches objects without remote-tracking branches.
+    let oid2 = commit_on_remote(&oid1, "remote commit");
+    assert!(remote::fetch(&Remote::resolve(REMOTE_DIR).unwrap())
+        .unwrap()
+        .is_empty());
+    assert!(object_exists(&oid2));
+
+    remote::fetch(&Remote::get("origin").unwrap().unwrap()).unwrap();
+    assert_eq!(get_oid("origin/main").unwrap(), oid2);
+
+    Upstream::set("main", "origin/main").unwrap();
+    assert!(Upstream::set("main", "origin/nothing").is_err());
+    assert!(Upstream::set("main", "main").is_err());
+    Remote::remove("origin").unwrap();
+    assert!(Remote::get("origin").unwrap().is_none());
+    assert!(Upstream::get("main").unwrap().is_none());
+    assert!(get_oid("origin/main").is_err());
+    fs::remove_dir_all(REMOTE_DIR).unwrap();
+}
+
+#[test]
+#[serial]
+fn tracking_status() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    setup_remote();
+    Remote::add("origin", REMOTE_DIR).unwrap();
+    remote::fetch(&origin()).unwrap();
+    assert_eq!(remote::tracking_status("main").unwrap(), None);
+
+    Upstream::set("main", "origin/main").unwrap();
+    let status = |ahead, behind| {
+        Some(TrackingStatus {
+            tracking: String::from("origin/main"),
+            ahead,
+            behind,
+        })
+    };
+    assert_eq!(remote::tracking_status("main").unwrap(), status(0, 0));
+
+    fs::write("./foo.txt", "foo bar").unwrap();
+    Commit::commit("2nd commit", &[]).unwrap();
+    Commit::commit("3rd commit", &[]).unwrap();
+    assert_eq!(remote::tracking_status("main").unwrap(), status(2, 0));
+
+    commit_on_remote(&oid1, "remote commit");
+    remote::fetch(&origin()).unwrap();
+    assert_eq!(remote::tracking_status("main").unwrap(), status(2, 1));
+
+    // The upstream follows the branch renamed.
+    reference::rename_branch("main", "renamed").unwrap();
+    assert_eq!(remote::tracking_status("renamed").unwrap(), status(2, 1));
+    fs::remove_file("./foo.txt").unwrap();
+    fs::remove_dir_all(REMOTE_DIR).unwrap();
+}
@@ -0,0 +1,165 @@
+mod common;
+
+use serial_test::serial;
+use std::collections::BTreeSet;
+use std::fs;
+
+use common::{setup, with_dsgit_dir};
+use dsgit::commit::Commit;
+use dsgit::data::object_exists;
+use dsgit::hash::ObjectId;
+use dsgit::protocol::FetchRequest;
+use dsgit::remote::{self, Remote};
+use dsgit::shallow;
+use dsgit::transport::FetchOptions;
+
+const SHALLOW_DIR: &str = "../test_shallow_clone.dsgit";
+
+// Make three commits, and return them from the oldest.
+fn commit_history() -> Vec<ObjectId> {
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    fs::write("./foo.txt", "foo\n").unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    fs::write("./foo.txt", "foo bar\n").unwrap();
+    let oid3 = Commit::commit("3rd commit", &[]).unwrap();
+    vec![oid1, oid2, oid3]
+}
+
+fn depth(depth: usize) -> FetchOptions {
+    FetchOptions {
+        depth: Some(depth),
+        filter: None,
+    }
+}
+
+fn shallows(oids: &[&ObjectId]) -> BTreeSet<ObjectId> {
+    oids.iter().map(|&oid| oid.clone()).collect()
+}
+
+#[test]
+#[serial]
+fn shallow_clone_and_unshallow() {
+    setup();
+    let oids = commit_history();
+    let _ = fs::remove_dir_all(SHALLOW_DIR);
+    with_dsgit_dir(SHALLOW_DIR, || {
+        remote::clone_with(".", true, &depth(2)).unwrap();
+        assert!(object_exists(&oids[2]));
+        assert!(object_exists(&oids[1]));
+        assert!(!object_exists(&oids[0]));
+        assert_eq!(shallow::read().unwrap(), shallows(&[&oids[1]]));
+
+        // Walks stop at the shallow commit instead of failing.
+        assert_eq!(Commit::get_commit(&oids[1]).unwrap().parent, None);
+        assert_eq!(
+            Commit::read_commit(&oids[1]).unwrap().parent,
+            Some(oids[0].to_owned())
+        );
+        assert!(Commit::is_ancestor(&oids[1], &oids[2]).unwrap());
+        assert_eq!(
+            Commit::count_ahead_behind(&oids[2], &oids[1]).unwrap(),
+            (1, 0)
+        );
+
+        // A written shallow file is seen at once, even with the same size.
+        assert!(shallow::is_shallow(&oids[1]).unwrap());
+        shallow::write(&shallows(&[&oids[2]])).unwrap();
+        assert!(shallow::is_shallow(&oids[2]).unwrap());
+        assert!(!shallow::is_shallow(&oids[1]).unwrap());
+        shallow::write(&shallows(&[&oids[1]])).unwrap();
+    });
+
+    // A new commit comes with fetch, leaving the old boundary as it is.
+    fs::write("./foo.txt", "foo bar baz\n").unwrap();
+    let oid4 = Commit::commit("4th commit", &[]).unwrap();
+    with_dsgit_dir(SHALLOW_DIR, || {
+        let remote = Remote::resolve(".").unwrap();
+        remote::fetch(&remote).unwrap();
+        assert!(object_exists(&oid4));
+        assert_eq!(shallow::read().unwrap(), shallows(&[&oids[1]]));
+
+        remote::unshallow(&remote).unwrap();
+        assert!(object_exists(&oids[0]));
+        assert!(shallow::read().unwrap().is_empty());
+        assert!(!shallow::is_shallow_repository());
+        assert_eq!(
+            Commit::get_commit(&oids[1]).unwrap().parent,
+            Some(oids[0].to_owned())
+        );
+        assert!(remote::unshallow(&remote).is_err());
+    });
+
+    fs::remove_file("./foo.txt").unwrap();
+    fs::remove_dir_all(SHALLOW_DIR).unwrap();
+}
+
+#[test]
+#[serial]
+fn shallow_clone_over_command() {
+    setup();
+    let oids = commit_history();
+    let path = fs::canonicalize(".").unwrap();
+    let url = format!(
+        "ext::{} %s {}",
+        env!("CARGO_BIN_EXE_dsgit"),
+        path.to_str().unwrap()
+    );
+    let _ = fs::remove_dir_all(SHALLOW_DIR);
+    with_dsgit_dir(SHALLOW_DIR, || {
+        remote::clone_with(&url, true, &depth(1)).unwrap();
+        assert!(object_exists(&oids[2]));
+        assert!(!object_exists(&oids[1]));
+        assert_eq!(shallow::read().unwrap(), shallows(&[&oids[2]]));
+
+        // The remote is told the shallow commit, so its parents are sent.
+        let remote = Remote::new("origin", &url);
+        remote::unshallow(&remote).unwrap();
+        assert!(object_exists(&oids[1]));
+        assert!(object_exists(&oids[0]));
+        assert!(!shallow::is_shallow_repository());
+    });
+
+    fs::remove_file("./foo.txt").unwrap();
+    fs::remove_dir_all(SHALLOW_DIR).unwrap();
+}
+
+#[test]
+#[serial]
+fn fetch_request() {
+    setup();
+    let oids = commit_history();
+    let request = FetchRequest {
+        wants: vec![oids[2].to_string()],
+        haves: vec![],
+        shallows: vec![],
+        depth: Some(1),
+        filter: None,
+    };
+    let text = request.to_string();
+    assert_eq!(text, format!("want {}\ndeepen 1\n", oids[2]));
+    assert_eq!(text.parse::<FetchRequest>().unwrap(), request);
+    let objects = request.objects().unwrap();
+    assert!(objects.contains(&oids[2]));
+    assert!(!objects.contains(&oids[1]));
+
+    // Parents of a shallow commit are sent even though a newer commit is had.
+    let request = FetchRequest {
+        wants: vec![oids[1].to_string()],
+        haves: vec![oids[2].to_string()],
+        shallows: vec![oids[2].to_string()],
+        depth: None,
+        filter: None,
+    };
+    let objects = request.objects().unwrap();
+    assert!(objects.contains(&oids[1]));
+    assert!(objects.contains(&oids[0]));
+    let request = FetchRequest {
+        shallows: vec![],
+        ..request
+    };
+    assert!(request.objects().unwrap().is_empty());
+
+    assert!("deepen 0\n".parse::<FetchRequest>().is_err());
+    assert!("wants x\n".parse::<FetchRequest>().is_err());
+    fs::remove_file("./foo.txt").unwrap();
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use serial_test::serial;
+use std::fs;
+use std::path::Path;
+
+use common::setup;
+use dsgit::commit::Commit;
+use dsgit::data::get_oid;
+use dsgit::reference::RefValue;
+use dsgit::stash;
+
+#[test]
+#[serial]
+fn push_and_pop() {
+    setup();
+    let base_oid = Commit::commit("base", &[]).unwrap();
+
+    // Nothing to stash on a clean working tree.
+    assert!(stash::push(None, &[]).is_err());
+
+    fs::write("./cat.txt", "cat cat\nmeow\n").unwrap();
+    fs::write("./lion.txt", "gao\n").unwrap();
+    let oid = stash::push(Some("animals"), &[]).unwrap();
+    assert_eq!(fs::read_to_string("./cat.txt").unwrap(), "cat cat\n");
+    assert!(!Path::new("./lion.txt").exists());
+
+    let entries = stash::list().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].oid, oid);
+    assert_eq!(entries[0].message, "On main: animals");
+    assert_eq!(get_oid("stash@{0}").unwrap(), oid);
+
+    // Pop onto a HEAD which has moved since the stash was created.
+    fs::write("./dogs.txt", "dog dog dog\nbow\n").unwrap();
+    Commit::commit("bow", &[]).unwrap();
+    stash::pop(0, &[]).unwrap();
+    assert_eq!(fs::read_to_string("./cat.txt").unwrap(), "cat cat\nmeow\n");
+    assert_eq!(fs::read_to_string("./lion.txt").unwrap(), "gao\n");
+    assert_eq!(
+        fs::read_to_string("./dogs.txt").unwrap(),
+        "dog dog dog\nbow\n"
+    );
+    assert!(stash::list().unwrap().is_empty());
+    assert!(RefValue::get_ref("refs/stash", false).unwrap().is_none());
+
+    // Teardown, restore the original files.
+    RefValue::switch(&base_oid, &[]).unwrap();
+}
+
+#[test]
+#[serial]
+fn apply_and_drop() {
+    setup();
+    let base_oid = Commit::commit("base", &[]).unwrap();
+
+    fs::write("./cat.txt", "first\n").unwrap();
+    let first_oid = stash::push(None, &[]).unwrap();
+    fs::write("./cat.txt", "second\n").unwrap();
+    let second_oid = stash::push(None, &[]).unwrap();
+
+    let entries = stash::list().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].oid, second_oid);
+    assert_eq!(entries[1].oid, first_oid);
+    assert!(entries[1].message.starts_with("WIP on main: "));
+
+    // `apply` keeps the entry in the stash list.
+    stash::apply(1, &[]).unwrap();
+    assert_eq!(fs::read_to_string("./cat.txt").unwrap(), "first\n");
+    assert_eq!(stash::list().unwrap().len(), 2);
+
+    assert_eq!(stash::drop(0).unwrap(), second_oid);
+    let entries = stash::list().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].oid, first_oid);
+    assert!(stash::drop(1).is_err());
+
+    // Teardown, restore the original files.
+    RefValue::switch(&base_oid, &[]).unwrap();
+}
+
+#[test]
+#[serial]
+fn push_without_commits() {
+    setup();
+    let err = stash::push(None, &[]).unwrap_err();
+    assert_eq!(err.to_string(), "You do not have the initial commit yet.");
+    assert!(stash::parse_stash_index("stash@{x}").is_err());
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use serial_test::serial;
+use std::fs;
+
+use common::{setup, with_dsgit_dir};
+use dsgit::commit::Commit;
+use dsgit::data::{get_oid, object_exists};
+use dsgit::pktline::{read_data, read_lines, write_data, write_error, write_flush, write_lines};
+use dsgit::protocol::serve_upload_pack;
+use dsgit::reference;
+use dsgit::remote::{self, Remote};
+use dsgit::transport::{SshUrl, Transport};
+
+const REMOTE_DIR: &str = "../test_stdio_remote.dsgit";
+
+// A remote reached by running the dsgit binary of this build as a
+// subprocess, in place of `ssh host dsgit`.
+fn setup_remote() -> Remote {
+    let _ = fs::remove_dir_all(REMOTE_DIR);
+    with_dsgit_dir(REMOTE_DIR, || remote::clone(".", true)).unwrap();
+    let path = fs::canonicalize(REMOTE_DIR).unwrap();
+    let url = format!(
+        "ext::{} %s {}",
+        env!("CARGO_BIN_EXE_dsgit"),
+        path.to_str().unwrap()
+    );
+    Remote::add("origin", &url).unwrap()
+}
+
+#[test]
+fn pkt_lines() {
+    let mut out = vec![];
+    write_lines(&mut out, &[String::from("hello"), String::from("")]).unwrap();
+    assert_eq!(out, b"000ahello\n0005\n0000");
+    assert_eq!(
+        read_lines(&mut out.as_slice()).unwrap(),
+        vec![String::from("hello"), String::from("")]
+    );
+
+    let data = vec![b'x'; 70000];
+    let mut out = vec![];
+    write_data(&mut out, &data).unwrap();
+    assert_eq!(&out[..4], b"fff0");
+    assert_eq!(read_data(&mut out.as_slice()).unwrap(), data);
+
+    let mut out = vec![];
+    write_error(&mut out, "not found").unwrap();
+    let err = read_lines(&mut out.as_slice()).unwrap_err();
+    assert_eq!(err.to_string(), "The remote failed: not found");
+    assert!(read_data(&mut out.as_slice()).is_err());
+
+    // A stream ending without a flush packet is broken.
+    assert!(read_lines(&mut "000ahello\n".as_bytes()).is_err());
+    assert!(read_lines(&mut "zzzz".as_bytes()).is_err());
+}
+
+#[test]
+#[serial]
+fn serve_upload_pack_without_request() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let mut input = vec![];
+    write_flush(&mut input).unwrap();
+    let mut out = vec![];
+    serve_upload_pack(&mut input.as_slice(), &mut out).unwrap();
+    assert_eq!(
+        read_lines(&mut out.as_slice()).unwrap(),
+        vec![
+            format!("{} HEAD refs/heads/main", oid1),
+            format!("{} refs/heads/main", oid1)
+        ]
+    );
+}
+
+#[test]
+#[serial]
+fn stdio_fetch_and_push() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let origin = setup_remote();
+
+    let changes = remote::fetch(&origin).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(get_oid("origin/main").unwrap(), oid1);
+
+    fs::write("./foo.txt", "foo bar").unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    reference::create_tag("tag1", &oid2).unwrap();
+    remote::push(&origin, "main", false).unwrap();
+    assert_eq!(
+        with_dsgit_dir(REMOTE_DIR, || get_oid("main")).unwrap(),
+        oid2
+    );
+    assert!(with_dsgit_dir(REMOTE_DIR, || object_exists(&oid2)));
+    assert_eq!(get_oid("origin/main").unwrap(), oid2);
+
+    // Errors of the remote come back through the pipe.
+    let transport = Transport::open(&origin.url).unwrap();
+    assert!(transport.fetch(&["0".repeat(40)]).is_err());
+    let missing = Remote::new(
+        "missing",
+        &format!("ext::{} %s ./nothing", env!("CARGO_BIN_EXE_dsgit")),
+    );
+    assert!(remote::fetch(&missing).is_err());
+
+    fs::remove_file("./foo.txt").unwrap();
+    fs::remove_dir_all(REMOTE_DIR).unwrap();
+}
+
+#[test]
+fn open_transport() {
+    assert_eq!(
+        Transport::open("ext::dsgit %s /srv/repo").unwrap(),
+        Transport::Command(String::from("dsgit %s /srv/repo"))
+    );
+    assert!(Transport::open("ext::dsgit upload-pack /srv/repo").is_err());
+    assert_eq!(
+        Transport::open("http://localhost:8080/").unwrap(),
+        Transport::Http(String::from("http://localhost:8080"))
+    );
+    assert_eq!(
+        Transport::open("ssh://user@host:2222/srv/repo").unwrap(),
+        Transport::Ssh(SshUrl {
+            host: String::from("user@host"),
+            port: Some(String::from("2222")),
+            path: String::from("/srv/repo"),
+        })
+    );
+    assert!(Transport::open("ssh://host").is_err());
+    // A host must not be taken as an option of ssh.
+    assert!(Transport::open("ssh://-oProxyCommand=touch%20x/srv/repo").is_err());
+    assert!(Transport::open("ssh://host:22;id/srv/repo").is_err());
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use chrono::{FixedOffset, TimeZone};
+use serial_test::serial;
+
+use common::setup;
+use dsgit::commit::Commit;
+use dsgit::data::{get_oid, get_oid_unpeeled, TypeObject};
+use dsgit::identity::format_timestamp;
+use dsgit::reference;
+use dsgit::tag::{delete_tag, list_tags, Tag};
+
+#[test]
+#[serial]
+fn create_annotated_tag() {
+    setup();
+    let oid = Commit::commit("1st commit", &[]).unwrap();
+
+    let tag_oid = Tag::create("v1.0", &oid, "First release\n\nWith notes.").unwrap();
+    let tag = Tag::get_tag(&tag_oid).unwrap();
+    assert_eq!(tag.object, oid);
+    assert_eq!(tag.obj_type, TypeObject::Commit);
+    assert_eq!(tag.tag, "v1.0");
+    assert!(tag.tagger.contains('<'));
+    assert_eq!(tag.message, "First release\n\nWith notes.");
+
+    // A tag name is peeled to the commit, unless asked for the tag itself.
+    assert_eq!(get_oid_unpeeled("v1.0").unwrap(), tag_oid);
+    assert_eq!(get_oid("v1.0").unwrap(), oid);
+
+    // A tag of a tag is peeled until the commit.
+    let nested_oid = Tag::create("v1.0-signed", &tag_oid, "Nested").unwrap();
+    assert_eq!(Tag::get_tag(&nested_oid).unwrap().obj_type, TypeObject::Tag);
+    assert_eq!(get_oid("v1.0-signed").unwrap(), oid);
+}
+
+#[test]
+fn timestamp_offset() {
+    // The offset from UTC is written after the seconds since epoch.
+    let tokyo = FixedOffset::east_opt(9 * 3600).unwrap();
+    let time = tokyo.timestamp_opt(1_600_000_000, 0).unwrap();
+    assert_eq!(format_timestamp(time), "1600000000 +0900");
+    let newfoundland = FixedOffset::west_opt(3 * 3600 + 1800).unwrap();
+    let time = newfoundland.timestamp_opt(1_600_000_000, 0).unwrap();
+    assert_eq!(format_timestamp(time), "1600000000 -0330");
+}
+
+#[test]
+#[serial]
+fn list_and_delete_tags() {
+    setup();
+    let oid = Commit::commit("1st commit", &[]).unwrap();
+    reference::create_tag("v1.0", &oid).unwrap();
+    reference::create_tag("v1.1", &oid).unwrap();
+    Tag::create("v2.0", &oid, "Second release").unwrap();
+    reference::create_tag("latest", &oid).unwrap();
+
+    assert_eq!(
+        list_tags(None).unwrap(),
+        vec!["latest", "v1.0", "v1.1", "v2.0"]
+    );
+    assert_eq!(list_tags(Some("v1.*")).unwrap(), vec!["v1.0", "v1.1"]);
+    assert_eq!(list_tags(Some("v?.0")).unwrap(), vec!["v1.0", "v2.0"]);
+
+    assert_eq!(delete_tag("v1.0").unwrap(), oid);
+    assert_eq!(list_tags(Some("v1.*")).unwrap(), vec!["v1.1"]);
+    assert!(delete_tag("v1.0").is_err());
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use serial_test::serial;
+use std::fs;
+use std::path::Path;
+
+use common::{assert_file_contents, setup, DSGIT_DIR};
+use dsgit::commit::Commit;
+use dsgit::hash::HashAlgorithm;
+use dsgit::reference;
+use dsgit::reference::RefValue;
+use dsgit::transaction::{LockFile, RefTransaction};
+
+fn ref_value(oid: &str) -> RefValue {
+    RefValue::new(Some(oid), false, oid)
+}
+
+#[test]
+#[serial]
+fn commit_transaction() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    reference::create_branch("old", &oid1).unwrap();
+    let zero_oid = HashAlgorithm::Sha1.zero_oid();
+
+    let mut transaction = RefTransaction::new();
+    transaction
+        .update(
+            "refs/heads/a",
+            ref_value(&oid1),
+            false,
+            Some(&zero_oid),
+            "a",
+        )
+        .update("refs/heads/b", ref_value(&oid2), false, None, "b")
+        .update("HEAD", ref_value(&oid1), true, Some(&oid2), "head")
+        .delete("refs/heads/old", Some(&oid1));
+    transaction.commit().unwrap();
+
+    assert_file_contents(&format!("{}/refs/heads/a", DSGIT_DIR), vec![oid1.clone()]);
+    assert_file_contents(&format!("{}/refs/heads/b", DSGIT_DIR), vec![oid2]);
+    assert_file_contents(&format!("{}/refs/heads/main", DSGIT_DIR), vec![oid1]);
+    assert!(RefValue::get_ref("refs/heads/old", false)
+        .unwrap()
+        .is_none());
+    assert!(!Path::new(&format!("{}/refs/heads/a.lock", DSGIT_DIR)).exists());
+}
+
+#[test]
+#[serial]
+fn abort_transaction_on_mismatch() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    reference::create_branch("a", &oid1).unwrap();
+    let zero_oid = HashAlgorithm::Sha1.zero_oid();
+
+    // `refs/heads/a` already exists, so nothing must be written.
+    let mut transaction = RefTransaction::new();
+    transaction
+        .update("refs/heads/b", ref_value(&oid1), false, None, "b")
+        .update(
+            "refs/heads/a",
+            ref_value(&oid2),
+            false,
+            Some(&zero_oid),
+            "a",
+        );
+    assert!(transaction.commit().is_err());
+    assert!(RefValue::get_ref("refs/heads/b", false).unwrap().is_none());
+    assert_file_contents(&format!("{}/refs/heads/a", DSGIT_DIR), vec![oid1.clone()]);
+
+    // A stale old value is rejected as well.
+    let mut transaction = RefTransaction::new();
+    transaction.update("refs/heads/a", ref_value(&oid2), false, Some(&oid2), "a");
+    assert!(transaction.commit().is_err());
+    assert_file_contents(&format!("{}/refs/heads/a", DSGIT_DIR), vec![oid1]);
+
+    let lock_files: Vec<_> = fs::read_dir(format!("{}/refs/heads", DSGIT_DIR))
+        .unwrap()
+        .filter_map(Result::ok)
+        .filter(|e| e.path().to_str().unwrap().ends_with(".lock"))
+        .collect();
+    assert!(lock_files.is_empty());
+}
+
+#[test]
+#[serial]
+fn locked_reference() {
+    setup();
+    let oid1 = Commit::commit("1st commit", &[]).unwrap();
+
+    // Another process holds the lock of the branch.
+    let lock = LockFile::acquire(&format!("{}/refs/heads/main", DSGIT_DIR)).unwrap();
+    assert!(RefValue::update_ref("HEAD", &ref_value(&oid1), true, "locked").is_err());
+    assert!(LockFile::acquire(&format!("{}/refs/heads/main", DSGIT_DIR)).is_err());
+    drop(lock);
+
+    let oid2 = Commit::commit("2nd commit", &[]).unwrap();
+    assert_eq!(reference::get_head_oid(), oid2);
+}